@@ -1,15 +1,66 @@
-use std::{fmt, ops::Range};
+use std::{collections::BTreeMap, fmt, ops::Range, time::Duration};
 
 use humansize::{make_format, BINARY};
 use rangemap::RangeMap;
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
+pub mod protocol;
+pub mod trace;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum MemState {
     Resident,
     NotResident,
     Untracked,
+
+    /// Resident, and written to at least once (as opposed to only read from,
+    /// in which case it might just be the shared zero page)
+    Dirty,
+
+    /// Was resident, but got evicted to swap
+    Swapped,
+}
+
+impl MemState {
+    /// Whether pages in this state count towards the resident set
+    pub fn is_resident(&self) -> bool {
+        matches!(self, MemState::Resident | MemState::Dirty)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum FaultAccess {
+    Read,
+    Write,
+}
+
+/// How a fault we handled got resolved. Either way, it's a minor fault: we
+/// never do I/O on the tracee's behalf.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum FaultSource {
+    /// Missing page, filled with zeroes
+    ZeroFill,
+
+    /// Page that was already in the page cache (shmem, hugetlbfs), just not
+    /// mapped in yet
+    PageCache,
+
+    /// Page that was already mapped, but write-protected
+    Unprotect,
+}
+
+/// What a tracee said about itself, through `mevi-payload`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum AnnotationKind {
+    /// Something happened, just now
+    Mark,
+
+    /// It started a phase of its own
+    Begin,
+
+    /// It ended the latest phase it started with that name
+    End,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -36,40 +87,476 @@ impl From<TraceeId> for nix::unistd::Pid {
     }
 }
 
+/// When something happened, in nanoseconds on `CLOCK_MONOTONIC`: it never
+/// jumps, but only means something next to other timestamps from the same
+/// boot. A [ClockAnchor] turns it into wall-clock time.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    #[cfg(feature = "nix")]
+    pub fn now() -> Self {
+        let ts = nix::time::clock_gettime(nix::time::ClockId::CLOCK_MONOTONIC).unwrap();
+        Self(ts.tv_sec() as u64 * 1_000_000_000 + ts.tv_nsec() as u64)
+    }
+
+    /// How long after `earlier` this happened, zero if it didn't
+    pub fn since(self, earlier: Timestamp) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+}
+
+/// The same instant, on the monotonic clock and the wall clock, taken when a
+/// session starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockAnchor {
+    pub monotonic: Timestamp,
+
+    /// Nanoseconds since the Unix epoch
+    pub unix_nanos: u64,
+}
+
+impl ClockAnchor {
+    #[cfg(feature = "nix")]
+    pub fn now() -> Self {
+        let unix = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            monotonic: Timestamp::now(),
+            unix_nanos: unix.as_nanos() as u64,
+        }
+    }
+
+    /// When `at` happened, in nanoseconds since the Unix epoch
+    pub fn to_unix_nanos(&self, at: Timestamp) -> u64 {
+        (self.unix_nanos as i128 + at.0 as i128 - self.monotonic.0 as i128) as u64
+    }
+
+    /// The other way around: when something that happened `unix_nanos` after
+    /// the epoch happened on this anchor's monotonic clock
+    pub fn to_timestamp(&self, unix_nanos: u64) -> Timestamp {
+        let at = self.monotonic.0 as i128 + unix_nanos as i128 - self.unix_nanos as i128;
+        Timestamp(at.max(0) as u64)
+    }
+}
+
+/// How far back a live session can be scrubbed, as served on `/history`, in
+/// milliseconds since the session started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryStatus {
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// Whether the tracees of a live session are frozen, as served on `/tracer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TracerStatus {
+    pub paused: bool,
+}
+
 pub type MemMap = RangeMap<u64, MemState>;
 
+/// How many bytes of `range` are resident
+pub fn resident_in(map: &MemMap, range: &Range<u64>) -> u64 {
+    map.overlapping(range)
+        .filter(|(_, state)| state.is_resident())
+        .map(|(r, _)| r.end.min(range.end) - r.start.max(range.start))
+        .sum()
+}
+
+/// Extra information about pages, sampled periodically and layered on top of
+/// residency
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, Hash)]
+pub enum Overlay {
+    /// Pages written to since the previous sample
+    Written,
+
+    /// Resident pages that weren't accessed since the previous sample
+    Idle,
+
+    /// Resident pages that KSM merged with identical ones
+    Deduplicated,
+}
+
+/// What a mapping is for, as far as we can tell
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, Hash)]
+pub enum MappingKind {
+    /// Plain anonymous memory, most likely from an allocator
+    Anonymous,
+    /// The `brk` heap
+    Heap,
+    /// The main thread's stack
+    Stack,
+    /// Another thread's stack
+    ThreadStack,
+    /// Inaccessible memory: guard pages, or address space reserved for later
+    Guard,
+    Vdso,
+    Vvar,
+    Vsyscall,
+    /// tmpfs or hugetlbfs
+    SharedMemory,
+    /// Backed by some other file that's neither code nor data: read-only
+    /// sections, or anything else that got mmapped
+    File,
+    /// Executable, and backed by a file: the program's or a library's text
+    Code,
+    /// Writable, private, and backed by a file: the program's or a
+    /// library's data
+    Data,
+}
+
+impl MappingKind {
+    /// The broader category this falls into, for grouped summaries: anon,
+    /// heap, stacks, code, data, file or shared
+    pub fn category(self) -> &'static str {
+        match self {
+            Self::Anonymous | Self::Guard => "anon",
+            Self::Heap => "heap",
+            Self::Stack | Self::ThreadStack => "stacks",
+            Self::Code | Self::Vdso | Self::Vsyscall => "code",
+            Self::Data | Self::Vvar => "data",
+            Self::File => "file",
+            Self::SharedMemory => "shared",
+        }
+    }
+}
+
+pub type MappingKinds = RangeMap<u64, MappingKind>;
+
+/// The file behind a mapping, as `/proc/<pid>/maps` shows it
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct MappingFile {
+    pub path: String,
+
+    /// Where in the file the mapping starts
+    pub offset: u64,
+
+    pub inode: u64,
+}
+
+impl MappingFile {
+    /// Just the file name, e.g. `libxml2.so.2`
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+pub type MappingFiles = RangeMap<u64, MappingFile>;
+
+/// What a mapping may be used for, and whether it's shared, from the `prot`
+/// and `flags` it was mapped with (or `/proc/<pid>/maps`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct MappingPerms {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+
+    /// `MAP_SHARED`: writes are seen by other processes mapping the same
+    /// thing, rather than copied on write
+    pub shared: bool,
+}
+
+impl MappingPerms {
+    /// Private, readable and writable, like the heap or stacks
+    pub const READ_WRITE: Self = Self {
+        read: true,
+        write: true,
+        exec: false,
+        shared: false,
+    };
+}
+
+/// As `/proc/<pid>/maps` shows them, e.g. `r-xp`
+impl fmt::Display for MappingPerms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.exec, 'x'),
+            if self.shared { 's' } else { 'p' }
+        )
+    }
+}
+
+pub type MappingPermsMap = RangeMap<u64, MappingPerms>;
+
+/// The `prot` and `flags` an mmap call was made with, exactly as the tracee
+/// passed them, newer kernels' flags included
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct MmapFlags {
+    pub prot: u64,
+    pub flags: u64,
+
+    /// The bits of `prot` and `flags` mevi doesn't know the meaning of
+    pub unknown_prot: u64,
+    pub unknown_flags: u64,
+}
+
+/// A NUMA memory policy mode, cf. `set_mempolicy(2)`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum MemPolicyMode {
+    Default,
+    Preferred,
+    Bind,
+    Interleave,
+    Local,
+    PreferredMany,
+    Unknown(u64),
+}
+
+/// A memory cgroup event, cf. `memory.events` in
+/// https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize, Hash)]
+pub enum CgroupEvent {
+    /// Reclaimed despite being under `memory.low`
+    Low,
+    /// Throttled and reclaimed for going over `memory.high`
+    High,
+    /// About to go over `memory.max`
+    Max,
+    /// Hit the limit and allocations failed
+    Oom,
+    /// A process got killed by the OOM killer
+    OomKill,
+    /// The whole cgroup got killed by the OOM killer
+    OomGroupKill,
+}
+
+/// Something about a process we only learn by sampling periodically, rather
+/// than as it happens
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Sample {
+    /// Bytes backed by transparent huge pages, for every mapping that has
+    /// any
+    HugePages { regions: Vec<(Range<u64>, u64)> },
+
+    /// Bytes on each NUMA node, for every mapping that has any resident
+    NumaNodes {
+        regions: Vec<(Range<u64>, Vec<(u32, u64)>)>,
+    },
+
+    /// Usage and limits of the memory cgroup the process is in, in bytes
+    CgroupMemory {
+        path: String,
+        current: u64,
+        high: Option<u64>,
+        max: Option<u64>,
+    },
+
+    /// The kernel's own accounting of the process's memory, from
+    /// smaps_rollup, in bytes
+    Rollup {
+        rss: u64,
+        pss: u64,
+        shared_clean: u64,
+        swap: u64,
+    },
+
+    /// Faults the process took since the previous sample. Major faults are
+    /// the ones that needed I/O (reading from a file, swapping in)
+    Faults { minor: u64, major: u64 },
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum MeviEvent {
     Snapshot(Vec<TraceeSnapshot>),
     TraceeEvent(TraceeId, TraceePayload),
+
+    /// The tracer is done and every tracee is gone: nothing else is coming
+    SessionEnd(SessionSummary),
+
+    /// What the session is, as told by whoever started it. Comes first, if
+    /// at all.
+    SessionInfo(SessionInfo),
+}
+
+/// A name and tags for a session (`--name`, `--tag`), so that whoever looks
+/// at it later knows what it was about
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub name: Option<String>,
+
+    /// `KEY=VALUE` pairs, in the order they were given
+    pub tags: Vec<(String, String)>,
+}
+
+impl SessionInfo {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.tags.is_empty()
+    }
+}
+
+/// e.g. `nightly-bench (git=abc123, cfg=jemalloc)`
+impl fmt::Display for SessionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("unnamed session"))?;
+        for (i, (key, value)) in self.tags.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            write!(f, "{sep}{key}={value}")?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
 }
 
-pub fn serialize_many(events: &[MeviEvent]) -> postcard::Result<Vec<u8>> {
-    postcard::to_allocvec(events)
+/// What a session came to, for clients to show once the processes they were
+/// looking at are gone
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionSummary {
+    /// Every tracee the relay saw, in the order they went away
+    pub tracees: Vec<TraceeTotals>,
+
+    /// How many events went through the relay
+    pub events: u64,
+}
+
+/// How a tracee was doing when it went away
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceeTotals {
+    pub tid: TraceeId,
+    pub cmdline: Vec<String>,
+
+    /// When it exited, if we saw it happen
+    pub exited_at: Option<Timestamp>,
+    pub mapped: u64,
+    pub resident: u64,
+
+    /// The most it ever had resident at once
+    pub peak_resident: u64,
+    pub faults: u64,
+
+    /// Resident bytes in each category of mapping, cf.
+    /// [MappingKind::category]
+    pub by_category: BTreeMap<String, u64>,
 }
 
-pub fn deserialize_many(data: &[u8]) -> postcard::Result<Vec<MeviEvent>> {
-    postcard::from_bytes(data)
+/// An event, and when it was captured
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedEvent {
+    pub at: Timestamp,
+
+    /// Numbers the events of each tracee, one after the other, as the relay
+    /// hands them out: a client that sees a jump missed some. Zero for
+    /// snapshots, and until the relay gets to it.
+    pub seq: u64,
+    pub event: MeviEvent,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TraceeSnapshot {
     pub tid: TraceeId,
     pub cmdline: Vec<String>,
+    #[serde(with = "protocol::runs")]
     pub map: MemMap,
+    #[serde(with = "protocol::runs")]
+    pub kinds: MappingKinds,
+    #[serde(with = "protocol::runs")]
+    pub files: MappingFiles,
+    #[serde(with = "protocol::runs")]
+    pub perms: MappingPermsMap,
+
+    /// The sequence number of the next event for this tracee
+    pub seq: u64,
+
+    /// With a coarser granularity than pages, how much of each bucket is
+    /// resident: `map` then only tells buckets with anything resident apart
+    /// from the others
+    pub resident_pages: Option<ResidentPages>,
+
+    /// Who the process is, if we know
+    pub info: Option<ProcessInfo>,
+
+    /// The traced process that forked this one, if any
+    pub parent: Option<TraceeId>,
+
+    /// The name of each thread we know of, main thread included
+    pub threads: Vec<(TraceeId, String)>,
+}
+
+/// What procfs says about a process when we start tracing it, so it can be
+/// told apart from others by more than its pid
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    /// Where `/proc/<pid>/exe` points
+    pub exe: Option<String>,
+
+    /// As the process sees it, which may have been rewritten (e.g.
+    /// `postgres: checkpointer`)
+    pub cmdline: Vec<String>,
+
+    pub cwd: Option<String>,
+
+    /// Real user and group ids
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Resident bytes in each `bucket`-sized, `bucket`-aligned slice of the
+/// address space, for runs of buckets that each have as many. Sent instead
+/// of page-level state when mevi runs with a coarser `--granularity`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResidentPages {
+    pub bucket: u64,
+    pub runs: Vec<(Range<u64>, u64)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TraceePayload {
-    /// Clears all memory mappings
-    Exec,
+    /// Clears all memory mappings: the process is now running another
+    /// program
+    Exec {
+        /// Where `/proc/<pid>/exe` points now
+        exe: Option<String>,
+        argv: Vec<String>,
+        /// The dynamic loader the new program asked for (its `PT_INTERP`),
+        /// unless it's static
+        interpreter: Option<String>,
+    },
 
-    // Used on mmap, madvise(DONTNEED), page faults
+    // A new mapping (or one we just found out about), and the file behind
+    // it, if any
+    Map {
+        range: Range<u64>,
+        state: MemState,
+        kind: MappingKind,
+        file: Option<MappingFile>,
+        perms: MappingPerms,
+
+        /// What mmap was called with, when we saw it happen rather than
+        /// found the mapping in `/proc/<pid>/maps`
+        raw: Option<MmapFlags>,
+    },
+
+    // Used on madvise(DONTNEED), page faults
     MemStateChange {
         range: Range<u64>,
         state: MemState,
     },
 
+    // First touch (or, in write-protect mode, first write) of a page
+    PageFault {
+        range: Range<u64>,
+        access: FaultAccess,
+
+        // the thread that faulted, if the kernel told us
+        thread: Option<TraceeId>,
+
+        source: FaultSource,
+
+        // the code that faulted, as `function+offset`, with --symbolicate
+        site: Option<String>,
+    },
+
     // Clears a specific mapping
     Unmap {
         range: Range<u64>,
@@ -85,7 +572,93 @@ pub enum TraceePayload {
         cmdline: Vec<String>,
     },
 
+    // The full set of pages an overlay currently applies to, replacing the
+    // previous sample
+    OverlaySample {
+        overlay: Overlay,
+        ranges: Vec<Range<u64>>,
+    },
+
+    // NUMA policy set with mbind (for a range) or set_mempolicy (for the
+    // whole thread, in which case there's no range)
+    MemPolicy {
+        range: Option<Range<u64>>,
+        mode: MemPolicyMode,
+        nodes: Vec<u32>,
+    },
+
+    // Something happened to the process's memory cgroup, `count` times since
+    // the previous sample
+    CgroupEvent {
+        event: CgroupEvent,
+        count: u64,
+    },
+
+    // The process got killed by the OOM killer, with that many bytes
+    // resident last we checked
+    OomKilled {
+        rss: u64,
+    },
+
+    // The latest value of something we sample periodically
+    Sample(Sample),
+
+    // Something the user should know about, e.g. tracking is degraded
+    Warning {
+        message: String,
+    },
+
     Exit,
+
+    // That many events about this tracee were dropped because mevi couldn't
+    // keep up, so what we know about it may be off
+    Lost {
+        count: u64,
+    },
+
+    // What changed in an overlay since the previous sample this client got,
+    // cf. [protocol::OverlayDeltas]. Never makes it past the server.
+    OverlayDelta {
+        overlay: Overlay,
+        added: Vec<Range<u64>>,
+        removed: Vec<Range<u64>>,
+    },
+
+    // How much of each bucket in these runs is resident now, in place of the
+    // page-level changes that got it there
+    ResidentPages(ResidentPages),
+
+    // Who the process is, once we start tracing it
+    ProcessInfo(ProcessInfo),
+
+    // The process was forked by `parent`, or, with `None`, its parent exited
+    // and it got reparented to something we don't trace
+    ParentChange {
+        parent: Option<TraceeId>,
+    },
+
+    // A thread of the process started out with, or switched to, that name
+    // (its `comm`, at most 15 bytes)
+    ThreadName {
+        thread: TraceeId,
+        name: String,
+    },
+
+    // A thread made one of the syscalls we were asked to watch: its
+    // arguments (in order, however many it takes) and what it returned
+    Syscall {
+        thread: TraceeId,
+        name: String,
+        args: [u64; 6],
+        ret: i64,
+    },
+
+    // The process told us where it's at, through mevi-payload, so what
+    // happens to its memory can be tied to phases of its own
+    Annotation {
+        name: String,
+        kind: AnnotationKind,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,15 +667,98 @@ pub enum ConnectSource {
 }
 
 impl TraceePayload {
+    /// Keeps track of what each mapping is for, alongside `apply_to_memmap`
+    pub fn apply_to_kinds(&self, kinds: &mut MappingKinds) {
+        match self {
+            TraceePayload::Exec { .. } => kinds.clear(),
+            TraceePayload::Map { range, kind, .. } => kinds.insert(range.clone(), *kind),
+            TraceePayload::Unmap { range } => kinds.remove(range.clone()),
+            TraceePayload::Remap {
+                old_range,
+                new_range,
+            } => {
+                // a mapping only has one kind, no need to carry it over page
+                // by page like residency
+                let kind = kinds.get(&old_range.start).copied();
+                kinds.remove(old_range.clone());
+                if let Some(kind) = kind {
+                    kinds.insert(new_range.clone(), kind);
+                }
+            }
+            _ => {
+                // doesn't change mappings
+            }
+        }
+    }
+
+    /// Keeps track of which file is behind each mapping, the same way
+    pub fn apply_to_files(&self, files: &mut MappingFiles) {
+        match self {
+            TraceePayload::Exec { .. } => files.clear(),
+            TraceePayload::Map { range, file, .. } => match file {
+                Some(file) => files.insert(range.clone(), file.clone()),
+                None => files.remove(range.clone()),
+            },
+            TraceePayload::Unmap { range } => files.remove(range.clone()),
+            TraceePayload::Remap {
+                old_range,
+                new_range,
+            } => {
+                let file = files.get(&old_range.start).cloned();
+                files.remove(old_range.clone());
+                if let Some(file) = file {
+                    files.insert(new_range.clone(), file);
+                }
+            }
+            _ => {
+                // doesn't change mappings
+            }
+        }
+    }
+
+    /// Keeps track of each mapping's permissions, the same way
+    pub fn apply_to_perms(&self, perms: &mut MappingPermsMap) {
+        match self {
+            TraceePayload::Exec { .. } => perms.clear(),
+            TraceePayload::Map {
+                range,
+                perms: map_perms,
+                ..
+            } => perms.insert(range.clone(), *map_perms),
+            TraceePayload::Unmap { range } => perms.remove(range.clone()),
+            TraceePayload::Remap {
+                old_range,
+                new_range,
+            } => {
+                let map_perms = perms.get(&old_range.start).copied();
+                perms.remove(old_range.clone());
+                if let Some(map_perms) = map_perms {
+                    perms.insert(new_range.clone(), map_perms);
+                }
+            }
+            _ => {
+                // doesn't change mappings
+            }
+        }
+    }
+
     pub fn apply_to_memmap(&self, map: &mut MemMap) {
         match self {
-            TraceePayload::Exec => {
+            TraceePayload::Exec { .. } => {
                 // all the mappings are invalidated on exec
                 map.clear();
             }
-            TraceePayload::MemStateChange { range, state } => {
+            TraceePayload::Map { range, state, .. }
+            | TraceePayload::MemStateChange { range, state } => {
                 map.insert(range.clone(), *state);
             }
+            TraceePayload::PageFault { range, access, .. } => {
+                let state = match access {
+                    FaultAccess::Read => MemState::Resident,
+                    FaultAccess::Write => MemState::Dirty,
+                };
+                map.insert(range.clone(), state);
+            }
             TraceePayload::Unmap { range } => {
                 if range.start >= range.end {
                     panic!("unmap range is invalid: {range:x?}");
@@ -202,12 +858,49 @@ impl TraceePayload {
                     }
                 }
             }
-            TraceePayload::CmdLineChange { .. } => {
+            TraceePayload::CmdLineChange { .. }
+            | TraceePayload::ProcessInfo(_)
+            | TraceePayload::ParentChange { .. }
+            | TraceePayload::ThreadName { .. }
+            | TraceePayload::Syscall { .. }
+            | TraceePayload::Annotation { .. } => {
+                // do nothing
+            }
+            TraceePayload::OverlaySample { .. } | TraceePayload::OverlayDelta { .. } => {
+                // overlays are tracked separately
+            }
+            TraceePayload::ResidentPages(pages) => {
+                // as close as a map of states gets to a count
+                for (range, resident) in &pages.runs {
+                    let state = if *resident > 0 {
+                        MemState::Resident
+                    } else {
+                        MemState::NotResident
+                    };
+                    map.insert(range.clone(), state);
+                }
+            }
+            TraceePayload::OomKilled { .. } => {
+                // the Exit that follows takes care of it
+            }
+            TraceePayload::CgroupEvent { .. } => {
+                // doesn't tell us which pages went away
+            }
+            TraceePayload::MemPolicy { .. } => {
+                // doesn't change residency, not right away anyway
+            }
+            TraceePayload::Sample(_) => {
+                // samples are tracked separately
+            }
+            TraceePayload::Warning { .. } => {
                 // do nothing
             }
             TraceePayload::Exit { .. } => {
                 // do nothing
             }
+            TraceePayload::Lost { .. } => {
+                // nothing we can do about it now
+            }
         }
     }
 }
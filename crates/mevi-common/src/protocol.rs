@@ -0,0 +1,768 @@
+//! The binary protocol spoken between mevi and whatever watches it (the
+//! frontend, or anything else connecting to `/stream`).
+//!
+//! Every frame starts with a one-byte tag. The first frame each side sends
+//! is a [Hello], which has a fixed layout that no version is allowed to
+//! change: that's how both sides find out whether they can understand each
+//! other at all. The client follows its hello with a [Frame::Resume], saying
+//! where it's at if it was connected before, and before that with a
+//! [Frame::Token] if the server wants one.
+//!
+//! After that, the server sends a [Frame::Clock] to line up event timestamps
+//! with wall-clock time, then only [Frame::Events], and only with payloads the
+//! client said it knows about, cf. [Features]. Leaving out payloads also
+//! leaves gaps in [TimedEvent::seq], so gaps only mean lost events for
+//! clients that asked for everything. When there's nothing to send, the
+//! server sends a [Frame::Heartbeat] every [HEARTBEAT_INTERVAL], so clients
+//! can tell a quiet tracee from a dead connection.
+
+use std::{collections::HashMap, fmt, ops::Range, time::Duration};
+
+use rangemap::RangeSet;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ClockAnchor, FaultAccess, MemState, MeviEvent, Overlay, ResidentPages, TimedEvent, Timestamp,
+    TraceeId, TraceePayload,
+};
+
+/// Only bumped when frames themselves change: their tags, or how they're laid
+/// out. Peers that don't agree on this can't talk to each other, so new kinds
+/// of events never bump it, cf. [Features].
+pub const VERSION: u16 = 1;
+
+/// Byte streams prefix each frame with its length. Frames bigger than that
+/// are more likely garbage than a real snapshot.
+pub const MAX_FRAME_LEN: u32 = 256 * 1024 * 1024;
+
+/// How long the server goes without sending anything, at most
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+const TAG_HELLO: u8 = 0;
+const TAG_EVENTS: u8 = 1;
+const TAG_CLOCK: u8 = 2;
+const TAG_HEARTBEAT: u8 = 3;
+const TAG_RESUME: u8 = 4;
+const TAG_TOKEN: u8 = 5;
+
+/// Optional kinds of payloads. Adding a variant to [TraceePayload] means
+/// adding a feature for it here, so that older clients can keep decoding
+/// everything they're sent.
+///
+/// For that to work, what's already on the wire is frozen: postcard has no
+/// field names and numbers variants by position, so existing variants and
+/// the structs in them never gain, lose or reorder fields, and new variants
+/// only ever go at the end of their enum. Something that needs more fields
+/// gets a new variant with them, behind a new feature, and [downgrade] turns
+/// it into the old one for peers that don't have it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct Features(u32);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+
+    /// `Map`, otherwise mappings show up as `MemStateChange`
+    pub const MAPPING_KINDS: Self = Self(1 << 0);
+
+    /// `PageFault`, otherwise faults show up as `MemStateChange`
+    pub const PAGE_FAULTS: Self = Self(1 << 1);
+
+    /// `OverlaySample`
+    pub const OVERLAYS: Self = Self(1 << 2);
+
+    /// `MemPolicy`
+    pub const MEM_POLICY: Self = Self(1 << 3);
+
+    /// `CgroupEvent` and `OomKilled`
+    pub const CGROUP: Self = Self(1 << 4);
+
+    /// `Sample`
+    pub const SAMPLES: Self = Self(1 << 5);
+
+    /// `Lost`, otherwise it shows up as a `Warning`
+    pub const LOST: Self = Self(1 << 6);
+
+    /// `OverlayDelta`, otherwise every sample is sent whole
+    pub const OVERLAY_DELTAS: Self = Self(1 << 7);
+
+    /// `ProcessInfo`
+    pub const PROCESS_INFO: Self = Self(1 << 8);
+
+    /// `ParentChange`
+    pub const PROCESS_TREE: Self = Self(1 << 9);
+
+    /// `ThreadName`
+    pub const THREAD_NAMES: Self = Self(1 << 10);
+
+    /// `MeviEvent::SessionEnd`
+    pub const SESSION_END: Self = Self(1 << 11);
+
+    /// `Syscall`
+    pub const SYSCALLS: Self = Self(1 << 12);
+
+    /// `MeviEvent::SessionInfo`
+    pub const SESSION_INFO: Self = Self(1 << 13);
+
+    /// `Annotation`
+    pub const ANNOTATIONS: Self = Self(1 << 14);
+
+    /// `ResidentPages`, otherwise they show up as `MemStateChange` when
+    /// they're all one state, and not at all when they aren't
+    pub const RESIDENT_PAGES: Self = Self(1 << 15);
+
+    /// Everything this version of mevi knows about
+    pub const ALL: Self = Self(
+        Self::MAPPING_KINDS.0
+            | Self::PAGE_FAULTS.0
+            | Self::OVERLAYS.0
+            | Self::MEM_POLICY.0
+            | Self::CGROUP.0
+            | Self::SAMPLES.0
+            | Self::LOST.0
+            | Self::OVERLAY_DELTAS.0
+            | Self::PROCESS_INFO.0
+            | Self::PROCESS_TREE.0
+            | Self::THREAD_NAMES.0
+            | Self::SESSION_END.0
+            | Self::SYSCALLS.0
+            | Self::SESSION_INFO.0
+            | Self::ANNOTATIONS.0
+            | Self::RESIDENT_PAGES.0,
+    );
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Keeps bits we don't know about: they might mean something to a
+    /// newer peer, and intersecting will get rid of them anyway.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+}
+
+/// What each side sends first
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Hello {
+    pub version: u16,
+    pub features: Features,
+}
+
+impl Hello {
+    /// What this build of mevi speaks
+    pub fn ours() -> Self {
+        Self {
+            version: VERSION,
+            features: Features::ALL,
+        }
+    }
+
+    /// What the two peers can use, if they can talk at all
+    pub fn negotiate(&self, theirs: &Hello) -> Result<Features, Error> {
+        if self.version != theirs.version {
+            return Err(Error::VersionMismatch {
+                ours: self.version,
+                theirs: theirs.version,
+            });
+        }
+        Ok(self.features.intersection(theirs.features))
+    }
+}
+
+#[derive(Debug)]
+pub enum Frame {
+    Hello(Hello),
+    Clock(ClockAnchor),
+    Events(Vec<TimedEvent>),
+
+    /// When the server sent it, on its monotonic clock
+    Heartbeat(Timestamp),
+
+    /// The next sequence number the client expects for each tracee it knows
+    /// about, empty if it's starting from scratch. If the server still has
+    /// everything that came after, it sends that, otherwise it starts over
+    /// with a snapshot.
+    Resume(Vec<(TraceeId, u64)>),
+
+    /// The token the server wants from its clients, if it wants one. Only
+    /// goes between the client's hello and its resume, from clients that
+    /// were given a token: servers that don't check tokens may not know it.
+    Token(String),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The frame was empty, or shorter than its tag says it should be
+    Truncated,
+
+    /// The frame's tag isn't one we know
+    UnknownTag(u8),
+
+    VersionMismatch {
+        ours: u16,
+        theirs: u16,
+    },
+
+    Postcard(postcard::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "truncated frame"),
+            Error::UnknownTag(tag) => write!(f, "unknown frame tag {tag}"),
+            Error::VersionMismatch { ours, theirs } => {
+                write!(
+                    f,
+                    "protocol version mismatch: we speak {ours}, peer speaks {theirs}"
+                )
+            }
+            Error::Postcard(e) => write!(f, "malformed events: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<postcard::Error> for Error {
+    fn from(e: postcard::Error) -> Self {
+        Error::Postcard(e)
+    }
+}
+
+/// Hello frames are laid out by hand (tag, then little-endian version and
+/// features) so they never depend on how serde or postcard evolve.
+pub fn encode_hello(hello: &Hello) -> Vec<u8> {
+    let mut out = Vec::with_capacity(7);
+    out.push(TAG_HELLO);
+    out.extend_from_slice(&hello.version.to_le_bytes());
+    out.extend_from_slice(&hello.features.bits().to_le_bytes());
+    out
+}
+
+pub fn encode_clock(anchor: &ClockAnchor) -> Result<Vec<u8>, Error> {
+    let mut out = vec![TAG_CLOCK];
+    out.extend(postcard::to_allocvec(anchor)?);
+    Ok(out)
+}
+
+pub fn encode_heartbeat(at: Timestamp) -> Result<Vec<u8>, Error> {
+    let mut out = vec![TAG_HEARTBEAT];
+    out.extend(postcard::to_allocvec(&at)?);
+    Ok(out)
+}
+
+pub fn encode_resume(from: &[(TraceeId, u64)]) -> Result<Vec<u8>, Error> {
+    let mut out = vec![TAG_RESUME];
+    out.extend(postcard::to_allocvec(from)?);
+    Ok(out)
+}
+
+pub fn encode_token(token: &str) -> Result<Vec<u8>, Error> {
+    let mut out = vec![TAG_TOKEN];
+    out.extend(postcard::to_allocvec(token)?);
+    Ok(out)
+}
+
+pub fn encode_events(events: &[TimedEvent]) -> Result<Vec<u8>, Error> {
+    let mut out = vec![TAG_EVENTS];
+    out.extend(postcard::to_allocvec(events)?);
+    Ok(out)
+}
+
+pub fn decode(data: &[u8]) -> Result<Frame, Error> {
+    let (&tag, rest) = data.split_first().ok_or(Error::Truncated)?;
+    match tag {
+        TAG_HELLO => {
+            if rest.len() < 6 {
+                return Err(Error::Truncated);
+            }
+            let version = u16::from_le_bytes([rest[0], rest[1]]);
+            let features = u32::from_le_bytes([rest[2], rest[3], rest[4], rest[5]]);
+            Ok(Frame::Hello(Hello {
+                version,
+                features: Features::from_bits(features),
+            }))
+        }
+        TAG_CLOCK => Ok(Frame::Clock(postcard::from_bytes(rest)?)),
+        TAG_HEARTBEAT => Ok(Frame::Heartbeat(postcard::from_bytes(rest)?)),
+        TAG_RESUME => Ok(Frame::Resume(postcard::from_bytes(rest)?)),
+        TAG_EVENTS => Ok(Frame::Events(postcard::from_bytes(rest)?)),
+        TAG_TOKEN => Ok(Frame::Token(postcard::from_bytes(rest)?)),
+        tag => Err(Error::UnknownTag(tag)),
+    }
+}
+
+/// Rewrites an event so a peer with only `features` can decode it, or drops
+/// it if there's nothing it could make of it.
+pub fn downgrade(ev: MeviEvent, features: Features) -> Option<MeviEvent> {
+    match ev {
+        MeviEvent::Snapshot(mut snapshots) => {
+            if !features.contains(Features::MAPPING_KINDS) {
+                for snapshot in &mut snapshots {
+                    snapshot.kinds.clear();
+                    snapshot.files.clear();
+                    snapshot.perms.clear();
+                }
+            }
+            if !features.contains(Features::PROCESS_INFO) {
+                for snapshot in &mut snapshots {
+                    snapshot.info = None;
+                }
+            }
+            if !features.contains(Features::PROCESS_TREE) {
+                for snapshot in &mut snapshots {
+                    snapshot.parent = None;
+                }
+            }
+            if !features.contains(Features::THREAD_NAMES) {
+                for snapshot in &mut snapshots {
+                    snapshot.threads.clear();
+                }
+            }
+            if !features.contains(Features::RESIDENT_PAGES) {
+                // the map has those buckets as resident already
+                for snapshot in &mut snapshots {
+                    snapshot.resident_pages = None;
+                }
+            }
+            Some(MeviEvent::Snapshot(snapshots))
+        }
+        MeviEvent::TraceeEvent(tid, payload) => {
+            downgrade_payload(payload, features).map(|payload| MeviEvent::TraceeEvent(tid, payload))
+        }
+        MeviEvent::SessionEnd(_) if !features.contains(Features::SESSION_END) => None,
+        ev @ MeviEvent::SessionEnd(_) => Some(ev),
+        MeviEvent::SessionInfo(_) if !features.contains(Features::SESSION_INFO) => None,
+        ev @ MeviEvent::SessionInfo(_) => Some(ev),
+    }
+}
+
+fn downgrade_payload(payload: TraceePayload, features: Features) -> Option<TraceePayload> {
+    let payload = match payload {
+        TraceePayload::Map { range, state, .. } if !features.contains(Features::MAPPING_KINDS) => {
+            TraceePayload::MemStateChange { range, state }
+        }
+        TraceePayload::PageFault { range, access, .. }
+            if !features.contains(Features::PAGE_FAULTS) =>
+        {
+            TraceePayload::MemStateChange {
+                range,
+                state: match access {
+                    FaultAccess::Read => MemState::Resident,
+                    FaultAccess::Write => MemState::Dirty,
+                },
+            }
+        }
+        TraceePayload::OverlaySample { .. } if !features.contains(Features::OVERLAYS) => {
+            return None
+        }
+        TraceePayload::MemPolicy { .. } if !features.contains(Features::MEM_POLICY) => return None,
+        TraceePayload::CgroupEvent { .. } | TraceePayload::OomKilled { .. }
+            if !features.contains(Features::CGROUP) =>
+        {
+            return None
+        }
+        TraceePayload::Sample(_) if !features.contains(Features::SAMPLES) => return None,
+        // the cmdline comes separately anyway
+        TraceePayload::ProcessInfo(_) if !features.contains(Features::PROCESS_INFO) => return None,
+        TraceePayload::ParentChange { .. } if !features.contains(Features::PROCESS_TREE) => {
+            return None
+        }
+        TraceePayload::ThreadName { .. } if !features.contains(Features::THREAD_NAMES) => {
+            return None
+        }
+        TraceePayload::Syscall { .. } if !features.contains(Features::SYSCALLS) => return None,
+        TraceePayload::Annotation { .. } if !features.contains(Features::ANNOTATIONS) => {
+            return None
+        }
+        TraceePayload::ResidentPages(pages) if !features.contains(Features::RESIDENT_PAGES) => {
+            resident_pages_as_state(&pages)?
+        }
+        TraceePayload::Lost { count } if !features.contains(Features::LOST) => {
+            TraceePayload::Warning {
+                message: format!("{count} events lost, mevi couldn't keep up"),
+            }
+        }
+        payload => payload,
+    };
+    Some(payload)
+}
+
+/// `pages` as a single `MemStateChange`, if it can be one: the runs follow
+/// each other, and they're either all resident or all not
+fn resident_pages_as_state(pages: &ResidentPages) -> Option<TraceePayload> {
+    let (first, _) = pages.runs.first()?;
+    let (last, _) = pages.runs.last()?;
+    let resident = pages.runs.iter().all(|(_, resident)| *resident > 0);
+    let not_resident = pages.runs.iter().all(|(_, resident)| *resident == 0);
+    let contiguous = pages.runs.windows(2).all(|w| w[0].0.end == w[1].0.start);
+    if !contiguous || resident == not_resident {
+        return None;
+    }
+    Some(TraceePayload::MemStateChange {
+        range: first.start..last.end,
+        // same as apply_to_memmap has it
+        state: if resident {
+            MemState::Resident
+        } else {
+            MemState::NotResident
+        },
+    })
+}
+
+/// How many deltas a client gets between two whole overlay samples, so that
+/// if it ever gets one wrong, it doesn't stay wrong for long
+const KEYFRAME_EVERY: u32 = 16;
+
+/// Turns overlay samples into deltas against the previous sample a client was
+/// sent. Samplers send the whole set every time, and most of it is the same
+/// as last time.
+#[derive(Default)]
+pub struct OverlayDeltas {
+    /// What the client has, and how many deltas it got since the last whole
+    /// sample
+    sent: HashMap<(TraceeId, Overlay), (RangeSet<u64>, u32)>,
+}
+
+impl OverlayDeltas {
+    pub fn encode(&mut self, ev: MeviEvent) -> MeviEvent {
+        let MeviEvent::TraceeEvent(tid, payload) = ev else {
+            return ev;
+        };
+        let payload = match payload {
+            TraceePayload::OverlaySample { overlay, ranges } => {
+                let mut now = RangeSet::new();
+                for range in ranges.iter().filter(|range| !range.is_empty()) {
+                    now.insert(range.clone());
+                }
+                match self.sent.get_mut(&(tid, overlay)) {
+                    Some((prev, deltas)) if *deltas < KEYFRAME_EVERY => {
+                        let added = difference(&now, prev);
+                        let removed = difference(prev, &now);
+                        *prev = now;
+                        *deltas += 1;
+                        TraceePayload::OverlayDelta {
+                            overlay,
+                            added,
+                            removed,
+                        }
+                    }
+                    _ => {
+                        self.sent.insert((tid, overlay), (now, 0));
+                        TraceePayload::OverlaySample { overlay, ranges }
+                    }
+                }
+            }
+            TraceePayload::Exec { .. } | TraceePayload::Exit => {
+                self.sent.retain(|(sent_tid, _), _| *sent_tid != tid);
+                payload
+            }
+            payload => payload,
+        };
+        MeviEvent::TraceeEvent(tid, payload)
+    }
+}
+
+/// The parts of `a` that aren't in `b`
+fn difference(a: &RangeSet<u64>, b: &RangeSet<u64>) -> Vec<Range<u64>> {
+    a.iter().flat_map(|range| b.gaps(range)).collect()
+}
+
+/// Brings the ranges of an overlay up to date with an `OverlayDelta`. Only
+/// the ranges that changed are touched, however many the overlay has.
+pub fn apply_overlay_delta(
+    ranges: &mut RangeSet<u64>,
+    added: &[Range<u64>],
+    removed: &[Range<u64>],
+) {
+    for range in added.iter().filter(|range| !range.is_empty()) {
+        ranges.insert(range.clone());
+    }
+    for range in removed.iter().filter(|range| !range.is_empty()) {
+        ranges.remove(range.clone());
+    }
+}
+
+/// Serializes range maps as runs: how far each range starts from the end of
+/// the previous one, how long it is, and its value. Addresses are big
+/// numbers, but gaps and lengths mostly aren't, and postcard's varints make
+/// small numbers short: a snapshot of a multi-gigabyte address space comes
+/// down to a few bytes per run.
+pub mod runs {
+    use rangemap::RangeMap;
+    use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<V, S>(map: &RangeMap<u64, V>, s: S) -> Result<S::Ok, S::Error>
+    where
+        V: Serialize + Eq + Clone,
+        S: Serializer,
+    {
+        let mut seq = s.serialize_seq(Some(map.iter().count()))?;
+        let mut prev_end = 0;
+        for (range, value) in map.iter() {
+            seq.serialize_element(&(range.start - prev_end, range.end - range.start, value))?;
+            prev_end = range.end;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, V, D>(d: D) -> Result<RangeMap<u64, V>, D::Error>
+    where
+        V: Deserialize<'de> + Eq + Clone,
+        D: Deserializer<'de>,
+    {
+        let mut map = RangeMap::new();
+        let mut prev_end = 0u64;
+        for (gap, len, value) in Vec::<(u64, u64, V)>::deserialize(d)? {
+            let start = prev_end
+                .checked_add(gap)
+                .ok_or_else(|| de::Error::custom("run starts past the address space"))?;
+            let end = start
+                .checked_add(len)
+                .ok_or_else(|| de::Error::custom("run ends past the address space"))?;
+            if len == 0 {
+                return Err(de::Error::custom("empty run"));
+            }
+            map.insert(start..end, value);
+            prev_end = end;
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnnotationKind, MappingKind, MappingPerms};
+
+    fn event(payload: TraceePayload) -> MeviEvent {
+        MeviEvent::TraceeEvent(TraceeId(1), payload)
+    }
+
+    fn map(state: MemState) -> MeviEvent {
+        event(TraceePayload::Map {
+            range: 0x1000..0x3000,
+            state,
+            kind: MappingKind::Anonymous,
+            file: None,
+            perms: MappingPerms::default(),
+            raw: None,
+        })
+    }
+
+    fn sample(ranges: &[Range<u64>]) -> MeviEvent {
+        event(TraceePayload::OverlaySample {
+            overlay: Overlay::Written,
+            ranges: ranges.to_vec(),
+        })
+    }
+
+    fn resident_pages(runs: &[(Range<u64>, u64)]) -> MeviEvent {
+        event(TraceePayload::ResidentPages(ResidentPages {
+            bucket: 0x1000,
+            runs: runs.to_vec(),
+        }))
+    }
+
+    /// What a client ends up with after being sent `ev`, and whether it was
+    /// sent the whole sample
+    fn apply(client: &mut RangeSet<u64>, ev: MeviEvent) -> bool {
+        match ev {
+            MeviEvent::TraceeEvent(_, TraceePayload::OverlaySample { ranges, .. }) => {
+                *client = RangeSet::new();
+                apply_overlay_delta(client, &ranges, &[]);
+                true
+            }
+            MeviEvent::TraceeEvent(_, TraceePayload::OverlayDelta { added, removed, .. }) => {
+                apply_overlay_delta(client, &added, &removed);
+                false
+            }
+            ev => panic!("not an overlay: {ev:?}"),
+        }
+    }
+
+    fn ranges(set: &RangeSet<u64>) -> Vec<Range<u64>> {
+        set.iter().cloned().collect()
+    }
+
+    #[test]
+    fn hello_round_trip() {
+        let ours = Hello::ours();
+        let Frame::Hello(theirs) = decode(&encode_hello(&ours)).unwrap() else {
+            panic!("not a hello");
+        };
+        assert_eq!(theirs, ours);
+        assert_eq!(ours.negotiate(&theirs).unwrap(), Features::ALL);
+
+        let older = Hello {
+            version: VERSION,
+            features: Features::MAPPING_KINDS,
+        };
+        assert_eq!(ours.negotiate(&older).unwrap(), Features::MAPPING_KINDS);
+
+        let other_framing = Hello {
+            version: VERSION + 1,
+            features: Features::ALL,
+        };
+        assert!(matches!(
+            ours.negotiate(&other_framing),
+            Err(Error::VersionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let events = [
+            map(MemState::NotResident),
+            sample(&[0x1000..0x2000]),
+            event(TraceePayload::Exit),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, event)| TimedEvent {
+            at: Timestamp(i as u64 * 1000),
+            seq: i as u64 + 1,
+            event,
+        })
+        .collect::<Vec<_>>();
+
+        let frame = encode_events(&events).unwrap();
+        let Frame::Events(decoded) = decode(&frame).unwrap() else {
+            panic!("not events");
+        };
+        assert_eq!(decoded.len(), events.len());
+        // nothing got lost or changed on the way
+        assert_eq!(encode_events(&decoded).unwrap(), frame);
+
+        assert!(matches!(
+            decode(&frame[..frame.len() - 1]),
+            Err(Error::Postcard(_))
+        ));
+        assert!(matches!(decode(&[]), Err(Error::Truncated)));
+        assert!(matches!(decode(&[0xff]), Err(Error::UnknownTag(0xff))));
+    }
+
+    #[test]
+    fn token_round_trip() {
+        let Frame::Token(token) = decode(&encode_token("0123abcd").unwrap()).unwrap() else {
+            panic!("not a token");
+        };
+        assert_eq!(token, "0123abcd");
+    }
+
+    #[test]
+    fn downgrade_for_older_peers() {
+        assert!(matches!(
+            downgrade(map(MemState::Resident), Features::ALL),
+            Some(MeviEvent::TraceeEvent(_, TraceePayload::Map { .. }))
+        ));
+        assert!(matches!(
+            downgrade(map(MemState::Resident), Features::NONE),
+            Some(MeviEvent::TraceeEvent(
+                _,
+                TraceePayload::MemStateChange {
+                    state: MemState::Resident,
+                    ..
+                }
+            ))
+        ));
+
+        let annotation = event(TraceePayload::Annotation {
+            name: "GC".to_owned(),
+            kind: AnnotationKind::Begin,
+        });
+        assert!(downgrade(annotation, Features::NONE).is_none());
+
+        assert!(matches!(
+            downgrade(event(TraceePayload::Lost { count: 3 }), Features::NONE),
+            Some(MeviEvent::TraceeEvent(_, TraceePayload::Warning { .. }))
+        ));
+
+        let resident = resident_pages(&[(0x1000..0x2000, 0x800), (0x2000..0x3000, 0x1000)]);
+        let Some(MeviEvent::TraceeEvent(_, TraceePayload::MemStateChange { range, state })) =
+            downgrade(resident, Features::NONE)
+        else {
+            panic!("not a state change");
+        };
+        assert_eq!(range, 0x1000..0x3000);
+        assert_eq!(state, MemState::Resident);
+
+        // there's no one state for those
+        let mixed = resident_pages(&[(0x1000..0x2000, 0x800), (0x2000..0x3000, 0)]);
+        assert!(downgrade(mixed, Features::NONE).is_none());
+        let apart = resident_pages(&[(0x1000..0x2000, 0x800), (0x3000..0x4000, 0x800)]);
+        assert!(downgrade(apart, Features::NONE).is_none());
+    }
+
+    #[test]
+    fn overlay_deltas_add_up() {
+        let mut deltas = OverlayDeltas::default();
+        let mut client = RangeSet::new();
+        assert!(apply(&mut client, deltas.encode(sample(&[0x1000..0x4000]))));
+
+        let ev = deltas.encode(sample(&[0x2000..0x5000]));
+        let MeviEvent::TraceeEvent(_, TraceePayload::OverlayDelta { added, removed, .. }) = &ev
+        else {
+            panic!("not a delta: {ev:?}");
+        };
+        assert_eq!(added, &[0x4000..0x5000]);
+        assert_eq!(removed, &[0x1000..0x2000]);
+        assert!(!apply(&mut client, ev));
+        assert_eq!(ranges(&client), [0x2000..0x5000]);
+
+        for i in 1..KEYFRAME_EVERY {
+            let now = [0x2000..0x5000 + i as u64 * 0x1000];
+            assert!(!apply(&mut client, deltas.encode(sample(&now))));
+            assert_eq!(ranges(&client), now);
+        }
+        // that's as many deltas as we send in a row
+        assert!(apply(&mut client, deltas.encode(sample(&[0x1000..0x2000]))));
+        assert_eq!(ranges(&client), [0x1000..0x2000]);
+        assert!(!apply(
+            &mut client,
+            deltas.encode(sample(&[0x1000..0x2000]))
+        ));
+
+        // other events go through untouched
+        assert!(matches!(
+            deltas.encode(map(MemState::Resident)),
+            MeviEvent::TraceeEvent(_, TraceePayload::Map { .. })
+        ));
+    }
+
+    #[test]
+    fn overlay_deltas_start_over() {
+        let exec = TraceePayload::Exec {
+            exe: None,
+            argv: vec![],
+            interpreter: None,
+        };
+        for gone in [exec, TraceePayload::Exit] {
+            let mut deltas = OverlayDeltas::default();
+            let mut client = RangeSet::new();
+            assert!(apply(&mut client, deltas.encode(sample(&[0x1000..0x2000]))));
+            assert!(!apply(
+                &mut client,
+                deltas.encode(sample(&[0x1000..0x3000]))
+            ));
+
+            // whatever the client had is gone with it
+            deltas.encode(event(gone));
+            assert!(apply(&mut client, deltas.encode(sample(&[0x5000..0x6000]))));
+            assert_eq!(ranges(&client), [0x5000..0x6000]);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! Recorded traces: a header, followed by length-prefixed records, each
+//! holding an event and when it happened relative to the start of the
+//! recording. The header says when that was, on both the monotonic clock
+//! events are stamped with and the wall clock.
+//!
+//! Records are only ever appended, so if mevi goes away mid-recording, all
+//! that's lost is (at most) the last, truncated record.
+//!
+//! Traces can also be compressed, in which case records are grouped in
+//! chunks, each compressed as its own zstd frame and preceded by its length
+//! and start time, so we can skip around without decompressing everything.
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{protocol, ClockAnchor, MeviEvent, Timestamp};
+
+const MAGIC: &[u8; 4] = b"MEVI";
+const MAGIC_ZSTD: &[u8; 4] = b"MEVZ";
+
+/// Magic, protocol version, then the clock anchor (monotonic and Unix
+/// nanoseconds)
+const HEADER_LEN: u64 = 22;
+
+/// Frame length, then start time
+const CHUNK_HEADER_LEN: u64 = 12;
+
+/// Compressed chunks hold this many bytes of records, or...
+#[cfg(feature = "zstd")]
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// ...however many happened in that amount of time, whichever comes first,
+/// so seeking never has to decompress more than about that much.
+#[cfg(feature = "zstd")]
+const CHUNK_SPAN: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// Time since the recording started
+    pub at: Duration,
+    pub event: MeviEvent,
+}
+
+/// Where `mevi replay` is at, as served on `/replay`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayStatus {
+    pub position_ms: u64,
+    pub duration_ms: u64,
+    pub paused: bool,
+    pub speed: f64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    /// Not a mevi trace at all
+    BadMagic,
+
+    /// Recorded by a mevi whose events we can't decode
+    VersionMismatch {
+        ours: u16,
+        theirs: u16,
+    },
+
+    /// The file ends in the middle of a record
+    Truncated,
+
+    /// A record or chunk says it's bigger than [protocol::MAX_FRAME_LEN],
+    /// which is more likely garbage than something we wrote
+    Oversized(u32),
+
+    /// The trace is compressed, and we were built without zstd (or ruzstd)
+    /// support
+    Compressed,
+
+    Postcard(postcard::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::BadMagic => write!(f, "not a mevi trace"),
+            Error::VersionMismatch { ours, theirs } => write!(
+                f,
+                "trace was recorded with protocol version {theirs}, we only read {ours}"
+            ),
+            Error::Truncated => write!(f, "trace ends with a truncated record"),
+            Error::Oversized(len) => write!(f, "trace has a {len}-byte record, it must be corrupt"),
+            Error::Compressed => write!(f, "trace is compressed, and we can't decompress it"),
+            Error::Postcard(e) => write!(f, "malformed record: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<postcard::Error> for Error {
+    fn from(e: postcard::Error) -> Self {
+        Error::Postcard(e)
+    }
+}
+
+pub struct TraceWriter<W: Write> {
+    w: W,
+
+    #[cfg(feature = "zstd")]
+    compression: Option<Compression>,
+}
+
+#[cfg(feature = "zstd")]
+struct Compression {
+    level: i32,
+
+    /// Records that haven't been compressed yet
+    chunk: Vec<u8>,
+
+    /// When the first record in `chunk` happened
+    chunk_start: Duration,
+}
+
+fn write_header(w: &mut impl Write, magic: &[u8; 4], anchor: &ClockAnchor) -> Result<(), Error> {
+    w.write_all(magic)?;
+    w.write_all(&protocol::VERSION.to_le_bytes())?;
+    w.write_all(&anchor.monotonic.0.to_le_bytes())?;
+    w.write_all(&anchor.unix_nanos.to_le_bytes())?;
+    Ok(())
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Records are timestamped relative to `anchor`
+    pub fn new(mut w: W, anchor: &ClockAnchor) -> Result<Self, Error> {
+        write_header(&mut w, MAGIC, anchor)?;
+        Ok(Self {
+            w,
+            #[cfg(feature = "zstd")]
+            compression: None,
+        })
+    }
+
+    /// Writes a zstd-compressed trace, cf. the module docs
+    #[cfg(feature = "zstd")]
+    pub fn compressed(mut w: W, level: i32, anchor: &ClockAnchor) -> Result<Self, Error> {
+        write_header(&mut w, MAGIC_ZSTD, anchor)?;
+        Ok(Self {
+            w,
+            compression: Some(Compression {
+                level,
+                chunk: Vec::with_capacity(CHUNK_SIZE),
+                chunk_start: Duration::ZERO,
+            }),
+        })
+    }
+
+    pub fn write(&mut self, at: Duration, event: &MeviEvent) -> Result<(), Error> {
+        // serializing a reference saves us a clone of the event
+        #[derive(Serialize)]
+        struct RecordRef<'a> {
+            at: Duration,
+            event: &'a MeviEvent,
+        }
+
+        let buf = postcard::to_allocvec(&RecordRef { at, event })?;
+        let len = (buf.len() as u32).to_le_bytes();
+
+        #[cfg(feature = "zstd")]
+        if let Some(c) = &mut self.compression {
+            if !c.chunk.is_empty()
+                && (c.chunk.len() >= CHUNK_SIZE || at.saturating_sub(c.chunk_start) >= CHUNK_SPAN)
+            {
+                write_chunk(&mut self.w, c)?;
+            }
+            if c.chunk.is_empty() {
+                c.chunk_start = at;
+            }
+            c.chunk.extend_from_slice(&len);
+            c.chunk.extend_from_slice(&buf);
+            return Ok(());
+        }
+
+        self.w.write_all(&len)?;
+        self.w.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Flushes the underlying writer. For compressed traces, records only
+    /// make it there once their chunk is complete (or on [Self::finish]).
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.w.flush()?)
+    }
+
+    /// Writes out whatever's left, and returns the underlying writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        #[cfg(feature = "zstd")]
+        if let Some(c) = &mut self.compression {
+            if !c.chunk.is_empty() {
+                write_chunk(&mut self.w, c)?;
+            }
+        }
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn write_chunk(w: &mut impl Write, c: &mut Compression) -> Result<(), Error> {
+    let compressed = zstd::stream::encode_all(&c.chunk[..], c.level)?;
+    w.write_all(&(compressed.len() as u32).to_le_bytes())?;
+    w.write_all(&(c.chunk_start.as_micros() as u64).to_le_bytes())?;
+    w.write_all(&compressed)?;
+    c.chunk.clear();
+    Ok(())
+}
+
+pub struct TraceReader<R: Read> {
+    r: R,
+    compressed: bool,
+    anchor: ClockAnchor,
+
+    /// Decompressed records of the current chunk, for compressed traces
+    chunk: io::Cursor<Vec<u8>>,
+
+    /// The record we stopped at while seeking
+    pending: Option<TraceRecord>,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut r: R) -> Result<Self, Error> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        // older versions had a shorter header, check the version before
+        // reading the rest
+        r.read_exact(&mut header[..6]).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::BadMagic,
+            _ => e.into(),
+        })?;
+        let compressed = match &header[..4] {
+            magic if magic == MAGIC => false,
+            magic if magic == MAGIC_ZSTD => true,
+            _ => return Err(Error::BadMagic),
+        };
+        if compressed && !cfg!(any(feature = "zstd", feature = "ruzstd")) {
+            return Err(Error::Compressed);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != protocol::VERSION {
+            return Err(Error::VersionMismatch {
+                ours: protocol::VERSION,
+                theirs: version,
+            });
+        }
+        r.read_exact(&mut header[6..]).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Truncated,
+            _ => e.into(),
+        })?;
+        let anchor = ClockAnchor {
+            monotonic: Timestamp(u64::from_le_bytes(header[6..14].try_into().unwrap())),
+            unix_nanos: u64::from_le_bytes(header[14..22].try_into().unwrap()),
+        };
+        Ok(Self {
+            r,
+            compressed,
+            anchor,
+            chunk: Default::default(),
+            pending: None,
+        })
+    }
+
+    /// When the recording started
+    pub fn anchor(&self) -> ClockAnchor {
+        self.anchor
+    }
+
+    /// Returns `None` once the trace ends cleanly
+    pub fn next_record(&mut self) -> Result<Option<TraceRecord>, Error> {
+        if let Some(record) = self.pending.take() {
+            return Ok(Some(record));
+        }
+        if !self.compressed {
+            return read_record(&mut self.r);
+        }
+
+        loop {
+            if let Some(record) = read_record(&mut self.chunk)? {
+                return Ok(Some(record));
+            }
+            let Some((len, _start)) = read_chunk_header(&mut self.r)? else {
+                return Ok(None);
+            };
+            if len > protocol::MAX_FRAME_LEN {
+                return Err(Error::Oversized(len));
+            }
+            let mut compressed = vec![0u8; len as usize];
+            if read_exact_or_eof(&mut self.r, &mut compressed)? != compressed.len() {
+                return Err(Error::Truncated);
+            }
+            self.chunk = io::Cursor::new(decompress(&compressed)?);
+        }
+    }
+}
+
+impl<R: Read + Seek> TraceReader<R> {
+    /// Makes it so the next record is the first one that happened at or
+    /// after `at`. Assumes the trace starts at the beginning of `r`.
+    ///
+    /// For compressed traces, this only decompresses the chunk `at` falls
+    /// in.
+    pub fn seek(&mut self, at: Duration) -> Result<(), Error> {
+        self.pending = None;
+        self.chunk = Default::default();
+        self.r.seek(SeekFrom::Start(HEADER_LEN))?;
+
+        if self.compressed {
+            // hop from chunk header to chunk header, until we find the last
+            // chunk that started before `at`
+            let mut target = HEADER_LEN;
+            let mut offset = HEADER_LEN;
+            while let Some((len, start)) = read_chunk_header(&mut self.r)? {
+                if start >= at {
+                    break;
+                }
+                target = offset;
+                offset += CHUNK_HEADER_LEN + len as u64;
+                self.r.seek(SeekFrom::Start(offset))?;
+            }
+            self.r.seek(SeekFrom::Start(target))?;
+        }
+
+        while let Some(record) = self.next_record()? {
+            if record.at >= at {
+                self.pending = Some(record);
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+fn read_record(r: &mut impl Read) -> Result<Option<TraceRecord>, Error> {
+    let mut len = [0u8; 4];
+    match read_exact_or_eof(r, &mut len)? {
+        0 => return Ok(None),
+        4 => {}
+        _ => return Err(Error::Truncated),
+    }
+
+    let len = u32::from_le_bytes(len);
+    if len > protocol::MAX_FRAME_LEN {
+        return Err(Error::Oversized(len));
+    }
+    let mut buf = vec![0u8; len as usize];
+    if read_exact_or_eof(r, &mut buf)? != buf.len() {
+        return Err(Error::Truncated);
+    }
+    Ok(Some(postcard::from_bytes(&buf)?))
+}
+
+/// Chunks start with the length of their zstd frame, and when their first
+/// record happened, in microseconds
+fn read_chunk_header(r: &mut impl Read) -> Result<Option<(u32, Duration)>, Error> {
+    let mut header = [0u8; CHUNK_HEADER_LEN as usize];
+    match read_exact_or_eof(r, &mut header)? {
+        0 => return Ok(None),
+        n if n == header.len() => {}
+        _ => return Err(Error::Truncated),
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let start = u64::from_le_bytes(header[4..].try_into().unwrap());
+    Ok(Some((len, Duration::from_micros(start))))
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(zstd::stream::decode_all(compressed)?)
+}
+
+/// Without zstd itself, e.g. in the browser, where it doesn't build
+#[cfg(all(feature = "ruzstd", not(feature = "zstd")))]
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoder = ruzstd::StreamingDecoder::new(compressed)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let mut decompressed = vec![];
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(not(any(feature = "zstd", feature = "ruzstd")))]
+fn decompress(_compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(Error::Compressed)
+}
+
+/// Like `read_exact`, but tells us how far it got instead of failing at EOF
+fn read_exact_or_eof(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
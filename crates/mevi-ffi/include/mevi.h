@@ -0,0 +1,68 @@
+#ifndef MEVI_H
+#define MEVI_H
+
+/* Generated by cbindgen from crates/mevi-ffi, don't edit by hand */
+
+#include <stdbool.h>
+#include <stdint.h>
+
+// A program being traced, from [mevi_spawn] or [mevi_attach] until
+// [mevi_wait]
+typedef struct MeviSession MeviSession;
+
+// Something that happened to a tracee, from [mevi_poll_event] until
+// [mevi_free_event]
+typedef struct MeviEvent {
+  // When it happened, in nanoseconds, on `CLOCK_MONOTONIC`
+  uint64_t at;
+  // The process or thread it happened to, 0 if it's about the session
+  uint64_t tid;
+  // What happened, as a NUL-terminated JSON object
+  char *json;
+} MeviEvent;
+
+// Starts `argv[0]` with the arguments that follow, up to a NULL, and traces
+// it. Returns NULL if it couldn't, cf. [mevi_last_error].
+//
+// # Safety
+//
+// `argv` has to be a NULL-terminated array of NUL-terminated strings.
+MeviSession *mevi_spawn(const char *const *argv);
+
+// Attaches to every thread of `pid`, and traces it. Returns NULL if it
+// couldn't, cf. [mevi_last_error].
+MeviSession *mevi_attach(uint32_t pid);
+
+// Gets the next event into `*event`, waiting for one if `wait` is set.
+// Returns 1 if there was one, 0 if there wasn't yet (only without `wait`),
+// and -1 once every tracee is gone.
+//
+// # Safety
+//
+// `session` has to come from [mevi_spawn] or [mevi_attach], and not be
+// waited for yet. `event` has to be writable.
+int32_t mevi_poll_event(MeviSession *session, bool wait, MeviEvent **event);
+
+// Frees an event [mevi_poll_event] got
+//
+// # Safety
+//
+// `event` has to come from [mevi_poll_event], and not be freed already.
+void mevi_free_event(MeviEvent *event);
+
+// Waits for every tracee to be gone, throwing away events that weren't
+// polled yet, and frees `session`. Returns the exit code of the process we
+// started (or attached to), like a shell has it, or -1 if we didn't see it
+// exit, cf. [mevi_last_error].
+//
+// # Safety
+//
+// `session` has to come from [mevi_spawn] or [mevi_attach], and not be
+// waited for already.
+int32_t mevi_wait(MeviSession *session);
+
+// What went wrong last, on this thread, or NULL if nothing did. It's ours,
+// and good until the next call that fails.
+const char *mevi_last_error(void);
+
+#endif /* MEVI_H */
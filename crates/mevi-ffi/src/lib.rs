@@ -0,0 +1,186 @@
+//! A C API for [mevi_tracer], for test frameworks and profilers that aren't
+//! written in Rust. `include/mevi.h` is what C sees of it:
+//!
+//! ```c
+//! const char *argv[] = {"ls", "-l", NULL};
+//! MeviSession *session = mevi_spawn(argv);
+//! if (!session) {
+//!     fprintf(stderr, "%s\n", mevi_last_error());
+//!     return 1;
+//! }
+//! MeviEvent *event;
+//! while (mevi_poll_event(session, true, &event) > 0) {
+//!     printf("%llu %s\n", event->at, event->json);
+//!     mevi_free_event(event);
+//! }
+//! printf("exited with %d\n", mevi_wait(session));
+//! ```
+//!
+//! Events come as JSON, the way `serde_json` has a [mevi_common::MeviEvent].
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    sync::mpsc::TryRecvError,
+};
+
+use mevi_common::{MeviEvent as Event, TimedEvent};
+use mevi_tracer::{Session, TracerBuilder};
+
+/// A program being traced, from [mevi_spawn] or [mevi_attach] until
+/// [mevi_wait]
+pub struct MeviSession(Session);
+
+/// Something that happened to a tracee, from [mevi_poll_event] until
+/// [mevi_free_event]
+#[repr(C)]
+pub struct MeviEvent {
+    /// When it happened, in nanoseconds, on `CLOCK_MONOTONIC`
+    pub at: u64,
+
+    /// The process or thread it happened to, 0 if it's about the session
+    pub tid: u64,
+
+    /// What happened, as a NUL-terminated JSON object
+    pub json: *mut c_char,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn fail(message: String) {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn start(builder: TracerBuilder) -> *mut MeviSession {
+    match builder.start() {
+        Ok(session) => Box::into_raw(Box::new(MeviSession(session))),
+        Err(e) => {
+            fail(format!("{e:#}"));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Starts `argv[0]` with the arguments that follow, up to a NULL, and traces
+/// it. Returns NULL if it couldn't, cf. [mevi_last_error].
+///
+/// # Safety
+///
+/// `argv` has to be a NULL-terminated array of NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn mevi_spawn(argv: *const *const c_char) -> *mut MeviSession {
+    let mut command = vec![];
+    let mut arg = argv;
+    while !(*arg).is_null() {
+        command.push(CStr::from_ptr(*arg).to_string_lossy().into_owned());
+        arg = arg.add(1);
+    }
+    if command.is_empty() {
+        fail("argv is empty".to_owned());
+        return std::ptr::null_mut();
+    }
+    start(TracerBuilder::spawn(command))
+}
+
+/// Attaches to every thread of `pid`, and traces it. Returns NULL if it
+/// couldn't, cf. [mevi_last_error].
+#[no_mangle]
+pub extern "C" fn mevi_attach(pid: u32) -> *mut MeviSession {
+    start(TracerBuilder::attach(pid))
+}
+
+/// Gets the next event into `*event`, waiting for one if `wait` is set.
+/// Returns 1 if there was one, 0 if there wasn't yet (only without `wait`),
+/// and -1 once every tracee is gone.
+///
+/// # Safety
+///
+/// `session` has to come from [mevi_spawn] or [mevi_attach], and not be
+/// waited for yet. `event` has to be writable.
+#[no_mangle]
+pub unsafe extern "C" fn mevi_poll_event(
+    session: *mut MeviSession,
+    wait: bool,
+    event: *mut *mut MeviEvent,
+) -> i32 {
+    let session = &mut (*session).0;
+    let next = if wait {
+        session.next().ok_or(TryRecvError::Disconnected)
+    } else {
+        session.try_next()
+    };
+    match next {
+        Ok(ev) => {
+            *event = Box::into_raw(Box::new(to_c(ev)));
+            1
+        }
+        Err(TryRecvError::Empty) => 0,
+        Err(TryRecvError::Disconnected) => -1,
+    }
+}
+
+fn to_c(TimedEvent { at, event, .. }: TimedEvent) -> MeviEvent {
+    let tid = match &event {
+        Event::TraceeEvent(tid, _) => tid.0,
+        _ => 0,
+    };
+    // it's all strings and numbers, nothing that can fail
+    let json = serde_json::to_string(&event).unwrap();
+    MeviEvent {
+        at: at.0,
+        tid,
+        json: CString::new(json).unwrap().into_raw(),
+    }
+}
+
+/// Frees an event [mevi_poll_event] got
+///
+/// # Safety
+///
+/// `event` has to come from [mevi_poll_event], and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn mevi_free_event(event: *mut MeviEvent) {
+    if event.is_null() {
+        return;
+    }
+    let event = Box::from_raw(event);
+    drop(CString::from_raw(event.json));
+}
+
+/// Waits for every tracee to be gone, throwing away events that weren't
+/// polled yet, and frees `session`. Returns the exit code of the process we
+/// started (or attached to), like a shell has it, or -1 if we didn't see it
+/// exit, cf. [mevi_last_error].
+///
+/// # Safety
+///
+/// `session` has to come from [mevi_spawn] or [mevi_attach], and not be
+/// waited for already.
+#[no_mangle]
+pub unsafe extern "C" fn mevi_wait(session: *mut MeviSession) -> i32 {
+    let session = Box::from_raw(session).0;
+    match session.wait() {
+        Ok(Some(code)) => code,
+        Ok(None) => {
+            fail("didn't see it exit".to_owned());
+            -1
+        }
+        Err(e) => {
+            fail(format!("{e:#}"));
+            -1
+        }
+    }
+}
+
+/// What went wrong last, on this thread, or NULL if nothing did. It's ours,
+/// and good until the next call that fails.
+#[no_mangle]
+pub extern "C" fn mevi_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
@@ -0,0 +1,35 @@
+//! Everything needed to make sense of what mevi produces, without linking
+//! the tracer: the events themselves, recorded traces (`mevi record`), and
+//! live streams (`mevi --listen-tcp` or `--listen-unix`, and `/stream`).
+//!
+//! To read a trace:
+//!
+//! ```no_run
+//! let file = std::fs::File::open("trace.mevi")?;
+//! let mut trace = mevi_format::trace::TraceReader::new(std::io::BufReader::new(file))?;
+//! while let Some(record) = trace.next_record()? {
+//!     println!("{:?} {:?}", record.at, record.event);
+//! }
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! And to follow a live stream, see [stream::Follower].
+//!
+//! This needs `std`: traces are read through [std::io], and the events
+//! themselves hold `RangeMap`s, which need it too.
+//!
+//! It does build for `wasm32-unknown-unknown` though, to read traces in the
+//! browser, as long as zstd stays off (its C library doesn't build there):
+//! with `default-features = false`, and the `ruzstd` feature for compressed
+//! traces, a pure Rust decoder. Read them from a byte slice, since that's
+//! what a file input gives you:
+//!
+//! ```no_run
+//! # let bytes: Vec<u8> = vec![];
+//! let mut trace = mevi_format::trace::TraceReader::new(&bytes[..])?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+pub use mevi_common::*;
+
+pub mod stream;
@@ -0,0 +1,164 @@
+//! Following a live mevi over a byte stream: a TCP connection to a mevi
+//! started with `--listen-tcp` (without TLS), or its `--listen-unix` socket.
+//! Websocket clients get the same frames, minus the length prefixes.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{protocol, ClockAnchor, TimedEvent, TraceeId};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Protocol(protocol::Error),
+
+    /// The server sent something it shouldn't have, at that point
+    Unexpected(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Protocol(e) => write!(f, "{e}"),
+            Error::Unexpected(what) => write!(f, "unexpected {what} from server"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<protocol::Error> for Error {
+    fn from(e: protocol::Error) -> Self {
+        Error::Protocol(e)
+    }
+}
+
+/// Frames are prefixed with their length, as a little-endian u32
+pub fn write_frame(w: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    w.write_all(&(frame.len() as u32).to_le_bytes())?;
+    w.write_all(frame)?;
+    w.flush()
+}
+
+/// The next frame, or `None` if the peer hung up in between two frames
+pub fn read_frame(r: &mut impl Read) -> Result<Option<Vec<u8>>, Error> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > protocol::MAX_FRAME_LEN {
+        return Err(Error::Unexpected("oversized frame"));
+    }
+    let mut frame = vec![0u8; len as usize];
+    r.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+/// A connection to a live mevi, past the handshake
+pub struct Follower<S: Read + Write> {
+    s: S,
+    features: protocol::Features,
+    anchor: Option<ClockAnchor>,
+}
+
+impl<S: Read + Write> Follower<S> {
+    /// Does the handshake, asking for `features` (see
+    /// [protocol::Features::ALL]) and starting from scratch
+    pub fn new(s: S, features: protocol::Features) -> Result<Self, Error> {
+        Self::resume(s, features, &[])
+    }
+
+    /// Does the handshake, asking for whatever came after `from` (the next
+    /// sequence number we expect for each tracee). If the server doesn't
+    /// have all of that anymore, we get a snapshot first instead.
+    pub fn resume(
+        s: S,
+        features: protocol::Features,
+        from: &[(TraceeId, u64)],
+    ) -> Result<Self, Error> {
+        Self::handshake(s, features, from, None)
+    }
+
+    /// Like [Follower::resume], for servers that want a token (those
+    /// that print one when they start, or were given `--token`)
+    pub fn resume_with_token(
+        s: S,
+        features: protocol::Features,
+        from: &[(TraceeId, u64)],
+        token: &str,
+    ) -> Result<Self, Error> {
+        Self::handshake(s, features, from, Some(token))
+    }
+
+    fn handshake(
+        mut s: S,
+        features: protocol::Features,
+        from: &[(TraceeId, u64)],
+        token: Option<&str>,
+    ) -> Result<Self, Error> {
+        // the server speaks first
+        let theirs = match read_frame(&mut s)? {
+            Some(frame) => match protocol::decode(&frame)? {
+                protocol::Frame::Hello(hello) => hello,
+                _ => return Err(Error::Unexpected("frame before hello")),
+            },
+            None => return Err(Error::Unexpected("hang-up before hello")),
+        };
+        let ours = protocol::Hello {
+            features,
+            ..protocol::Hello::ours()
+        };
+        write_frame(&mut s, &protocol::encode_hello(&ours))?;
+        let features = ours.negotiate(&theirs)?;
+        if let Some(token) = token {
+            write_frame(&mut s, &protocol::encode_token(token)?)?;
+        }
+        write_frame(&mut s, &protocol::encode_resume(from)?)?;
+
+        Ok(Self {
+            s,
+            features,
+            anchor: None,
+        })
+    }
+
+    /// What both sides agreed on
+    pub fn features(&self) -> protocol::Features {
+        self.features
+    }
+
+    /// Ties the server's event timestamps to wall-clock time, once it's
+    /// sent that
+    pub fn anchor(&self) -> Option<ClockAnchor> {
+        self.anchor
+    }
+
+    /// The next batch of events, or `None` once the server is gone. With
+    /// [protocol::Features::OVERLAY_DELTAS], overlays come as deltas, cf.
+    /// [protocol::apply_overlay_delta].
+    pub fn next_events(&mut self) -> Result<Option<Vec<TimedEvent>>, Error> {
+        while let Some(frame) = read_frame(&mut self.s)? {
+            match protocol::decode(&frame)? {
+                protocol::Frame::Events(evs) => return Ok(Some(evs)),
+                protocol::Frame::Clock(anchor) => self.anchor = Some(anchor),
+                protocol::Frame::Heartbeat(_) => {}
+                protocol::Frame::Hello(_) => return Err(Error::Unexpected("second hello")),
+                protocol::Frame::Resume(_) => return Err(Error::Unexpected("resume")),
+                protocol::Frame::Token(_) => return Err(Error::Unexpected("token")),
+            }
+        }
+        Ok(None)
+    }
+}
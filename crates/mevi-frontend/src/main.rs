@@ -1,12 +1,25 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Range,
+};
 
-use futures_util::StreamExt;
-use gloo_net::websocket::{futures::WebSocket, Message};
+use futures_util::{stream::SplitStream, SinkExt, StreamExt};
+use gloo_net::{
+    http::Request,
+    websocket::{futures::WebSocket, Message},
+};
 use humansize::{make_format, BINARY};
 use itertools::Itertools;
-use mevi_common::{MemMap, MemState, MeviEvent, TraceeId, TraceePayload};
+use mevi_common::{
+    protocol, trace::ReplayStatus, AnnotationKind, CgroupEvent, HistoryStatus, MappingFiles,
+    MappingKind, MappingKinds, MappingPermsMap, MemMap, MemPolicyMode, MemState, MeviEvent,
+    Overlay, ProcessInfo, Sample, SessionInfo, SessionSummary, TimedEvent, Timestamp, TraceeId,
+    TraceePayload, TracerStatus,
+};
+use mevi_format::trace::{self as trace_file, TraceReader, TraceRecord};
+use rangemap::{RangeMap, RangeSet};
 use wasm_bindgen_futures::spawn_local;
-use yew::prelude::*;
+use yew::{prelude::*, web_sys};
 
 struct Group {
     start: u64,
@@ -18,15 +31,121 @@ struct Group {
 struct TraceeState {
     tid: TraceeId,
     map: MemMap,
+    kinds: MappingKinds,
+    // the file behind each mapping, for those that have one
+    files: MappingFiles,
+    // what each mapping may be used for, and whether it's shared
+    perms: MappingPermsMap,
     cmdline: Vec<String>,
+    // exe, cwd, uid and gid, once the server has told us
+    info: Option<ProcessInfo>,
+    // the traced process that forked this one, if any
+    parent: Option<TraceeId>,
+    warnings: Vec<String>,
+    overlays: HashMap<Overlay, RangeSet<u64>>,
+
+    // how much memory each thread faulted in
+    faulted_by_thread: HashMap<TraceeId, u64>,
+
+    // what each thread calls itself, if we know
+    thread_names: HashMap<TraceeId, String>,
+
+    // the programs this process ran, oldest first, e.g. `sh`, then `cargo`
+    programs: Vec<String>,
+
+    // the phases it said it's in, through mevi-payload, outermost first
+    phases: Vec<String>,
+
+    // how much memory each function faulted in, with --symbolicate
+    faulted_by_site: HashMap<String, u64>,
+
+    // transparent huge page coverage, per mapping
+    huge_pages: Vec<(Range<u64>, u64)>,
+
+    // bytes on each NUMA node, per mapping
+    numa_nodes: Vec<(Range<u64>, Vec<(u32, u64)>)>,
+
+    // the last process-wide NUMA policy
+    mem_policy: Option<(MemPolicyMode, Vec<u32>)>,
+
+    // the last sample of the memory cgroup: usage and limit
+    cgroup_memory: Option<(u64, Option<u64>)>,
+
+    // how many of each memory cgroup event we've seen
+    cgroup_events: BTreeMap<CgroupEvent, u64>,
+
+    // the last sample of smaps_rollup: rss, pss, swap
+    rollup: Option<(u64, u64, u64)>,
+
+    // faults taken during the last sampling interval: minor, major
+    faults: Option<(u64, u64)>,
+
+    // how many events mevi dropped for this process, because it couldn't
+    // keep up: if any, what we show may be off
+    lost: u64,
+
+    // the sequence number we expect next: anything past it, we missed
+    next_seq: u64,
+
+    // if set, we keep the process around after it exits, so it's clear
+    // what happened
+    oom_killed: bool,
+
+    // with a coarser granularity than pages, the bucket size, and resident
+    // bytes per bucket: `map` only tells which buckets have any
+    resident_pages: Option<(u64, RangeMap<u64, u64>)>,
 }
 
 impl TraceeState {
+    fn new(tid: TraceeId) -> Self {
+        Self {
+            tid,
+            map: Default::default(),
+            kinds: Default::default(),
+            files: Default::default(),
+            perms: Default::default(),
+            cmdline: Default::default(),
+            info: None,
+            parent: None,
+            warnings: Default::default(),
+            overlays: Default::default(),
+            faulted_by_thread: Default::default(),
+            thread_names: Default::default(),
+            programs: Default::default(),
+            phases: Default::default(),
+            faulted_by_site: Default::default(),
+            huge_pages: Default::default(),
+            numa_nodes: Default::default(),
+            mem_policy: Default::default(),
+            cgroup_memory: Default::default(),
+            cgroup_events: Default::default(),
+            rollup: Default::default(),
+            faults: Default::default(),
+            lost: 0,
+            next_seq: 0,
+            oom_killed: false,
+            resident_pages: None,
+        }
+    }
+
+    fn ran(&mut self, exe: &str) {
+        let name = exe.rsplit('/').next().unwrap_or(exe);
+        if self.programs.last().map(|last| last.as_str()) != Some(name) {
+            self.programs.push(name.to_owned());
+        }
+    }
+
     fn total_rss(&self) -> u64 {
+        if let Some((bucket, resident_pages)) = &self.resident_pages {
+            return resident_pages
+                .iter()
+                .map(|(range, resident)| resident * ((range.end - range.start) / bucket))
+                .sum();
+        }
         self.map
             .iter()
             .map(|(range, state)| {
-                if state == &MemState::Resident {
+                if state.is_resident() {
                     range.end - range.start
                 } else {
                     0
@@ -37,7 +156,8 @@ impl TraceeState {
 }
 
 async fn connect_to_ws() -> WebSocket {
-    let addr = "ws://localhost:5001/stream";
+    let addr = server_url("ws", "/stream");
+    let addr = addr.as_str();
     gloo_console::log!("Connecting to", addr);
     let mut ws = WebSocket::open(addr).unwrap();
 
@@ -72,6 +192,535 @@ async fn connect_to_ws() -> WebSocket {
     ws
 }
 
+/// `path` on the mevi we're watching: the one that served this page unless
+/// its URL says otherwise with `?server=HOST:PORT`, and with the `?token=` it
+/// was given, if any
+fn server_url(scheme: &str, path: &str) -> String {
+    let location = web_sys::window().map(|window| window.location());
+    let params = location
+        .as_ref()
+        .and_then(|location| location.search().ok())
+        .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok());
+    let server = params
+        .as_ref()
+        .and_then(|params| params.get("server"))
+        .or_else(|| {
+            // wherever we were served from, under whatever path that was
+            let location = location.as_ref()?;
+            let host = location.host().ok()?;
+            let path = location.pathname().ok()?;
+            let base = path.trim_end_matches("index.html").trim_end_matches('/');
+            Some(format!("{host}{base}"))
+        })
+        .unwrap_or_else(|| "localhost:5001".to_owned());
+
+    // pages served over TLS can only talk to servers over TLS
+    let secure = location
+        .as_ref()
+        .and_then(|location| location.protocol().ok())
+        .map_or(false, |protocol| protocol == "https:");
+    let scheme = if secure {
+        format!("{scheme}s")
+    } else {
+        scheme.to_owned()
+    };
+    let mut url = format!("{scheme}://{server}{path}");
+    if let Some(token) = params.and_then(|params| params.get("token")) {
+        let query = web_sys::UrlSearchParams::new().unwrap();
+        query.append("token", &token);
+        url.push(if path.contains('?') { '&' } else { '?' });
+        url.push_str(&String::from(query.to_string()));
+    }
+    url
+}
+
+fn format_ms(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!("{}:{:02}.{:01}", secs / 60, secs % 60, (ms % 1000) / 100)
+}
+
+/// Playback controls, only shown when the server is replaying a trace
+#[function_component(ReplayBar)]
+fn replay_bar() -> Html {
+    let status = use_state(|| -> Option<ReplayStatus> { None });
+
+    {
+        let status = status.clone();
+        use_effect_with_deps(
+            move |_| {
+                spawn_local(async move {
+                    loop {
+                        let res = Request::get(&server_url("http", "/replay")).send().await;
+                        let new_status = match res {
+                            Ok(res) if res.ok() => res.json::<ReplayStatus>().await.ok(),
+                            // not replaying, or not reachable
+                            _ => None,
+                        };
+                        status.set(new_status);
+                        gloo_timers::future::sleep(std::time::Duration::from_millis(250)).await;
+                    }
+                });
+            },
+            (),
+        );
+    }
+
+    let Some(replay) = *status else {
+        return html! {};
+    };
+
+    let control = |path: String| {
+        spawn_local(async move {
+            let url = server_url("http", &format!("/replay/{path}"));
+            if let Err(e) = Request::post(&url).send().await {
+                gloo_console::log!("Replay control failed:", e.to_string());
+            }
+        })
+    };
+
+    let toggle = {
+        let action = if replay.paused { "play" } else { "pause" };
+        move |_| control(action.to_string())
+    };
+    let seek = move |e: Event| {
+        let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+        control(format!("seek?ms={}", input.value()))
+    };
+    let speed = move |e: Event| {
+        let select: web_sys::HtmlSelectElement = e.target_unchecked_into();
+        control(format!("speed?x={}", select.value()))
+    };
+
+    html! {
+        <span class="replay-bar">
+            <button onclick={toggle}>{ if replay.paused { "Play" } else { "Pause" } }</button>
+            <input type="range" min="0" max={replay.duration_ms.to_string()} value={replay.position_ms.to_string()} onchange={seek} />
+            <span class="replay-time">{format!("{} / {}", format_ms(replay.position_ms), format_ms(replay.duration_ms))}</span>
+            <select onchange={speed}>
+                {
+                    [0.25, 0.5, 1.0, 2.0, 4.0, 8.0].into_iter().map(|x| {
+                        html! { <option value={x.to_string()} selected={x == replay.speed}>{format!("{x}x")}</option> }
+                    }).collect::<Html>()
+                }
+            </select>
+        </span>
+    }
+}
+
+/// Freezes and thaws the tracees, only shown when the server is tracing
+#[function_component(PauseButton)]
+fn pause_button() -> Html {
+    let status = use_state(|| -> Option<TracerStatus> { None });
+
+    {
+        let status = status.clone();
+        use_effect_with_deps(
+            move |_| {
+                spawn_local(async move {
+                    loop {
+                        let res = Request::get(&server_url("http", "/tracer")).send().await;
+                        let new_status = match res {
+                            Ok(res) if res.ok() => res.json::<TracerStatus>().await.ok(),
+                            // not tracing, or not reachable
+                            _ => None,
+                        };
+                        status.set(new_status);
+                        gloo_timers::future::sleep(std::time::Duration::from_millis(500)).await;
+                    }
+                });
+            },
+            (),
+        );
+    }
+
+    let Some(tracer) = *status else {
+        return html! {};
+    };
+
+    let toggle = {
+        let action = if tracer.paused { "resume" } else { "pause" };
+        move |_| {
+            spawn_local(async move {
+                let url = server_url("http", &format!("/tracer/{action}"));
+                if let Err(e) = Request::post(&url).send().await {
+                    gloo_console::log!("Tracer control failed:", e.to_string());
+                }
+            })
+        }
+    };
+
+    html! {
+        <button class={ if tracer.paused { "pause-button paused" } else { "pause-button" } } onclick={toggle}>
+            { if tracer.paused { "Resume" } else { "Pause" } }
+        </button>
+    }
+}
+
+/// What `/api/tracees/:tid/series` has to say, or the part we draw
+#[derive(serde::Deserialize, Clone, PartialEq)]
+struct Series {
+    points: Vec<SeriesPoint>,
+}
+
+#[derive(serde::Deserialize, Clone, PartialEq)]
+struct SeriesPoint {
+    resident: u64,
+}
+
+#[derive(Properties, PartialEq)]
+struct RssChartProps {
+    tid: TraceeId,
+}
+
+/// Resident bytes over time, as the server sampled them, only shown when it
+/// does
+#[function_component(RssChart)]
+fn rss_chart(props: &RssChartProps) -> Html {
+    let series = use_state(|| -> Option<Series> { None });
+
+    {
+        let series = series.clone();
+        use_effect_with_deps(
+            move |tid: &TraceeId| {
+                let url = server_url("http", &format!("/api/tracees/{}/series", tid.0));
+                spawn_local(async move {
+                    loop {
+                        let res = Request::get(&url).send().await;
+                        let new_series = match res {
+                            Ok(res) if res.ok() => res.json::<Series>().await.ok(),
+                            // not sampling, or not reachable
+                            _ => None,
+                        };
+                        series.set(new_series);
+                        gloo_timers::future::sleep(std::time::Duration::from_secs(5)).await;
+                    }
+                });
+            },
+            props.tid,
+        );
+    }
+
+    let Some(series) = &*series else {
+        return html! {};
+    };
+    if series.points.len() < 2 {
+        return html! {};
+    }
+
+    let max = series
+        .points
+        .iter()
+        .map(|p| p.resident)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let last = (series.points.len() - 1) as f64;
+    let points = series
+        .points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            format!(
+                "{:.2},{:.2}",
+                i as f64 / last * 100.0,
+                100.0 - p.resident as f64 / max as f64 * 100.0
+            )
+        })
+        .join(" ");
+    html! {
+        <svg class="rss-chart" viewBox="0 0 100 100" preserveAspectRatio="none">
+            <polyline {points} />
+        </svg>
+    }
+}
+
+/// What things looked like some milliseconds into the session, and how
+/// they looked then
+type Past = (u64, HashMap<TraceeId, TraceeState>);
+
+#[derive(Properties, PartialEq)]
+struct HistoryBarProps {
+    /// Where we're looking, if not at the present
+    viewing: Option<u64>,
+
+    /// Called with the past to show, or `None` to go back to the present
+    on_view: Callback<Option<Past>>,
+}
+
+/// Scrubbing back through a live session, only shown when the server keeps
+/// history
+#[function_component(HistoryBar)]
+fn history_bar(props: &HistoryBarProps) -> Html {
+    let status = use_state(|| -> Option<HistoryStatus> { None });
+
+    {
+        let status = status.clone();
+        use_effect_with_deps(
+            move |_| {
+                spawn_local(async move {
+                    loop {
+                        let res = Request::get(&server_url("http", "/history")).send().await;
+                        let new_status = match res {
+                            Ok(res) if res.ok() => res.json::<HistoryStatus>().await.ok(),
+                            // no history, or not reachable
+                            _ => None,
+                        };
+                        status.set(new_status);
+                        gloo_timers::future::sleep(std::time::Duration::from_millis(1000)).await;
+                    }
+                });
+            },
+            (),
+        );
+    }
+
+    let Some(history) = *status else {
+        return html! {};
+    };
+
+    let seek = {
+        let on_view = props.on_view.clone();
+        move |e: Event| {
+            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+            let ms: u64 = input.value().parse().unwrap_or_default();
+            let on_view = on_view.clone();
+            spawn_local(async move {
+                let url = server_url("http", &format!("/history/at?ms={ms}"));
+                let frame = match Request::get(&url).send().await {
+                    Ok(res) if res.ok() => res.binary().await,
+                    Ok(res) => {
+                        gloo_console::log!("Seeking failed:", res.status());
+                        return;
+                    }
+                    Err(e) => Err(e),
+                };
+                let evs = match frame.map(|frame| protocol::decode(&frame)) {
+                    Ok(Ok(protocol::Frame::Events(evs))) => evs,
+                    _ => {
+                        gloo_console::log!("Seeking failed: bad response");
+                        return;
+                    }
+                };
+                let mut past = HashMap::new();
+                for ev in evs {
+                    apply_ev(&mut past, ev);
+                }
+                on_view.emit(Some((ms, past)));
+            })
+        }
+    };
+    let back_to_live = {
+        let on_view = props.on_view.clone();
+        move |_| on_view.emit(None)
+    };
+
+    let position = props.viewing.unwrap_or(history.end_ms);
+    html! {
+        <span class="replay-bar">
+            <input type="range" min={history.start_ms.to_string()} max={history.end_ms.to_string()} value={position.to_string()} onchange={seek} />
+            <span class="replay-time">{format!("{} / {}", format_ms(position), format_ms(history.end_ms))}</span>
+            {
+                if props.viewing.is_some() {
+                    html! { <button onclick={back_to_live}>{"Back to live"}</button> }
+                } else {
+                    html! {}
+                }
+            }
+        </span>
+    }
+}
+
+/// Connects, answers the server's hello with ours, and tells it where to
+/// resume from. Returns the half of the socket events come in on.
+async fn connect_and_greet(resume: &[(TraceeId, u64)]) -> SplitStream<WebSocket> {
+    loop {
+        let (mut write, mut read) = connect_to_ws().await.split();
+        let theirs = match read.next().await {
+            Some(Ok(Message::Bytes(b))) => match protocol::decode(&b) {
+                Ok(protocol::Frame::Hello(hello)) => hello,
+                Ok(_) => {
+                    gloo_console::log!("Server didn't say hello first");
+                    continue;
+                }
+                Err(e) => {
+                    gloo_console::log!("Bad hello from server:", e.to_string());
+                    continue;
+                }
+            },
+            _ => continue,
+        };
+
+        let ours = protocol::Hello::ours();
+        if let Err(e) = ours.negotiate(&theirs) {
+            // retrying won't help, but the server might get upgraded
+            gloo_console::log!(e.to_string());
+            gloo_timers::future::sleep(std::time::Duration::from_secs(5)).await;
+            continue;
+        }
+        if write
+            .send(Message::Bytes(protocol::encode_hello(&ours)))
+            .await
+            .is_err()
+        {
+            continue;
+        }
+        let resume = protocol::encode_resume(resume).unwrap();
+        if write.send(Message::Bytes(resume)).await.is_err() {
+            continue;
+        }
+        return read;
+    }
+}
+
+/// Where we are in each tracee's stream. Tracees the OOM killer got are only
+/// still around for us to look at, the server has forgotten them.
+fn resume_from(tracees: &HashMap<TraceeId, TraceeState>) -> Vec<(TraceeId, u64)> {
+    tracees
+        .values()
+        .filter(|tracee| !tracee.oom_killed)
+        .map(|tracee| (tracee.tid, tracee.next_seq))
+        .collect()
+}
+
+/// A trace opened from disk, and read entirely in the browser: what it's
+/// called, and how things were by the end of it
+struct Loaded {
+    name: String,
+    tracees: HashMap<TraceeId, TraceeState>,
+    session: Option<SessionInfo>,
+    ended: Option<SessionSummary>,
+}
+
+/// Reads a trace `mevi record` wrote, start to finish, the way the server
+/// would have streamed it
+fn load_trace(name: String, bytes: &[u8]) -> Result<Loaded, trace_file::Error> {
+    let mut reader = TraceReader::new(bytes)?;
+    let start = reader.anchor().monotonic;
+    let mut loaded = Loaded {
+        name,
+        tracees: HashMap::new(),
+        session: None,
+        ended: None,
+    };
+    while let Some(TraceRecord { at, event }) = reader.next_record()? {
+        match &event {
+            MeviEvent::SessionEnd(summary) => loaded.ended = Some(summary.clone()),
+            MeviEvent::SessionInfo(info) => loaded.session = Some(info.clone()),
+            _ => {}
+        }
+        // traces don't number events, so nothing looks lost
+        let at = Timestamp(start.0 + at.as_nanos() as u64);
+        apply_ev(&mut loaded.tracees, TimedEvent { at, seq: 0, event });
+    }
+    Ok(loaded)
+}
+
+#[derive(Properties, PartialEq)]
+struct OpenTraceProps {
+    /// Called with the trace, once it's read
+    on_open: Callback<Loaded>,
+}
+
+/// Opens a recorded trace without the server's help: it doesn't even have
+/// to be running
+#[function_component(OpenTrace)]
+fn open_trace(props: &OpenTraceProps) -> Html {
+    let open = {
+        let on_open = props.on_open.clone();
+        move |e: Event| {
+            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+            let Some(file) = input.files().and_then(|files| files.get(0)) else {
+                return;
+            };
+            let file = gloo_file::File::from(file);
+            let on_open = on_open.clone();
+            spawn_local(async move {
+                let bytes = match gloo_file::futures::read_as_bytes(&file).await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        gloo_console::log!("Reading trace failed:", e.to_string());
+                        return;
+                    }
+                };
+                match load_trace(file.name(), &bytes) {
+                    Ok(loaded) => on_open.emit(loaded),
+                    Err(e) => gloo_console::log!("Bad trace:", e.to_string()),
+                }
+            })
+        }
+    };
+
+    html! {
+        <span class="option open-trace">
+            <label title="a trace mevi recorded, read right here in the browser">
+                {"Open trace "}
+                <input type="file" accept=".mevi" onchange={open} />
+            </label>
+        </span>
+    }
+}
+
+/// How every process was doing when it went away, once the session is over
+fn session_summary(summary: &SessionSummary) -> Html {
+    let formatter = make_format(BINARY);
+    html! {
+        <div class="session-summary">
+            <span class="name">{format!("Session over, {} events", summary.events)}</span>
+            <table>
+                <tr>
+                    <th>{"PID"}</th>
+                    <th>{"Command"}</th>
+                    <th>{"Peak resident"}</th>
+                    <th>{"Resident at exit"}</th>
+                    <th>{"Faults"}</th>
+                </tr>
+                {
+                    summary.tracees.iter().map(|totals| {
+                        let categories = totals
+                            .by_category
+                            .iter()
+                            .map(|(category, resident)| format!("{category} {}", formatter(*resident)))
+                            .join(", ");
+                        html! {
+                            <tr>
+                                <td>{totals.tid.0}</td>
+                                <td>{totals.cmdline.join(" ")}</td>
+                                <td>{formatter(totals.peak_resident).to_string()}</td>
+                                <td title={categories}>{formatter(totals.resident).to_string()}</td>
+                                <td>{totals.faults}</td>
+                            </tr>
+                        }
+                    }).collect::<Html>()
+                }
+            </table>
+        </div>
+    }
+}
+
+/// Resident bytes of `tid` and every traced process it forked, and those
+/// they forked, etc.
+fn subtree_rss(tracees: &HashMap<TraceeId, TraceeState>, tid: TraceeId) -> u64 {
+    let mut total = 0;
+    let mut seen = HashSet::new();
+    let mut todo = vec![tid];
+    while let Some(tid) = todo.pop() {
+        // a reused pid could make a loop
+        if !seen.insert(tid) {
+            continue;
+        }
+        if let Some(tracee) = tracees.get(&tid) {
+            total += tracee.total_rss();
+        }
+        todo.extend(
+            tracees
+                .values()
+                .filter(|tracee| tracee.parent == Some(tid))
+                .map(|tracee| tracee.tid),
+        );
+    }
+    total
+}
+
 #[derive(Clone)]
 struct Options {
     show_gaps: bool,
@@ -108,10 +757,16 @@ fn app() -> Html {
     let options = use_state(Options::default);
     let live = use_state(|| false);
     let tracees = use_state(|| -> HashMap<TraceeId, TraceeState> { Default::default() });
+    let past = use_state(|| -> Option<Past> { None });
+    let ended = use_state(|| -> Option<SessionSummary> { None });
+    let session = use_state(|| -> Option<SessionInfo> { None });
+    let loaded = use_state(|| -> Option<Loaded> { None });
 
     {
         let tracees = tracees.clone();
         let live = live.clone();
+        let ended = ended.clone();
+        let session = session.clone();
         use_effect_with_deps(
             move |_| {
                 let mut tracees_acc = HashMap::new();
@@ -119,7 +774,7 @@ fn app() -> Html {
                 spawn_local(async move {
                     let mut batch_size = 0;
 
-                    let (_, mut read) = connect_to_ws().await.split();
+                    let mut read = connect_and_greet(&[]).await;
                     live.set(true);
 
                     while let Some(msg) = read.next().await {
@@ -130,9 +785,9 @@ fn app() -> Html {
                                 live.set(false);
 
                                 gloo_console::log!("Reconnecting...");
-                                (_, read) = connect_to_ws().await.split();
-                                tracees_acc.clear();
-                                tracees.set(tracees_acc.clone());
+                                // the server sends what we missed if it can,
+                                // and a snapshot that replaces all this if not
+                                read = connect_and_greet(&resume_from(&tracees_acc)).await;
                                 live.set(true);
                                 continue;
                             }
@@ -143,12 +798,29 @@ fn app() -> Html {
                                 gloo_console::log!(format!("text message: {t}"));
                             }
                             Message::Bytes(b) => {
-                                let evs = mevi_common::deserialize_many(&b).unwrap();
+                                let evs = match protocol::decode(&b).unwrap() {
+                                    protocol::Frame::Events(evs) => evs,
+                                    // nothing shows wall-clock time yet
+                                    protocol::Frame::Hello(_)
+                                    | protocol::Frame::Clock(_)
+                                    | protocol::Frame::Heartbeat(_)
+                                    | protocol::Frame::Resume(_)
+                                    | protocol::Frame::Token(_) => continue,
+                                };
                                 batch_size += evs.len();
                                 _ = batch_size;
 
                                 for ev in evs {
                                     // gloo_console::log!(format!("{:?}", ev));
+                                    match &ev.event {
+                                        MeviEvent::SessionEnd(summary) => {
+                                            ended.set(Some(summary.clone()))
+                                        }
+                                        MeviEvent::SessionInfo(info) => {
+                                            session.set(Some(info.clone()))
+                                        }
+                                        _ => {}
+                                    }
                                     apply_ev(&mut tracees_acc, ev);
                                 }
 
@@ -165,24 +837,69 @@ fn app() -> Html {
         );
     }
 
+    // the past, while we're looking at it, then a trace opened from disk,
+    // the present otherwise
+    let shown = match (&*past, &*loaded) {
+        (Some((_, past)), _) => past,
+        (None, Some(loaded)) => &loaded.tracees,
+        (None, None) => &*tracees,
+    };
+    let on_view = {
+        let past = past.clone();
+        Callback::from(move |view: Option<Past>| past.set(view))
+    };
+    let on_open = {
+        let loaded = loaded.clone();
+        Callback::from(move |trace: Loaded| loaded.set(Some(trace)))
+    };
+    let close_trace = {
+        let loaded = loaded.clone();
+        move |_| loaded.set(None)
+    };
+    let (session, ended) = match &*loaded {
+        Some(loaded) => (&loaded.session, &loaded.ended),
+        None => (&*session, &*ended),
+    };
+
     let mut total_virt: u64 = 0;
-    let mut total_res: u64 = 0;
-    for (range, mem_state) in tracees.values().flat_map(|v| v.map.iter()) {
+    for (range, _) in shown.values().flat_map(|v| v.map.iter()) {
         total_virt += range.end - range.start;
-
-        if *mem_state == MemState::Resident {
-            total_res += range.end - range.start;
-        }
     }
+    let total_res: u64 = shown.values().map(|v| v.total_rss()).sum();
 
     let formatter = make_format(BINARY);
     html! {
         <>
             <div class="top-bar">
                 <span class="brand"><span>{"me"}</span><span class="brand-rest">{"vi"}</span></span>
+                {
+                    match &*session {
+                        Some(info) => html! { <span class="session">{info.to_string()}</span> },
+                        None => html! {},
+                    }
+                }
                 <span class="mem-stats rss"><span class="mem-square"></span><span class="name">{"Resident set"}</span>{format!("{}", formatter(total_res))}</span>
                 <span class="mem-stats virt"><span class="mem-square"></span><span class="name">{"Virtual set"}</span>{format!("{}", formatter(total_virt))}</span>
-                <span class={ if *live { "live-indicator live" } else { "live-indicator offline" } }>{ if *live { "LIVE" } else { "OFFLINE" } }</span>
+                {
+                    if let Some(loaded) = &*loaded {
+                        html! {
+                            <>
+                                <span class="live-indicator file" title="opened from disk">{loaded.name.clone()}</span>
+                                <button onclick={close_trace}>{"Close"}</button>
+                            </>
+                        }
+                    } else if ended.is_some() {
+                        html! { <span class="live-indicator ended">{"ENDED"}</span> }
+                    } else {
+                        html! {
+                            <span class={ if *live { "live-indicator live" } else { "live-indicator offline" } }>{ if *live { "LIVE" } else { "OFFLINE" } }</span>
+                        }
+                    }
+                }
+                <ReplayBar />
+                <PauseButton />
+                <HistoryBar viewing={(*past).as_ref().map(|(ms, _)| *ms)} {on_view} />
+                <OpenTrace {on_open} />
 
                 <span class="option">
                     <label>
@@ -197,28 +914,136 @@ fn app() -> Html {
                     </label>
                 </span>
             </div>
+            {
+                if let Some(summary) = &*ended {
+                    session_summary(summary)
+                } else {
+                    html! {}
+                }
+            }
             {{
-                tracees.values().sorted_by_key(|p| std::cmp::Reverse(p.total_rss())).map(|tracee| {
+                shown.values().sorted_by_key(|p| std::cmp::Reverse(p.total_rss())).map(|tracee| {
                     html! {
                         <>
                             <div class="process">
                                 <div class="process-info">
                                     <span class="arg">{"PID "}{tracee.tid.0}</span>
+                                    {
+                                        if tracee.programs.len() > 1 {
+                                            html! {
+                                                <span class="arg" title="programs this process ran">{tracee.programs.join(" → ")}</span>
+                                            }
+                                        } else {
+                                            html! {}
+                                        }
+                                    }
+                                    {
+                                        if tracee.phases.is_empty() {
+                                            html! {}
+                                        } else {
+                                            html! {
+                                                <span class="arg" title="what it says it's doing">{tracee.phases.join(" › ")}</span>
+                                            }
+                                        }
+                                    }
+                                    {
+                                        if let Some(parent) = tracee.parent {
+                                            html! {
+                                                <span class="arg">{"child of "}{parent.0}</span>
+                                            }
+                                        } else {
+                                            html! {}
+                                        }
+                                    }
+                                    {{
+                                        let subtree = subtree_rss(shown, tracee.tid);
+                                        if subtree > tracee.total_rss() {
+                                            html! {
+                                                <span class="arg" title="resident, counting every descendant">{format!("subtree {}", formatter(subtree))}</span>
+                                            }
+                                        } else {
+                                            html! {}
+                                        }
+                                    }}
                                     {{
                                         // collect virt/rss stats for process
                                         let mut virt: u64 = 0;
-                                        let mut res: u64 = 0;
-                                        for (range, mem_state) in tracee.map.iter() {
-                                                virt += range.end - range.start;
-
-                                            if *mem_state == MemState::Resident {
-                                                res += range.end - range.start;
-                                            }
+                                        for (range, _) in tracee.map.iter() {
+                                            virt += range.end - range.start;
                                         }
+                                        let res = tracee.total_rss();
                                         html! {
                                             <>
                                                 <span class="mem-stats rss"><span class="mem-square"></span><span>{format!("{}", formatter(res))}</span></span>
                                                 <span class="mem-stats virt"><span class="mem-square"></span><span>{format!("{}", formatter(virt))}</span></span>
+                                                {{
+                                                    let mut per_node: BTreeMap<u32, u64> = BTreeMap::new();
+                                                    for (node, bytes) in tracee.numa_nodes.iter().flat_map(|(_, nodes)| nodes) {
+                                                        *per_node.entry(*node).or_default() += bytes;
+                                                    }
+                                                    per_node.into_iter().map(|(node, bytes)| {
+                                                        html! {
+                                                            <span class="arg">{format!("N{node} {}", formatter(bytes))}</span>
+                                                        }
+                                                    }).collect::<Html>()
+                                                }}
+                                                {
+                                                    if let Some((mode, nodes)) = &tracee.mem_policy {
+                                                        html! {
+                                                            <span class="arg">{format!("{mode:?} {nodes:?}")}</span>
+                                                        }
+                                                    } else {
+                                                        html! {}
+                                                    }
+                                                }
+                                                {
+                                                    if let Some((minor, major)) = tracee.faults {
+                                                        html! {
+                                                            <span class={ if major > 0 { "arg major-faults" } else { "arg" } }>{format!("{minor} minor / {major} major faults")}</span>
+                                                        }
+                                                    } else {
+                                                        html! {}
+                                                    }
+                                                }
+                                                {
+                                                    if let Some((rss, pss, swap)) = tracee.rollup {
+                                                        html! {
+                                                            <span class="arg" title="according to smaps_rollup">{format!("kernel RSS {} PSS {} swap {}", formatter(rss), formatter(pss), formatter(swap))}</span>
+                                                        }
+                                                    } else {
+                                                        html! {}
+                                                    }
+                                                }
+                                                {
+                                                    if let Some((current, max)) = tracee.cgroup_memory {
+                                                        let max = match max {
+                                                            Some(max) => formatter(max).to_string(),
+                                                            None => "max".to_string(),
+                                                        };
+                                                        html! {
+                                                            <span class="arg">{format!("cgroup {} / {max}", formatter(current))}</span>
+                                                        }
+                                                    } else {
+                                                        html! {}
+                                                    }
+                                                }
+                                                {
+                                                    tracee.cgroup_events.iter().map(|(event, count)| {
+                                                        html! {
+                                                            <span class="arg cgroup-event">{format!("{event:?} ×{count}")}</span>
+                                                        }
+                                                    }).collect::<Html>()
+                                                }
+                                                {{
+                                                    let huge: u64 = tracee.huge_pages.iter().map(|(_, huge)| huge).sum();
+                                                    if huge > 0 {
+                                                        html! {
+                                                            <span class="mem-stats thp"><span class="mem-square"></span><span>{format!("{} THP", formatter(huge))}</span></span>
+                                                        }
+                                                    } else {
+                                                        html! {}
+                                                    }
+                                                }}
                                             </>
                                         }
                                     }}
@@ -229,10 +1054,82 @@ fn app() -> Html {
                                             }
                                         }).collect::<Html>()
                                     }
+                                    {
+                                        if let Some(info) = &tracee.info {
+                                            let exe = info.exe.as_deref().unwrap_or("?");
+                                            let cwd = info.cwd.as_deref().unwrap_or("?");
+                                            html! {
+                                                <span class="arg" title={format!("{exe}, in {cwd}")}>
+                                                    {format!("uid {} gid {}", info.uid, info.gid)}
+                                                </span>
+                                            }
+                                        } else {
+                                            html! {}
+                                        }
+                                    }
                                 </div>
+                                <RssChart tid={tracee.tid} />
+                                {
+                                    if tracee.faulted_by_thread.len() > 1 {
+                                        html! {
+                                            <div class="process-threads">
+                                                {
+                                                    tracee.faulted_by_thread.iter().sorted_by_key(|(_, faulted)| std::cmp::Reverse(**faulted)).map(|(thread, faulted)| {
+                                                        html! {
+                                                            <span class="arg" title={format!("TID {}", thread.0)}>
+                                                                {
+                                                                    match tracee.thread_names.get(thread) {
+                                                                        Some(name) => name.clone(),
+                                                                        None => format!("TID {}", thread.0),
+                                                                    }
+                                                                }
+                                                                {" faulted "}{format!("{}", formatter(*faulted))}
+                                                            </span>
+                                                        }
+                                                    }).collect::<Html>()
+                                                }
+                                            </div>
+                                        }
+                                    } else {
+                                        html! {}
+                                    }
+                                }
+                                {
+                                    if !tracee.faulted_by_site.is_empty() {
+                                        html! {
+                                            <div class="process-threads">
+                                                {
+                                                    tracee.faulted_by_site.iter().sorted_by_key(|(_, faulted)| std::cmp::Reverse(**faulted)).take(5).map(|(site, faulted)| {
+                                                        html! {
+                                                            <span class="arg" title={site.clone()}>{site}{" faulted "}{format!("{}", formatter(*faulted))}</span>
+                                                        }
+                                                    }).collect::<Html>()
+                                                }
+                                            </div>
+                                        }
+                                    } else {
+                                        html! {}
+                                    }
+                                }
+                                {
+                                    if tracee.lost > 0 {
+                                        html! {
+                                            <div class="warning">{"⚠ "}{tracee.lost}{" events lost, mevi couldn't keep up"}</div>
+                                        }
+                                    } else {
+                                        html! {}
+                                    }
+                                }
+                                {
+                                    tracee.warnings.iter().map(|warning| {
+                                        html! {
+                                            <div class="warning">{"⚠ "}{warning}</div>
+                                        }
+                                    }).collect::<Html>()
+                                }
                                 {{
                                     let map = &tracee.map;
-                                    let has_any_memory_resident = map.iter().any(|(_, state)| *state == MemState::Resident);
+                                    let has_any_memory_resident = map.iter().any(|(_, state)| state.is_resident());
                                     if !has_any_memory_resident {
                                         return html!{ };
                                     }
@@ -274,7 +1171,7 @@ fn app() -> Html {
                                     for group in groups {
                                         let mut group_markup = vec![];
 
-                                        let has_any_memory_resident = group.ranges.iter().any(|(_, state)| *state == MemState::Resident);
+                                        let has_any_memory_resident = group.ranges.iter().any(|(_, state)| state.is_resident());
                                         if !has_any_memory_resident && !options.show_nonresident_groups {
                                             continue;
                                         }
@@ -315,10 +1212,19 @@ fn app() -> Html {
                                                     MemState::Resident => "r",
                                                     MemState::NotResident => "n",
                                                     MemState::Untracked => "u",
+                                                    MemState::Dirty => "d",
+                                                    MemState::Swapped => "s",
                                                 }
                                             };
 
-                                            let style = format!("width:{}%;left:{}%;", size as f64 * scale_ratio, (range.start - group.start) as f64 * scale_ratio);
+                                            let mut style = format!("width:{}%;left:{}%;", size as f64 * scale_ratio, (range.start - group.start) as f64 * scale_ratio);
+                                            // buckets that are only partly resident are fainter
+                                            if let (Some((bucket, resident_pages)), true) = (&tracee.resident_pages, mem_state.is_resident()) {
+                                                let resident: u64 = resident_pages.overlapping(&range).map(|(r, resident)| {
+                                                    resident * ((r.end.min(range.end) - r.start.max(range.start)) / bucket)
+                                                }).sum();
+                                                style.push_str(&format!("opacity:{};", 0.3 + 0.7 * resident as f64 / size as f64));
+                                            }
                                             let h = if size >= min_size_for_print {
                                                 html! {
                                                     <i class={state_class(mem_state)} title={format!("{} at {:x?}", formatter(size), range)} style={style}>{
@@ -333,6 +1239,53 @@ fn app() -> Html {
                                             group_markup.push(h)
                                         }
 
+                                        let group_end = group.start + group.size;
+                                        for (overlay, ranges) in tracee.overlays.iter() {
+                                            let overlay_class = match overlay {
+                                                Overlay::Written => "ov written",
+                                                Overlay::Idle => "ov idle",
+                                                Overlay::Deduplicated => "ov deduplicated",
+                                            };
+                                            for range in ranges.overlapping(&(group.start..group_end)) {
+                                                let start = range.start.max(group.start);
+                                                let end = range.end.min(group_end);
+                                                let style = format!("width:{}%;left:{}%;", (end - start) as f64 * scale_ratio, (start - group.start) as f64 * scale_ratio);
+                                                group_markup.push(html! {
+                                                    <i class={overlay_class} style={style}></i>
+                                                });
+                                            }
+                                        }
+
+                                        let mut group_kinds = vec![];
+                                        let mut group_files = vec![];
+                                        for (range, kind) in tracee.kinds.overlapping(&(group.start..group_end)) {
+                                            if !group_kinds.contains(kind) {
+                                                group_kinds.push(*kind);
+                                            }
+                                            let file = tracee.files.get(&range.start);
+                                            if let Some(file) = file {
+                                                if !group_files.contains(&file.name()) {
+                                                    group_files.push(file.name());
+                                                }
+                                            }
+                                            // plain anonymous memory is the default, no need to point it out
+                                            if *kind == MappingKind::Anonymous {
+                                                continue;
+                                            }
+                                            let start = range.start.max(group.start);
+                                            let end = range.end.min(group_end);
+                                            let style = format!("width:{}%;left:{}%;", (end - start) as f64 * scale_ratio, (start - group.start) as f64 * scale_ratio);
+                                            group_markup.push(html! {
+                                                <i class={format!("kind {}", kind_class(*kind))} title={{
+                                                    let perms = tracee.perms.get(&range.start).map(|perms| format!(" {perms}")).unwrap_or_default();
+                                                    match file {
+                                                        Some(file) => format!("{} at offset {:#x}{perms}", file.path, file.offset),
+                                                        None => format!("{kind:?}{perms}"),
+                                                    }
+                                                }} style={style}></i>
+                                            });
+                                        }
+
                                         if !group_markup.is_empty() {
                                             groups_markup.push(html! {
                                                 <>
@@ -359,6 +1312,22 @@ fn app() -> Html {
                                                             <span class="scale">
                                                                 { format!("{} scale", formatter(max_bytes)) }
                                                             </span>
+                                                            <span class="kinds">
+                                                                { group_kinds.iter().map(|kind| kind_class(*kind)).join(", ") }
+                                                            </span>
+                                                            <span class="files" title={group_files.join("\n")}>
+                                                                { group_files.join(", ") }
+                                                            </span>
+                                                            {{
+                                                                let huge: u64 = tracee.huge_pages.iter().filter(|(r, _)| r.end > group.start && r.start < group_end).map(|(_, huge)| huge).sum();
+                                                                if huge > 0 {
+                                                                    html! {
+                                                                        <span class="thp">{ format!("{} THP", formatter(huge)) }</span>
+                                                                    }
+                                                                } else {
+                                                                    html! {}
+                                                                }
+                                                            }}
                                                         </div>
                                                         <div class="group">
                                                             { group_markup }
@@ -382,38 +1351,180 @@ fn app() -> Html {
     }
 }
 
-fn apply_ev(tracees: &mut HashMap<TraceeId, TraceeState>, ev: MeviEvent) {
-    let (tid, payload) = match ev {
+fn apply_ev(tracees: &mut HashMap<TraceeId, TraceeState>, ev: TimedEvent) {
+    let (tid, payload) = match ev.event {
         MeviEvent::Snapshot(snap_tracees) => {
+            // we might be resyncing after falling behind, and missed some
+            // exits
+            tracees.retain(|tid, tracee| {
+                tracee.oom_killed || snap_tracees.iter().any(|snap| snap.tid == *tid)
+            });
             for snap_tracee in snap_tracees {
                 let tracee = tracees
                     .entry(snap_tracee.tid)
-                    .or_insert_with(|| TraceeState {
-                        tid: snap_tracee.tid,
-                        map: Default::default(),
-                        cmdline: Default::default(),
-                    });
+                    .or_insert_with(|| TraceeState::new(snap_tracee.tid));
                 tracee.cmdline = snap_tracee.cmdline;
+                if let Some(exe) = snap_tracee
+                    .info
+                    .as_ref()
+                    .and_then(|info| info.exe.as_deref())
+                {
+                    tracee.ran(exe);
+                }
+                tracee.info = snap_tracee.info;
+                tracee.parent = snap_tracee.parent;
+                tracee.thread_names = snap_tracee.threads.into_iter().collect();
                 tracee.map = snap_tracee.map;
+                tracee.kinds = snap_tracee.kinds;
+                tracee.files = snap_tracee.files;
+                tracee.perms = snap_tracee.perms;
+                tracee.next_seq = snap_tracee.seq;
+                tracee.resident_pages = snap_tracee
+                    .resident_pages
+                    .map(|pages| (pages.bucket, pages.runs.into_iter().collect()));
             }
             return;
         }
         MeviEvent::TraceeEvent(tid, ev) => (tid, ev),
+        // the app shows those, there's nothing to apply them to
+        MeviEvent::SessionEnd(_) | MeviEvent::SessionInfo(_) => return,
     };
 
-    let tracee = tracees.entry(tid).or_insert_with(|| TraceeState {
-        tid,
-        map: Default::default(),
-        cmdline: Default::default(),
-    });
+    let tracee = tracees.entry(tid).or_insert_with(|| TraceeState::new(tid));
+    // events we never got are just as lost as the ones mevi dropped
+    tracee.lost += ev.seq.saturating_sub(tracee.next_seq);
+    tracee.next_seq = ev.seq + 1;
 
     payload.apply_to_memmap(&mut tracee.map);
+    payload.apply_to_kinds(&mut tracee.kinds);
+    payload.apply_to_files(&mut tracee.files);
+    payload.apply_to_perms(&mut tracee.perms);
     match payload {
         TraceePayload::CmdLineChange { cmdline } => {
             tracee.cmdline = cmdline;
         }
+        TraceePayload::ProcessInfo(info) => {
+            if let Some(exe) = &info.exe {
+                tracee.ran(exe);
+            }
+            tracee.info = Some(info);
+        }
+        TraceePayload::ParentChange { parent } => {
+            tracee.parent = parent;
+        }
+        TraceePayload::ThreadName { thread, name } => {
+            tracee.thread_names.insert(thread, name);
+        }
+        TraceePayload::Annotation { name, kind } => match kind {
+            AnnotationKind::Begin => tracee.phases.push(name),
+            AnnotationKind::End => {
+                if let Some(i) = tracee.phases.iter().rposition(|phase| *phase == name) {
+                    tracee.phases.remove(i);
+                }
+            }
+            AnnotationKind::Mark => {}
+        },
+        TraceePayload::PageFault {
+            range,
+            thread,
+            site,
+            ..
+        } => {
+            if let Some(thread) = thread {
+                *tracee.faulted_by_thread.entry(thread).or_default() += range.end - range.start;
+            }
+            if let Some(site) = site {
+                *tracee.faulted_by_site.entry(site).or_default() += range.end - range.start;
+            }
+        }
+        TraceePayload::Sample(Sample::HugePages { regions }) => {
+            tracee.huge_pages = regions;
+        }
+        TraceePayload::Sample(Sample::NumaNodes { regions }) => {
+            tracee.numa_nodes = regions;
+        }
+        TraceePayload::Sample(Sample::Faults { minor, major }) => {
+            tracee.faults = Some((minor, major));
+        }
+        TraceePayload::Sample(Sample::Rollup { rss, pss, swap, .. }) => {
+            tracee.rollup = Some((rss, pss, swap));
+        }
+        TraceePayload::Sample(Sample::CgroupMemory { current, max, .. }) => {
+            tracee.cgroup_memory = Some((current, max));
+        }
+        TraceePayload::OomKilled { rss } => {
+            tracee.oom_killed = true;
+            let formatter = make_format(BINARY);
+            tracee.warnings.push(format!(
+                "killed by the OOM killer, with {} resident",
+                formatter(rss)
+            ));
+        }
+        TraceePayload::CgroupEvent { event, count } => {
+            *tracee.cgroup_events.entry(event).or_default() += count;
+        }
+        TraceePayload::MemPolicy {
+            range: None,
+            mode,
+            nodes,
+        } => {
+            tracee.mem_policy = Some((mode, nodes));
+        }
+        TraceePayload::MemPolicy {
+            range: Some(range),
+            mode,
+            nodes,
+        } => {
+            gloo_console::log!(format!(
+                "{tid}: mbind {range:x?} {mode:?} on nodes {nodes:?}"
+            ));
+        }
+        TraceePayload::OverlaySample { overlay, ranges } => {
+            let ranges = ranges
+                .into_iter()
+                .filter(|range| !range.is_empty())
+                .collect();
+            tracee.overlays.insert(overlay, ranges);
+        }
+        TraceePayload::OverlayDelta {
+            overlay,
+            added,
+            removed,
+        } => {
+            let ranges = tracee.overlays.entry(overlay).or_default();
+            protocol::apply_overlay_delta(ranges, &added, &removed);
+        }
+        TraceePayload::Warning { message } => {
+            gloo_console::warn!(format!("{tid}: {message}"));
+            tracee.warnings.push(message);
+        }
+        TraceePayload::Lost { count } => {
+            tracee.lost += count;
+        }
+        TraceePayload::ResidentPages(pages) => {
+            let (_, resident_pages) = tracee
+                .resident_pages
+                .get_or_insert_with(|| (pages.bucket, Default::default()));
+            for (range, resident) in pages.runs {
+                resident_pages.insert(range, resident);
+            }
+        }
+        TraceePayload::Exec { exe, argv, .. } => {
+            tracee.resident_pages = None;
+            tracee.faulted_by_site.clear();
+            if let Some(exe) = exe.as_deref().or(argv.first().map(|arg| arg.as_str())) {
+                tracee.ran(exe);
+            }
+        }
+        TraceePayload::Unmap { range } => {
+            if let Some((_, resident_pages)) = &mut tracee.resident_pages {
+                resident_pages.remove(range);
+            }
+        }
         TraceePayload::Exit { .. } => {
-            tracees.remove(&tid);
+            if !tracee.oom_killed {
+                tracees.remove(&tid);
+            }
         }
         _ => {
             // ignore
@@ -421,6 +1532,23 @@ fn apply_ev(tracees: &mut HashMap<TraceeId, TraceeState>, ev: MeviEvent) {
     }
 }
 
+fn kind_class(kind: MappingKind) -> &'static str {
+    match kind {
+        MappingKind::Anonymous => "anonymous",
+        MappingKind::Heap => "heap",
+        MappingKind::Stack => "stack",
+        MappingKind::ThreadStack => "thread-stack",
+        MappingKind::Guard => "guard",
+        MappingKind::Vdso => "vdso",
+        MappingKind::Vvar => "vvar",
+        MappingKind::Vsyscall => "vsyscall",
+        MappingKind::SharedMemory => "shm",
+        MappingKind::File => "file",
+        MappingKind::Code => "code",
+        MappingKind::Data => "data",
+    }
+}
+
 fn main() {
     yew::Renderer::<App>::new().render();
 }
@@ -0,0 +1,127 @@
+//! Lets a program tell mevi what it's doing while it's traced, so what
+//! happens to its memory can be tied to phases of its own: mevi puts
+//! annotations in the event stream, between the faults and mappings that
+//! happened before and after them.
+//!
+//! ```no_run
+//! mevi_payload::mark("config loaded");
+//!
+//! {
+//!     let _phase = mevi_payload::phase("loading index");
+//!     // everything that happens in here is "loading index"
+//! }
+//!
+//! mevi_payload::begin("GC");
+//! // ...
+//! mevi_payload::end("GC");
+//! ```
+//!
+//! Outside of mevi, all of that does nothing, and costs about as much. The
+//! same goes for programs mevi attached to rather than started: annotations
+//! go to a socket mevi tells the programs it starts about in their
+//! environment (`$MEVI_PAYLOAD`), which the programs they start inherit.
+//!
+//! Annotations are sent as they're made, from any thread, and wait for mevi
+//! if it's behind. Names are cut to about a kilobyte.
+
+use std::{os::unix::net::UnixDatagram, sync::OnceLock};
+
+/// Where mevi says its socket is, in the environment of programs it starts
+pub const ENV: &str = "MEVI_PAYLOAD";
+
+/// Something happened, just now
+pub fn mark(name: &str) {
+    send(wire::Kind::Mark, name)
+}
+
+/// A phase starts, until [end] is called with the same name
+pub fn begin(name: &str) {
+    send(wire::Kind::Begin, name)
+}
+
+/// The latest phase started with that name ends
+pub fn end(name: &str) {
+    send(wire::Kind::End, name)
+}
+
+/// A phase that starts now, and ends when the returned [Phase] is dropped
+pub fn phase(name: &str) -> Phase {
+    begin(name);
+    Phase {
+        name: name.to_owned(),
+    }
+}
+
+/// Whether mevi is listening, for annotations that are expensive to come
+/// up with
+pub fn enabled() -> bool {
+    socket().is_some()
+}
+
+/// A phase from [phase], which ends when this is dropped
+#[must_use = "the phase ends as soon as this is dropped"]
+pub struct Phase {
+    name: String,
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        end(&self.name)
+    }
+}
+
+fn socket() -> Option<&'static UnixDatagram> {
+    static SOCKET: OnceLock<Option<UnixDatagram>> = OnceLock::new();
+    SOCKET
+        .get_or_init(|| {
+            let path = std::env::var_os(ENV)?;
+            let socket = UnixDatagram::unbound().ok()?;
+            socket.connect(path).ok()?;
+            Some(socket)
+        })
+        .as_ref()
+}
+
+fn send(kind: wire::Kind, name: &str) {
+    if let Some(socket) = socket() {
+        // mevi might be gone already, there's no one to tell
+        _ = socket.send(&wire::encode(kind, name));
+    }
+}
+
+/// What's in each datagram: a kind byte, then the name, as UTF-8. Who sent
+/// it, mevi gets from the kernel.
+#[doc(hidden)]
+pub mod wire {
+    /// Datagrams are at most this long, names are cut to fit
+    pub const MAX_LEN: usize = 1024;
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum Kind {
+        Mark = 0,
+        Begin = 1,
+        End = 2,
+    }
+
+    pub fn encode(kind: Kind, name: &str) -> Vec<u8> {
+        let mut len = name.len().min(MAX_LEN - 1);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut datagram = Vec::with_capacity(len + 1);
+        datagram.push(kind as u8);
+        datagram.extend_from_slice(&name.as_bytes()[..len]);
+        datagram
+    }
+
+    pub fn decode(datagram: &[u8]) -> Option<(Kind, &str)> {
+        let (kind, name) = datagram.split_first()?;
+        let kind = match kind {
+            0 => Kind::Mark,
+            1 => Kind::Begin,
+            2 => Kind::End,
+            _ => return None,
+        };
+        Some((kind, std::str::from_utf8(name).ok()?))
+    }
+}
@@ -0,0 +1,26 @@
+"""Tracing what programs do with their memory, and reading what mevi
+recorded about it, from Python.
+
+    import mevi
+
+    session = mevi.spawn(["ls", "-l"])
+    for at, event in session:
+        print(at, event)
+    print("exited with", session.wait())
+
+    import pandas
+    ranges = pandas.DataFrame(mevi.load_trace("trace.mevi"))
+"""
+
+from ._native import KINDS, STATES, Session, Trace, attach, load_trace, read_trace, spawn
+
+__all__ = [
+    "KINDS",
+    "STATES",
+    "Session",
+    "Trace",
+    "attach",
+    "load_trace",
+    "read_trace",
+    "spawn",
+]
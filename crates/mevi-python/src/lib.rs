@@ -0,0 +1,270 @@
+//! Python bindings for [mevi_tracer] and mevi traces, built with maturin
+//! into the `mevi` package (`python/mevi` re-exports what's in here):
+//!
+//! ```python
+//! import mevi
+//!
+//! session = mevi.spawn(["ls", "-l"])
+//! for at, event in session:
+//!     print(at, event)
+//! print("exited with", session.wait())
+//! ```
+//!
+//! Events are dicts, the way serde has a [mevi_common::MeviEvent], and `at`
+//! is in nanoseconds since the session started. [load_trace] reads a whole
+//! trace into numpy columns instead, for pandas and friends.
+
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use mevi_common::{
+    trace::{TraceReader, TraceRecord},
+    FaultAccess, MemState, MeviEvent, TimedEvent, Timestamp, TraceePayload,
+};
+use mevi_tracer::TracerBuilder;
+use numpy::IntoPyArray;
+use pyo3::{
+    exceptions::{PyOSError, PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+
+/// What the `kind` column of [load_trace] means, by code
+const KINDS: [&str; 4] = ["map", "state", "fault", "unmap"];
+
+/// What the `state` column of [load_trace] means, by code. Unmaps have
+/// [NO_STATE].
+const STATES: [&str; 5] = ["resident", "not_resident", "untracked", "dirty", "swapped"];
+
+const NO_STATE: u8 = u8::MAX;
+
+fn runtime_error(e: impl std::fmt::Display) -> PyErr {
+    PyRuntimeError::new_err(format!("{e:#}"))
+}
+
+fn trace_error(e: mevi_common::trace::Error) -> PyErr {
+    match e {
+        mevi_common::trace::Error::Io(e) => e.into(),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+fn to_py(py: Python<'_>, at: u64, event: &MeviEvent) -> PyResult<PyObject> {
+    let event = pythonize::pythonize(py, event)?;
+    Ok((at, event).into_py(py))
+}
+
+/// A program being traced, from `spawn` or `attach`. Iterating over it
+/// gives `(at, event)` tuples as things happen, and ends once every tracee
+/// is gone.
+#[pyclass(module = "mevi")]
+struct Session {
+    /// Gone once waited for
+    session: Option<mevi_tracer::Session>,
+    start: Timestamp,
+}
+
+#[pymethods]
+impl Session {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(None);
+        };
+        // other Python threads get to run while nothing happens
+        match py.allow_threads(|| session.next()) {
+            Some(TimedEvent { at, event, .. }) => {
+                let at = at.since(self.start).as_nanos() as u64;
+                to_py(py, at, &event).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Stops every tracee until `resume`
+    fn pause(&self) {
+        if let Some(session) = &self.session {
+            session.control().set_paused(true);
+        }
+    }
+
+    fn resume(&self) {
+        if let Some(session) = &self.session {
+            session.control().set_paused(false);
+        }
+    }
+
+    /// Waits for every tracee to be gone, throwing away events that weren't
+    /// read yet, and returns the exit code of the process we started (or
+    /// attached to), or None if we didn't see it exit
+    fn wait(&mut self, py: Python<'_>) -> PyResult<Option<i32>> {
+        let Some(session) = self.session.take() else {
+            return Err(PyRuntimeError::new_err("already waited for"));
+        };
+        py.allow_threads(|| session.wait()).map_err(runtime_error)
+    }
+}
+
+fn start(py: Python<'_>, builder: TracerBuilder) -> PyResult<Session> {
+    let session = py
+        .allow_threads(|| builder.start())
+        .map_err(runtime_error)?;
+    let start = session.anchor().monotonic;
+    Ok(Session {
+        session: Some(session),
+        start,
+    })
+}
+
+/// Starts `argv[0]` with the rest of `argv` as its arguments, and traces it
+#[pyfunction]
+fn spawn(py: Python<'_>, argv: Vec<String>) -> PyResult<Session> {
+    if argv.is_empty() {
+        return Err(PyValueError::new_err("argv is empty"));
+    }
+    start(py, TracerBuilder::spawn(argv))
+}
+
+/// Attaches to every thread of `pid`, and traces it
+#[pyfunction]
+fn attach(py: Python<'_>, pid: u32) -> PyResult<Session> {
+    start(py, TracerBuilder::attach(pid))
+}
+
+/// What mevi recorded, from `read_trace`. Iterating over it gives `(at,
+/// event)` tuples like a [Session] does, with `at` in nanoseconds since the
+/// recording started.
+#[pyclass(module = "mevi")]
+struct Trace {
+    reader: TraceReader<BufReader<File>>,
+}
+
+#[pymethods]
+impl Trace {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        match self.reader.next_record().map_err(trace_error)? {
+            Some(TraceRecord { at, event }) => to_py(py, at.as_nanos() as u64, &event).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// When the recording started, as `(monotonic, unix)` nanoseconds
+    fn anchor(&self) -> (u64, u64) {
+        let anchor = self.reader.anchor();
+        (anchor.monotonic.0, anchor.unix_nanos)
+    }
+}
+
+fn open(path: PathBuf) -> PyResult<TraceReader<BufReader<File>>> {
+    let file = File::open(&path)
+        .map_err(|e| PyOSError::new_err(format!("opening {}: {e}", path.display())))?;
+    TraceReader::new(BufReader::new(file)).map_err(trace_error)
+}
+
+/// Reads the trace at `path`, one event at a time
+#[pyfunction]
+fn read_trace(path: PathBuf) -> PyResult<Trace> {
+    Ok(Trace {
+        reader: open(path)?,
+    })
+}
+
+/// Reads the whole trace at `path` into a dict of numpy columns, one row per
+/// map, state change, fault and unmap, ready for `pandas.DataFrame`:
+///
+/// - `at`: nanoseconds since the recording started
+/// - `tid`: the process or thread it happened to
+/// - `start`, `end`: the address range, end excluded
+/// - `kind`: an index into `KINDS`
+/// - `state`: an index into `STATES` (what the range is now), 255 for unmaps
+///
+/// Everything else (execs, samples, exits...) is only in `read_trace`.
+#[pyfunction]
+fn load_trace(py: Python<'_>, path: PathBuf) -> PyResult<PyObject> {
+    let mut reader = open(path)?;
+    let mut columns = Columns::default();
+    py.allow_threads(|| {
+        while let Some(record) = reader.next_record()? {
+            columns.push(record);
+        }
+        Ok::<_, mevi_common::trace::Error>(())
+    })
+    .map_err(trace_error)?;
+
+    let dict = PyDict::new(py);
+    dict.set_item("at", columns.at.into_pyarray(py))?;
+    dict.set_item("tid", columns.tid.into_pyarray(py))?;
+    dict.set_item("start", columns.start.into_pyarray(py))?;
+    dict.set_item("end", columns.end.into_pyarray(py))?;
+    dict.set_item("kind", columns.kind.into_pyarray(py))?;
+    dict.set_item("state", columns.state.into_pyarray(py))?;
+    Ok(dict.into())
+}
+
+#[derive(Default)]
+struct Columns {
+    at: Vec<u64>,
+    tid: Vec<u64>,
+    start: Vec<u64>,
+    end: Vec<u64>,
+    kind: Vec<u8>,
+    state: Vec<u8>,
+}
+
+impl Columns {
+    fn push(&mut self, TraceRecord { at, event }: TraceRecord) {
+        let MeviEvent::TraceeEvent(tid, payload) = event else {
+            return;
+        };
+        let (range, kind, state) = match payload {
+            TraceePayload::Map { range, state, .. } => (range, 0, state_code(state)),
+            TraceePayload::MemStateChange { range, state } => (range, 1, state_code(state)),
+            TraceePayload::PageFault { range, access, .. } => {
+                // same as apply_to_memmap has it
+                let state = match access {
+                    FaultAccess::Read => MemState::Resident,
+                    FaultAccess::Write => MemState::Dirty,
+                };
+                (range, 2, state_code(state))
+            }
+            TraceePayload::Unmap { range } => (range, 3, NO_STATE),
+            _ => return,
+        };
+        self.at.push(at.as_nanos() as u64);
+        self.tid.push(tid.0);
+        self.start.push(range.start);
+        self.end.push(range.end);
+        self.kind.push(kind);
+        self.state.push(state);
+    }
+}
+
+fn state_code(state: MemState) -> u8 {
+    match state {
+        MemState::Resident => 0,
+        MemState::NotResident => 1,
+        MemState::Untracked => 2,
+        MemState::Dirty => 3,
+        MemState::Swapped => 4,
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "_native")]
+fn mevi_python(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Session>()?;
+    m.add_class::<Trace>()?;
+    m.add_function(wrap_pyfunction!(spawn, m)?)?;
+    m.add_function(wrap_pyfunction!(attach, m)?)?;
+    m.add_function(wrap_pyfunction!(read_trace, m)?)?;
+    m.add_function(wrap_pyfunction!(load_trace, m)?)?;
+    m.add("KINDS", KINDS.to_vec())?;
+    m.add("STATES", STATES.to_vec())?;
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! What programs we started tell us about themselves with mevi-payload: they
+//! find a datagram socket in their environment, and a thread of ours turns
+//! what comes in on it into events, stamped as it does.
+
+use std::{
+    io::IoSliceMut,
+    os::{fd::AsRawFd, unix::net::UnixDatagram},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use mevi_common::{AnnotationKind, MeviEvent, TraceeId, TraceePayload};
+use mevi_payload::wire;
+use nix::{
+    errno::Errno,
+    sys::socket::{
+        recvmsg, setsockopt, sockopt::PassCred, ControlMessageOwned, MsgFlags, UnixAddr,
+    },
+};
+use tracing::{debug, warn};
+
+use crate::sink::Events;
+
+/// How long we wait for an annotation before checking whether we should
+/// stop
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Where tracees send annotations, until this is dropped
+pub(crate) struct Annotations {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+}
+
+impl Annotations {
+    pub(crate) fn listen(path: PathBuf, tx: Events) -> Result<Self> {
+        _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)
+            .wrap_err_with(|| format!("listening on {}", path.display()))?;
+        // the kernel tells us who sent what, so they don't have to
+        setsockopt(&socket, PassCred, &true)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        std::thread::spawn({
+            let stop = stop.clone();
+            move || receive(socket, tx, stop)
+        });
+        Ok(Self { path, stop })
+    }
+
+    /// What tracees find in `$MEVI_PAYLOAD`
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Annotations {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn receive(socket: UnixDatagram, tx: Events, stop: Arc<AtomicBool>) {
+    let mut buf = vec![0u8; wire::MAX_LEN];
+    while !stop.load(Ordering::Relaxed) {
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut cmsgs = nix::cmsg_space!(libc::ucred);
+        let msg = match recvmsg::<UnixAddr>(
+            socket.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsgs),
+            MsgFlags::empty(),
+        ) {
+            Ok(msg) => msg,
+            Err(Errno::EAGAIN | Errno::EINTR) => continue,
+            Err(e) => {
+                warn!("stopped listening for annotations: {e}");
+                return;
+            }
+        };
+        let len = msg.bytes;
+        let Some(pid) = msg.cmsgs().find_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmCredentials(creds) => Some(creds.pid()),
+            _ => None,
+        }) else {
+            continue;
+        };
+        let tid = TraceeId(pid as u64);
+
+        let Some((kind, name)) = wire::decode(&buf[..len]) else {
+            debug!("{tid} sent an annotation we can't read");
+            continue;
+        };
+        let kind = match kind {
+            wire::Kind::Mark => AnnotationKind::Mark,
+            wire::Kind::Begin => AnnotationKind::Begin,
+            wire::Kind::End => AnnotationKind::End,
+        };
+        let payload = TraceePayload::Annotation {
+            name: name.to_owned(),
+            kind,
+        };
+        if tx.send(MeviEvent::TraceeEvent(tid, payload)).is_err() {
+            // the session's over
+            return;
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Coarser views of residency, for `--granularity`: the relay keeps track of
+//! every page, but clients get resident bytes per bucket instead, which
+//! stays manageable when a process maps hundreds of gigabytes.
+//!
+//! With `--track-granularity`, the tracer doesn't even send pages: it counts
+//! faults per bucket, and only sends how much of each bucket is resident.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use mevi_common::{
+    resident_in, FaultSource, MemMap, MemState, MeviEvent, ResidentPages, Timestamp, TraceeId,
+    TraceePayload,
+};
+
+use crate::sink::Events;
+
+/// How long the tracer sits on bucket counts before sending them
+const COUNT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What clients get instead of `payload`, now that `map` accounts for it
+pub fn coarsen(payload: TraceePayload, map: &MemMap, bucket: u64) -> TraceePayload {
+    match payload {
+        TraceePayload::PageFault { range, .. } | TraceePayload::MemStateChange { range, .. } => {
+            let span = round_out(&range, bucket);
+            let mut runs: Vec<(Range<u64>, u64)> = vec![];
+            for start in (span.start..span.end).step_by(bucket as usize) {
+                push_run(
+                    &mut runs,
+                    start..start + bucket,
+                    resident_in(map, &(start..start + bucket)),
+                );
+            }
+            TraceePayload::ResidentPages(ResidentPages { bucket, runs })
+        }
+        payload => payload,
+    }
+}
+
+/// `map` as a snapshot shows it: in whole buckets, resident if anything in
+/// them is, and how much of each bucket that has anything resident is
+pub fn snapshot(map: &MemMap, bucket: u64) -> (MemMap, ResidentPages) {
+    let mut coarse = MemMap::new();
+    // residency wins over whatever else shares its buckets
+    for (range, state) in map.iter().filter(|(_, state)| !state.is_resident()) {
+        coarse.insert(round_out(range, bucket), *state);
+    }
+
+    let mut runs: Vec<(Range<u64>, u64)> = vec![];
+    let mut next = 0;
+    for (range, _) in map.iter().filter(|(_, state)| state.is_resident()) {
+        let span = round_out(range, bucket);
+        // neighboring ranges can share a bucket, it's counted already
+        for start in (span.start.max(next)..span.end).step_by(bucket as usize) {
+            push_run(
+                &mut runs,
+                start..start + bucket,
+                resident_in(map, &(start..start + bucket)),
+            );
+        }
+        next = next.max(span.end);
+    }
+    for (range, _) in &runs {
+        coarse.insert(range.clone(), MemState::Resident);
+    }
+
+    (coarse, ResidentPages { bucket, runs })
+}
+
+/// Counts the faults one tracee takes, per bucket, for `--track-granularity`.
+/// It only sees faults and what the uffd tells it about, so it's an
+/// approximation: removed ranges are assumed to have been all resident, and
+/// remapped ones start over from nothing. Removals themselves are reported
+/// page by page by the tracer, so they don't get sent from here.
+pub(crate) struct Counter {
+    tid: TraceeId,
+    bucket: u64,
+
+    /// Resident bytes in each bucket that has any, by where it starts
+    resident: BTreeMap<u64, u64>,
+
+    /// Buckets that changed since we last sent them
+    changed: BTreeSet<u64>,
+
+    /// When the first of those changed
+    since: Option<(Instant, Timestamp)>,
+}
+
+impl Counter {
+    pub(crate) fn new(tid: TraceeId, bucket: u64) -> Self {
+        Self {
+            tid,
+            bucket,
+            resident: Default::default(),
+            changed: Default::default(),
+            since: None,
+        }
+    }
+
+    /// Counts a fault, if it made anything resident
+    pub(crate) fn fault(&mut self, payload: &TraceePayload) {
+        let TraceePayload::PageFault { range, source, .. } = payload else {
+            return;
+        };
+        // the first write to a page that was there already
+        if *source == FaultSource::Unprotect {
+            return;
+        }
+        for start in self.update(range, |resident, overlap| resident + overlap) {
+            self.changed.insert(start);
+        }
+        self.since
+            .get_or_insert_with(|| (Instant::now(), Timestamp::now()));
+    }
+
+    /// Pages in `range` were madvised away
+    pub(crate) fn remove(&mut self, range: &Range<u64>) {
+        self.update(range, |resident, overlap| resident.saturating_sub(overlap));
+    }
+
+    /// `range` was unmapped, or moved somewhere else
+    pub(crate) fn forget(&mut self, range: &Range<u64>) {
+        let span = round_out(range, self.bucket);
+        self.resident.retain(|start, _| !span.contains(start));
+        self.changed.retain(|start| !span.contains(start));
+    }
+
+    /// Applies `f` to the resident bytes of every bucket `range` overlaps,
+    /// along with how much it overlaps them. Returns where those start.
+    fn update(&mut self, range: &Range<u64>, f: impl Fn(u64, u64) -> u64) -> Vec<u64> {
+        let span = round_out(range, self.bucket);
+        let starts = (span.start..span.end)
+            .step_by(self.bucket as usize)
+            .collect::<Vec<_>>();
+        for &start in &starts {
+            let end = start + self.bucket;
+            let overlap = range.end.min(end) - range.start.max(start);
+            let resident = self.resident.get(&start).copied().unwrap_or_default();
+            match f(resident, overlap).min(self.bucket) {
+                0 => self.resident.remove(&start),
+                resident => self.resident.insert(start, resident),
+            };
+        }
+        starts
+    }
+
+    /// Sends how much of every bucket that changed is resident now
+    pub(crate) fn flush(&mut self, tx: &Events) -> Result<(), mpsc::SendError<MeviEvent>> {
+        let Some((_, at)) = self.since.take() else {
+            return Ok(());
+        };
+        let mut runs: Vec<(Range<u64>, u64)> = vec![];
+        for start in std::mem::take(&mut self.changed) {
+            let resident = self.resident.get(&start).copied().unwrap_or_default();
+            push_run(&mut runs, start..start + self.bucket, resident);
+        }
+        let pages = ResidentPages {
+            bucket: self.bucket,
+            runs,
+        };
+        tx.send_at(
+            at,
+            MeviEvent::TraceeEvent(self.tid, TraceePayload::ResidentPages(pages)),
+        )
+    }
+
+    /// When the counts have to go out, if anything changed
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.since.map(|(since, _)| since + COUNT_INTERVAL)
+    }
+}
+
+fn round_out(range: &Range<u64>, bucket: u64) -> Range<u64> {
+    let start = range.start / bucket * bucket;
+    let end = range.end.div_ceil(bucket) * bucket;
+    start..end
+}
+
+/// Appends a bucket, merging it into the last run if that ends right where
+/// it starts, with as many resident bytes
+fn push_run(runs: &mut Vec<(Range<u64>, u64)>, bucket: Range<u64>, resident: u64) {
+    if let Some((last, last_resident)) = runs.last_mut() {
+        if last.end == bucket.start && *last_resident == resident {
+            last.end = bucket.end;
+            return;
+        }
+    }
+    runs.push((bucket, resident));
+}
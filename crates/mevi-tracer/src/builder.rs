@@ -0,0 +1,212 @@
+//! Tracing from another program: [TracerBuilder] sets it up, and the
+//! [Session] it starts hands out events as they happen.
+
+use std::{
+    sync::{
+        mpsc::{self, TryRecvError},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use mevi_common::{ClockAnchor, TimedEvent};
+
+use crate::{
+    channel::{self, Backpressure, ChannelStats, EventRx},
+    track::TrackFilter,
+    Backend, Control, EventSink, OnLimit, Options, Target, Tracer,
+};
+
+/// How many events can wait to be read before backpressure kicks in, like
+/// for the mevi binary
+const CAPACITY: usize = 4096;
+
+/// What to trace, and how, until [Self::start] starts tracing it
+pub struct TracerBuilder {
+    options: Options,
+    backpressure: Backpressure,
+    capacity: usize,
+}
+
+impl TracerBuilder {
+    /// Starts `command`, the program then its arguments, traced from its
+    /// very first instruction
+    pub fn spawn<I, S>(command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::new(Target::Spawn(command.into_iter().map(Into::into).collect()))
+    }
+
+    /// Attaches to every thread of the process `pid`, which we have to be
+    /// allowed to ptrace
+    pub fn attach(pid: u32) -> Self {
+        Self::new(Target::Attach(pid))
+    }
+
+    /// Takes everything else from `options`, with `target` left as it is
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = Options {
+            target: self.options.target,
+            ..options
+        };
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.options.backend = backend;
+        self
+    }
+
+    /// What happens when events come in faster than they're read. Blocking
+    /// (the default) stops the tracees until they are.
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// How many events can wait to be read before backpressure kicks in
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Whether to trace the processes tracees fork, or only their threads
+    pub fn follow_children(mut self, follow_children: bool) -> Self {
+        self.options.follow_children = follow_children;
+        self
+    }
+
+    /// Which mappings (or parts of them) to track at all
+    pub fn track(mut self, track: TrackFilter) -> Self {
+        self.options.track = track;
+        self
+    }
+
+    /// Resolve where each fault came from in the tracee's code
+    pub fn symbolicate(mut self, symbolicate: bool) -> Self {
+        self.options.symbolicate = symbolicate;
+        self
+    }
+
+    /// Stop tracing after `max_duration`, and do `on_limit` with the tracees
+    pub fn max_duration(mut self, max_duration: Duration, on_limit: OnLimit) -> Self {
+        self.options.max_duration = Some(max_duration);
+        self.options.on_limit = on_limit;
+        self
+    }
+
+    /// Starts (or attaches to) the target on a thread of its own, and
+    /// returns once it's traced
+    pub fn start(self) -> Result<Session> {
+        let (tx, rx) = channel::channel(self.capacity, self.backpressure);
+        let control = Control::default();
+        let (started_tx, started_rx) = mpsc::channel();
+        let tracer = std::thread::spawn({
+            let control = control.clone();
+            let options = self.options;
+            // ptrace only listens to the thread that attached, so it's
+            // traced from start to finish on there
+            move || {
+                let mut tracer = Tracer::new(tx, options)?.controlled(control);
+                _ = started_tx.send(());
+                tracer.run()
+            }
+        });
+        if started_rx.recv().is_err() {
+            // it gave up before tracing anything, and says why
+            join(tracer)?;
+            bail!("the tracer gave up before tracing anything");
+        }
+        Ok(Session {
+            rx,
+            control,
+            tracer,
+        })
+    }
+
+    /// Like [Self::start], for async code: events come as a stream, and
+    /// blocking once [Self::capacity] of them weren't read yet only blocks
+    /// the tracer's threads. Needs the `tokio` feature.
+    #[cfg(feature = "tokio")]
+    pub async fn events(self) -> Result<crate::EventStream> {
+        crate::EventStream::start(self.options, self.capacity).await
+    }
+
+    /// Traces on the calling thread until every tracee is gone, with events
+    /// going straight to `sink` (from whichever thread they happen on)
+    /// rather than to a [Session]. Backpressure is up to `sink`.
+    pub fn run(self, sink: impl EventSink + 'static) -> Result<Option<i32>> {
+        Tracer::new(sink, self.options)?.run()
+    }
+
+    fn new(target: Target) -> Self {
+        Self {
+            options: Options {
+                target,
+                ..Default::default()
+            },
+            backpressure: Backpressure::Block,
+            capacity: CAPACITY,
+        }
+    }
+}
+
+/// What's being traced, as an iterator of everything that happens to it.
+/// Iterating blocks until something does, and ends once every tracee is
+/// gone.
+pub struct Session {
+    rx: EventRx,
+    control: Control,
+    tracer: JoinHandle<Result<Option<i32>>>,
+}
+
+impl Session {
+    /// When the session started, for turning event timestamps into
+    /// durations or wall-clock time
+    pub fn anchor(&self) -> ClockAnchor {
+        self.rx.anchor()
+    }
+
+    /// How many events went through, and how many were dropped
+    pub fn stats(&self) -> Arc<ChannelStats> {
+        self.rx.stats()
+    }
+
+    /// Pausing, resuming, and evicting memory, cf. [Control]
+    pub fn control(&self) -> &Control {
+        &self.control
+    }
+
+    /// Like [Iterator::next], without waiting for something to happen.
+    /// Errors with [TryRecvError::Disconnected] once every tracee is gone.
+    pub fn try_next(&mut self) -> Result<TimedEvent, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// Waits for every tracee to be gone, throwing away events that weren't
+    /// read yet, and returns the exit code of the process we started (or
+    /// attached to), cf. [Tracer::run]
+    pub fn wait(self) -> Result<Option<i32>> {
+        while self.rx.recv().is_ok() {}
+        join(self.tracer)
+    }
+}
+
+impl Iterator for Session {
+    type Item = TimedEvent;
+
+    fn next(&mut self) -> Option<TimedEvent> {
+        self.rx.recv().ok()
+    }
+}
+
+fn join(tracer: JoinHandle<Result<Option<i32>>>) -> Result<Option<i32>> {
+    tracer.join().map_err(|_| eyre!("the tracer panicked"))?
+}
@@ -0,0 +1,162 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use mevi_common::{CgroupEvent, MeviEvent, Sample, TraceeId, TraceePayload};
+use tracing::{debug, info};
+
+use crate::{sampler::Sampler, sink::Events};
+
+/// The (v2) cgroup a process belongs to, as a directory under
+/// `/sys/fs/cgroup`
+pub(crate) fn dir(tid: TraceeId) -> Result<PathBuf> {
+    let cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", tid.0))?;
+    let rel = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| eyre!("{tid} isn't in a cgroup v2 hierarchy"))?;
+    Ok(Path::new("/sys/fs/cgroup").join(rel.trim_start_matches('/')))
+}
+
+/// Where [Transient] cgroups go: next to ours, unless we're in the root
+/// cgroup
+pub fn transient_parent() -> Result<PathBuf> {
+    let ours = dir(TraceeId(std::process::id() as _))?;
+    match ours.parent() {
+        Some(parent) if parent.starts_with("/sys/fs/cgroup") => Ok(parent.to_owned()),
+        _ => Ok(ours),
+    }
+}
+
+/// A cgroup we made for the program we start, to give it a memory limit.
+/// It goes away with us, if everything in it is gone by then.
+pub(crate) struct Transient {
+    dir: PathBuf,
+}
+
+impl Transient {
+    /// Makes a cgroup next to ours (we can't put it under ours: cgroups
+    /// with processes in them can't delegate memory control), that can only
+    /// use `max` bytes
+    pub(crate) fn create(max: u64) -> Result<Self> {
+        let parent = &transient_parent()?;
+        let subtree_control = parent.join("cgroup.subtree_control");
+        let controllers = std::fs::read_to_string(&subtree_control)?;
+        if !controllers.split_whitespace().any(|c| c == "memory") {
+            std::fs::write(&subtree_control, "+memory").wrap_err_with(|| {
+                format!("enabling the memory controller in {}", parent.display())
+            })?;
+        }
+
+        let dir = parent.join(format!("mevi-{}", std::process::id()));
+        std::fs::create_dir(&dir)
+            .wrap_err_with(|| format!("creating {} (are we root?)", dir.display()))?;
+        let cgroup = Self { dir };
+        std::fs::write(cgroup.dir.join("memory.max"), max.to_string())
+            .wrap_err_with(|| format!("limiting {} to {max} bytes", cgroup.dir.display()))?;
+        info!(
+            "created {} with a limit of {max} bytes",
+            cgroup.dir.display()
+        );
+        Ok(cgroup)
+    }
+
+    /// Moves `tid` in, along with whatever it starts from then on
+    pub(crate) fn add(&self, tid: TraceeId) -> Result<()> {
+        std::fs::write(self.dir.join("cgroup.procs"), tid.0.to_string())
+            .wrap_err_with(|| format!("moving {tid} to {}", self.dir.display()))
+    }
+}
+
+impl Drop for Transient {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir(&self.dir) {
+            debug!("couldn't remove {}: {e}", self.dir.display());
+        }
+    }
+}
+
+/// Reads the counters in `memory.events`
+pub(crate) fn read_events(dir: &Path) -> Result<Vec<(CgroupEvent, u64)>> {
+    let events = std::fs::read_to_string(dir.join("memory.events"))?;
+    let mut counts = vec![];
+    for line in events.lines() {
+        let Some((key, count)) = line.split_once(' ') else {
+            continue;
+        };
+        let event = match key {
+            "low" => CgroupEvent::Low,
+            "high" => CgroupEvent::High,
+            "max" => CgroupEvent::Max,
+            "oom" => CgroupEvent::Oom,
+            "oom_kill" => CgroupEvent::OomKill,
+            "oom_group_kill" => CgroupEvent::OomGroupKill,
+            _ => continue,
+        };
+        counts.push((event, count.trim().parse()?));
+    }
+    Ok(counts)
+}
+
+/// Reads a file like `memory.max`, which is either a number of bytes or "max"
+fn read_limit(path: &Path) -> Result<Option<u64>> {
+    match std::fs::read_to_string(path)?.trim() {
+        "max" => Ok(None),
+        bytes => Ok(Some(bytes.parse()?)),
+    }
+}
+
+/// Periodically reports the memory usage and limits of the tracee's cgroup,
+/// along with any `memory.events` that happened since the previous sample,
+/// so residency changes can be correlated with hitting limits.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration) -> Sampler {
+    let mut state: Option<(PathBuf, Vec<(CgroupEvent, u64)>)> = None;
+
+    Sampler::spawn("memory cgroup", tid, interval, move || {
+        if state.is_none() {
+            let dir = dir(tid)?;
+            // whatever happened before we got there isn't news
+            let events = read_events(&dir)?;
+            state = Some((dir, events));
+        }
+        let (dir, prev_events) = state.as_mut().unwrap();
+
+        tx.send(MeviEvent::TraceeEvent(
+            tid,
+            TraceePayload::Sample(Sample::CgroupMemory {
+                path: dir.to_string_lossy().into_owned(),
+                current: std::fs::read_to_string(dir.join("memory.current"))?
+                    .trim()
+                    .parse()?,
+                high: read_limit(&dir.join("memory.high"))?,
+                max: read_limit(&dir.join("memory.max"))?,
+            }),
+        ))?;
+
+        let events = read_events(dir)?;
+        for (event, count) in &events {
+            let prev_count = prev_events
+                .iter()
+                .find(|(prev_event, _)| prev_event == event)
+                .map(|(_, count)| *count)
+                .unwrap_or_default();
+            if *count > prev_count {
+                tx.send(MeviEvent::TraceeEvent(
+                    tid,
+                    TraceePayload::CgroupEvent {
+                        event: *event,
+                        count: count - prev_count,
+                    },
+                ))?;
+            }
+        }
+        *prev_events = events;
+
+        Ok(())
+    })
+}
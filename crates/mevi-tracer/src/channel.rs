@@ -0,0 +1,479 @@
+//! How events get from the tracer, its uffd threads and samplers to the
+//! relay. Faults come in by the thousands when a program touches fresh
+//! memory, so the hot path is a preallocated lock-free ring: senders and the
+//! receiver only take a lock when one of them has to wait for the other.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+};
+
+use color_eyre::{eyre::bail, Result};
+use crossbeam_queue::ArrayQueue;
+use mevi_common::{ClockAnchor, MeviEvent, TimedEvent, Timestamp, TraceeId, TraceePayload};
+
+use crate::coalesce;
+
+/// With [Backpressure::Coalesce], how many events we hold on to before
+/// blocking after all
+const OVERFLOW_BOUND: usize = 4096;
+
+/// What senders do when the relay can't keep up with them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for room, which stops the tracee too
+    Block,
+
+    /// Drop residency changes and samples, and tell the relay how many
+    /// were lost
+    Drop,
+
+    /// Hold on to events, merging contiguous faults, and only block once
+    /// too many pile up
+    Coalesce,
+}
+
+impl FromStr for Backpressure {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "block" => Ok(Self::Block),
+            "drop" => Ok(Self::Drop),
+            "coalesce" => Ok(Self::Coalesce),
+            other => {
+                bail!("unknown backpressure policy {other:?}, expected block, drop or coalesce")
+            }
+        }
+    }
+}
+
+/// How many events went through the channel, so we can tell how far behind
+/// the relay is
+#[derive(Default)]
+pub struct ChannelStats {
+    sent: AtomicU64,
+    received: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl ChannelStats {
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Events sent but not received yet
+    pub fn depth(&self) -> u64 {
+        // senders count events once they're in the channel, so the relay
+        // can get ahead of them for a bit
+        let received = self.received.load(Ordering::Relaxed);
+        self.sent.load(Ordering::SeqCst).saturating_sub(received)
+    }
+
+    /// Events that never made it to the relay, because of
+    /// [Backpressure::Drop]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// What senders and the receiver share
+struct Shared {
+    ring: ArrayQueue<TimedEvent>,
+
+    /// With [Backpressure::Drop], how many events we dropped per tracee and
+    /// haven't told the relay about yet
+    lost: Mutex<HashMap<TraceeId, u64>>,
+    any_lost: AtomicBool,
+
+    /// With [Backpressure::Coalesce], events that came in while the ring
+    /// was full. They're newer than anything in the ring.
+    overflow: Mutex<VecDeque<TimedEvent>>,
+    drained: Condvar,
+
+    /// Only taken by whoever has to wait: the receiver for events, senders
+    /// for room in the ring
+    lock: Mutex<()>,
+    ready: Condvar,
+    room: Condvar,
+    receiver_waiting: AtomicBool,
+    senders_waiting: AtomicUsize,
+
+    /// How many [EventTx] are around, the receiver is done once they're all
+    /// gone
+    senders: AtomicUsize,
+    closed: AtomicBool,
+
+    /// Set by [EventTx::hang_up]: the receiver is done once it's seen
+    /// everything before that, even if some senders are still around
+    hung_up: AtomicBool,
+
+    /// When the channel was created, which is as good a start of the session
+    /// as any
+    anchor: ClockAnchor,
+}
+
+impl Shared {
+    fn wake_receiver(&self) {
+        fence(Ordering::SeqCst);
+        if self.receiver_waiting.load(Ordering::SeqCst) {
+            let _lock = self.lock.lock().unwrap();
+            self.ready.notify_one();
+        }
+    }
+
+    fn wake_senders(&self) {
+        fence(Ordering::SeqCst);
+        if self.senders_waiting.load(Ordering::SeqCst) > 0 {
+            let _lock = self.lock.lock().unwrap();
+            self.room.notify_all();
+        }
+    }
+}
+
+/// Where the tracer, samplers etc. send events, to be picked up by the relay
+/// (or the recorder, or an exporter)
+pub struct EventTx {
+    stats: Arc<ChannelStats>,
+    shared: Arc<Shared>,
+    backpressure: Backpressure,
+}
+
+impl Clone for EventTx {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Self {
+            stats: self.stats.clone(),
+            shared: self.shared.clone(),
+            backpressure: self.backpressure,
+        }
+    }
+}
+
+impl Drop for EventTx {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // the receiver might be waiting for events that won't come
+            let _lock = self.shared.lock.lock().unwrap();
+            self.shared.ready.notify_all();
+        }
+    }
+}
+
+impl EventTx {
+    /// Stamps `event` with the current time, and sends it. Blocks if the
+    /// channel is full, unless the backpressure policy says otherwise.
+    /// Events that change the shape of things (mappings, exits...) always get
+    /// through, only residency changes, faults and samples can be dropped or
+    /// merged.
+    pub fn send(&self, event: MeviEvent) -> Result<(), mpsc::SendError<MeviEvent>> {
+        self.send_at(Timestamp::now(), event)
+    }
+
+    /// Like [Self::send], for events that were captured a bit earlier
+    pub fn send_at(
+        &self,
+        at: Timestamp,
+        event: MeviEvent,
+    ) -> Result<(), mpsc::SendError<MeviEvent>> {
+        let ev = TimedEvent { at, seq: 0, event };
+        let res = match self.backpressure {
+            Backpressure::Block => self.send_blocking(ev),
+            Backpressure::Drop if lossy(&ev.event) => self.send_or_drop(ev),
+            Backpressure::Drop => self.send_blocking(ev),
+            // everything goes through the overflow, so held events stay in
+            // order with the rest, but only lossy ones get merged
+            Backpressure::Coalesce => self.send_or_hold(ev),
+        };
+        res.map_err(|mpsc::SendError(ev)| mpsc::SendError(ev.event))
+    }
+
+    /// Ends the session, even though other senders may still be around:
+    /// those that can't tell their tracee went away, e.g. after we detached
+    /// from it. They can't send anything from then on.
+    pub fn hang_up(&self) {
+        self.shared.hung_up.store(true, Ordering::SeqCst);
+        let _lock = self.shared.lock.lock().unwrap();
+        self.shared.ready.notify_all();
+    }
+
+    pub fn stats(&self) -> Arc<ChannelStats> {
+        self.stats.clone()
+    }
+
+    /// Pushes to the ring if there's room. Doesn't wake the receiver.
+    fn try_push(&self, ev: TimedEvent) -> Result<(), mpsc::TrySendError<TimedEvent>> {
+        if self.shared.closed.load(Ordering::Relaxed) || self.shared.hung_up.load(Ordering::SeqCst)
+        {
+            return Err(mpsc::TrySendError::Disconnected(ev));
+        }
+        self.shared
+            .ring
+            .push(ev)
+            .map_err(mpsc::TrySendError::Full)?;
+        self.stats.sent.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn try_send(&self, ev: TimedEvent) -> Result<(), mpsc::TrySendError<TimedEvent>> {
+        self.try_push(ev)?;
+        self.shared.wake_receiver();
+        Ok(())
+    }
+
+    fn send_blocking(&self, ev: TimedEvent) -> Result<(), mpsc::SendError<TimedEvent>> {
+        let mut ev = match self.try_send(ev) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::TrySendError::Full(ev)) => ev,
+            Err(mpsc::TrySendError::Disconnected(ev)) => return Err(mpsc::SendError(ev)),
+        };
+
+        let mut lock = self.shared.lock.lock().unwrap();
+        self.shared.senders_waiting.fetch_add(1, Ordering::SeqCst);
+        let res = loop {
+            match self.try_push(ev) {
+                Ok(()) => break Ok(()),
+                Err(mpsc::TrySendError::Full(full)) => {
+                    ev = full;
+                    lock = self.shared.room.wait(lock).unwrap();
+                }
+                Err(mpsc::TrySendError::Disconnected(ev)) => break Err(mpsc::SendError(ev)),
+            }
+        };
+        self.shared.senders_waiting.fetch_sub(1, Ordering::SeqCst);
+        drop(lock);
+
+        self.shared.wake_receiver();
+        res
+    }
+
+    fn send_or_drop(&self, ev: TimedEvent) -> Result<(), mpsc::SendError<TimedEvent>> {
+        if self.shared.any_lost.load(Ordering::Relaxed) {
+            self.send_lost()?;
+        }
+
+        match self.try_send(ev) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(ev)) => {
+                if let MeviEvent::TraceeEvent(tid, _) = ev.event {
+                    *self.shared.lost.lock().unwrap().entry(tid).or_default() += 1;
+                    self.shared.any_lost.store(true, Ordering::Relaxed);
+                }
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Disconnected(ev)) => Err(mpsc::SendError(ev)),
+        }
+    }
+
+    /// Tells the relay about events we dropped, as far as there's room
+    fn send_lost(&self) -> Result<(), mpsc::SendError<TimedEvent>> {
+        let mut lost = self.shared.lost.lock().unwrap();
+        while let Some((&tid, &count)) = lost.iter().next() {
+            let ev = TimedEvent {
+                at: Timestamp::now(),
+                seq: 0,
+                event: MeviEvent::TraceeEvent(tid, TraceePayload::Lost { count }),
+            };
+            match self.try_send(ev) {
+                Ok(()) => {
+                    lost.remove(&tid);
+                }
+                // next time, then
+                Err(mpsc::TrySendError::Full(_)) => return Ok(()),
+                Err(mpsc::TrySendError::Disconnected(ev)) => return Err(mpsc::SendError(ev)),
+            }
+        }
+        self.shared.any_lost.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn send_or_hold(&self, ev: TimedEvent) -> Result<(), mpsc::SendError<TimedEvent>> {
+        let mut overflow = self.shared.overflow.lock().unwrap();
+        let ev = if overflow.is_empty() {
+            match self.try_push(ev) {
+                Ok(()) => {
+                    drop(overflow);
+                    self.shared.wake_receiver();
+                    return Ok(());
+                }
+                Err(mpsc::TrySendError::Full(ev)) => ev,
+                Err(mpsc::TrySendError::Disconnected(ev)) => return Err(mpsc::SendError(ev)),
+            }
+        } else {
+            ev
+        };
+
+        // the relay is behind: merge into the latest held event if we can
+        if let (
+            Some(TimedEvent {
+                event: MeviEvent::TraceeEvent(held_tid, held),
+                ..
+            }),
+            MeviEvent::TraceeEvent(tid, payload),
+        ) = (overflow.back_mut(), &ev.event)
+        {
+            if held_tid == tid && lossy(&ev.event) && coalesce::merge(held, payload) {
+                return Ok(());
+            }
+        }
+
+        while overflow.len() >= OVERFLOW_BOUND {
+            if self.shared.closed.load(Ordering::Relaxed) {
+                return Err(mpsc::SendError(ev));
+            }
+            overflow = self.shared.drained.wait(overflow).unwrap();
+        }
+        overflow.push_back(ev);
+        self.stats.sent.fetch_add(1, Ordering::SeqCst);
+        drop(overflow);
+
+        self.shared.wake_receiver();
+        Ok(())
+    }
+}
+
+/// Whether an event can be dropped or merged without the relay's picture of
+/// a tracee falling apart
+fn lossy(ev: &MeviEvent) -> bool {
+    matches!(
+        ev,
+        MeviEvent::TraceeEvent(
+            _,
+            TraceePayload::MemStateChange { .. }
+                | TraceePayload::PageFault { .. }
+                | TraceePayload::OverlaySample { .. }
+                | TraceePayload::Sample(_)
+        )
+    )
+}
+
+pub struct EventRx {
+    stats: Arc<ChannelStats>,
+    shared: Arc<Shared>,
+}
+
+impl EventRx {
+    pub fn recv(&self) -> Result<TimedEvent, mpsc::RecvError> {
+        loop {
+            match self.try_recv() {
+                Ok(ev) => return Ok(ev),
+                Err(mpsc::TryRecvError::Disconnected) => return Err(mpsc::RecvError),
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+
+            let lock = self.shared.lock.lock().unwrap();
+            self.shared.receiver_waiting.store(true, Ordering::SeqCst);
+            fence(Ordering::SeqCst);
+            // whoever sends next sees we're waiting, and can't notify us
+            // before we're actually waiting, since we're holding the lock
+            let idle = self.stats.depth() == 0
+                && self.shared.senders.load(Ordering::SeqCst) > 0
+                && !self.shared.hung_up.load(Ordering::SeqCst);
+            let lock = if idle {
+                self.shared.ready.wait(lock).unwrap()
+            } else {
+                lock
+            };
+            self.shared.receiver_waiting.store(false, Ordering::SeqCst);
+            drop(lock);
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<TimedEvent, mpsc::TryRecvError> {
+        let ev = self.pop_ring().or_else(|| self.pop_overflow());
+        match ev {
+            Some(ev) => {
+                self.stats.received.fetch_add(1, Ordering::Relaxed);
+                Ok(ev)
+            }
+            // senders might have pushed things right before leaving
+            None if (self.shared.senders.load(Ordering::SeqCst) == 0
+                || self.shared.hung_up.load(Ordering::SeqCst))
+                && self.stats.depth() == 0 =>
+            {
+                Err(mpsc::TryRecvError::Disconnected)
+            }
+            None => Err(mpsc::TryRecvError::Empty),
+        }
+    }
+
+    fn pop_ring(&self) -> Option<TimedEvent> {
+        let ev = self.shared.ring.pop()?;
+        self.shared.wake_senders();
+        Some(ev)
+    }
+
+    fn pop_overflow(&self) -> Option<TimedEvent> {
+        let mut overflow = self.shared.overflow.lock().unwrap();
+        // coalescing senders only push to the ring while holding this lock,
+        // so if it's still empty now, the overflow is next
+        if let Some(ev) = self.shared.ring.pop() {
+            drop(overflow);
+            self.shared.wake_senders();
+            return Some(ev);
+        }
+        let ev = overflow.pop_front()?;
+        self.shared.drained.notify_one();
+        Some(ev)
+    }
+
+    /// When the session started, for turning event timestamps into durations
+    /// or wall-clock time
+    pub fn anchor(&self) -> ClockAnchor {
+        self.shared.anchor
+    }
+
+    pub fn stats(&self) -> Arc<ChannelStats> {
+        self.stats.clone()
+    }
+}
+
+impl Drop for EventRx {
+    fn drop(&mut self) {
+        // wake up senders waiting for room, there won't be any
+        self.shared.closed.store(true, Ordering::Relaxed);
+        {
+            let _lock = self.shared.lock.lock().unwrap();
+            self.shared.room.notify_all();
+        }
+        let _overflow = self.shared.overflow.lock().unwrap();
+        self.shared.drained.notify_all();
+    }
+}
+
+pub fn channel(capacity: usize, backpressure: Backpressure) -> (EventTx, EventRx) {
+    let stats = Arc::new(ChannelStats::default());
+    let shared = Arc::new(Shared {
+        ring: ArrayQueue::new(capacity),
+        lost: Default::default(),
+        any_lost: Default::default(),
+        overflow: Default::default(),
+        drained: Default::default(),
+        lock: Default::default(),
+        ready: Default::default(),
+        room: Default::default(),
+        receiver_waiting: Default::default(),
+        senders_waiting: Default::default(),
+        senders: AtomicUsize::new(1),
+        closed: Default::default(),
+        hung_up: Default::default(),
+        anchor: ClockAnchor::now(),
+    });
+    (
+        EventTx {
+            stats: stats.clone(),
+            shared: shared.clone(),
+            backpressure,
+        },
+        EventRx { stats, shared },
+    )
+}
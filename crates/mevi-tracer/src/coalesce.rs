@@ -0,0 +1,115 @@
+use std::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use mevi_common::{MeviEvent, Timestamp, TraceeId, TraceePayload};
+
+use crate::sink::Events;
+
+/// Holds on to page-level events for a little while, so that a run of
+/// contiguous ones (say, from a memset over a fresh mapping) goes out as a
+/// single range instead of one event per page.
+pub(crate) struct Coalescer {
+    tid: TraceeId,
+
+    /// How long an event can wait for neighbors, zero to send everything
+    /// right away
+    window: Duration,
+
+    /// When the first event in the run happened, which is also the timestamp
+    /// the merged event goes out with
+    pending: Option<(Instant, Timestamp, TraceePayload)>,
+}
+
+impl Coalescer {
+    pub(crate) fn new(tid: TraceeId, window: Duration) -> Self {
+        Self {
+            tid,
+            window,
+            pending: None,
+        }
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        tx: &Events,
+        payload: TraceePayload,
+    ) -> Result<(), mpsc::SendError<MeviEvent>> {
+        if let Some((since, _, pending)) = &mut self.pending {
+            if since.elapsed() < self.window && merge(pending, &payload) {
+                return Ok(());
+            }
+        }
+        self.flush(tx)?;
+
+        if self.window.is_zero() {
+            tx.send(MeviEvent::TraceeEvent(self.tid, payload))?;
+        } else {
+            self.pending = Some((Instant::now(), Timestamp::now(), payload));
+        }
+        Ok(())
+    }
+
+    /// Sends whatever we're holding on to
+    pub(crate) fn flush(&mut self, tx: &Events) -> Result<(), mpsc::SendError<MeviEvent>> {
+        if let Some((_, at, payload)) = self.pending.take() {
+            tx.send_at(at, MeviEvent::TraceeEvent(self.tid, payload))?;
+        }
+        Ok(())
+    }
+
+    /// When the pending event has to go out, if there's one
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.pending
+            .as_ref()
+            .map(|(since, _, _)| *since + self.window)
+    }
+}
+
+/// Grows `into` to cover `next` if they're the same kind of transition and
+/// their ranges touch
+pub(crate) fn merge(into: &mut TraceePayload, next: &TraceePayload) -> bool {
+    let (a, b) = match (into, next) {
+        (
+            TraceePayload::PageFault {
+                range: a,
+                access,
+                thread,
+                source,
+                site,
+            },
+            TraceePayload::PageFault {
+                range: b,
+                access: next_access,
+                thread: next_thread,
+                source: next_source,
+                site: next_site,
+            },
+        ) if access == next_access
+            && thread == next_thread
+            && source == next_source
+            && site == next_site =>
+        {
+            (a, b)
+        }
+        (
+            TraceePayload::MemStateChange { range: a, state },
+            TraceePayload::MemStateChange {
+                range: b,
+                state: next_state,
+            },
+        ) if state == next_state => (a, b),
+        _ => return false,
+    };
+
+    // memsets go up, but stacks grow down
+    if a.end == b.start {
+        a.end = b.end;
+    } else if b.end == a.start {
+        a.start = b.start;
+    } else {
+        return false;
+    }
+    true
+}
@@ -0,0 +1,129 @@
+//! Telling a tracer what to do while it traces, from another thread.
+//!
+//! Pausing freezes every tracee, so whatever its memory looks like stays put
+//! while someone takes a look, until it's resumed. They're attached without
+//! `PTRACE_SEIZE`, so there's no `PTRACE_INTERRUPT`: they get a SIGSTOP
+//! each, and the tracer holds on to their stops until it's told to resume.
+//!
+//! Evicting has a process `madvise` a range away, cf. [crate::evict].
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{JoinHandle, Thread},
+};
+
+use nix::sys::{
+    pthread::{pthread_kill, pthread_self},
+    signal::Signal,
+};
+
+use crate::{evict::Eviction, limits};
+
+/// What a tracer is asked to do while it traces, cf. [crate::Tracer::controlled]
+#[derive(Clone, Default)]
+pub struct Control(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    paused: AtomicBool,
+
+    /// Asked for, but not done yet
+    evictions: Mutex<Vec<Eviction>>,
+
+    /// Something was asked of the tracer that it hasn't looked at yet
+    changed: AtomicBool,
+
+    /// What wakes the tracer up, while it's tracing
+    waker: Mutex<Option<Thread>>,
+}
+
+impl Control {
+    /// Whether the tracees should be frozen
+    pub fn paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        if self.0.paused.swap(paused, Ordering::SeqCst) != paused {
+            self.wake();
+        }
+    }
+
+    pub fn evict(&self, eviction: Eviction) {
+        self.0.evictions.lock().unwrap().push(eviction);
+        self.wake();
+    }
+
+    /// Evictions asked for since the last time
+    pub(crate) fn take_evictions(&self) -> Vec<Eviction> {
+        std::mem::take(&mut *self.0.evictions.lock().unwrap())
+    }
+
+    /// Gets the tracer out of `waitpid` so it finds out, if it's around
+    fn wake(&self) {
+        self.0.changed.store(true, Ordering::SeqCst);
+        if let Some(waker) = &*self.0.waker.lock().unwrap() {
+            waker.unpark();
+        }
+    }
+
+    /// The tracer is about to look at what was asked of it, there's no need
+    /// to wake it up anymore
+    pub(crate) fn acknowledge(&self) {
+        self.0.changed.store(false, Ordering::SeqCst);
+    }
+
+    /// Wakes the calling thread (the tracer) up out of `waitpid` whenever
+    /// something is asked of it, until the returned guard is dropped. It
+    /// has to be [limits::interruptible] by then.
+    pub(crate) fn watch(&self) -> Watching {
+        let tracer = pthread_self();
+        let done = Arc::new(AtomicBool::new(false));
+        let waker = std::thread::spawn({
+            let inner = self.0.clone();
+            let done = done.clone();
+            move || {
+                while !done.load(Ordering::SeqCst) {
+                    if inner.changed.load(Ordering::SeqCst) {
+                        // until it acknowledges, it might not have been
+                        // waiting yet
+                        _ = pthread_kill(tracer, Signal::SIGALRM);
+                        std::thread::park_timeout(limits::NUDGE_INTERVAL);
+                    } else {
+                        std::thread::park();
+                    }
+                }
+            }
+        });
+        *self.0.waker.lock().unwrap() = Some(waker.thread().clone());
+        // in case something was asked before it could be woken up
+        waker.thread().unpark();
+        Watching {
+            inner: self.0.clone(),
+            done,
+            waker: Some(waker),
+        }
+    }
+}
+
+/// Stops waking the tracer up once it's done tracing
+pub(crate) struct Watching {
+    inner: Arc<Inner>,
+    done: Arc<AtomicBool>,
+    waker: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watching {
+    fn drop(&mut self) {
+        *self.inner.waker.lock().unwrap() = None;
+        self.done.store(true, Ordering::SeqCst);
+        if let Some(waker) = self.waker.take() {
+            waker.thread().unpark();
+            // it mustn't signal a thread that's gone
+            _ = waker.join();
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Evicting a range of a tracee's memory on request, by having it call
+//! `madvise` on it, then watching how much of it comes back and how fast:
+//! that's what touching supposedly cold memory again costs.
+
+use std::{
+    ops::Range,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{eyre::bail, Result};
+use humansize::{make_format, BINARY};
+use mevi_common::{MemState, MeviEvent, TraceeId, TraceePayload};
+use rangemap::RangeSet;
+use tracing::{debug, info};
+
+use crate::{pagemap, sink::Events};
+
+/// How often we check what came back
+const REFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long we keep checking, if not everything comes back
+const REFAULT_WATCH: Duration = Duration::from_secs(300);
+
+/// Which `madvise` to evict with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// Off to swap (or back to the file), contents kept. Needs Linux 5.4+.
+    PageOut,
+
+    /// Dropped: anonymous private memory reads back as zeroes after that
+    DontNeed,
+}
+
+impl FromStr for Advice {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pageout" => Ok(Self::PageOut),
+            "dontneed" => Ok(Self::DontNeed),
+            other => bail!("unknown advice {other:?}, expected pageout or dontneed"),
+        }
+    }
+}
+
+impl Advice {
+    pub(crate) fn raw(self) -> i32 {
+        match self {
+            Self::PageOut => libc::MADV_PAGEOUT,
+            Self::DontNeed => libc::MADV_DONTNEED,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PageOut => "pageout",
+            Self::DontNeed => "dontneed",
+        }
+    }
+}
+
+/// A range of a traced process to evict
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    pub tid: TraceeId,
+    pub range: Range<u64>,
+    pub advice: Advice,
+}
+
+/// Reports what's left of `range` once the tracee's `madvise` returned, then
+/// what comes back as it gets touched again, until all of it has, it's
+/// gone, or we've waited long enough. Refaults from swap never reach a
+/// userfaultfd, hence the polling.
+pub(crate) fn watch(tid: TraceeId, range: Range<u64>, tx: Events) {
+    std::thread::spawn(move || {
+        let formatter = make_format(BINARY);
+        let start = Instant::now();
+        let report = |range: &Range<u64>, state: MemState| {
+            let ev = TraceePayload::MemStateChange {
+                range: range.clone(),
+                state,
+            };
+            tx.send(MeviEvent::TraceeEvent(tid, ev))
+        };
+
+        let mut evicted: Option<RangeSet<u64>> = None;
+        loop {
+            let map = match pagemap::scan_range(tid, range.clone()) {
+                Ok(map) => map,
+                Err(e) => {
+                    debug!("{tid} stopped watching {range:x?} come back: {e}");
+                    return;
+                }
+            };
+            match &mut evicted {
+                None => {
+                    // whatever isn't resident right after is what we evicted
+                    let mut set = RangeSet::new();
+                    for (r, state) in map.iter().filter(|(_, state)| !state.is_resident()) {
+                        if report(r, *state).is_err() {
+                            return;
+                        }
+                        set.insert(r.clone());
+                    }
+                    let size = set.iter().map(|r| r.end - r.start).sum::<u64>();
+                    info!("{tid} evicted {} of {range:x?}", formatter(size));
+                    evicted = Some(set);
+                }
+                Some(evicted) => {
+                    for (r, state) in map.iter().filter(|(_, state)| state.is_resident()) {
+                        if !evicted.overlaps(r) {
+                            continue;
+                        }
+                        if report(r, *state).is_err() {
+                            return;
+                        }
+                        evicted.remove(r.clone());
+                    }
+                }
+            }
+
+            let left = evicted
+                .iter()
+                .flat_map(|set| set.iter())
+                .map(|r| r.end - r.start)
+                .sum::<u64>();
+            if left == 0 {
+                info!("{tid} {range:x?} is all back after {:?}", start.elapsed());
+                return;
+            }
+            if start.elapsed() >= REFAULT_WATCH {
+                info!(
+                    "{tid} {} of {range:x?} still isn't back after {REFAULT_WATCH:?}",
+                    formatter(left)
+                );
+                return;
+            }
+            std::thread::sleep(REFAULT_INTERVAL);
+        }
+    });
+}
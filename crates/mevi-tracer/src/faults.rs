@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use mevi_common::{MeviEvent, Sample, TraceeId, TraceePayload};
+
+use crate::{sampler::Sampler, sink::Events};
+
+/// Periodically reports how many minor and major faults a process took,
+/// from `/proc/<pid>/stat`. Faults we resolve through userfaultfd are always
+/// minor: major ones (reading from disk, swapping in) never reach us.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration) -> Sampler {
+    let mut prev: Option<(u64, u64)> = None;
+
+    Sampler::spawn("fault counts", tid, interval, move || {
+        let stat = procfs::process::Process::new(tid.0 as _)?.stat()?;
+        let counts = (stat.minflt, stat.majflt);
+
+        if let Some((prev_minor, prev_major)) = prev {
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::Sample(Sample::Faults {
+                    minor: counts.0.saturating_sub(prev_minor),
+                    major: counts.1.saturating_sub(prev_major),
+                }),
+            ))?;
+        }
+        prev = Some(counts);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,83 @@
+use std::{collections::HashMap, fs::File, ops::Range, os::unix::fs::FileExt, time::Duration};
+
+use color_eyre::{eyre::bail, Result};
+use mevi_common::{MeviEvent, Overlay, TraceeId, TraceePayload};
+use procfs::process::{MemoryPageFlags, PageInfo};
+use rangemap::RangeSet;
+
+use crate::{pagemap, sampler::Sampler, sink::Events, track::TrackFilter};
+
+const BITMAP_PATH: &str = "/sys/kernel/mm/page_idle/bitmap";
+
+/// Periodically reports which resident pages haven't been accessed since the
+/// previous sample, using idle page tracking, cf.
+/// https://www.kernel.org/doc/html/latest/admin-guide/mm/idle_page_tracking.html
+///
+/// This needs CAP_SYS_ADMIN, both to see page frame numbers in pagemap and
+/// to access the bitmap.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration, track: TrackFilter) -> Sampler {
+    // nothing is marked idle before the first sample
+    let mut marked = false;
+
+    Sampler::spawn("idle pages", tid, interval, move || {
+        let bitmap = File::options().read(true).write(true).open(BITMAP_PATH)?;
+
+        let mut pages: Vec<(Range<u64>, u64)> = vec![];
+        pagemap::for_each_page(tid, &track, |page, pi| {
+            if let PageInfo::MemoryPage(mp) = pi {
+                if mp.contains(MemoryPageFlags::PRESENT) {
+                    pages.push((page, mp.get_page_frame_number().0));
+                }
+            }
+        })?;
+        if pages.iter().any(|(_, pfn)| *pfn == 0) {
+            bail!("pagemap doesn't show page frame numbers, are we missing CAP_SYS_ADMIN?");
+        }
+
+        if marked {
+            let mut words: HashMap<u64, u64> = HashMap::new();
+            let mut idle = RangeSet::new();
+            for (page, pfn) in &pages {
+                let word = match words.get(&(pfn / 64)) {
+                    Some(word) => *word,
+                    None => {
+                        let word = read_word(&bitmap, pfn / 64)?;
+                        words.insert(pfn / 64, word);
+                        word
+                    }
+                };
+                // the kernel clears the bit whenever the page gets accessed
+                if word & (1 << (pfn % 64)) != 0 {
+                    idle.insert(page.clone());
+                }
+            }
+
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::OverlaySample {
+                    overlay: Overlay::Idle,
+                    ranges: idle.into_iter().collect(),
+                },
+            ))?;
+        }
+
+        // now mark everything idle again. bits we write as zero are left
+        // alone, so we can't clobber other processes' pages.
+        let mut masks: HashMap<u64, u64> = HashMap::new();
+        for (_, pfn) in &pages {
+            *masks.entry(pfn / 64).or_default() |= 1 << (pfn % 64);
+        }
+        for (word_idx, mask) in masks {
+            bitmap.write_all_at(&mask.to_ne_bytes(), word_idx * 8)?;
+        }
+        marked = true;
+
+        Ok(())
+    })
+}
+
+fn read_word(bitmap: &File, word_idx: u64) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    bitmap.read_exact_at(&mut buf, word_idx * 8)?;
+    Ok(u64::from_ne_bytes(buf))
+}
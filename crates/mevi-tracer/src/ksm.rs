@@ -0,0 +1,60 @@
+use std::{fs::File, os::unix::fs::FileExt, time::Duration};
+
+use color_eyre::{eyre::bail, Result};
+use mevi_common::{MeviEvent, Overlay, TraceeId, TraceePayload};
+use procfs::process::{MemoryPageFlags, PageInfo};
+use rangemap::RangeSet;
+
+use crate::{pagemap, sampler::Sampler, sink::Events, track::TrackFilter};
+
+const KPAGEFLAGS_PATH: &str = "/proc/kpageflags";
+
+/// KPF_KSM, cf. https://www.kernel.org/doc/html/latest/admin-guide/mm/pagemap.html
+const KPF_KSM: u64 = 1 << 21;
+
+/// Periodically reports which resident pages KSM merged with identical pages
+/// (from this process or others), which is why RSS can end up lower than
+/// the amount of memory that was touched.
+///
+/// Like idle page tracking, this needs CAP_SYS_ADMIN to see page frame
+/// numbers and read `/proc/kpageflags`.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration, track: TrackFilter) -> Sampler {
+    Sampler::spawn("KSM pages", tid, interval, move || {
+        let kpageflags = File::open(KPAGEFLAGS_PATH)?;
+
+        let mut pages = vec![];
+        pagemap::for_each_page(tid, &track, |page, pi| {
+            if let PageInfo::MemoryPage(mp) = pi {
+                if mp.contains(MemoryPageFlags::PRESENT) {
+                    pages.push((page, mp.get_page_frame_number().0));
+                }
+            }
+        })?;
+        if pages.iter().any(|(_, pfn)| *pfn == 0) {
+            bail!("pagemap doesn't show page frame numbers, are we missing CAP_SYS_ADMIN?");
+        }
+
+        let mut merged = RangeSet::new();
+        for (page, pfn) in pages {
+            if read_flags(&kpageflags, pfn)? & KPF_KSM != 0 {
+                merged.insert(page);
+            }
+        }
+
+        tx.send(MeviEvent::TraceeEvent(
+            tid,
+            TraceePayload::OverlaySample {
+                overlay: Overlay::Deduplicated,
+                ranges: merged.into_iter().collect(),
+            },
+        ))?;
+
+        Ok(())
+    })
+}
+
+fn read_flags(kpageflags: &File, pfn: u64) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    kpageflags.read_exact_at(&mut buf, pfn * 8)?;
+    Ok(u64::from_ne_bytes(buf))
+}
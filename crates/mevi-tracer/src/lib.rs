@@ -0,0 +1,95 @@
+//! What the mevi binary traces with, for tools that want to know what a
+//! program does with its memory without running mevi itself.
+//!
+//! To trace a program from start to finish:
+//!
+//! ```no_run
+//! use mevi_tracer::TracerBuilder;
+//!
+//! let mut session = TracerBuilder::spawn(["ls", "-l"]).start()?;
+//! let start = session.anchor().monotonic;
+//! for ev in &mut session {
+//!     println!("{:?} {:?}", ev.at.since(start), ev.event);
+//! }
+//! println!("exited with {:?}", session.wait()?);
+//! # Ok::<_, color_eyre::Report>(())
+//! ```
+//!
+//! Or to attach to a process that's already running, with
+//! [TracerBuilder::attach]. Events are the same as mevi streams and records,
+//! cf. [mevi_common::MeviEvent].
+//!
+//! To get them some other way than through a [Session], e.g. in a callback,
+//! or straight to a file, implement [EventSink] (closures already do) and
+//! pass it to [TracerBuilder::run]:
+//!
+//! ```no_run
+//! use mevi_common::{MeviEvent, Timestamp};
+//! use mevi_tracer::TracerBuilder;
+//!
+//! TracerBuilder::attach(1234).run(|at: Timestamp, ev: MeviEvent| {
+//!     println!("{at:?} {ev:?}");
+//! })?;
+//! # Ok::<_, color_eyre::Report>(())
+//! ```
+//!
+//! From async code, with the `tokio` feature, [TracerBuilder::events] gives
+//! a `Stream` of events instead:
+//!
+//! ```ignore
+//! use futures_util::StreamExt;
+//! use mevi_tracer::TracerBuilder;
+//!
+//! let mut events = TracerBuilder::spawn(["ls", "-l"]).events().await?;
+//! while let Some(ev) = events.next().await {
+//!     println!("{:?}", ev.event);
+//! }
+//! println!("exited with {:?}", events.wait().await?);
+//! ```
+//!
+//! To drive a [Tracer] yourself, see [Tracer::new]: the mevi binary has it
+//! send to a [channel::channel].
+//!
+//! Tracing is Linux-only (x86_64), and takes over a few process-wide
+//! things while it goes on: SIGALRM is used to wake the tracer up (whatever
+//! handled it before does again once every tracer is done), and tracees
+//! connect to a Unix socket in `/tmp`. The tracer only waits for its own
+//! tracees, unless [Options::subreaper] says every child is its to reap.
+
+mod annotations;
+pub mod buckets;
+mod builder;
+pub mod cgroup;
+pub mod channel;
+mod coalesce;
+mod control;
+pub mod evict;
+mod faults;
+mod idle;
+mod ksm;
+mod limits;
+mod numa;
+mod oom;
+mod options;
+mod pagemap;
+mod pty;
+mod rollup;
+mod sampler;
+mod sink;
+mod smaps;
+mod softdirty;
+#[cfg(feature = "tokio")]
+mod stream;
+mod symbols;
+mod thp;
+mod tracer;
+pub mod track;
+mod userfault;
+
+pub use builder::{Session, TracerBuilder};
+pub use control::Control;
+pub use options::{Backend, Env, InjectTrigger, OnLimit, Options, RunAs, Target};
+pub use sink::EventSink;
+#[cfg(feature = "tokio")]
+pub use stream::EventStream;
+pub use tracer::{supported_features, Tracer};
@@ -0,0 +1,174 @@
+//! `--max-duration` and `--max-events`: a watchdog that tells the tracer
+//! when it's time to stop. The tracer spends most of its time blocked in
+//! `waitpid`, which a signal interrupts.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use nix::sys::{
+    pthread::{pthread_kill, pthread_self},
+    signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+};
+use tracing::info;
+
+/// How often we check whether a limit was hit
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often whoever wants the tracer's attention signals it again, in case
+/// it wasn't waiting yet the last time
+pub(crate) const NUDGE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What SIGALRM did before tracers took it over, and how many of them are
+/// using it still: once they're all done, it does that again
+static PREVIOUS: Mutex<Option<(SigAction, usize)>> = Mutex::new(None);
+
+/// Watches the limits on behalf of the calling thread, and interrupts it
+/// once one is hit, until it's dropped.
+pub(crate) struct Watchdog {
+    hit: Arc<AtomicBool>,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub(crate) fn spawn(
+        max_duration: Option<Duration>,
+        max_events: Option<u64>,
+        sent: Arc<AtomicU64>,
+    ) -> Self {
+        let hit = Arc::new(AtomicBool::new(false));
+        let done = Arc::new(AtomicBool::new(false));
+        let watcher = pthread_self();
+        let start = Instant::now();
+        let thread = std::thread::spawn({
+            let hit = hit.clone();
+            let done = done.clone();
+            move || {
+                loop {
+                    std::thread::park_timeout(CHECK_INTERVAL);
+                    if done.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    if max_duration.is_some_and(|max| start.elapsed() >= max) {
+                        info!("traced for {:?}, that's enough", start.elapsed());
+                    } else if max_events.is_some_and(|max| sent.load(Ordering::Relaxed) >= max) {
+                        info!("got {} events, that's enough", sent.load(Ordering::Relaxed));
+                    } else {
+                        continue;
+                    }
+                    break;
+                }
+                hit.store(true, Ordering::SeqCst);
+                // the tracer drops us once it noticed
+                while !done.load(Ordering::SeqCst) {
+                    _ = pthread_kill(watcher, Signal::SIGALRM);
+                    std::thread::park_timeout(NUDGE_INTERVAL);
+                }
+            }
+        });
+        Self {
+            hit,
+            done,
+            thread: Some(thread),
+        }
+    }
+
+    /// Whether a limit was hit
+    pub(crate) fn hit(&self) -> bool {
+        self.hit.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            // it mustn't signal a thread that's gone
+            _ = thread.join();
+        }
+    }
+}
+
+/// Makes SIGALRM interrupt the calling thread (the tracer) while it waits
+/// for tracees, cf. [Interruptible::wait], rather than killing us. Anywhere
+/// else, the signal waits until then, rather than having some other syscall
+/// fail with EINTR.
+///
+/// The handler is process-wide, so programs we're part of can't use SIGALRM
+/// while we trace. They get their own handler back once we're done.
+pub(crate) fn interruptible() -> Result<Interruptible> {
+    extern "C" fn interrupt(_: libc::c_int) {}
+    // no SA_RESTART, so that whatever we're blocked on returns EINTR
+    let action = SigAction::new(
+        SigHandler::Handler(interrupt),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    let mask = SigSet::thread_get_mask()?;
+    {
+        let mut previous = PREVIOUS.lock().unwrap();
+        match previous.as_mut() {
+            Some((_, users)) => *users += 1,
+            None => {
+                let old = unsafe { sigaction(Signal::SIGALRM, &action)? };
+                *previous = Some((old, 1));
+            }
+        }
+    }
+
+    // from here on, dropping it puts everything back
+    let interruptible = Interruptible { mask };
+    alarm().thread_block()?;
+    Ok(interruptible)
+}
+
+fn alarm() -> SigSet {
+    let mut set = SigSet::empty();
+    set.add(Signal::SIGALRM);
+    set
+}
+
+/// SIGALRM interrupts the tracer's waits, until this is dropped, then it
+/// does whatever it did before
+pub(crate) struct Interruptible {
+    /// What the thread blocked before
+    mask: SigSet,
+}
+
+impl Interruptible {
+    /// Runs `f`, which blocks until something happens to a tracee, or until
+    /// SIGALRM comes in. One that comes in right before `f` blocks doesn't
+    /// interrupt it, so whoever sends it keeps sending it until the tracer
+    /// notices.
+    pub(crate) fn wait<T>(&self, f: impl FnOnce() -> T) -> T {
+        _ = alarm().thread_unblock();
+        let res = f();
+        _ = alarm().thread_block();
+        res
+    }
+}
+
+impl Drop for Interruptible {
+    fn drop(&mut self) {
+        // one might still be pending, it's ours to handle
+        _ = alarm().thread_unblock();
+        _ = self.mask.thread_set_mask();
+
+        let mut previous = PREVIOUS.lock().unwrap();
+        match previous.take() {
+            Some((action, 1)) => {
+                _ = unsafe { sigaction(Signal::SIGALRM, &action) };
+            }
+            Some((action, users)) => *previous = Some((action, users - 1)),
+            None => {}
+        }
+    }
+}
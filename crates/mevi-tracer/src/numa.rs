@@ -0,0 +1,77 @@
+use std::{collections::HashMap, ops::Range, time::Duration};
+
+use color_eyre::Result;
+use mevi_common::{MeviEvent, Sample, TraceeId, TraceePayload};
+
+use crate::{sampler::Sampler, sink::Events};
+
+/// Periodically reports how many bytes of each mapping live on each NUMA
+/// node, from `/proc/<pid>/numa_maps`.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration) -> Sampler {
+    let mut prev = vec![];
+
+    Sampler::spawn("NUMA nodes", tid, interval, move || {
+        let regions = read(tid)?;
+        if regions != prev {
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::Sample(Sample::NumaNodes {
+                    regions: regions.clone(),
+                }),
+            ))?;
+            prev = regions;
+        }
+        Ok(())
+    })
+}
+
+type Regions = Vec<(Range<u64>, Vec<(u32, u64)>)>;
+
+fn read(tid: TraceeId) -> Result<Regions> {
+    // numa_maps only has start addresses
+    let ends: HashMap<u64, u64> = procfs::process::Process::new(tid.0 as _)?
+        .maps()?
+        .into_iter()
+        .map(|m| (m.address.0, m.address.1))
+        .collect();
+
+    let numa_maps = std::fs::read_to_string(format!("/proc/{}/numa_maps", tid.0))?;
+
+    let mut regions = vec![];
+    for line in numa_maps.lines() {
+        // e.g. `7f0000000000 default anon=3 dirty=3 N0=2 N1=1 kernelpagesize_kB=4`
+        let mut fields = line.split_whitespace();
+        let Some(start) = fields.next().and_then(|s| u64::from_str_radix(s, 16).ok()) else {
+            continue;
+        };
+        let Some(end) = ends.get(&start) else {
+            // unmapped in between the two reads
+            continue;
+        };
+
+        let mut page_size = 4096;
+        let mut pages_per_node = vec![];
+        for field in fields {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            if key == "kernelpagesize_kB" {
+                page_size = value.parse::<u64>()? * 1024;
+            } else if let Some(node) = key.strip_prefix('N') {
+                if let (Ok(node), Ok(pages)) = (node.parse::<u32>(), value.parse::<u64>()) {
+                    pages_per_node.push((node, pages));
+                }
+            }
+        }
+        if pages_per_node.is_empty() {
+            continue;
+        }
+
+        let bytes_per_node = pages_per_node
+            .into_iter()
+            .map(|(node, pages)| (node, pages * page_size))
+            .collect();
+        regions.push((start..*end, bytes_per_node));
+    }
+    Ok(regions)
+}
@@ -0,0 +1,114 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use mevi_common::{CgroupEvent, TraceeId};
+use nix::unistd::{sysconf, SysconfVar};
+use tracing::debug;
+
+use crate::{cgroup, sampler::Sampler};
+
+/// How often we note down the RSS of processes, in case they get OOM-killed
+const RSS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Remembers enough about a process to tell, once it's been SIGKILLed,
+/// whether the OOM killer did it, and how much memory it had at the time.
+pub(crate) struct OomWatch {
+    cgroup_dir: Option<PathBuf>,
+
+    // OOM kill counters when we started watching: the cgroup's, and the
+    // system-wide one (for processes that aren't in a memory cgroup)
+    cgroup_kills: u64,
+    global_kills: u64,
+
+    last_rss: Arc<AtomicU64>,
+    _sampler: Sampler,
+
+    reported: bool,
+}
+
+impl OomWatch {
+    pub(crate) fn new(tid: TraceeId) -> Self {
+        let cgroup_dir = cgroup::dir(tid)
+            .map_err(|e| debug!("{tid} can't watch for cgroup OOM kills: {e}"))
+            .ok();
+        let cgroup_kills = cgroup_dir.as_deref().map(cgroup_kills).unwrap_or_default();
+        let global_kills = global_kills().unwrap_or_default();
+
+        let page_size = sysconf(SysconfVar::PAGE_SIZE).unwrap().unwrap() as u64;
+        let last_rss = Arc::new(AtomicU64::new(0));
+        let sampler = Sampler::spawn("rss", tid, RSS_INTERVAL, {
+            let last_rss = last_rss.clone();
+            move || {
+                let statm = std::fs::read_to_string(format!("/proc/{}/statm", tid.0))?;
+                // size, then resident, in pages
+                let resident: u64 = statm
+                    .split_whitespace()
+                    .nth(1)
+                    .ok_or_else(|| eyre!("unexpected statm: {statm:?}"))?
+                    .parse()?;
+                last_rss.store(resident * page_size, Ordering::Relaxed);
+                Ok(())
+            }
+        });
+
+        Self {
+            cgroup_dir,
+            cgroup_kills,
+            global_kills,
+            last_rss,
+            _sampler: sampler,
+            reported: false,
+        }
+    }
+
+    /// For a process that just got SIGKILLed: if the OOM killer did it,
+    /// returns how much memory it had last we checked. Only does so once.
+    pub(crate) fn oom_killed(&mut self) -> Option<u64> {
+        if self.reported {
+            return None;
+        }
+
+        let by_cgroup = self
+            .cgroup_dir
+            .as_deref()
+            .map(|dir| cgroup_kills(dir) > self.cgroup_kills)
+            .unwrap_or_default();
+        let globally = global_kills()
+            .map(|kills| kills > self.global_kills)
+            .unwrap_or_default();
+
+        // not bulletproof: someone else might have been OOM-killed in the
+        // meantime, and our process SIGKILLed by hand
+        self.reported = by_cgroup || globally;
+        self.reported.then(|| self.last_rss.load(Ordering::Relaxed))
+    }
+}
+
+fn cgroup_kills(dir: &Path) -> u64 {
+    cgroup::read_events(dir)
+        .ok()
+        .and_then(|events| {
+            events
+                .into_iter()
+                .find(|(event, _)| *event == CgroupEvent::OomKill)
+                .map(|(_, count)| count)
+        })
+        .unwrap_or_default()
+}
+
+/// The system-wide OOM kill counter, cf. `oom_kill` in `/proc/vmstat`
+fn global_kills() -> Result<u64> {
+    let vmstat = std::fs::read_to_string("/proc/vmstat")?;
+    let count = vmstat
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .ok_or_else(|| eyre!("no oom_kill in /proc/vmstat"))?;
+    Ok(count.trim().parse()?)
+}
@@ -0,0 +1,252 @@
+//! What to trace, and how, cf. [crate::Tracer::new]
+
+use std::{collections::HashMap, path::PathBuf, str::FromStr, time::Duration};
+
+use color_eyre::{eyre::bail, Result};
+use nix::unistd::{Gid, Uid};
+
+use crate::track::TrackFilter;
+
+/// How we find out which pages of a tracee are resident
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Inject a userfaultfd into the tracee and get told about every fault
+    Uffd,
+
+    /// Periodically read `/proc/<pid>/pagemap`: coarser, but works where
+    /// userfaultfd is unavailable (unprivileged_userfaultfd=0, containers)
+    Pagemap,
+}
+
+impl FromStr for Backend {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "uffd" => Ok(Self::Uffd),
+            "pagemap" => Ok(Self::Pagemap),
+            other => bail!("unknown backend {other:?}, expected uffd or pagemap"),
+        }
+    }
+}
+
+/// When to inject a userfaultfd into a process we haven't connected to yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectTrigger {
+    /// On the first syscall exit (other than execve)
+    FirstSyscall,
+
+    /// On the first anonymous mmap or brk call: processes that never
+    /// allocate anything (e.g. that fork and exec right away) are left alone
+    FirstMapping,
+}
+
+impl FromStr for InjectTrigger {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first-syscall" => Ok(Self::FirstSyscall),
+            "first-mapping" => Ok(Self::FirstMapping),
+            other => {
+                bail!("unknown inject trigger {other:?}, expected first-syscall or first-mapping")
+            }
+        }
+    }
+}
+
+/// What happens to tracees once `--max-duration` or `--max-events` is hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnLimit {
+    /// Let them go on without us
+    Detach,
+
+    /// SIGKILL them
+    Kill,
+}
+
+impl FromStr for OnLimit {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "detach" => Ok(Self::Detach),
+            "kill" => Ok(Self::Kill),
+            other => bail!("unknown limit policy {other:?}, expected detach or kill"),
+        }
+    }
+}
+
+/// What to trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Start a program, with its arguments
+    Spawn(Vec<String>),
+
+    /// Attach to a process that's already running, and every thread in it
+    Attach(u32),
+}
+
+/// What the environment of a program we start looks like, compared to ours
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Env {
+    /// Start from nothing rather than our own environment
+    pub clear: bool,
+
+    /// Variables to leave out
+    pub remove: Vec<String>,
+
+    /// Variables to set, after everything else
+    pub set: Vec<(String, String)>,
+}
+
+/// Who a program we start runs as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunAs {
+    pub uid: Uid,
+    pub gid: Gid,
+
+    /// Supplementary groups
+    pub groups: Vec<Gid>,
+}
+
+/// Everything a tracer needs to know before it starts
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub target: Target,
+
+    /// Only for [Target::Spawn]
+    pub env: Env,
+
+    /// What the program we start sees as its argv[0], if not its path as
+    /// given. Only for [Target::Spawn].
+    pub argv0: Option<String>,
+
+    /// Give the program we start a pseudo-terminal of its own. Only for
+    /// [Target::Spawn].
+    pub pty: bool,
+
+    /// Where the program we start writes its stdout and stderr, if not
+    /// wherever ours go. Only for [Target::Spawn].
+    pub stdout: Option<PathBuf>,
+    pub stderr: Option<PathBuf>,
+
+    /// Give the program we start nothing to read, when the terminal isn't
+    /// its to use. Only for [Target::Spawn].
+    pub null_stdin: bool,
+
+    /// Who the program we start runs as, if not whoever we run as. Only for
+    /// [Target::Spawn].
+    pub run_as: Option<RunAs>,
+
+    /// If set, how much memory the program we start (and its children) can
+    /// use, enforced with a cgroup of their own. Only for [Target::Spawn].
+    pub memory_limit: Option<u64>,
+
+    pub backend: Backend,
+    pub poll_interval: Duration,
+
+    /// When to start tracking a process we haven't connected to yet
+    pub inject_on: InjectTrigger,
+
+    /// Register ranges in write-protect mode too, to tell reads and writes
+    /// apart
+    pub write_protect: bool,
+
+    /// How long a fault can wait to be merged with faults on neighboring
+    /// pages
+    pub coalesce_window: Duration,
+
+    /// Resolve where each fault came from in the tracee's code
+    pub symbolicate: bool,
+
+    /// If set, count faults per bucket of that many bytes rather than
+    /// sending every page, cf. [crate::buckets]
+    pub bucket: Option<u64>,
+
+    /// If set, how often to look for pages that got swapped out or back in
+    pub swap_interval: Option<Duration>,
+
+    /// If set, how often to sample soft-dirty bits
+    pub soft_dirty_interval: Option<Duration>,
+
+    /// If set, how often to sample idle pages
+    pub idle_interval: Option<Duration>,
+
+    /// If set, how often to sample transparent huge page coverage
+    pub thp_interval: Option<Duration>,
+
+    /// If set, how often to look for pages merged by KSM
+    pub ksm_interval: Option<Duration>,
+
+    /// If set, how often to sample NUMA node residency
+    pub numa_interval: Option<Duration>,
+
+    /// If set, how often to sample the memory cgroup
+    pub cgroup_interval: Option<Duration>,
+
+    /// If set, how often to sample smaps_rollup
+    pub rollup_interval: Option<Duration>,
+
+    /// If set, how often to sample fault counts
+    pub faults_interval: Option<Duration>,
+
+    /// Which mappings (or parts of them) to track at all
+    pub track: TrackFilter,
+
+    /// Whether to trace the processes tracees fork, or only their threads
+    pub follow_children: bool,
+
+    /// Syscalls to report as they happen, by number, with their names
+    pub watch_syscalls: HashMap<i64, String>,
+
+    /// If set, when to stop tracing, and what happens to the tracees then
+    pub max_duration: Option<Duration>,
+    pub max_events: Option<u64>,
+    pub on_limit: OnLimit,
+
+    /// Make the whole process the child subreaper: tracees orphaned along
+    /// the way are handed to us rather than to init, and the tracer reaps
+    /// them, along with every other child the process has. Only for
+    /// programs that do nothing but trace, like mevi.
+    pub subreaper: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            target: Target::Spawn(Default::default()),
+            env: Default::default(),
+            argv0: None,
+            pty: false,
+            stdout: None,
+            stderr: None,
+            null_stdin: false,
+            run_as: None,
+            memory_limit: None,
+            backend: Backend::Uffd,
+            poll_interval: Duration::from_millis(250),
+            inject_on: InjectTrigger::FirstMapping,
+            write_protect: false,
+            coalesce_window: Duration::from_millis(5),
+            symbolicate: false,
+            bucket: None,
+            swap_interval: Some(Duration::from_millis(1000)),
+            soft_dirty_interval: None,
+            idle_interval: None,
+            thp_interval: None,
+            ksm_interval: None,
+            numa_interval: None,
+            cgroup_interval: None,
+            rollup_interval: None,
+            faults_interval: None,
+            track: Default::default(),
+            follow_children: true,
+            watch_syscalls: Default::default(),
+            max_duration: None,
+            max_events: None,
+            on_limit: OnLimit::Detach,
+            subreaper: false,
+        }
+    }
+}
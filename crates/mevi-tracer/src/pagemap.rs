@@ -0,0 +1,312 @@
+use std::{ops::Range, path::Path, time::Duration};
+
+use color_eyre::Result;
+use mevi_common::{
+    MappingFile, MappingKind, MappingPerms, MemMap, MemState, MeviEvent, TraceeId, TraceePayload,
+};
+use nix::unistd::{sysconf, SysconfVar};
+use procfs::process::{MMPermissions, MMapPath, MemoryMap, MemoryPageFlags, PageInfo};
+use rangemap::RangeSet;
+
+use crate::{sampler::Sampler, sink::Events, track::TrackFilter};
+
+/// What kind of memory backs a mapping, as far as userfaultfd is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Backing {
+    /// Private anonymous memory (and the heap)
+    Anonymous,
+
+    /// tmpfs: `/dev/shm`, `MAP_SHARED | MAP_ANONYMOUS`
+    Shmem,
+
+    /// hugetlbfs, including `MAP_HUGETLB`
+    Hugetlbfs,
+}
+
+impl Backing {
+    /// Classifies a file we found in maps (or behind an fd). hugetlbfs can
+    /// be mounted anywhere, but `/dev/hugepages` is where it usually is.
+    pub(crate) fn of_path(p: &Path) -> Option<Self> {
+        let p = p.to_string_lossy();
+        if p.starts_with("/dev/hugepages/") || p.starts_with("/anon_hugepage") {
+            Some(Self::Hugetlbfs)
+        } else if p.starts_with("/dev/shm/") {
+            Some(Self::Shmem)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn of_map(map: &MemoryMap) -> Option<Self> {
+        match &map.pathname {
+            MMapPath::Heap => Some(Self::Anonymous),
+            MMapPath::Anonymous if map.perms.contains(MMPermissions::SHARED) => Some(Self::Shmem),
+            MMapPath::Anonymous => Some(Self::Anonymous),
+            MMapPath::Path(p) => Self::of_path(p),
+            _ => None,
+        }
+    }
+}
+
+/// Whether we want to track a mapping at all: we only care about R+W memory
+/// that's anonymous (or the heap), plus shared memory from tmpfs and
+/// hugetlbfs.
+pub(crate) fn is_tracked(map: &MemoryMap) -> bool {
+    if !map.perms.contains(
+        MMPermissions::READ | MMPermissions::WRITE, /* | MMPermissions::PRIVATE */
+    ) {
+        // we only want RW+PRIVATE, although we're
+        // probably losing out on some regions if
+        // they're mprotected as RW later?
+        return false;
+    }
+
+    // if map.perms.contains(MMPermissions::SHARED) {
+    //     // nope
+    //     return false;
+    // }
+
+    match &map.pathname {
+        MMapPath::Heap | MMapPath::Anonymous => true,
+        // only if it's shared memory
+        MMapPath::Path(p) => Backing::of_path(p).is_some(),
+        MMapPath::Stack
+        | MMapPath::TStack(_)
+        | MMapPath::Vdso
+        | MMapPath::Vvar
+        | MMapPath::Vsyscall
+        | MMapPath::Rollup
+        | MMapPath::Vsys(_)
+        | MMapPath::Other(_) => false,
+    }
+}
+
+/// Figures out what a mapping is for. `below` is the mapping right before it
+/// in the address space, if any: thread stacks aren't labelled in maps
+/// anymore, but they're the ones with a guard page right below them.
+///
+/// Returns `None` for mappings we don't show at all.
+pub(crate) fn mapping_kind(map: &MemoryMap, below: Option<&MemoryMap>) -> Option<MappingKind> {
+    let is_guard = |map: &MemoryMap| {
+        matches!(map.pathname, MMapPath::Anonymous)
+            && !map
+                .perms
+                .intersects(MMPermissions::READ | MMPermissions::WRITE | MMPermissions::EXECUTE)
+    };
+
+    let kind = match &map.pathname {
+        MMapPath::Heap => MappingKind::Heap,
+        MMapPath::Stack => MappingKind::Stack,
+        MMapPath::TStack(_) => MappingKind::ThreadStack,
+        MMapPath::Vdso => MappingKind::Vdso,
+        MMapPath::Vvar => MappingKind::Vvar,
+        MMapPath::Vsyscall => MappingKind::Vsyscall,
+        MMapPath::Anonymous if is_guard(map) => MappingKind::Guard,
+        MMapPath::Anonymous if map.perms.contains(MMPermissions::SHARED) => {
+            MappingKind::SharedMemory
+        }
+        MMapPath::Anonymous => match below {
+            Some(below) if below.address.1 == map.address.0 && is_guard(below) => {
+                MappingKind::ThreadStack
+            }
+            _ => MappingKind::Anonymous,
+        },
+        MMapPath::Path(p) if Backing::of_path(p).is_some() => MappingKind::SharedMemory,
+        MMapPath::Path(_) if map.perms.contains(MMPermissions::EXECUTE) => MappingKind::Code,
+        MMapPath::Path(_)
+            if map
+                .perms
+                .contains(MMPermissions::WRITE | MMPermissions::PRIVATE) =>
+        {
+            MappingKind::Data
+        }
+        MMapPath::Path(_) => MappingKind::File,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// The file behind a mapping, if there's one
+pub(crate) fn mapping_file(map: &MemoryMap) -> Option<MappingFile> {
+    match &map.pathname {
+        MMapPath::Path(p) => Some(MappingFile {
+            path: p.to_string_lossy().into_owned(),
+            offset: map.offset,
+            inode: map.inode,
+        }),
+        _ => None,
+    }
+}
+
+/// A mapping's permissions, as maps shows them
+pub(crate) fn mapping_perms(map: &MemoryMap) -> MappingPerms {
+    MappingPerms {
+        read: map.perms.contains(MMPermissions::READ),
+        write: map.perms.contains(MMPermissions::WRITE),
+        exec: map.perms.contains(MMPermissions::EXECUTE),
+        shared: map.perms.contains(MMPermissions::SHARED),
+    }
+}
+
+/// Calls `f` with every page of every tracked mapping of `tid` (as far as
+/// `track` lets us), along with what `/proc/<pid>/pagemap` knows about it.
+pub(crate) fn for_each_page(
+    tid: TraceeId,
+    track: &TrackFilter,
+    mut f: impl FnMut(Range<u64>, PageInfo),
+) -> Result<()> {
+    let page_size = sysconf(SysconfVar::PAGE_SIZE)?.unwrap() as u64;
+
+    let p = procfs::process::Process::new(tid.0 as _)?;
+    let mut pm = p.pagemap()?;
+
+    let maps: Vec<_> = p.maps()?.into_iter().collect();
+    for (i, m) in maps.iter().enumerate() {
+        if !is_tracked(m) {
+            continue;
+        }
+
+        let below = i.checked_sub(1).map(|i| &maps[i]);
+        let kind = mapping_kind(m, below).unwrap_or(MappingKind::Anonymous);
+        let file = mapping_file(m);
+        for part in track.parts(&(m.address.0..m.address.1), kind, file.as_ref()) {
+            let start_idx = (part.start / page_size) as usize;
+            let end_idx = (part.end / page_size) as usize;
+            for (rel_idx, pi) in pm
+                .get_range_info(start_idx..end_idx)?
+                .into_iter()
+                .enumerate()
+            {
+                let addr = part.start + rel_idx as u64 * page_size;
+                f(addr..addr + page_size, pi);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn page_state(pi: &PageInfo) -> MemState {
+    match pi {
+        PageInfo::MemoryPage(mp) if mp.contains(MemoryPageFlags::PRESENT) => MemState::Resident,
+        PageInfo::SwapPage(_) => MemState::Swapped,
+        _ => MemState::NotResident,
+    }
+}
+
+/// Reads the residency of every tracked mapping of `tid`
+pub(crate) fn scan(tid: TraceeId, track: &TrackFilter) -> Result<MemMap> {
+    let mut map = MemMap::default();
+    for_each_page(tid, track, |page, pi| {
+        // rangemap coalesces adjacent pages with the same state for us
+        map.insert(page, page_state(&pi));
+    })?;
+    Ok(map)
+}
+
+/// Reads the residency of a single range of `tid`'s address space
+pub(crate) fn scan_range(tid: TraceeId, range: Range<u64>) -> Result<MemMap> {
+    let page_size = sysconf(SysconfVar::PAGE_SIZE)?.unwrap() as u64;
+
+    let p = procfs::process::Process::new(tid.0 as _)?;
+    let mut pm = p.pagemap()?;
+
+    let start_idx = (range.start / page_size) as usize;
+    let end_idx = range.end.div_ceil(page_size) as usize;
+
+    let mut map = MemMap::default();
+    for (rel_idx, pi) in pm
+        .get_range_info(start_idx..end_idx)?
+        .into_iter()
+        .enumerate()
+    {
+        let addr = (start_idx + rel_idx) as u64 * page_size;
+        map.insert(addr..addr + page_size, page_state(&pi));
+    }
+    Ok(map)
+}
+
+/// Periodically scans a process's pagemap and reports residency changes, for
+/// address spaces we can't put a userfaultfd on.
+pub(crate) fn spawn_poller(
+    tid: TraceeId,
+    tx: Events,
+    interval: Duration,
+    track: TrackFilter,
+) -> Sampler {
+    let mut prev = MemMap::default();
+    Sampler::spawn("pagemap", tid, interval, move || {
+        let map = scan(tid, &track)?;
+
+        for (range, state) in map.iter() {
+            let mut overlapping = prev.overlapping(range);
+            let unchanged = matches!(
+                (overlapping.next(), overlapping.next()),
+                (Some((prev_range, prev_state)), None)
+                    if prev_state == state
+                        && prev_range.start <= range.start
+                        && prev_range.end >= range.end
+            );
+            if unchanged {
+                continue;
+            }
+
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::MemStateChange {
+                    range: range.clone(),
+                    state: *state,
+                },
+            ))?;
+        }
+        prev = map;
+
+        Ok(())
+    })
+}
+
+/// Periodically looks for pages that got swapped out, or swapped back in:
+/// neither of those goes through userfaultfd.
+pub(crate) fn spawn_swap_watcher(
+    tid: TraceeId,
+    tx: Events,
+    interval: Duration,
+    track: TrackFilter,
+) -> Sampler {
+    let mut swapped: RangeSet<u64> = RangeSet::new();
+    Sampler::spawn("swap", tid, interval, move || {
+        let map = scan(tid, &track)?;
+
+        let mut now_swapped = RangeSet::new();
+        for (range, state) in map.iter() {
+            if *state == MemState::Swapped {
+                now_swapped.insert(range.clone());
+            }
+        }
+
+        let send = |range: Range<u64>, state: MemState| {
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::MemStateChange { range, state },
+            ))
+        };
+
+        for range in now_swapped.iter() {
+            for out in swapped.gaps(range) {
+                send(out, MemState::Swapped)?;
+            }
+        }
+        for range in swapped.iter() {
+            for back in now_swapped.gaps(range) {
+                // either swapped back in, or freed
+                for (range, state) in map.overlapping(&back) {
+                    let range = range.start.max(back.start)..range.end.min(back.end);
+                    send(range, *state)?;
+                }
+            }
+        }
+        swapped = now_swapped;
+
+        Ok(())
+    })
+}
@@ -0,0 +1,113 @@
+//! `--pty`: the program gets a pseudo-terminal of its own, so it does
+//! colors, line editing and everything else it'd do in a terminal, and we
+//! shuttle bytes between it and ours.
+
+use std::{
+    fs::File,
+    io::{IsTerminal, Read, Write},
+    os::fd::{AsRawFd, OwnedFd},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use tracing::debug;
+
+/// How often we check whether our terminal was resized
+const RESIZE_INTERVAL: Duration = Duration::from_millis(250);
+
+pub(crate) struct Pty {
+    master: File,
+
+    /// What the program gets as its stdin, stdout and stderr
+    pub(crate) slave: OwnedFd,
+}
+
+impl Pty {
+    pub(crate) fn open() -> Result<Self> {
+        let pty = nix::pty::openpty(None, None)?;
+        let master = File::from(pty.master);
+        resize(&master);
+        Ok(Self {
+            master,
+            slave: pty.slave,
+        })
+    }
+
+    /// Forwards our stdin to the program and its output to our stdout,
+    /// with our terminal in raw mode (if it's one) until the program and
+    /// every child it shared the terminal with are gone. Drops our end of
+    /// the slave, or we'd never see the last of them go.
+    pub(crate) fn proxy(self) -> Result<()> {
+        let Self { master, slave } = self;
+        drop(slave);
+
+        let raw = std::io::stdin().is_terminal();
+        if raw {
+            crossterm::terminal::enable_raw_mode()?;
+        }
+
+        let mut input = master.try_clone()?;
+        std::thread::spawn(move || {
+            // this one never finishes if stdin doesn't, which is fine: it
+            // goes away with us
+            let mut buf = [0u8; 4096];
+            let mut stdin = std::io::stdin().lock();
+            while let Ok(n @ 1..) = stdin.read(&mut buf) {
+                if input.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let resized = master.try_clone()?;
+        std::thread::spawn(move || {
+            let mut size = crossterm::terminal::size().ok();
+            loop {
+                std::thread::sleep(RESIZE_INTERVAL);
+                let new_size = crossterm::terminal::size().ok();
+                if new_size != size {
+                    size = new_size;
+                    resize(&resized);
+                }
+            }
+        });
+
+        let mut output = master;
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            let mut stdout = std::io::stdout();
+            // EIO once the last slave is closed
+            while let Ok(n @ 1..) = output.read(&mut buf) {
+                if stdout
+                    .write_all(&buf[..n])
+                    .and_then(|_| stdout.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            debug!("the program's terminal is closed");
+            if raw {
+                _ = crossterm::terminal::disable_raw_mode();
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Makes the program's terminal as big as ours
+fn resize(master: &File) {
+    let Ok((cols, rows)) = crossterm::terminal::size() else {
+        return;
+    };
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe {
+        libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+    }
+}
@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use mevi_common::{MeviEvent, Sample, TraceeId, TraceePayload};
+
+use crate::{sampler::Sampler, sink::Events, smaps};
+
+/// Periodically reports the kernel's own accounting of a process's memory,
+/// from `/proc/<pid>/smaps_rollup`: a ground truth to hold our residency
+/// model up against.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration) -> Sampler {
+    Sampler::spawn("smaps_rollup", tid, interval, move || {
+        let rollup = smaps::read_rollup(tid)?;
+        tx.send(MeviEvent::TraceeEvent(
+            tid,
+            TraceePayload::Sample(Sample::Rollup {
+                rss: rollup.size("Rss"),
+                pss: rollup.size("Pss"),
+                shared_clean: rollup.size("Shared_Clean"),
+                swap: rollup.size("Swap"),
+            }),
+        ))?;
+        Ok(())
+    })
+}
@@ -0,0 +1,47 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use color_eyre::Result;
+use mevi_common::TraceeId;
+use tracing::{debug, info};
+
+/// A thread that samples something about a process at a fixed interval.
+/// Stops when dropped, or as soon as sampling fails (which usually means the
+/// process is gone).
+pub(crate) struct Sampler {
+    stop: Arc<AtomicBool>,
+}
+
+impl Sampler {
+    pub(crate) fn spawn<F>(name: &'static str, tid: TraceeId, interval: Duration, mut f: F) -> Self
+    where
+        F: FnMut() -> Result<()> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                info!("{tid} sampling {name} every {interval:?}");
+                while !stop.load(Ordering::Relaxed) {
+                    if let Err(e) = f() {
+                        debug!("{tid} stopped sampling {name}: {e}");
+                        return;
+                    }
+                    std::thread::sleep(interval);
+                }
+            }
+        });
+        Self { stop }
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
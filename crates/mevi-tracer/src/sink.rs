@@ -0,0 +1,90 @@
+//! Where the tracer, its uffd threads and samplers send events: a
+//! [channel](crate::channel) for the mevi binary, or anything else that's an
+//! [EventSink], like a closure.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc, Arc,
+};
+
+use mevi_common::{MeviEvent, Timestamp};
+
+use crate::channel::EventTx;
+
+/// Takes events from every thread of a tracer, as they happen
+pub trait EventSink: Send + Sync {
+    /// Takes `event`, captured `at`. Tracees wait while this blocks, when
+    /// it's about a fault. Erroring means nobody wants events anymore, and
+    /// the tracer gives up.
+    fn send_at(&self, at: Timestamp, event: MeviEvent) -> Result<(), mpsc::SendError<MeviEvent>>;
+
+    /// Called once the session is over, even though threads that can't tell
+    /// their tracee went away may still have a hold of the sink (e.g. after
+    /// we detached from it). Nothing's sent from then on.
+    fn hang_up(&self) {}
+}
+
+impl EventSink for EventTx {
+    fn send_at(&self, at: Timestamp, event: MeviEvent) -> Result<(), mpsc::SendError<MeviEvent>> {
+        EventTx::send_at(self, at, event)
+    }
+
+    fn hang_up(&self) {
+        EventTx::hang_up(self)
+    }
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(Timestamp, MeviEvent) + Send + Sync,
+{
+    fn send_at(&self, at: Timestamp, event: MeviEvent) -> Result<(), mpsc::SendError<MeviEvent>> {
+        self(at, event);
+        Ok(())
+    }
+}
+
+/// What the tracer's threads hold on to, to send events to its sink. The
+/// sink goes away once they're all gone, which for a channel tells the
+/// receiver the session's over.
+#[derive(Clone)]
+pub(crate) struct Events {
+    sink: Arc<dyn EventSink>,
+
+    /// How many events made it to the sink, for `--max-events`
+    sent: Arc<AtomicU64>,
+}
+
+impl Events {
+    pub(crate) fn new(sink: impl EventSink + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            sent: Default::default(),
+        }
+    }
+
+    /// Stamps `event` with the current time, and sends it
+    pub(crate) fn send(&self, event: MeviEvent) -> Result<(), mpsc::SendError<MeviEvent>> {
+        self.send_at(Timestamp::now(), event)
+    }
+
+    /// Like [Self::send], for events that were captured a bit earlier
+    pub(crate) fn send_at(
+        &self,
+        at: Timestamp,
+        event: MeviEvent,
+    ) -> Result<(), mpsc::SendError<MeviEvent>> {
+        self.sink.send_at(at, event)?;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn hang_up(&self) {
+        self.sink.hang_up()
+    }
+
+    /// How many events made it to the sink so far, kept up to date
+    pub(crate) fn sent(&self) -> Arc<AtomicU64> {
+        self.sent.clone()
+    }
+}
@@ -0,0 +1,83 @@
+use std::{collections::HashMap, ops::Range};
+
+use color_eyre::{eyre::eyre, Result};
+use mevi_common::TraceeId;
+
+/// One mapping's worth of `/proc/<pid>/smaps` (or the single pseudo-mapping
+/// of `smaps_rollup`)
+pub(crate) struct Entry {
+    pub(crate) range: Range<u64>,
+
+    /// Every field that's a size (`Rss`, `AnonHugePages`, `KernelPageSize`,
+    /// etc.), in bytes
+    pub(crate) sizes: HashMap<String, u64>,
+}
+
+impl Entry {
+    pub(crate) fn size(&self, key: &str) -> u64 {
+        self.sizes.get(key).copied().unwrap_or_default()
+    }
+}
+
+/// Reads `/proc/<pid>/smaps`
+pub(crate) fn read(tid: TraceeId) -> Result<Vec<Entry>> {
+    parse(&std::fs::read_to_string(format!("/proc/{}/smaps", tid.0))?)
+}
+
+/// Reads `/proc/<pid>/smaps_rollup`, which has the same fields as smaps,
+/// summed over all mappings (and is much cheaper to read)
+pub(crate) fn read_rollup(tid: TraceeId) -> Result<Entry> {
+    parse(&std::fs::read_to_string(format!(
+        "/proc/{}/smaps_rollup",
+        tid.0
+    ))?)?
+    .pop()
+    .ok_or_else(|| eyre!("{tid} has an empty smaps_rollup"))
+}
+
+fn parse(smaps: &str) -> Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = vec![];
+    for line in smaps.lines() {
+        // fields look like `Rss:  1234 kB`
+        if let Some((key, value)) = line.split_once(':') {
+            if let Some(kb) = value.trim().strip_suffix(" kB") {
+                let entry = entries
+                    .last_mut()
+                    .ok_or_else(|| eyre!("smaps field before any mapping: {line:?}"))?;
+                entry
+                    .sizes
+                    .insert(key.to_string(), kb.trim().parse::<u64>()? * 1024);
+                continue;
+            }
+        }
+
+        // mapping headers look like `7f0000000000-7f0000200000 rw-s ...`
+        let Some(addresses) = line.split_whitespace().next() else {
+            continue;
+        };
+        let Some((start, end)) = addresses.split_once('-') else {
+            continue;
+        };
+        if let (Ok(start), Ok(end)) = (u64::from_str_radix(start, 16), u64::from_str_radix(end, 16))
+        {
+            entries.push(Entry {
+                range: start..end,
+                sizes: Default::default(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Finds the mapping of `tid` that contains `addr`, and the size of the pages
+/// the kernel uses for it (which is only interesting for hugetlbfs).
+pub(crate) fn kernel_page_size(tid: TraceeId, addr: u64) -> Result<(Range<u64>, u64)> {
+    read(tid)?
+        .into_iter()
+        .find(|e| e.range.contains(&addr))
+        .map(|e| {
+            let size = e.size("KernelPageSize");
+            (e.range, size)
+        })
+        .ok_or_else(|| eyre!("{tid} has no mapping containing {addr:x}"))
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use mevi_common::{MeviEvent, Overlay, TraceeId, TraceePayload};
+use procfs::process::{MemoryPageFlags, PageInfo};
+use rangemap::RangeSet;
+
+use crate::{pagemap, sampler::Sampler, sink::Events, track::TrackFilter};
+
+/// Periodically reports which pages were written to since the previous
+/// sample, using the kernel's soft-dirty bits: unlike userfaultfd missing
+/// faults, this also catches writes to pages that were already resident.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration, track: TrackFilter) -> Sampler {
+    // every page starts out soft-dirty, which isn't very informative
+    let mut cleared = false;
+
+    Sampler::spawn("soft-dirty bits", tid, interval, move || {
+        if cleared {
+            let mut written = RangeSet::new();
+            pagemap::for_each_page(tid, &track, |page, pi| {
+                if let PageInfo::MemoryPage(mp) = pi {
+                    if mp.contains(MemoryPageFlags::SOFT_DIRTY) {
+                        written.insert(page);
+                    }
+                }
+            })?;
+
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::OverlaySample {
+                    overlay: Overlay::Written,
+                    ranges: written.into_iter().collect(),
+                },
+            ))?;
+        }
+
+        // "4" clears the soft-dirty bits of all the process's pages, cf.
+        // https://www.kernel.org/doc/html/latest/admin-guide/mm/soft-dirty.html
+        std::fs::write(format!("/proc/{}/clear_refs", tid.0), "4")?;
+        cleared = true;
+
+        Ok(())
+    })
+}
@@ -0,0 +1,104 @@
+//! Tracing from async code, with the `tokio` feature: [TracerBuilder::events]
+//! is like [TracerBuilder::start], with a [Stream] of events rather than an
+//! iterator.
+//!
+//! [TracerBuilder::events]: crate::TracerBuilder::events
+//! [TracerBuilder::start]: crate::TracerBuilder::start
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use color_eyre::{eyre::eyre, Result};
+use futures_core::Stream;
+use mevi_common::{ClockAnchor, MeviEvent, TimedEvent, Timestamp};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{Control, EventSink, Options, Tracer};
+
+impl EventSink for mpsc::Sender<TimedEvent> {
+    fn send_at(
+        &self,
+        at: Timestamp,
+        event: MeviEvent,
+    ) -> Result<(), std::sync::mpsc::SendError<MeviEvent>> {
+        // the tracer's threads aren't async, they can block until there's
+        // room, which stops the tracee too
+        self.blocking_send(TimedEvent { at, seq: 0, event })
+            .map_err(|mpsc::error::SendError(ev)| std::sync::mpsc::SendError(ev.event))
+    }
+}
+
+/// What's being traced, as a [Stream] of everything that happens to it.
+/// It ends once every tracee is gone.
+pub struct EventStream {
+    rx: mpsc::Receiver<TimedEvent>,
+    anchor: ClockAnchor,
+    control: Control,
+    done: oneshot::Receiver<Result<Option<i32>>>,
+}
+
+impl EventStream {
+    /// Starts tracing what `options` say on a thread of its own, with up to
+    /// `capacity` events waiting to be read before tracees wait for room
+    pub(crate) async fn start(options: Options, capacity: usize) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(capacity);
+        let control = Control::default();
+        let (started_tx, started_rx) = oneshot::channel();
+        let (done_tx, done_rx) = oneshot::channel();
+        let anchor = ClockAnchor::now();
+        std::thread::spawn({
+            let control = control.clone();
+            // ptrace only listens to the thread that attached, so it's
+            // traced from start to finish on there, not on the runtime's
+            move || {
+                let mut tracer = match Tracer::new(tx, options) {
+                    Ok(tracer) => tracer.controlled(control),
+                    Err(e) => {
+                        _ = started_tx.send(Err(e));
+                        return;
+                    }
+                };
+                _ = started_tx.send(Ok(()));
+                _ = done_tx.send(tracer.run());
+            }
+        });
+        started_rx
+            .await
+            .map_err(|_| eyre!("the tracer panicked"))??;
+        Ok(Self {
+            rx,
+            anchor,
+            control,
+            done: done_rx,
+        })
+    }
+
+    /// When the session started, for turning event timestamps into
+    /// durations or wall-clock time
+    pub fn anchor(&self) -> ClockAnchor {
+        self.anchor
+    }
+
+    /// Pausing, resuming, and evicting memory, cf. [Control]
+    pub fn control(&self) -> &Control {
+        &self.control
+    }
+
+    /// Waits for every tracee to be gone, throwing away events that weren't
+    /// read yet, and returns the exit code of the process we started (or
+    /// attached to), cf. [Tracer::run]
+    pub async fn wait(mut self) -> Result<Option<i32>> {
+        while self.rx.recv().await.is_some() {}
+        self.done.await.map_err(|_| eyre!("the tracer panicked"))?
+    }
+}
+
+impl Stream for EventStream {
+    type Item = TimedEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<TimedEvent>> {
+        self.rx.poll_recv(cx)
+    }
+}
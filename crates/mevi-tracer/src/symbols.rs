@@ -0,0 +1,147 @@
+//! Which code touched memory, for `--symbolicate`: while a thread waits on a
+//! userfault, the kernel still shows where it was in userspace, and that
+//! address resolves against whichever ELF object the tracee mapped there.
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::eyre, Result};
+use mevi_common::TraceeId;
+use object::{Object as _, ObjectSegment as _};
+use procfs::process::{MMPermissions, MMapPath};
+use tracing::{debug, warn};
+
+/// Where `thread` of `pid` is in userspace, if it's blocked. For a thread
+/// waiting on a fault, that's the instruction that faulted, or the syscall
+/// that was copying to or from userspace.
+pub(crate) fn instruction_pointer(pid: TraceeId, thread: TraceeId) -> Option<u64> {
+    // e.g. `-1 0x7ffd2c1e8e30 0x55d0c5a6b1d9` outside a syscall: the last two
+    // fields are always the stack and instruction pointers
+    let syscall =
+        std::fs::read_to_string(format!("/proc/{}/task/{}/syscall", pid.0, thread.0)).ok()?;
+    let ip = syscall.split_whitespace().last()?.strip_prefix("0x")?;
+    u64::from_str_radix(ip, 16).ok()
+}
+
+/// Resolves addresses in one process to `function+offset`
+pub(crate) struct Symbolizer {
+    pid: TraceeId,
+
+    /// Executable file mappings, as of the last time we looked: where, which
+    /// file, and from which offset
+    maps: Vec<(Range<u64>, PathBuf, u64)>,
+
+    /// Every object we've tried to load, `None` if that didn't work out
+    objects: HashMap<PathBuf, Option<Object>>,
+}
+
+impl Symbolizer {
+    pub(crate) fn new(pid: TraceeId) -> Self {
+        Self {
+            pid,
+            maps: Default::default(),
+            objects: Default::default(),
+        }
+    }
+
+    /// `function+offset` for `ip`, or `file+offset` if the file has no
+    /// symbol for it. `None` for code that isn't in a file (JIT, vdso).
+    pub(crate) fn resolve(&mut self, ip: u64) -> Option<String> {
+        if self.mapping(ip).is_none() {
+            // it may have been mapped since we last looked
+            if let Err(e) = self.refresh() {
+                debug!("{} couldn't read maps: {e}", self.pid);
+                return None;
+            }
+        }
+        let (range, path, offset) = self.mapping(ip)?.clone();
+        let file_offset = ip - range.start + offset;
+
+        let object =
+            self.objects
+                .entry(path.clone())
+                .or_insert_with(|| match Object::load(&path) {
+                    Ok(object) => Some(object),
+                    Err(e) => {
+                        warn!("{} couldn't load symbols from {path:?}: {e}", self.pid);
+                        None
+                    }
+                });
+        if let Some(symbol) = object.as_ref().and_then(|o| o.symbolize(file_offset)) {
+            return Some(symbol);
+        }
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        Some(format!("{}+{file_offset:#x}", name.to_string_lossy()))
+    }
+
+    fn mapping(&self, ip: u64) -> Option<&(Range<u64>, PathBuf, u64)> {
+        self.maps.iter().find(|(range, _, _)| range.contains(&ip))
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        self.maps = procfs::process::Process::new(self.pid.0 as _)?
+            .maps()?
+            .into_iter()
+            .filter(|m| m.perms.contains(MMPermissions::EXECUTE))
+            .filter_map(|m| match m.pathname {
+                MMapPath::Path(path) => Some((m.address.0..m.address.1, path, m.offset)),
+                _ => None,
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+/// What we need out of an ELF object, so we don't hold on to all of it
+struct Object {
+    /// Loadable segments: where they are in the file, and at which address
+    /// they're linked
+    segments: Vec<(Range<u64>, u64)>,
+
+    /// Function and data symbols, sorted by address
+    symbols: Vec<(u64, String)>,
+}
+
+impl Object {
+    fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let file = object::File::parse(&*data).map_err(|e| eyre!("{e}"))?;
+        let segments = file
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (offset..offset + size, segment.address())
+            })
+            .collect();
+        // that's the symbol table if there is one, the dynamic one otherwise
+        let symbols = file
+            .symbol_map()
+            .symbols()
+            .iter()
+            .map(|symbol| (symbol.address(), demangle(symbol.name())))
+            .collect();
+        Ok(Self { segments, symbols })
+    }
+
+    fn symbolize(&self, file_offset: u64) -> Option<String> {
+        let (range, address) = self
+            .segments
+            .iter()
+            .find(|(range, _)| range.contains(&file_offset))?;
+        let address = address + (file_offset - range.start);
+        let index = self
+            .symbols
+            .partition_point(|(start, _)| *start <= address)
+            .checked_sub(1)?;
+        let (start, name) = &self.symbols[index];
+        Some(format!("{name}+{:#x}", address - start))
+    }
+}
+
+/// Rust symbols, without the hash. Anything else is left as is.
+fn demangle(name: &str) -> String {
+    format!("{:#}", rustc_demangle::demangle(name))
+}
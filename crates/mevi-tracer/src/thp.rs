@@ -0,0 +1,35 @@
+use std::{ops::Range, time::Duration};
+
+use mevi_common::{MeviEvent, Sample, TraceeId, TraceePayload};
+
+use crate::{sampler::Sampler, sink::Events, smaps};
+
+/// Periodically reports how much of each mapping is backed by transparent
+/// huge pages (`AnonHugePages` in smaps), so we can see khugepaged collapse
+/// ranges into 2MB pages, and those getting split back.
+pub(crate) fn spawn(tid: TraceeId, tx: Events, interval: Duration) -> Sampler {
+    let mut prev: Vec<(Range<u64>, u64)> = vec![];
+
+    Sampler::spawn("transparent huge pages", tid, interval, move || {
+        let regions: Vec<_> = smaps::read(tid)?
+            .into_iter()
+            .filter_map(|entry| {
+                let huge = entry.size("AnonHugePages");
+                (huge > 0).then_some((entry.range, huge))
+            })
+            .collect();
+
+        // coverage rarely changes, don't flood the frontend
+        if regions != prev {
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::Sample(Sample::HugePages {
+                    regions: regions.clone(),
+                }),
+            ))?;
+            prev = regions;
+        }
+
+        Ok(())
+    })
+}
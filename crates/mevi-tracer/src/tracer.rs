@@ -0,0 +1,2660 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Read,
+    ops::Range,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::{FileExt, MetadataExt},
+            net::UnixListener,
+            process::CommandExt,
+        },
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use humansize::{make_format, BINARY};
+use libc::{sockaddr_un, user_regs_struct};
+use mevi_common::{
+    MappingFile, MappingKind, MappingPerms, MemPolicyMode, MemState, MeviEvent, MmapFlags,
+    ProcessInfo, TraceeId, TraceePayload,
+};
+use nix::{
+    errno::Errno,
+    sys::{
+        mman::{MapFlags, ProtFlags},
+        ptrace,
+        signal::{kill, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{Pid, SysconfVar},
+};
+use passfd::FdPassingExt;
+use procfs::process::{MMapPath, MemoryPageFlags, PageInfo};
+use tracing::{debug, info, trace, warn};
+use userfaultfd::{raw, FeatureFlags, IoctlFlags, RegisterMode, Uffd};
+
+use crate::{
+    annotations::Annotations,
+    cgroup,
+    control::Control,
+    evict::{self, Eviction},
+    faults, idle, ksm,
+    limits::{self, Watchdog},
+    numa,
+    oom::OomWatch,
+    options::{Backend, InjectTrigger, OnLimit, Options, Target},
+    pagemap::{self, Backing},
+    pty::Pty,
+    rollup,
+    sampler::Sampler,
+    sink::{EventSink, Events},
+    softdirty, thp,
+    track::TrackFilter,
+};
+
+struct MemoryEvent {
+    for_tid: TraceeId,
+    change: MemoryChange,
+}
+
+enum MemoryChange {
+    Map {
+        range: Range<u64>,
+        state: MemState,
+        raw_flags: Option<RawMmapFlags>,
+        /// `None` for mappings we can't track, like most file mappings
+        backing: Option<Backing>,
+        kind: MappingKind,
+        file: Option<MappingFile>,
+        perms: MappingPerms,
+    },
+    Remap {
+        old_range: Range<u64>,
+        new_range: Range<u64>,
+    },
+    Unmap {
+        range: Range<u64>,
+    },
+    PageOut {
+        range: Range<u64>,
+    },
+    // the kernel was asked to reclaim a range, some of it may be in swap now
+    Reclaim {
+        range: Range<u64>,
+    },
+    // mbind (for a range) or set_mempolicy (for the whole thread)
+    MemPolicy {
+        range: Option<Range<u64>>,
+        mode: MemPolicyMode,
+        nodes: Vec<u32>,
+    },
+    /// The tracee created a userfaultfd of its own
+    TraceeUffd,
+    /// A thread renamed itself with prctl(PR_SET_NAME)
+    ThreadName {
+        thread: TraceeId,
+        name: String,
+    },
+}
+
+/// The `prot` and `flags` arguments of an mmap call, exactly as the tracee
+/// passed them: nix only knows about some of the bits, and we don't want to
+/// lose (or choke on) the others.
+#[derive(Debug, Clone, Copy)]
+struct RawMmapFlags {
+    prot: u64,
+    flags: u64,
+}
+
+impl RawMmapFlags {
+    fn prot_flags(&self) -> ProtFlags {
+        ProtFlags::from_bits_truncate(self.prot as _)
+    }
+
+    fn map_flags(&self) -> MapFlags {
+        MapFlags::from_bits_truncate(self.flags as _)
+    }
+
+    fn perms(&self) -> MappingPerms {
+        let prot = self.prot_flags();
+        MappingPerms {
+            read: prot.contains(ProtFlags::PROT_READ),
+            write: prot.contains(ProtFlags::PROT_WRITE),
+            exec: prot.contains(ProtFlags::PROT_EXEC),
+            shared: self.map_flags().contains(MapFlags::MAP_SHARED),
+        }
+    }
+
+    /// Bits of `prot` that nix doesn't know about
+    fn unknown_prot(&self) -> u64 {
+        self.prot & !(self.prot_flags().bits() as u64)
+    }
+
+    /// Bits of `flags` that nix doesn't know about (MAP_FIXED_NOREPLACE,
+    /// MAP_SYNC, etc. depending on the nix version)
+    fn unknown_flags(&self) -> u64 {
+        self.flags & !(self.map_flags().bits() as u64)
+    }
+
+    /// All of it, for whoever's watching to make sense of
+    fn to_event(self) -> MmapFlags {
+        MmapFlags {
+            prot: self.prot,
+            flags: self.flags,
+            unknown_prot: self.unknown_prot(),
+            unknown_flags: self.unknown_flags(),
+        }
+    }
+}
+
+impl InjectTrigger {
+    /// Whether the syscall a fresh tracee just returned from should make us
+    /// connect to it.
+    fn should_connect(&self, regs: &user_regs_struct) -> bool {
+        match regs.orig_rax as i64 {
+            libc::SYS_execve => {
+                // bad idea, we're about to replace all memory mappings anyway
+                false
+            }
+            libc::SYS_userfaultfd => {
+                // connect right away, so we notice it wants its own
+                true
+            }
+            _ if *self == Self::FirstSyscall => true,
+            _ if syscall_error(regs.rax).is_some() => false,
+            libc::SYS_mmap => {
+                let raw_flags = RawMmapFlags {
+                    prot: regs.rdx,
+                    flags: regs.r10,
+                };
+                regs.r8 as i32 == -1 && raw_flags.map_flags().contains(MapFlags::MAP_ANONYMOUS)
+            }
+            // brk(0) is just a query
+            libc::SYS_brk => regs.rdi != 0,
+            _ => false,
+        }
+    }
+}
+
+/// What tracees need from the tracer to track them
+struct Context {
+    tx: Events,
+    listener: Arc<UnixListener>,
+    backend: Backend,
+    poll_interval: Duration,
+    inject_on: InjectTrigger,
+    write_protect: bool,
+    coalesce_window: Duration,
+    symbolicate: bool,
+    bucket: Option<u64>,
+    swap_interval: Option<Duration>,
+    soft_dirty_interval: Option<Duration>,
+    idle_interval: Option<Duration>,
+    thp_interval: Option<Duration>,
+    ksm_interval: Option<Duration>,
+    numa_interval: Option<Duration>,
+    cgroup_interval: Option<Duration>,
+    rollup_interval: Option<Duration>,
+    faults_interval: Option<Duration>,
+    track: TrackFilter,
+    watch_syscalls: HashMap<i64, String>,
+}
+
+impl Context {
+    /// Starts all the optional samplers for a process we just connected to
+    fn spawn_samplers(&self, tid: TraceeId) -> Vec<Sampler> {
+        let mut samplers = vec![];
+        if let Some(interval) = self.soft_dirty_interval {
+            samplers.push(softdirty::spawn(
+                tid,
+                self.tx.clone(),
+                interval,
+                self.track.clone(),
+            ));
+        }
+        if let Some(interval) = self.idle_interval {
+            samplers.push(idle::spawn(
+                tid,
+                self.tx.clone(),
+                interval,
+                self.track.clone(),
+            ));
+        }
+        if let Some(interval) = self.thp_interval {
+            samplers.push(thp::spawn(tid, self.tx.clone(), interval));
+        }
+        if let Some(interval) = self.ksm_interval {
+            samplers.push(ksm::spawn(
+                tid,
+                self.tx.clone(),
+                interval,
+                self.track.clone(),
+            ));
+        }
+        if let Some(interval) = self.numa_interval {
+            samplers.push(numa::spawn(tid, self.tx.clone(), interval));
+        }
+        if let Some(interval) = self.cgroup_interval {
+            samplers.push(cgroup::spawn(tid, self.tx.clone(), interval));
+        }
+        if let Some(interval) = self.rollup_interval {
+            samplers.push(rollup::spawn(tid, self.tx.clone(), interval));
+        }
+        if let Some(interval) = self.faults_interval {
+            samplers.push(faults::spawn(tid, self.tx.clone(), interval));
+        }
+        samplers
+    }
+}
+
+/// What we want to hear about from every tracee. With `exit_kill`, tracees
+/// die with us. Without `follow_children`, only new threads get traced:
+/// children the kernel doesn't attach us to go on without us.
+fn trace_options(exit_kill: bool, follow_children: bool) -> ptrace::Options {
+    let mut options = ptrace::Options::PTRACE_O_TRACESYSGOOD
+        | ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACEEXEC
+        | ptrace::Options::PTRACE_O_TRACEEXIT;
+    if follow_children {
+        options |= ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORKDONE;
+    }
+    if exit_kill {
+        options |= ptrace::Options::PTRACE_O_EXITKILL;
+    }
+    options
+}
+
+/// Starts `command` the way `options` say, stopped right after it exec'd.
+/// It sends annotations to `annotations`, if that's set.
+fn spawn(command: &[String], options: &Options, annotations: Option<&Path>) -> Result<Pid> {
+    let Options {
+        env,
+        argv0,
+        pty,
+        stdout,
+        stderr,
+        null_stdin,
+        run_as,
+        follow_children,
+        ..
+    } = options;
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..]);
+    if let Some(argv0) = argv0 {
+        cmd.arg0(argv0);
+    }
+    if env.clear {
+        cmd.env_clear();
+    }
+    for key in &env.remove {
+        cmd.env_remove(key);
+    }
+    cmd.envs(env.set.iter().map(|(key, value)| (key, value)));
+    if let Some(path) = annotations {
+        cmd.env(mevi_payload::ENV, path);
+    }
+    if *null_stdin {
+        cmd.stdin(Stdio::null());
+    }
+
+    let create = |path: &PathBuf| {
+        std::fs::File::create(path).wrap_err_with(|| format!("creating {}", path.display()))
+    };
+    if let Some(path) = stdout {
+        let out = create(path)?;
+        if stderr.as_ref() == Some(path) {
+            cmd.stderr(out.try_clone()?);
+        }
+        cmd.stdout(out);
+    }
+    if let Some(path) = stderr
+        .as_ref()
+        .filter(|path| stdout.as_ref() != Some(*path))
+    {
+        cmd.stderr(create(path)?);
+    }
+
+    let pty = pty.then(Pty::open).transpose()?;
+    if let Some(pty) = &pty {
+        let slave = || -> Result<Stdio> { Ok(pty.slave.try_clone()?.into()) };
+        cmd.stdin(slave()?).stdout(slave()?).stderr(slave()?);
+    }
+    let own_terminal = pty.is_some();
+    let run_as = run_as.clone();
+
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(run_as) = &run_as {
+                // groups first, we can't change them once we're not root
+                nix::unistd::setgroups(&run_as.groups)?;
+                nix::unistd::setgid(run_as.gid)?;
+                nix::unistd::setuid(run_as.uid)?;
+            }
+            if own_terminal {
+                // a session of its own, with the pty (its stdin by now) as
+                // the controlling terminal
+                nix::unistd::setsid()?;
+                if libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            ptrace::traceme()?;
+            Ok(())
+        });
+    }
+
+    let child = cmd
+        .spawn()
+        .wrap_err_with(|| format!("starting {}", command[0]))?;
+    // our copies of the slave go away with `cmd`
+    drop(cmd);
+    if let Some(pty) = pty {
+        pty.proxy()?;
+    }
+
+    let pid = Pid::from_raw(child.id() as _);
+    std::mem::forget(child);
+
+    let res = waitpid(pid, None)?;
+    trace!("first waitpid: {res:?}");
+
+    ptrace::setoptions(pid, trace_options(true, *follow_children))?;
+    Ok(pid)
+}
+
+/// Attaches to every thread of `pid`, stopping each of them
+fn attach(pid: Pid, exit_kill: bool, follow_children: bool) -> Result<Vec<Pid>> {
+    let mut attached = vec![];
+    // threads it starts while we're at it aren't traced yet, so go again
+    // until there are none we don't know about
+    loop {
+        let tasks = std::fs::read_dir(format!("/proc/{pid}/task"))
+            .wrap_err_with(|| format!("listing the threads of {pid}"))?;
+        let mut found = false;
+        for task in tasks {
+            let Some(tid) = task?.file_name().to_str().and_then(|s| s.parse().ok()) else {
+                continue;
+            };
+            let tid = Pid::from_raw(tid);
+            if attached.contains(&tid) {
+                continue;
+            }
+            match ptrace::attach(tid) {
+                Ok(()) => {}
+                // it exited since we listed it
+                Err(Errno::ESRCH) => continue,
+                Err(e) => {
+                    return Err(e).wrap_err_with(|| {
+                        format!(
+                            "attaching to {tid} (is kernel.yama.ptrace_scope 0, or are we root?)"
+                        )
+                    })
+                }
+            }
+            // it stops with a SIGSTOP once it's ours
+            let res = waitpid(tid, Some(WaitPidFlag::__WALL))?;
+            trace!("first waitpid for {tid}: {res:?}");
+            ptrace::setoptions(tid, trace_options(exit_kill, follow_children))?;
+            info!("attached to {tid}");
+            attached.push(tid);
+            found = true;
+        }
+        if !found {
+            break;
+        }
+    }
+    if attached.is_empty() {
+        bail!("{pid} is gone");
+    }
+    Ok(attached)
+}
+
+/// Where tracees connect to send us their userfaultfd. There's one per
+/// tracer, and a process can have more than one.
+fn bind_sock() -> Result<UnixListener> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let path = match n {
+        0 => format!("/tmp/mevi-{}.sock", std::process::id()),
+        n => format!("/tmp/mevi-{}-{n}.sock", std::process::id()),
+    };
+    _ = std::fs::remove_file(&path);
+    UnixListener::bind(&path).wrap_err_with(|| format!("listening on {path}"))
+}
+
+/// Traces processes, and sends what happens to their memory (and to them)
+/// to an [EventSink]
+pub struct Tracer {
+    ctx: Context,
+    tracees: HashMap<TraceeId, Tracee>,
+
+    /// Which process forked each process we trace, if we trace that one too
+    parents: HashMap<TraceeId, TraceeId>,
+
+    /// Where the program we started is kept to `--memory-limit`
+    _cgroup: Option<cgroup::Transient>,
+
+    /// Where the program we started (and what it starts) sends annotations
+    _annotations: Option<Annotations>,
+
+    max_duration: Option<Duration>,
+    max_events: Option<u64>,
+    on_limit: OnLimit,
+
+    /// The process we started or attached to, and how it ended, as an exit
+    /// code, once it has
+    root: TraceeId,
+    root_exit_code: Option<i32>,
+
+    /// What we wait for: only what this thread traces, unless every child
+    /// of the process is ours to reap, cf. [Options::subreaper]
+    wait_flags: WaitPidFlag,
+
+    /// What tells us what to do besides tracing, cf. [Control]
+    control: Option<Control>,
+
+    /// Whether the tracees are frozen, and the stops we're sitting on until
+    /// they thaw
+    paused: bool,
+    held: VecDeque<WaitStatus>,
+
+    /// What to evict, by process, once one of its threads makes a syscall
+    evictions: HashMap<TraceeId, Vec<Eviction>>,
+}
+
+impl Tracer {
+    /// Starts (or attaches to) what `options` say to trace. It's stopped
+    /// until [Self::run] is called, from the same thread: ptrace only
+    /// listens to the thread that attached.
+    pub fn new(sink: impl EventSink + 'static, options: Options) -> Result<Self> {
+        if options.subreaper {
+            let errno = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+            if errno < 0 {
+                panic!(
+                    "while setting ourselves as the child subreaper: {}",
+                    nix::Error::from_i32(errno)
+                );
+            }
+        }
+
+        let listener = bind_sock()?;
+        let tx = Events::new(sink);
+        // programs we start can tell us what they're doing, cf. mevi-payload
+        let annotations = match (&options.target, listener.local_addr()?.as_pathname()) {
+            (Target::Spawn(_), Some(path)) => Some(Annotations::listen(
+                path.with_extension("payload.sock"),
+                tx.clone(),
+            )?),
+            _ => None,
+        };
+        if let (Target::Spawn(_), Some(run_as)) = (&options.target, &options.run_as) {
+            // the tracee has to be able to connect to us to send its
+            // userfaultfd over, and its annotations
+            if let Some(path) = listener.local_addr()?.as_pathname() {
+                nix::unistd::chown(path, Some(run_as.uid), Some(run_as.gid))?;
+            }
+            if let Some(annotations) = &annotations {
+                nix::unistd::chown(annotations.path(), Some(run_as.uid), Some(run_as.gid))?;
+            }
+        }
+
+        let cgroup = match (&options.target, options.memory_limit) {
+            (Target::Spawn(_), Some(max)) => Some(cgroup::Transient::create(max)?),
+            _ => None,
+        };
+
+        let pids = match &options.target {
+            Target::Spawn(command) => {
+                let pid = spawn(
+                    command,
+                    &options,
+                    annotations.as_ref().map(Annotations::path),
+                )?;
+                // it hasn't run any of its own code yet
+                if let Some(cgroup) = &cgroup {
+                    cgroup.add(pid.into())?;
+                }
+                vec![pid]
+            }
+            // with uffd, its faults end up waiting on us, so it can't go on
+            // without us
+            Target::Attach(pid) => attach(
+                Pid::from_raw(*pid as _),
+                options.backend == Backend::Uffd,
+                options.follow_children,
+            )?,
+        };
+        let root = pids[0].into();
+        for pid in pids {
+            ptrace::syscall(pid, None)?;
+        }
+
+        Ok(Self {
+            ctx: Context {
+                tx,
+                listener: Arc::new(listener),
+                backend: options.backend,
+                poll_interval: options.poll_interval,
+                inject_on: options.inject_on,
+                write_protect: options.write_protect,
+                coalesce_window: options.coalesce_window,
+                symbolicate: options.symbolicate,
+                bucket: options.bucket,
+                swap_interval: options.swap_interval,
+                soft_dirty_interval: options.soft_dirty_interval,
+                idle_interval: options.idle_interval,
+                thp_interval: options.thp_interval,
+                ksm_interval: options.ksm_interval,
+                numa_interval: options.numa_interval,
+                cgroup_interval: options.cgroup_interval,
+                rollup_interval: options.rollup_interval,
+                faults_interval: options.faults_interval,
+                track: options.track.clone(),
+                watch_syscalls: options.watch_syscalls.clone(),
+            },
+            tracees: Default::default(),
+            parents: Default::default(),
+            _cgroup: cgroup,
+            _annotations: annotations,
+            max_duration: options.max_duration,
+            max_events: options.max_events,
+            on_limit: options.on_limit,
+            root,
+            root_exit_code: None,
+            wait_flags: if options.subreaper {
+                WaitPidFlag::__WALL
+            } else {
+                WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD
+            },
+            control: None,
+            paused: false,
+            held: Default::default(),
+            evictions: Default::default(),
+        })
+    }
+
+    /// Does what `control` asks for, as it's asked for
+    pub fn controlled(mut self, control: Control) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Traces until every tracee is gone, or until we stop tracing them.
+    /// Returns the exit code the process we started (or attached to) would
+    /// have had, like a shell has it: its exit status, or 128 plus the signal
+    /// that killed it. `None` if we didn't see it end.
+    pub fn run(&mut self) -> Result<Option<i32>> {
+        let limited = self.max_duration.is_some() || self.max_events.is_some();
+        // the watchdog and the control API send us SIGALRM when they want
+        // our attention, so this goes before either of them
+        let interruptible = (limited || self.control.is_some())
+            .then(limits::interruptible)
+            .transpose()?;
+        let wait_flags = Some(self.wait_flags);
+        let wait = || match &interruptible {
+            Some(interruptible) => interruptible.wait(|| waitpid(None, wait_flags)),
+            None => waitpid(None, wait_flags),
+        };
+        let mut watchdog = limited
+            .then(|| Watchdog::spawn(self.max_duration, self.max_events, self.ctx.tx.sent()));
+        let _watching = self.control.as_ref().map(Control::watch);
+        // once a limit is hit with `--on-limit kill`, anything that shows up
+        // after that gets killed too
+        let mut killing = false;
+
+        'main_loop: loop {
+            if watchdog.as_ref().is_some_and(Watchdog::hit) {
+                watchdog = None;
+                match self.on_limit {
+                    OnLimit::Kill => {
+                        info!("killing every tracee");
+                        killing = true;
+                        for tid in self.tracees.keys() {
+                            _ = kill(Pid::from_raw(tid.0 as _), Signal::SIGKILL);
+                        }
+                    }
+                    OnLimit::Detach => {
+                        self.detach_all();
+                        return Ok(None);
+                    }
+                }
+            }
+
+            if let Some(control) = &self.control {
+                control.acknowledge();
+                for eviction in control.take_evictions() {
+                    self.evictions
+                        .entry(eviction.tid)
+                        .or_default()
+                        .push(eviction);
+                }
+                if control.paused() != self.paused {
+                    self.paused = !self.paused;
+                    if self.paused {
+                        info!("freezing {} tracees", self.tracees.len());
+                        for tid in self.tracees.keys() {
+                            stop(*tid);
+                        }
+                    } else {
+                        info!("thawing {} tracees", self.tracees.len());
+                    }
+                }
+            }
+
+            // once thawed, the stops we held on to go first
+            let held = if self.paused {
+                None
+            } else {
+                self.held.pop_front()
+            };
+            let wait_status = match held.map(Ok).unwrap_or_else(wait) {
+                Ok(s) => s,
+                // the watchdog (or the control API) wants our attention
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    if e == nix::errno::Errno::ECHILD {
+                        info!("no more children");
+                        break 'main_loop;
+                    } else {
+                        panic!("waitpid failed: {}", e);
+                    }
+                }
+            };
+            if let WaitStatus::Exited(pid, _) | WaitStatus::Signaled(pid, ..) = wait_status {
+                if !self.knows(pid.into()) {
+                    // some other child of the process we're part of, that we
+                    // reaped as the subreaper
+                    debug!("{pid} isn't ours, ignoring its {wait_status:?}");
+                    continue;
+                }
+            }
+            if killing {
+                if let Some(pid) = wait_status.pid() {
+                    _ = kill(pid, Signal::SIGKILL);
+                }
+            }
+            if self.paused
+                && matches!(
+                    wait_status,
+                    WaitStatus::Stopped(..)
+                        | WaitStatus::PtraceSyscall(_)
+                        | WaitStatus::PtraceEvent(..)
+                )
+            {
+                // it stays stopped for as long as we don't resume it
+                self.held.push_back(wait_status);
+                continue;
+            }
+
+            tracing::debug!("wait_status: {:?}", wait_status);
+            match wait_status {
+                WaitStatus::Stopped(pid, sig) => {
+                    let tid: TraceeId = pid.into();
+                    match sig {
+                        Signal::SIGWINCH | Signal::SIGSTOP => {
+                            // don't show those, they're spammy
+                        }
+                        _ => {
+                            info!("{tid} caught sig {sig}");
+                        }
+                    }
+
+                    match sig {
+                        Signal::SIGTRAP => {
+                            // probably ptrace stuff?
+                            ptrace::syscall(pid, None)?;
+                        }
+                        Signal::SIGSTOP => {
+                            // probably a process freshly cloned or forked
+                            ptrace::syscall(pid, None)?;
+                        }
+                        _ => {
+                            // probably not ptrace stuff, forward the signal?
+                            ptrace::syscall(pid, sig)?;
+                        }
+                    }
+                    continue;
+                }
+                WaitStatus::Exited(pid, status) => {
+                    if TraceeId::from(pid) == self.root {
+                        self.root_exit_code = Some(status);
+                    }
+                    if status == 0 {
+                        debug!("{pid} exited with status {status}");
+                    } else {
+                        warn!("{pid} exited with non-zero status {status}");
+                    }
+                    self.exited(pid.into())?;
+                }
+                WaitStatus::PtraceSyscall(pid) => {
+                    let tid: TraceeId = pid.into();
+                    debug!("{tid} in sys_enter / sys_exit");
+
+                    let tracee = self.tracees.entry(tid).or_insert_with(|| Tracee {
+                        was_in_syscall: false,
+                        tid,
+                        kind: TraceeKind::Fresh,
+                    });
+
+                    if tracee.was_in_syscall {
+                        tracee.was_in_syscall = false;
+
+                        if let Some(MemoryEvent { for_tid, change }) =
+                            tracee.on_sys_exit(&self.ctx)?
+                        {
+                            if matches!(tracee.kind, TraceeKind::Fresh) {
+                                warn!(
+                                    "{} unknown tracee kind, and Mapped, assuming process",
+                                    tracee.tid
+                                );
+                            }
+
+                            match change {
+                                MemoryChange::Map {
+                                    range,
+                                    mut state,
+                                    raw_flags,
+                                    backing,
+                                    kind,
+                                    file,
+                                    perms,
+                                } => {
+                                    let formatter = make_format(BINARY);
+                                    info!(
+                                        "{tid} => {for_tid} mapping {range:x?} ({}) with {state:?} ({backing:?}, raw flags: {raw_flags:x?})",
+                                        formatter(range.end - range.start)
+                                    );
+                                    let tracked = self.ctx.track.parts(&range, kind, file.as_ref());
+                                    if tracked.is_empty() {
+                                        state = MemState::Untracked;
+                                    }
+                                    let target = self.tracees.get(&for_tid).unwrap();
+                                    match (&target.kind, backing) {
+                                        (TraceeKind::Fresh, _) => unreachable!(),
+                                        (TraceeKind::Thread { pid }, _) => {
+                                            panic!("thread {for_tid} of process {pid} mapping memory should show up in the parent");
+                                        }
+                                        (_, None) => state = MemState::Untracked,
+                                        (
+                                            TraceeKind::Process {
+                                                tracking: Tracking::Uffd { uffd, modes, .. },
+                                                ..
+                                            },
+                                            Some(backing),
+                                        ) => {
+                                            if !modes.can_track(backing) {
+                                                state = MemState::Untracked;
+                                            } else if let Err(e) =
+                                                tracked.iter().try_for_each(|part| {
+                                                    register(uffd, part, *modes, backing)
+                                                })
+                                            {
+                                                tracing::warn!(
+                                                    "failed to register {range:?} with uffd: {e:?}"
+                                                );
+                                                state = MemState::Untracked;
+                                            }
+                                        }
+                                        (
+                                            TraceeKind::Process {
+                                                tracking: Tracking::Pagemap { .. },
+                                                ..
+                                            },
+                                            Some(_),
+                                        ) => {
+                                            // the poller will pick it up
+                                        }
+                                    }
+
+                                    // whatever we don't track shows up as untracked
+                                    let whole = tracked.len() == 1 && tracked[0] == range;
+                                    let ev = MeviEvent::TraceeEvent(
+                                        for_tid,
+                                        TraceePayload::Map {
+                                            range,
+                                            state: if whole { state } else { MemState::Untracked },
+                                            kind,
+                                            file,
+                                            perms,
+                                            raw: raw_flags.map(|raw| raw.to_event()),
+                                        },
+                                    );
+                                    self.ctx.tx.send(ev)?;
+                                    if !whole && state != MemState::Untracked {
+                                        for range in tracked {
+                                            let ev = MeviEvent::TraceeEvent(
+                                                for_tid,
+                                                TraceePayload::MemStateChange { range, state },
+                                            );
+                                            self.ctx.tx.send(ev)?;
+                                        }
+                                    }
+                                }
+                                MemoryChange::Remap {
+                                    old_range,
+                                    new_range,
+                                } => {
+                                    // note: uffd follows remaps, we don't need to
+                                    // unregister or re-register anything
+
+                                    let ev = MeviEvent::TraceeEvent(
+                                        for_tid,
+                                        TraceePayload::Remap {
+                                            old_range,
+                                            new_range,
+                                        },
+                                    );
+                                    self.ctx.tx.send(ev)?;
+                                }
+                                MemoryChange::Unmap { range } => {
+                                    // note: uffd follows unmaps, we don't need
+                                    // to unregister anything.
+
+                                    let ev = MeviEvent::TraceeEvent(
+                                        for_tid,
+                                        TraceePayload::Unmap { range },
+                                    );
+                                    self.ctx.tx.send(ev)?;
+                                }
+                                MemoryChange::PageOut { range } => {
+                                    let ev = MeviEvent::TraceeEvent(
+                                        for_tid,
+                                        TraceePayload::MemStateChange {
+                                            range,
+                                            state: MemState::NotResident,
+                                        },
+                                    );
+                                    self.ctx.tx.send(ev)?;
+                                }
+                                MemoryChange::MemPolicy { range, mode, nodes } => {
+                                    let ev = MeviEvent::TraceeEvent(
+                                        for_tid,
+                                        TraceePayload::MemPolicy { range, mode, nodes },
+                                    );
+                                    self.ctx.tx.send(ev)?;
+                                }
+                                MemoryChange::ThreadName { thread, name } => {
+                                    let ev = MeviEvent::TraceeEvent(
+                                        for_tid,
+                                        TraceePayload::ThreadName { thread, name },
+                                    );
+                                    self.ctx.tx.send(ev)?;
+                                }
+                                MemoryChange::Reclaim { range } => {
+                                    // only report what actually left memory, pages
+                                    // that are still there keep their state
+                                    match pagemap::scan_range(for_tid, range.clone()) {
+                                        Ok(map) => {
+                                            for (range, state) in map.iter() {
+                                                if state.is_resident() {
+                                                    continue;
+                                                }
+                                                let ev = MeviEvent::TraceeEvent(
+                                                    for_tid,
+                                                    TraceePayload::MemStateChange {
+                                                        range: range.clone(),
+                                                        state: *state,
+                                                    },
+                                                );
+                                                self.ctx.tx.send(ev)?;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            warn!("{for_tid} couldn't scan {range:x?} after reclaim: {e}");
+                                        }
+                                    }
+                                }
+                                MemoryChange::TraceeUffd => {
+                                    let target = self.tracees.get_mut(&for_tid).unwrap();
+                                    if let TraceeKind::Process { tracking, .. } = &mut target.kind {
+                                        if matches!(tracking, Tracking::Uffd { .. }) {
+                                            tracking.fall_back_to_polling(for_tid, &self.ctx);
+
+                                            let ev = MeviEvent::TraceeEvent(
+                                                for_tid,
+                                                TraceePayload::Warning {
+                                                    message: "process uses userfaultfd itself, falling back to pagemap polling".to_string(),
+                                                },
+                                            );
+                                            self.ctx.tx.send(ev)?;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        self.evict_pending(tid);
+                        if let Err(e) = ptrace::syscall(pid, None) {
+                            if e == nix::errno::Errno::ESRCH {
+                                // the process has exited, we don't care
+                                info!("{pid} exited while we spied");
+                            } else {
+                                panic!("while doing setregs: {e:?}")
+                            }
+                        }
+                    } else {
+                        tracee.was_in_syscall = true;
+                        match ptrace::syscall(pid, None) {
+                            Ok(_) => {}
+                            Err(e) => {
+                                if e == nix::errno::Errno::ESRCH {
+                                    // the process has exited, we don't care
+                                    info!(
+                                        "{tid} exited while we were spying on its syscalls, that's ok"
+                                    );
+                                } else {
+                                    panic!("{tid} ptrace::syscall failed: {e:?}");
+                                }
+                            }
+                        }
+                    }
+                }
+                WaitStatus::PtraceEvent(pid, sig, event) => {
+                    let tid: TraceeId = pid.into();
+                    let child_tid = TraceeId(ptrace::getevent(pid)? as _);
+
+                    match event {
+                        libc::PTRACE_EVENT_FORK => {
+                            info!("{tid} forked into {child_tid} (with {sig})");
+                            // don't replace whatever we have in `self.tracees`,
+                            // sometimes we get that event AFTER getting some
+                            // sys_enter/sys_exit from the child
+                            self.forked(tid, child_tid)?;
+                        }
+                        libc::PTRACE_EVENT_VFORK => {
+                            info!("{tid} vforked into {child_tid} (with {sig})");
+                            self.tracees.insert(
+                                child_tid,
+                                Tracee {
+                                    was_in_syscall: false,
+                                    tid: child_tid,
+                                    kind: TraceeKind::Fresh {},
+                                },
+                            );
+                            self.forked(tid, child_tid)?;
+                        }
+                        libc::PTRACE_EVENT_VFORK_DONE => {
+                            info!("{tid} vfork-doned into {child_tid} (with {sig})");
+                        }
+                        libc::PTRACE_EVENT_CLONE => {
+                            info!("{tid} cloned into {child_tid} (with {sig})");
+                            let tracee = match self.tracees.get(&tid) {
+                                Some(t) => t,
+                                None => {
+                                    panic!("{tid} cloned, but we didn't know about that process");
+                                }
+                            };
+                            if let TraceeKind::Thread { pid } = &tracee.kind {
+                                info!("{tid} is a thread of {pid}, so it made a sibling!");
+                                self.tracees.insert(
+                                    child_tid,
+                                    Tracee {
+                                        was_in_syscall: false,
+                                        tid: child_tid,
+                                        kind: TraceeKind::Thread { pid: *pid },
+                                    },
+                                );
+                            } else {
+                                self.tracees.insert(
+                                    child_tid,
+                                    Tracee {
+                                        was_in_syscall: false,
+                                        tid: child_tid,
+                                        kind: TraceeKind::Thread { pid: tid },
+                                    },
+                                );
+                            }
+
+                            // it goes by its creator's name until it picks one
+                            if let (TraceeKind::Thread { pid }, Some(name)) =
+                                (&self.tracees[&child_tid].kind, thread_name(child_tid))
+                            {
+                                let ev = MeviEvent::TraceeEvent(
+                                    *pid,
+                                    TraceePayload::ThreadName {
+                                        thread: child_tid,
+                                        name,
+                                    },
+                                );
+                                self.ctx.tx.send(ev)?;
+                            }
+                        }
+                        libc::PTRACE_EVENT_EXEC => {
+                            info!("{tid} exec'd with sig {sig}");
+                            let tracee = match self.tracees.get_mut(&tid) {
+                                Some(t) => t,
+                                None => {
+                                    panic!("{tid} exec'd, but we didn't know about that process");
+                                }
+                            };
+                            // this clear out the uffd, too
+                            tracee.kind = TraceeKind::Fresh;
+
+                            // the new program is in place by now
+                            let exe = std::fs::read_link(format!("/proc/{}/exe", tid.0))
+                                .ok()
+                                .map(|path| path.to_string_lossy().into_owned());
+                            let interpreter = exe.as_deref().and_then(elf_interpreter);
+                            info!("{tid} is now running {exe:?} (interpreter {interpreter:?})");
+                            let ev = TraceePayload::Exec {
+                                exe,
+                                argv: get_cmdline(tid),
+                                interpreter,
+                            };
+                            self.ctx.tx.send(MeviEvent::TraceeEvent(tid, ev))?;
+                        }
+                        libc::PTRACE_EVENT_EXIT => {
+                            info!("{tid} exited with sig {sig}");
+                            // for this event, what we got is the wait status
+                            let status = child_tid.0 as i32;
+                            if libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGKILL
+                            {
+                                self.check_oom_kill(tid)?;
+                            }
+                            self.exited(tid)?;
+                        }
+                        _ => {
+                            info!(
+                                "{tid} got event {event} with sig {sig}, child_tid = {child_tid}"
+                            );
+                            // do nothing
+                        }
+                    }
+
+                    ptrace::syscall(pid, None)?;
+                }
+                WaitStatus::Signaled(pid, signal, core_dump) => {
+                    let tid: TraceeId = pid.into();
+                    info!("{tid} was terminated with signal {signal} with, WCOREDUMP({core_dump})");
+                    if tid == self.root {
+                        self.root_exit_code = Some(128 + signal as i32);
+                    }
+                    if signal == Signal::SIGKILL {
+                        self.check_oom_kill(tid)?;
+                    }
+                    self.exited(tid)?;
+                }
+                other => {
+                    panic!("unexpected wait status: {:?}", other);
+                }
+            }
+        }
+
+        // whoever's reading events finds out we're done once the last
+        // sender is gone, and wraps up from there
+        info!("every tracee is gone");
+        Ok(self.root_exit_code)
+    }
+
+    /// Does whatever evictions were asked of `tid`'s process, now that `tid`
+    /// is stopped at a syscall exit and can make a syscall of ours
+    fn evict_pending(&mut self, tid: TraceeId) {
+        let process = match self.tracees.get(&tid).map(|tracee| &tracee.kind) {
+            Some(TraceeKind::Thread { pid }) => *pid,
+            Some(TraceeKind::Process { .. }) => tid,
+            // not connected yet, it'll get to it
+            _ => return,
+        };
+        let Some(evictions) = self.evictions.remove(&process) else {
+            return;
+        };
+        let saved_regs = match ptrace::getregs(tid.into()) {
+            Ok(regs) => regs,
+            Err(e) => {
+                warn!("{tid} couldn't evict anything: {e}");
+                return;
+            }
+        };
+
+        for Eviction { range, advice, .. } in evictions {
+            let len = range.end - range.start;
+            let args = [range.start, len, advice.raw() as _];
+            match inject(tid, &saved_regs, libc::SYS_madvise, &args) {
+                Ok(ret) => match syscall_error(ret) {
+                    Some(errno) => {
+                        warn!(
+                            "{process} couldn't evict {range:x?} ({}): {errno}",
+                            advice.name()
+                        )
+                    }
+                    None => {
+                        info!("{process} evicted {range:x?} ({})", advice.name());
+                        evict::watch(process, range, self.ctx.tx.clone());
+                    }
+                },
+                Err(e) => warn!("{process} couldn't evict {range:x?}: {e}"),
+            }
+        }
+
+        if let Err(e) = ptrace::setregs(tid.into(), saved_regs) {
+            warn!("{tid} couldn't get its registers back after evicting: {e}");
+        }
+    }
+
+    /// Lets every tracee go on without us: takes our userfaultfds off them,
+    /// stops them, detaches from every thread and lets them continue. Then
+    /// ends the session, which can't tell otherwise.
+    fn detach_all(&mut self) {
+        info!("detaching from every tracee");
+        let mut processes = vec![];
+        for (tid, tracee) in &self.tracees {
+            match &tracee.kind {
+                TraceeKind::Thread { .. } => continue,
+                TraceeKind::Process {
+                    tracking: Tracking::Uffd { uffd, .. },
+                    ..
+                } => release_ranges(*tid, uffd),
+                _ => {}
+            }
+            processes.push(Pid::from_raw(tid.0 as _));
+        }
+        for pid in &processes {
+            _ = kill(*pid, Signal::SIGSTOP);
+        }
+
+        let mut pending: HashSet<TraceeId> = self.tracees.keys().copied().collect();
+        while !pending.is_empty() {
+            // those are stopped already, and won't tell us again
+            let held = self.held.pop_front();
+            let wait_status = match held
+                .map(Ok)
+                .unwrap_or_else(|| waitpid(None, Some(self.wait_flags)))
+            {
+                Ok(s) => s,
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    debug!("waitpid failed while detaching: {e}");
+                    break;
+                }
+            };
+            let Some(pid) = wait_status.pid() else {
+                continue;
+            };
+            pending.remove(&pid.into());
+            let res = match wait_status {
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => continue,
+                // let signals through, our SIGSTOP included, so that the
+                // other threads stop too
+                WaitStatus::Stopped(_, sig) => ptrace::detach(pid, sig),
+                _ => ptrace::detach(pid, None),
+            };
+            if let Err(e) = res {
+                warn!("couldn't detach from {pid}: {e}");
+            }
+        }
+        for pid in &processes {
+            _ = kill(*pid, Signal::SIGCONT);
+        }
+
+        for (_, tracee) in self.tracees.drain() {
+            // uffd handler threads are blocked reading them, and would report
+            // the process as dead if we closed them
+            match tracee.kind {
+                TraceeKind::Process {
+                    tracking: Tracking::Uffd { uffd, .. },
+                    ..
+                }
+                | TraceeKind::Process {
+                    tracking:
+                        Tracking::Pagemap {
+                            retired: Some(uffd),
+                            ..
+                        },
+                    ..
+                } => std::mem::forget(uffd),
+                _ => {}
+            }
+        }
+        self.ctx.tx.hang_up();
+    }
+
+    /// Whether `tid` is something we trace, or are about to
+    fn knows(&self, tid: TraceeId) -> bool {
+        tid == self.root || self.tracees.contains_key(&tid) || self.parents.contains_key(&tid)
+    }
+
+    /// `child` is a new process, forked by a thread of some process
+    fn forked(&mut self, tid: TraceeId, child: TraceeId) -> Result<()> {
+        let parent = match self.tracees.get(&tid).map(|t| &t.kind) {
+            Some(TraceeKind::Thread { pid }) => *pid,
+            _ => tid,
+        };
+        self.parents.insert(child, parent);
+        let ev = MeviEvent::TraceeEvent(
+            child,
+            TraceePayload::ParentChange {
+                parent: Some(parent),
+            },
+        );
+        self.ctx.tx.send(ev)?;
+        Ok(())
+    }
+
+    /// `tid` is gone. If it was a process, the kernel hands its children to
+    /// the closest subreaper, which is us: as far as the trace goes, they
+    /// have no parent anymore.
+    fn exited(&mut self, tid: TraceeId) -> Result<()> {
+        let ev = MeviEvent::TraceeEvent(tid, TraceePayload::Exit);
+        self.ctx.tx.send(ev)?;
+
+        self.parents.remove(&tid);
+        let orphans: Vec<_> = self
+            .parents
+            .iter()
+            .filter(|(_, parent)| **parent == tid)
+            .map(|(child, _)| *child)
+            .collect();
+        for orphan in orphans {
+            self.parents.remove(&orphan);
+            let ev = MeviEvent::TraceeEvent(orphan, TraceePayload::ParentChange { parent: None });
+            self.ctx.tx.send(ev)?;
+        }
+        Ok(())
+    }
+
+    /// For a process that just got SIGKILLed, tells the frontend if it was
+    /// the OOM killer. We may get to check twice (on exit, and once it's
+    /// dead), but only report once.
+    fn check_oom_kill(&mut self, tid: TraceeId) -> Result<()> {
+        if let Some(Tracee {
+            kind: TraceeKind::Process { oom, .. },
+            ..
+        }) = self.tracees.get_mut(&tid)
+        {
+            if let Some(rss) = oom.oom_killed() {
+                warn!("{tid} was killed by the OOM killer");
+                let ev = MeviEvent::TraceeEvent(tid, TraceePayload::OomKilled { rss });
+                self.ctx.tx.send(ev)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        // nobody's connecting anymore
+        if let Some(path) = self
+            .ctx
+            .listener
+            .local_addr()
+            .ok()
+            .as_ref()
+            .and_then(|addr| addr.as_pathname())
+        {
+            _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+struct Tracee {
+    was_in_syscall: bool,
+    tid: TraceeId,
+    kind: TraceeKind,
+}
+
+enum TraceeKind {
+    // we're not sure yet, we're waiting for ptrace to tell us about it
+    Fresh,
+
+    // it's a process, and we know how to track its memory
+    Process {
+        heap_range: Range<u64>,
+        tracking: Tracking,
+
+        // stopped when the process execs or goes away
+        _samplers: Vec<Sampler>,
+
+        // to tell OOM kills from other SIGKILLs
+        oom: OomWatch,
+    },
+
+    // it's a thread of a process we know about
+    Thread {
+        pid: TraceeId,
+    },
+}
+
+enum Tracking {
+    // we injected a userfaultfd, faults tell us what becomes resident
+    Uffd {
+        uffd: Uffd,
+        modes: UffdModes,
+
+        // swapping doesn't go through uffd, so we look for it separately
+        _swap_watcher: Option<Sampler>,
+    },
+
+    // we periodically read pagemap
+    Pagemap {
+        _poller: Sampler,
+
+        // the userfaultfd we gave up on, if any. it has nothing registered
+        // anymore, but its handler thread is still blocked reading it, and
+        // closing it from under it would make it report the process as dead.
+        retired: Option<Uffd>,
+    },
+}
+
+impl Tracking {
+    /// Stops tracking the process with our userfaultfd (releasing all the
+    /// ranges we registered, so the tracee can register them with its own)
+    /// and starts polling pagemap instead.
+    fn fall_back_to_polling(&mut self, tid: TraceeId, ctx: &Context) {
+        if let Tracking::Uffd { uffd, .. } = self {
+            release_ranges(tid, uffd);
+        }
+
+        let poller =
+            pagemap::spawn_poller(tid, ctx.tx.clone(), ctx.poll_interval, ctx.track.clone());
+        let prev = std::mem::replace(
+            self,
+            Tracking::Pagemap {
+                _poller: poller,
+                retired: None,
+            },
+        );
+        if let (Tracking::Uffd { uffd, .. }, Tracking::Pagemap { retired, .. }) = (prev, self) {
+            *retired = Some(uffd);
+        }
+    }
+}
+
+impl Tracee {
+    /// Reads a node mask (as passed to `mbind` and `set_mempolicy`) out of
+    /// the tracee's memory, returning the node numbers that are set.
+    fn read_nodemask(&self, addr: u64, maxnode: u64) -> Result<Vec<u32>> {
+        if addr == 0 {
+            return Ok(vec![]);
+        }
+
+        // that's 1024 nodes, plenty
+        const MAX_WORDS: u64 = 16;
+        let num_words = maxnode.div_ceil(64).min(MAX_WORDS);
+
+        let mut nodes = vec![];
+        for i in 0..num_words {
+            let word = ptrace::read(self.tid.into(), (addr + i * 8) as _)? as u64;
+            for bit in 0..64 {
+                if word & (1 << bit) != 0 {
+                    nodes.push((i * 64 + bit) as u32);
+                }
+            }
+        }
+        Ok(nodes)
+    }
+
+    fn on_sys_exit(&mut self, ctx: &Context) -> Result<Option<MemoryEvent>> {
+        let regs = ptrace::getregs(self.tid.into())?;
+        trace!("on sys_exit: {regs:?}");
+        let ret = regs.rax;
+
+        if let Some(name) = ctx.watch_syscalls.get(&(regs.orig_rax as i64)) {
+            let pid = match &self.kind {
+                TraceeKind::Thread { pid } => *pid,
+                _ => self.tid,
+            };
+            let ev = TraceePayload::Syscall {
+                thread: self.tid,
+                name: name.clone(),
+                args: [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9],
+                ret: ret as i64,
+            };
+            ctx.tx.send(MeviEvent::TraceeEvent(pid, ev))?;
+        }
+
+        if matches!(self.kind, TraceeKind::Fresh) {
+            let syscall_nr = regs.orig_rax as i64;
+            if !ctx.inject_on.should_connect(&regs) {
+                trace!(
+                    "{} not connecting out of syscall nr. {syscall_nr}",
+                    self.tid
+                );
+                return Ok(None);
+            }
+
+            info!("{} connecting out of syscall nr. {syscall_nr}", self.tid);
+            let res = match ctx.backend {
+                Backend::Uffd => self.connect(regs, ctx),
+                Backend::Pagemap => self.connect_polling(ctx),
+            };
+            if let Err(e) = res {
+                if let Some(nix_err) = e.downcast_ref::<nix::Error>() {
+                    if nix_err == &nix::Error::ESRCH {
+                        // the process has exited, we don't care
+                        info!(
+                            "{} exited while we were trying to connect to it, that's ok",
+                            self.tid
+                        );
+                        return Ok(None);
+                    }
+                }
+                panic!("while connecting: {e:?}");
+            }
+
+            if syscall_nr != libc::SYS_userfaultfd {
+                // `connect` just walked the process's maps, so whatever this
+                // syscall did is already accounted for.
+                return Ok(None);
+            }
+        }
+
+        let for_tid = match &self.kind {
+            TraceeKind::Thread { pid } => *pid,
+            TraceeKind::Fresh => {
+                // nevermind then
+                return Ok(None);
+            }
+            TraceeKind::Process { .. } => self.tid,
+        };
+
+        if let Some(errno) = syscall_error(ret) {
+            // none of the syscalls we decode change anything when they fail,
+            // and their return value is not an address we should be mapping.
+            // note that `brk` never gets here: on failure, it returns the
+            // current program break, which the brk handling below treats as
+            // "nothing changed".
+            debug!(
+                "{} thread of {for_tid} syscall {} failed with {errno}, ignoring",
+                self.tid, regs.orig_rax
+            );
+            return Ok(None);
+        }
+
+        match regs.orig_rax as i64 {
+            libc::SYS_mmap => {
+                let addr_in = regs.rdi;
+                let len = regs.rsi;
+                let prot = regs.rdx;
+                let flags = regs.r10;
+                let fd = regs.r8 as i32;
+                let raw_flags = RawMmapFlags { prot, flags };
+                let map_flags = raw_flags.map_flags();
+                let prot_flags = raw_flags.prot_flags();
+                if raw_flags.unknown_prot() != 0 || raw_flags.unknown_flags() != 0 {
+                    warn!(
+                        "{} mmap with unknown bits: prot={:x?} flags={:x?}",
+                        self.tid,
+                        raw_flags.unknown_prot(),
+                        raw_flags.unknown_flags()
+                    );
+                }
+
+                let file = if fd >= 0 {
+                    mapped_file(self.tid, fd, regs.r9)
+                } else {
+                    None
+                };
+                let backing = if fd == -1 && map_flags.contains(MapFlags::MAP_ANONYMOUS) {
+                    if map_flags.contains(MapFlags::MAP_HUGETLB) {
+                        Some(Backing::Hugetlbfs)
+                    } else if map_flags.contains(MapFlags::MAP_SHARED) {
+                        Some(Backing::Shmem)
+                    } else {
+                        Some(Backing::Anonymous)
+                    }
+                } else {
+                    // shm_open & co.
+                    file.as_ref()
+                        .and_then(|file| Backing::of_path(Path::new(&file.path)))
+                };
+
+                let backing = backing.filter(|_| {
+                    addr_in == 0
+                        && prot_flags.contains(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)
+                });
+                // we can't track other files, but they're good to know about
+                if backing.is_some() || file.is_some() {
+                    let start = ret;
+                    if let Some(end) = ret.checked_add(len) {
+                        let range = start..end;
+                        debug!("{} thread of {for_tid} just did mmap {range:x?} addr_in={addr_in:x?} len={len:x?} prot=({prot_flags:?}) flags=({map_flags:?}) fd={fd} ret={ret:x?}", self.tid);
+                        return Ok(Some(MemoryEvent {
+                            for_tid,
+                            change: MemoryChange::Map {
+                                range,
+                                state: if map_flags.contains(MapFlags::MAP_POPULATE) {
+                                    MemState::Resident
+                                } else {
+                                    MemState::NotResident
+                                },
+                                raw_flags: Some(raw_flags),
+                                backing,
+                                kind: match backing {
+                                    // glibc asks for that for thread stacks
+                                    Some(Backing::Anonymous)
+                                        if map_flags.contains(MapFlags::MAP_STACK) =>
+                                    {
+                                        MappingKind::ThreadStack
+                                    }
+                                    Some(Backing::Anonymous) => MappingKind::Anonymous,
+                                    Some(Backing::Shmem | Backing::Hugetlbfs) => {
+                                        MappingKind::SharedMemory
+                                    }
+                                    None if prot_flags.contains(ProtFlags::PROT_EXEC) => {
+                                        MappingKind::Code
+                                    }
+                                    None if prot_flags.contains(ProtFlags::PROT_WRITE)
+                                        && map_flags.contains(MapFlags::MAP_PRIVATE) =>
+                                    {
+                                        MappingKind::Data
+                                    }
+                                    None => MappingKind::File,
+                                },
+                                file,
+                                perms: raw_flags.perms(),
+                            },
+                        }));
+                    }
+                }
+            }
+            libc::SYS_mremap => {
+                let addr = regs.rdi;
+                let old_len = regs.rsi;
+                let new_len = regs.rdx;
+                let flags = regs.r10;
+                let new_addr = ret;
+
+                let old_range = addr..addr + old_len;
+                let new_range = new_addr..new_addr + new_len;
+
+                {
+                    let formatter = make_format(BINARY);
+                    let old_len = formatter(old_len);
+                    let new_len = formatter(new_len);
+                    debug!("{} thread of {for_tid} just did mremap {old_range:x?} => {new_range:x?} addr={addr:x?} old_len={old_len} new_len={new_len} flags={flags:x?} new_addr={new_addr:x?}", self.tid);
+                }
+
+                return Ok(Some(MemoryEvent {
+                    for_tid,
+                    change: MemoryChange::Remap {
+                        old_range,
+                        new_range,
+                    },
+                }));
+            }
+            libc::SYS_munmap => {
+                let addr = regs.rdi;
+                let len = regs.rsi;
+                let range = addr..addr + len;
+
+                {
+                    let formatter = make_format(BINARY);
+                    let len = formatter(len);
+                    debug!(
+                        "{} thread of {for_tid} just did munmap {range:x?} addr={addr:x?} len={len}",
+                        self.tid
+                    );
+                }
+
+                if addr == 0 || len == 0 {
+                    tracing::warn!(
+                        "{} thread of {for_tid} suspiciously just did munmap {range:x?} addr={addr:x?} len={len}",
+                        self.tid
+                    );
+                    return Ok(None);
+                }
+
+                return Ok(Some(MemoryEvent {
+                    for_tid,
+                    change: MemoryChange::Unmap { range },
+                }));
+            }
+            libc::SYS_madvise => {
+                let addr = regs.rdi;
+                let len = regs.rsi;
+                let advice = regs.rdx as i32;
+
+                match advice {
+                    libc::MADV_DONTNEED | libc::MADV_REMOVE => {
+                        {
+                            let formatter = make_format(BINARY);
+                            let len = formatter(len);
+                            debug!("{} thread of {for_tid} just did madvise-dontneed/remove addr={addr:x?} len={len} advice={advice}", self.tid);
+                        }
+
+                        return Ok(Some(MemoryEvent {
+                            for_tid,
+                            change: MemoryChange::PageOut {
+                                range: addr..addr + len,
+                            },
+                        }));
+                    }
+                    libc::MADV_PAGEOUT => {
+                        debug!(
+                            "{} thread of {for_tid} just did madvise-pageout addr={addr:x?} len={len}",
+                            self.tid
+                        );
+
+                        return Ok(Some(MemoryEvent {
+                            for_tid,
+                            change: MemoryChange::Reclaim {
+                                range: addr..addr + len,
+                            },
+                        }));
+                    }
+                    _ => {
+                        // ignore
+                    }
+                }
+            }
+            libc::SYS_mbind => {
+                let addr = regs.rdi;
+                let len = regs.rsi;
+                let mode = mempolicy_mode(regs.rdx);
+                let nodes = self.read_nodemask(regs.r10, regs.r8)?;
+                debug!(
+                    "{} thread of {for_tid} just did mbind addr={addr:x?} len={len:x?} mode={mode:?} nodes={nodes:?}",
+                    self.tid
+                );
+
+                return Ok(Some(MemoryEvent {
+                    for_tid,
+                    change: MemoryChange::MemPolicy {
+                        range: Some(addr..addr + len),
+                        mode,
+                        nodes,
+                    },
+                }));
+            }
+            libc::SYS_set_mempolicy => {
+                let mode = mempolicy_mode(regs.rdi);
+                let nodes = self.read_nodemask(regs.rsi, regs.rdx)?;
+                debug!(
+                    "{} thread of {for_tid} just did set_mempolicy mode={mode:?} nodes={nodes:?}",
+                    self.tid
+                );
+
+                return Ok(Some(MemoryEvent {
+                    for_tid,
+                    change: MemoryChange::MemPolicy {
+                        range: None,
+                        mode,
+                        nodes,
+                    },
+                }));
+            }
+            libc::SYS_prctl if regs.rdi == libc::PR_SET_NAME as u64 => {
+                // the kernel truncated it for us, read it back rather than
+                // from the tracee's memory
+                if let Some(name) = thread_name(self.tid) {
+                    debug!("{} thread of {for_tid} is now called {name:?}", self.tid);
+                    return Ok(Some(MemoryEvent {
+                        for_tid,
+                        change: MemoryChange::ThreadName {
+                            thread: self.tid,
+                            name,
+                        },
+                    }));
+                }
+            }
+            libc::SYS_brk => {
+                // FIXME: calling brk from a thread should mutate the heap of
+                // the whole process
+                if let TraceeKind::Process { heap_range, .. } = &mut self.kind {
+                    if regs.rdi == 0 {
+                        // just a query: ignore
+                    } else {
+                        // either growing or shrinking the heap,
+                        // and we know the previous top
+                        let old_top = heap_range.end;
+                        heap_range.end = ret;
+
+                        if heap_range.end > old_top {
+                            // heap just grew
+                            debug!("heap grew from {old_top:x?} to {:x?}", heap_range.end);
+                            return Ok(Some(MemoryEvent {
+                                for_tid,
+                                change: MemoryChange::Map {
+                                    range: old_top..heap_range.end,
+                                    state: MemState::Resident,
+                                    raw_flags: None,
+                                    backing: Some(Backing::Anonymous),
+                                    kind: MappingKind::Heap,
+                                    file: None,
+                                    perms: MappingPerms::READ_WRITE,
+                                },
+                            }));
+                        }
+                        if heap_range.end < old_top {
+                            // heap just shrunk
+                            debug!("heap shrunk from {old_top:x?} to {:x?}", heap_range.end);
+                            return Ok(Some(MemoryEvent {
+                                for_tid,
+                                change: MemoryChange::Unmap {
+                                    range: heap_range.end..old_top,
+                                },
+                            }));
+                        }
+                    }
+                } else {
+                    warn!("a thread is changing the brk for the process, we should handle that");
+                }
+            }
+            libc::SYS_userfaultfd => {
+                // the tracee wants to handle its own faults, and we'll be in
+                // its way: ranges can only be registered with one userfaultfd.
+                warn!(
+                    "{} thread of {for_tid} just created its own userfaultfd (fd {ret})",
+                    self.tid
+                );
+                return Ok(Some(MemoryEvent {
+                    for_tid,
+                    change: MemoryChange::TraceeUffd,
+                }));
+            }
+            _ => {
+                // let's ignore those
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// `staging_area` is area that was _just_ mmap'd, and that we can write
+    /// to, so we can pass pointers-to-structs to the kernel
+    #[allow(clippy::useless_transmute)]
+    fn connect(&mut self, saved_regs: user_regs_struct, ctx: &Context) -> Result<()> {
+        let Context { tx, listener, .. } = ctx;
+        let tid = self.tid;
+        let pid: Pid = self.tid.into();
+
+        const WORD_SIZE: usize = 8;
+        assert_eq!(
+            std::mem::size_of::<usize>(),
+            WORD_SIZE,
+            "this is all 64-bit only"
+        );
+
+        let invoke = |nr: i64, args: &[u64]| inject(tid, &saved_regs, nr, args);
+
+        let real_pid = TraceeId(invoke(libc::SYS_getpid, &[])?);
+        if real_pid != tid {
+            tracing::info!("{tid} is a thread of {real_pid}, not connecting");
+            self.kind = TraceeKind::Thread { pid: real_pid };
+
+            ptrace::setregs(pid, saved_regs)?;
+            return Ok(());
+        }
+
+        debug!("allocate staging area");
+        let staging_area = invoke(
+            libc::SYS_mmap,
+            &[
+                0,
+                0x1000,
+                (libc::PROT_READ | libc::PROT_WRITE) as _,
+                (libc::MAP_PRIVATE | libc::MAP_ANONYMOUS) as _,
+                (-1_i32) as u64,
+                0,
+            ],
+        )? as usize;
+        if staging_area == libc::MAP_FAILED as usize {
+            panic!("failed to allocate staging area: returned MAP_FAILED");
+        }
+
+        let write_to_staging = |addr: *const u64, addr_size: usize| -> Result<()> {
+            let num_words = addr_size / WORD_SIZE;
+            for i in 0..num_words {
+                let word = unsafe { addr.add(i) };
+                let word = unsafe { *word };
+                // info!("api word {i}: {:016x}", word);
+                unsafe { ptrace::write(pid, (staging_area + i * WORD_SIZE) as _, word as _)? };
+            }
+            Ok(())
+        };
+
+        let read_from_staging = |addr: *mut u64, addr_size: usize| -> Result<()> {
+            let num_words = addr_size / WORD_SIZE;
+            for i in 0..num_words {
+                let word_dst = unsafe { addr.add(i) };
+                let word = ptrace::read(pid, (staging_area + i * WORD_SIZE) as _)?;
+                // info!("api word {i}: {:016x}", word);
+                unsafe { *word_dst = word as _ };
+            }
+            Ok(())
+        };
+
+        debug!("making userfaultfd sycall");
+        let ret = invoke(libc::SYS_userfaultfd, &[0])? as i32;
+        if ret < 0 {
+            panic!("userfaultfd failed with {}", Errno::from_i32(-ret));
+        }
+        let raw_uffd = ret;
+        debug!("making userfaultfd sycall.. done! got fd {raw_uffd}");
+
+        // asking for a feature the kernel doesn't have fails the whole
+        // handshake, so only ask for the optional ones it has.
+        let supported_features = supported_features();
+
+        let mut req_features = FeatureFlags::EVENT_REMAP
+            | FeatureFlags::EVENT_REMOVE
+            | FeatureFlags::EVENT_UNMAP
+            | FeatureFlags::THREAD_ID
+            // TODO: this is experimental, figure out if how to do accounting there
+            | FeatureFlags::MISSING_SHMEM;
+        if ctx.write_protect {
+            // needs Linux 5.7+ for anonymous memory
+            req_features |= supported_features & FeatureFlags::PAGEFAULT_FLAG_WP;
+        }
+        // needs Linux 5.13+ for hugetlbfs, 5.14+ for shmem
+        req_features |=
+            supported_features & (FeatureFlags::MINOR_HUGETLBFS | FeatureFlags::MINOR_SHMEM);
+        let mut api = raw::uffdio_api {
+            api: raw::UFFD_API,
+            features: req_features.bits(),
+            ioctls: 0,
+        };
+
+        // write the api struct to the staging area
+        write_to_staging(
+            unsafe { std::mem::transmute(&api) },
+            std::mem::size_of_val(&api),
+        )?;
+
+        let ret = invoke(
+            libc::SYS_ioctl,
+            &[raw_uffd as _, raw::UFFDIO_API as _, staging_area as _],
+        )? as i32;
+        if ret < 0 {
+            panic!("ioctl failed with {ret} / {}", Errno::from_i32(-ret));
+        }
+        debug!("ioctl returned {ret}");
+
+        // read the api struct back from the staging area
+        read_from_staging(
+            unsafe { std::mem::transmute(&mut api) },
+            std::mem::size_of_val(&api),
+        )?;
+
+        let supported = IoctlFlags::from_bits(api.ioctls).unwrap();
+        debug!("supported ioctls: {supported:?}");
+
+        let features = FeatureFlags::from_bits_truncate(api.features);
+        let modes = UffdModes {
+            write_protect: ctx.write_protect && features.contains(FeatureFlags::PAGEFAULT_FLAG_WP),
+            minor_shmem: features.contains(FeatureFlags::MINOR_SHMEM),
+            minor_hugetlbfs: features.contains(FeatureFlags::MINOR_HUGETLBFS),
+        };
+        if ctx.write_protect && !modes.write_protect {
+            warn!("{tid} write-protect mode isn't supported, only tracking first touch");
+        }
+        debug!("{tid} uffd modes: {modes:?}");
+
+        let ret = invoke(
+            libc::SYS_socket,
+            &[
+                libc::AF_UNIX as _,
+                (libc::SOCK_STREAM | libc::SOCK_CLOEXEC) as _,
+                0,
+            ],
+        )? as i32;
+        if ret < 0 {
+            panic!("socket failed with {ret} / {}", Errno::from_i32(-ret));
+        }
+        let sock_fd = ret;
+        debug!("socket fd: {sock_fd}");
+
+        let mut addr_un = sockaddr_un {
+            sun_family: libc::AF_UNIX as _,
+            sun_path: [0; 108],
+        };
+        // each mevi listens somewhere of its own, so several can trace at once
+        let local_addr = listener.local_addr()?;
+        let sock_path = local_addr
+            .as_pathname()
+            .expect("we listen on a path")
+            .as_os_str()
+            .as_bytes();
+        // the rest is zeroes already, that's the nul terminator
+        for (dst, src) in addr_un.sun_path.iter_mut().zip(sock_path) {
+            *dst = *src as libc::c_char;
+        }
+        let addr_len = 2 + sock_path.len() + 1;
+        debug!("addr_len = {addr_len}");
+
+        write_to_staging(
+            unsafe { std::mem::transmute(&addr_un) },
+            std::mem::size_of_val(&addr_un),
+        )?;
+
+        let accept_jh = std::thread::spawn({
+            let tx = tx.clone();
+            let listener = Arc::clone(listener);
+            let coalesce_window = ctx.coalesce_window;
+            let symbolicate = ctx.symbolicate;
+            let bucket = ctx.bucket;
+            move || {
+                receive_uffd(
+                    tx,
+                    &listener,
+                    modes.write_protect,
+                    coalesce_window,
+                    symbolicate,
+                    bucket,
+                )
+            }
+        });
+
+        let ret = invoke(
+            libc::SYS_connect,
+            &[sock_fd as _, staging_area as _, addr_len as _],
+        )? as i32;
+        if ret < 0 {
+            panic!("connect failed with {ret} / {}", Errno::from_i32(-ret));
+        }
+        debug!("connect returned {ret}");
+
+        // now let's write the pid
+        unsafe {
+            ptrace::write(pid, staging_area as _, pid.as_raw() as u64 as _)?;
+        }
+        let ret = invoke(libc::SYS_write, &[sock_fd as _, staging_area as _, 8 as _])? as i32;
+        if ret < 0 {
+            panic!("write failed with {ret} / {}", Errno::from_i32(-ret));
+        }
+        debug!("write returned {ret}");
+
+        // this is the big one: sendmsg.
+        let mut msghdr = libc::msghdr {
+            msg_name: std::ptr::null_mut(),
+            msg_namelen: 0,
+            msg_iov: std::ptr::null_mut(),
+            msg_iovlen: 0,
+            msg_control: std::ptr::null_mut(),
+            msg_controllen: 24,
+            msg_flags: 0,
+        };
+
+        // here's our data layout.
+        //
+        // staging_area
+        // [ msghdr ] [ payload ] [  iovec  ] [ cmsghdr | cmsg_data ]
+        // 0x0        0x100       0x200       0x300
+        //
+
+        // write payload
+        unsafe {
+            ptrace::write(pid, (staging_area + 0x100) as _, 0x0 as _)?;
+        }
+
+        // write iovec
+        let iovec = libc::iovec {
+            iov_base: (staging_area + 0x100) as _,
+            iov_len: 4,
+        };
+        unsafe {
+            #[allow(clippy::identity_op)]
+            ptrace::write(pid, (staging_area + 0x200 + 0) as _, iovec.iov_base)?;
+            ptrace::write(pid, (staging_area + 0x200 + 8) as _, iovec.iov_len as _)?;
+        }
+
+        msghdr.msg_iov = (staging_area + 0x200) as _;
+        msghdr.msg_iovlen = 1;
+
+        // write cmsghdr
+        let cmsghdr = libc::cmsghdr {
+            cmsg_len: 20,
+            cmsg_level: libc::SOL_SOCKET,
+            cmsg_type: libc::SCM_RIGHTS,
+        };
+        unsafe {
+            #[allow(clippy::identity_op)]
+            ptrace::write(pid, (staging_area + 0x300 + 0) as _, cmsghdr.cmsg_len as _)?;
+
+            let cmsg_level_ptr: *const u64 = std::mem::transmute(&cmsghdr.cmsg_level);
+            ptrace::write(pid, (staging_area + 0x300 + 8) as _, *cmsg_level_ptr as _)?;
+
+            ptrace::write(pid, (staging_area + 0x300 + 16) as _, raw_uffd as _)?;
+        }
+
+        msghdr.msg_control = (staging_area + 0x300) as _;
+        msghdr.msg_controllen = 24;
+
+        write_to_staging(
+            unsafe { std::mem::transmute(&msghdr) },
+            std::mem::size_of_val(&msghdr),
+        )?;
+
+        let ret = invoke(libc::SYS_sendmsg, &[sock_fd as _, staging_area as _, 0])? as i32;
+        if ret < 0 {
+            panic!("sendmsg failed with {}", Errno::from_i32(-ret));
+        }
+        debug!("sendmsg returned {ret}");
+
+        // now close the socket
+        let ret = invoke(libc::SYS_close, &[sock_fd as _])?;
+        debug!("close(sock_fd) returned {ret}");
+
+        // now close the uffd from the child
+        let ret = invoke(libc::SYS_close, &[raw_uffd as _])?;
+        debug!("close(uffd) returned {ret}");
+
+        // now free the staging area
+        let ret = invoke(libc::SYS_munmap, &[staging_area as _, 0x1000])?;
+        debug!("munmap(staging_area) returned {ret}");
+
+        // TODO: get break start from `/proc/:pid/stat` field 47 instead?
+        // cf. https://man7.org/linux/man-pages/man5/proc.5.html
+        let end_brk = invoke(libc::SYS_brk, &[0])?;
+        debug!("brk(0) returned {end_brk}");
+
+        // at this point we should've received the uffd from the other thread.
+        let uffd = accept_jh.join().unwrap();
+
+        // now's a good time to register all the ranges that are R+W, private and anonymous.
+        let p = procfs::process::Process::new(tid.0 as _)?;
+        if let Some(start_brk) = p.stat()?.start_brk {
+            if end_brk > start_brk {
+                for part in ctx
+                    .track
+                    .parts(&(start_brk..end_brk), MappingKind::Heap, None)
+                {
+                    // FIXME: only accept EBUSY
+                    _ = register(&uffd, &part, modes, Backing::Anonymous);
+                }
+
+                let formatter = make_format(BINARY);
+                tracing::info!(
+                    "{tid} at connect time, heap was {:x?}, which is {}",
+                    start_brk..end_brk,
+                    formatter(end_brk - start_brk),
+                );
+            } else {
+                tracing::info!(
+                    "{tid} at connect time, heap was odd (start_brk={start_brk:x?}, end_brk={end_brk:x?})"
+                );
+            }
+        }
+
+        let maps: Vec<_> = p.maps()?.into_iter().collect();
+        for (i, map) in maps.iter().enumerate() {
+            let range = map.address.0..map.address.1;
+            let below = i.checked_sub(1).map(|i| &maps[i]);
+            let kind = pagemap::mapping_kind(map, below);
+
+            if !pagemap::is_tracked(map) {
+                if let Some(kind) = kind {
+                    // we can't track it, but it's good to know it's there
+                    tx.send(MeviEvent::TraceeEvent(
+                        tid,
+                        TraceePayload::Map {
+                            range,
+                            state: MemState::Untracked,
+                            kind,
+                            file: pagemap::mapping_file(map),
+                            perms: pagemap::mapping_perms(map),
+                            raw: None,
+                        },
+                    ))
+                    .unwrap();
+                } else {
+                    info!(
+                        "{tid} skipping over pathname {:?} with dev {:?}",
+                        map.pathname, map.dev
+                    );
+                }
+                continue;
+            }
+
+            info!("{tid} has stuff at {range:x?} with perms {:?}", map.perms);
+
+            let kind = kind.unwrap_or(MappingKind::Anonymous);
+            let file = pagemap::mapping_file(map);
+            let tracked = ctx.track.parts(&range, kind, file.as_ref());
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::Map {
+                    range: range.clone(),
+                    state: MemState::Untracked,
+                    kind,
+                    file,
+                    perms: pagemap::mapping_perms(map),
+                    raw: None,
+                },
+            ))
+            .unwrap();
+
+            let backing = Backing::of_map(map).unwrap_or(Backing::Anonymous);
+            if !modes.can_track(backing) {
+                info!("{tid} can't track {range:x?} ({backing:?}) with this kernel");
+                continue;
+            }
+            if tracked.is_empty() {
+                info!("{tid} was told not to track {range:x?}");
+                continue;
+            }
+
+            let mut num_pages = 0;
+            let mut num_present_pages = 0;
+
+            let page_size = nix::unistd::sysconf(SysconfVar::PAGE_SIZE)?.unwrap() as u64;
+            let mut pm = p.pagemap()?;
+            for part in tracked {
+                if let Err(e) = register(&uffd, &part, modes, backing) {
+                    match e {
+                        userfaultfd::Error::SystemError(e) => {
+                            if e == nix::Error::EBUSY {
+                                // this is fine, we're just trying to register the same range twice
+                                // which is a no-op
+                                tracing::info!("That range was already registered");
+                            } else {
+                                return Err(
+                                    color_eyre::eyre::eyre!(e).wrap_err("registering range")
+                                );
+                            }
+                        }
+                        other => return Err(other.into()),
+                    }
+                }
+
+                let start_idx = (part.start / page_size) as usize;
+                let end_idx = (part.end / page_size) as usize;
+                for (rel_idx, pi) in pm
+                    .get_range_info(start_idx..end_idx)?
+                    .into_iter()
+                    .enumerate()
+                {
+                    let addr = part.start + rel_idx as u64 * page_size;
+                    if let PageInfo::MemoryPage(mp) = pi {
+                        tracing::debug!("{tid} {addr:x?} = {pi:?}",);
+                        num_pages += 1;
+                        if mp.contains(MemoryPageFlags::PRESENT) {
+                            num_present_pages += 1;
+                        }
+
+                        // TODO: we can do a lot fewer events here by coalescing those
+                        // ranges but let's worry about that later
+                        tx.send(MeviEvent::TraceeEvent(
+                            tid,
+                            TraceePayload::MemStateChange {
+                                range: addr..addr + page_size,
+                                state: if mp.contains(MemoryPageFlags::PRESENT) {
+                                    MemState::Resident
+                                } else {
+                                    MemState::NotResident
+                                },
+                            },
+                        ))
+                        .unwrap();
+                    } else {
+                        num_pages += 1;
+                        tx.send(MeviEvent::TraceeEvent(
+                            tid,
+                            TraceePayload::MemStateChange {
+                                range: addr..addr + page_size,
+                                state: MemState::Swapped,
+                            },
+                        ))
+                        .unwrap();
+                    }
+                }
+            }
+
+            tracing::info!(
+                "{tid} that range had {num_present_pages} present pages out of {num_pages}"
+            );
+        }
+
+        // retrieve the cmdline and send it
+        let cmdline = get_cmdline(tid);
+        tracing::info!("{tid} has cmdline {cmdline:?}");
+        tx.send(MeviEvent::TraceeEvent(
+            tid,
+            TraceePayload::CmdLineChange { cmdline },
+        ))?;
+        tx.send(MeviEvent::TraceeEvent(
+            tid,
+            TraceePayload::ProcessInfo(process_info(&p)),
+        ))?;
+        for (thread, name) in thread_names(&p) {
+            tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::ThreadName { thread, name },
+            ))?;
+        }
+
+        self.kind = TraceeKind::Process {
+            heap_range: end_brk..end_brk,
+            tracking: Tracking::Uffd {
+                uffd,
+                modes,
+                _swap_watcher: ctx.swap_interval.map(|interval| {
+                    pagemap::spawn_swap_watcher(tid, tx.clone(), interval, ctx.track.clone())
+                }),
+            },
+            _samplers: ctx.spawn_samplers(tid),
+            oom: OomWatch::new(tid),
+        };
+        ptrace::setregs(pid, saved_regs)?;
+
+        Ok(())
+    }
+
+    /// Like `connect`, but for the pagemap backend: no need to inject
+    /// anything into the tracee, we can learn all we need from procfs.
+    fn connect_polling(&mut self, ctx: &Context) -> Result<()> {
+        let tid = self.tid;
+        let p = procfs::process::Process::new(tid.0 as _)?;
+
+        let real_pid = TraceeId(p.status()?.tgid as _);
+        if real_pid != tid {
+            tracing::info!("{tid} is a thread of {real_pid}, not connecting");
+            self.kind = TraceeKind::Thread { pid: real_pid };
+            return Ok(());
+        }
+
+        // the heap mapping ends at the current program break
+        let end_brk = p
+            .maps()?
+            .into_iter()
+            .find(|map| matches!(map.pathname, MMapPath::Heap))
+            .map(|map| map.address.1)
+            .or(p.stat()?.start_brk)
+            .unwrap_or_default();
+        debug!("{tid} program break is at {end_brk:x?}");
+
+        let cmdline = get_cmdline(tid);
+        tracing::info!("{tid} has cmdline {cmdline:?}");
+        ctx.tx.send(MeviEvent::TraceeEvent(
+            tid,
+            TraceePayload::CmdLineChange { cmdline },
+        ))?;
+        ctx.tx.send(MeviEvent::TraceeEvent(
+            tid,
+            TraceePayload::ProcessInfo(process_info(&p)),
+        ))?;
+        for (thread, name) in thread_names(&p) {
+            ctx.tx.send(MeviEvent::TraceeEvent(
+                tid,
+                TraceePayload::ThreadName { thread, name },
+            ))?;
+        }
+
+        let maps: Vec<_> = p.maps()?.into_iter().collect();
+        for (i, map) in maps.iter().enumerate() {
+            let below = i.checked_sub(1).map(|i| &maps[i]);
+            if let Some(kind) = pagemap::mapping_kind(map, below) {
+                ctx.tx.send(MeviEvent::TraceeEvent(
+                    tid,
+                    TraceePayload::Map {
+                        range: map.address.0..map.address.1,
+                        state: MemState::Untracked,
+                        kind,
+                        file: pagemap::mapping_file(map),
+                        perms: pagemap::mapping_perms(map),
+                        raw: None,
+                    },
+                ))?;
+            }
+        }
+
+        // the first scan reports the state of every tracked mapping
+        self.kind = TraceeKind::Process {
+            heap_range: end_brk..end_brk,
+            tracking: Tracking::Pagemap {
+                _poller: pagemap::spawn_poller(
+                    tid,
+                    ctx.tx.clone(),
+                    ctx.poll_interval,
+                    ctx.track.clone(),
+                ),
+                retired: None,
+            },
+            _samplers: ctx.spawn_samplers(tid),
+            oom: OomWatch::new(tid),
+        };
+
+        Ok(())
+    }
+}
+
+/// The file behind `fd` in `tid`, when mapped from `offset`
+fn mapped_file(tid: TraceeId, fd: i32, offset: u64) -> Option<MappingFile> {
+    let link = format!("/proc/{}/fd/{fd}", tid.0);
+    let path = std::fs::read_link(&link).ok()?;
+    // metadata follows the link, to the file itself
+    let inode = std::fs::metadata(&link).map_or(0, |meta| meta.ino());
+    Some(MappingFile {
+        path: path.to_string_lossy().into_owned(),
+        offset,
+        inode,
+    })
+}
+
+fn receive_uffd(
+    mut tx: Events,
+    listener: &UnixListener,
+    write_protect: bool,
+    coalesce_window: Duration,
+    symbolicate: bool,
+    bucket: Option<u64>,
+) -> Uffd {
+    let (mut stream, addr) = listener.accept().unwrap();
+    debug!("accepted unix stream from {addr:?}!");
+
+    // TODO: SO_PEERCRED can be used here instead of sending the PID in-band
+    // https://stackoverflow.com/questions/8104904/identify-program-that-connects-to-a-unix-domain-socket
+    //
+    // but it's also a huge PITA, and this isn't security-sensitive, so.
+    let mut pid_bytes = [0u8; 8];
+    stream.read_exact(&mut pid_bytes).unwrap();
+
+    let tid = TraceeId(u64::from_le_bytes(pid_bytes));
+
+    let uffd_raw = stream.recv_fd().unwrap();
+    drop(stream);
+
+    let uffd = unsafe { Uffd::from_raw_fd(uffd_raw) };
+    debug!("{tid} sent us uffd {}", uffd.as_raw_fd());
+
+    std::thread::spawn(move || {
+        let handled = crate::userfault::handle(
+            &mut tx,
+            tid,
+            uffd,
+            write_protect,
+            coalesce_window,
+            symbolicate,
+            bucket,
+        );
+        if handled.is_err() {
+            debug!("{tid} stops handling faults, nobody wants them anymore");
+        }
+    });
+
+    unsafe { Uffd::from_raw_fd(uffd_raw) }
+}
+
+/// Which optional userfaultfd modes the kernel agreed to for a given uffd
+#[derive(Clone, Copy, Debug)]
+struct UffdModes {
+    // also register anonymous memory in write-protect mode
+    write_protect: bool,
+
+    // register shared memory in minor mode, so we hear about pages that are
+    // already in the page cache (written by another process, or through the
+    // file) when they get mapped in
+    minor_shmem: bool,
+    minor_hugetlbfs: bool,
+}
+
+impl UffdModes {
+    /// We can't zeropage hugetlbfs mappings, so we only register them in
+    /// minor mode: without it, there's nothing we can do with them.
+    fn can_track(&self, backing: Backing) -> bool {
+        match backing {
+            Backing::Anonymous | Backing::Shmem => true,
+            Backing::Hugetlbfs => self.minor_hugetlbfs,
+        }
+    }
+}
+
+/// Asks the kernel which userfaultfd features it has, with a userfaultfd of
+/// our own: when no features are requested, the handshake reports all of
+/// them.
+pub fn supported_features() -> FeatureFlags {
+    let fd = unsafe { libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC) };
+    if fd < 0 {
+        debug!(
+            "couldn't create a userfaultfd to probe features: {}",
+            nix::Error::last()
+        );
+        return FeatureFlags::empty();
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as _) };
+
+    let mut api = raw::uffdio_api {
+        api: raw::UFFD_API,
+        features: 0,
+        ioctls: 0,
+    };
+    match unsafe { raw::api(fd.as_raw_fd(), &mut api) } {
+        Ok(_) => FeatureFlags::from_bits_truncate(api.features),
+        Err(e) => {
+            debug!("UFFDIO_API failed while probing features: {e}");
+            FeatureFlags::empty()
+        }
+    }
+}
+
+/// Registers `range` with `uffd` in missing mode, plus whichever other modes
+/// make sense for that kind of memory.
+fn register(
+    uffd: &Uffd,
+    range: &Range<u64>,
+    modes: UffdModes,
+    backing: Backing,
+) -> Result<(), userfaultfd::Error> {
+    let start = range.start as _;
+    let len = (range.end.checked_sub(range.start).unwrap()) as _;
+
+    if backing == Backing::Hugetlbfs {
+        // this means pages nobody has touched yet go unnoticed, until another
+        // process (or this one, through another mapping) faults them in.
+        uffd.register_with_mode(start, len, RegisterMode::MINOR)?;
+    } else if backing == Backing::Shmem && modes.minor_shmem {
+        uffd.register_with_mode(start, len, RegisterMode::MISSING | RegisterMode::MINOR)?;
+    } else if modes.write_protect && backing == Backing::Anonymous {
+        uffd.register_with_mode(
+            start,
+            len,
+            RegisterMode::MISSING | RegisterMode::WRITE_PROTECT,
+        )?;
+        // pages that are already resident won't fault as missing: protect
+        // them so we notice when they get dirtied
+        uffd.write_protect(start, len)?;
+    } else {
+        uffd.register(start, len)?;
+    }
+    Ok(())
+}
+
+/// Unregisters every range of `tid` we may have registered with `uffd`.
+fn release_ranges(tid: TraceeId, uffd: &Uffd) {
+    let maps = match procfs::process::Process::new(tid.0 as _).and_then(|p| p.maps()) {
+        Ok(maps) => maps,
+        Err(e) => {
+            warn!("{tid} could not read maps to release uffd ranges: {e}");
+            return;
+        }
+    };
+
+    for map in maps {
+        if !pagemap::is_tracked(&map) {
+            continue;
+        }
+        let range = map.address.0..map.address.1;
+        if let Err(e) = uffd.unregister(range.start as _, (range.end - range.start) as _) {
+            debug!("{tid} failed to unregister {range:x?}: {e:?}");
+        }
+    }
+}
+
+/// Lets `tid`, stopped at a syscall stop, go on to the next one
+fn sys_step(tid: TraceeId) -> Result<()> {
+    let pid: Pid = tid.into();
+    if let Err(e) = ptrace::syscall(pid, None) {
+        // if ESRCH, the process is dead, we can ignore that
+        if e == nix::Error::ESRCH {
+            warn!("{tid} died while we were making it do syscalls, ignoring");
+            return Ok(());
+        }
+    }
+    let waitres = waitpid(pid, None)?;
+    match waitres {
+        WaitStatus::PtraceSyscall(_) => {
+            // good.
+        }
+        WaitStatus::Stopped(pid, signal) => {
+            // forward signal, try to step again
+            ptrace::syscall(pid, signal)?;
+        }
+        other => {
+            panic!("{tid} making syscalls, unexpected wait status: {other:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Has `tid`, stopped at a syscall exit with `saved_regs`, make the syscall
+/// `nr` with `args`, by going back to the `syscall` instruction it just
+/// ran. Returns what it returned. It's stopped at the exit of ours after
+/// that: put `saved_regs` back before letting it go.
+fn inject(tid: TraceeId, saved_regs: &user_regs_struct, nr: i64, args: &[u64]) -> Result<u64> {
+    let pid: Pid = tid.into();
+    let mut call_regs = *saved_regs;
+    call_regs.rax = nr as _;
+    call_regs.rip -= 2;
+
+    for (i, arg) in args.iter().enumerate() {
+        match i {
+            0 => call_regs.rdi = *arg,
+            1 => call_regs.rsi = *arg,
+            2 => call_regs.rdx = *arg,
+            3 => call_regs.r10 = *arg,
+            4 => call_regs.r8 = *arg,
+            5 => call_regs.r9 = *arg,
+            _ => panic!("too many args"),
+        }
+    }
+
+    ptrace::setregs(pid, call_regs)?;
+
+    sys_step(tid)?;
+    sys_step(tid)?;
+
+    Ok(ptrace::getregs(pid)?.rax)
+}
+
+/// Sends SIGSTOP to `tid` alone: with `kill`, only one of its process's
+/// threads would stop
+fn stop(tid: TraceeId) {
+    let ret = unsafe { libc::syscall(libc::SYS_tkill, tid.0 as libc::pid_t, libc::SIGSTOP) };
+    if ret != 0 {
+        debug!("{tid} couldn't be stopped: {}", Errno::last());
+    }
+}
+
+/// Decodes the mode argument of `mbind` and `set_mempolicy`, ignoring the
+/// `MPOL_F_*` flags.
+fn mempolicy_mode(raw: u64) -> MemPolicyMode {
+    // MPOL_F_STATIC_NODES, MPOL_F_RELATIVE_NODES, MPOL_F_NUMA_BALANCING
+    const MPOL_MODE_FLAGS: u64 = 0b111 << 13;
+
+    match raw & !MPOL_MODE_FLAGS {
+        0 => MemPolicyMode::Default,
+        1 => MemPolicyMode::Preferred,
+        2 => MemPolicyMode::Bind,
+        3 => MemPolicyMode::Interleave,
+        4 => MemPolicyMode::Local,
+        5 => MemPolicyMode::PreferredMany,
+        other => MemPolicyMode::Unknown(other),
+    }
+}
+
+/// Syscalls report failure by returning `-errno`, which looks like a huge
+/// address when read as unsigned. The kernel reserves the last 4095 values
+/// for that purpose, so anything in there is an error, not a result.
+fn syscall_error(ret: u64) -> Option<Errno> {
+    let ret = ret as i64;
+    if (-4095..0).contains(&ret) {
+        Some(Errno::from_i32(-ret as i32))
+    } else {
+        None
+    }
+}
+
+/// Whatever procfs will tell us about `p`: it may be gone already, or not
+/// ours to look at too closely
+fn process_info(p: &procfs::process::Process) -> ProcessInfo {
+    let path = |res: procfs::ProcResult<std::path::PathBuf>| {
+        res.ok().map(|path| path.to_string_lossy().into_owned())
+    };
+    let status = p.status().ok();
+    ProcessInfo {
+        exe: path(p.exe()),
+        cmdline: get_cmdline(TraceeId(p.pid as _)),
+        cwd: path(p.cwd()),
+        uid: status.as_ref().map_or(0, |status| status.ruid),
+        gid: status.as_ref().map_or(0, |status| status.rgid),
+    }
+}
+
+/// What `tid` calls itself, if it's still around
+fn thread_name(tid: TraceeId) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", tid.0)).ok()?;
+    Some(comm.trim_end_matches('\n').to_owned())
+}
+
+/// Every thread `p` has right now, and what it's called
+fn thread_names(p: &procfs::process::Process) -> Vec<(TraceeId, String)> {
+    p.tasks()
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|task| {
+            let thread = TraceeId(task.tid as _);
+            Some((thread, thread_name(thread)?))
+        })
+        .collect()
+}
+
+/// The `PT_INTERP` of a 64-bit little-endian ELF, i.e. the dynamic loader it
+/// needs, if it's dynamically linked. Only reads the headers, executables
+/// can be big.
+fn elf_interpreter(path: &str) -> Option<String> {
+    const PT_INTERP: u32 = 3;
+
+    let file = std::fs::File::open(path).ok()?;
+    let mut header = [0u8; 64];
+    file.read_exact_at(&mut header, 0).ok()?;
+    // magic, then ELFCLASS64 and ELFDATA2LSB
+    if header[..6] != *b"\x7fELF\x02\x01" {
+        return None;
+    }
+    let u16_at = |buf: &[u8], at: usize| u16::from_le_bytes(buf[at..at + 2].try_into().unwrap());
+    let u32_at = |buf: &[u8], at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+    let u64_at = |buf: &[u8], at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+
+    let phoff = u64_at(&header, 0x20);
+    let phentsize = u16_at(&header, 0x36) as u64;
+    let phnum = u16_at(&header, 0x38) as u64;
+    let mut phdr = [0u8; 56];
+    for i in 0..phnum {
+        file.read_exact_at(&mut phdr, phoff + i * phentsize).ok()?;
+        if u32_at(&phdr, 0) != PT_INTERP {
+            continue;
+        }
+        let offset = u64_at(&phdr, 0x08);
+        let size = u64_at(&phdr, 0x20);
+        // a path, there's no reason for it to be big
+        let mut interp = vec![0u8; size.min(4096) as usize];
+        file.read_exact_at(&mut interp, offset).ok()?;
+        let interp = interp.split(|b| *b == 0).next()?;
+        return Some(String::from_utf8_lossy(interp).into_owned());
+    }
+    None
+}
+
+fn get_cmdline(tid: TraceeId) -> Vec<String> {
+    std::fs::read_to_string(format!("/proc/{}/cmdline", tid.0))
+        .unwrap_or_default()
+        .split('\0')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
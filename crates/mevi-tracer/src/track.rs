@@ -0,0 +1,112 @@
+//! `--track-range`, `--min-map-size`, `--max-map-size`, `--include` and
+//! `--exclude`: which parts of the address space we bother with at all.
+//! What's left out still shows up as mapped, but it's never registered with
+//! userfaultfd nor scanned, so a 1TB reservation costs us nothing.
+
+use std::ops::Range;
+
+use mevi_common::{MappingFile, MappingKind};
+use rangemap::RangeSet;
+
+#[derive(Debug, Clone, Default)]
+pub struct TrackFilter {
+    /// Only addresses in there, or anywhere if empty. Page-aligned.
+    pub ranges: RangeSet<u64>,
+
+    /// Only mappings at least this big
+    pub min_size: u64,
+
+    /// Only mappings at most this big, if set
+    pub max_size: Option<u64>,
+
+    /// Only mappings whose name matches one of these globs, if any, cf.
+    /// [mapping_name]
+    pub include: Vec<String>,
+
+    /// No mappings whose name matches one of these globs
+    pub exclude: Vec<String>,
+}
+
+impl TrackFilter {
+    /// The parts of the mapping at `range` we track, in order. Sizes don't
+    /// apply to the heap: it grows a bit at a time.
+    pub(crate) fn parts(
+        &self,
+        range: &Range<u64>,
+        kind: MappingKind,
+        file: Option<&MappingFile>,
+    ) -> Vec<Range<u64>> {
+        let len = range.end - range.start;
+        let heap = kind == MappingKind::Heap;
+        if !heap && (len < self.min_size || self.max_size.is_some_and(|max| len > max)) {
+            return vec![];
+        }
+        if !self.include.is_empty() || !self.exclude.is_empty() {
+            let name = mapping_name(kind, file);
+            // `libfoo*` should match `/usr/lib/libfoo.so.1` too
+            let base = name.rsplit('/').next().unwrap_or(name);
+            let matches = |glob: &String| glob_match(glob, name) || glob_match(glob, base);
+            if !self.include.is_empty() && !self.include.iter().any(matches) {
+                return vec![];
+            }
+            if self.exclude.iter().any(matches) {
+                return vec![];
+            }
+        }
+        if self.ranges.is_empty() {
+            return vec![range.clone()];
+        }
+        self.ranges
+            .overlapping(range)
+            .map(|r| r.start.max(range.start)..r.end.min(range.end))
+            .collect()
+    }
+}
+
+/// What `--include` and `--exclude` match against: the path of the file
+/// behind a mapping, or `[heap]`, `[stack]` (for any thread), `[vdso]`,
+/// `[vvar]`, `[vsyscall]` or `[anon]`.
+pub(crate) fn mapping_name(kind: MappingKind, file: Option<&MappingFile>) -> &str {
+    if let Some(file) = file {
+        return &file.path;
+    }
+    match kind {
+        MappingKind::Heap => "[heap]",
+        MappingKind::Stack | MappingKind::ThreadStack => "[stack]",
+        MappingKind::Vdso => "[vdso]",
+        MappingKind::Vvar => "[vvar]",
+        MappingKind::Vsyscall => "[vsyscall]",
+        _ => "[anon]",
+    }
+}
+
+/// Shell-style globs, but only `*` and `?` are special, so that `[stack]`
+/// means just that
+fn glob_match(glob: &str, name: &str) -> bool {
+    let (glob, name) = (glob.as_bytes(), name.as_bytes());
+    let (mut g, mut n) = (0, 0);
+    // where the last `*` was, and where in `name` it matched up to
+    let mut star = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the `*` eat one more byte
+                Some((star_g, star_n)) => {
+                    star = Some((star_g, star_n + 1));
+                    g = star_g + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
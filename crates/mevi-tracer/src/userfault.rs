@@ -0,0 +1,334 @@
+use std::{
+    os::fd::AsRawFd,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use humansize::{make_format, BINARY};
+use mevi_common::{FaultAccess, FaultSource, MeviEvent, TraceeId, TraceePayload};
+use nix::unistd::{sysconf, SysconfVar};
+use rangemap::RangeMap;
+use tracing::{debug, warn};
+use userfaultfd::{FaultKind, ReadWrite, Uffd};
+
+use crate::{
+    buckets::Counter,
+    coalesce::Coalescer,
+    sink::Events,
+    smaps,
+    symbols::{self, Symbolizer},
+};
+
+/// Serves faults on `uffd` until it goes away. With `write_protect`, ranges
+/// are expected to be registered in write-protect mode as well, and the
+/// first write to each page is reported separately from the first read.
+///
+/// Faults on contiguous pages within `coalesce_window` of each other are
+/// reported as one.
+///
+/// With `symbolicate`, faults say which code took them.
+///
+/// With a `bucket` size, faults are only counted, and how much of each
+/// bucket is resident gets sent every so often instead.
+///
+/// Errors once nobody wants events anymore, like the tracer does.
+pub(crate) fn handle(
+    tx: &mut Events,
+    tid: TraceeId,
+    uffd: Uffd,
+    write_protect: bool,
+    coalesce_window: Duration,
+    symbolicate: bool,
+    bucket: Option<u64>,
+) -> Result<(), mpsc::SendError<MeviEvent>> {
+    let page_size = sysconf(SysconfVar::PAGE_SIZE).unwrap().unwrap() as u64;
+
+    // kernel page sizes of the mappings we've seen minor faults in, since
+    // hugetlbfs faults have to be resolved a whole huge page at a time.
+    let mut minor_page_sizes: RangeMap<u64, u64> = RangeMap::new();
+
+    let mut coalescer = Coalescer::new(tid, coalesce_window);
+    let mut counter = bucket.map(|bucket| Counter::new(tid, bucket));
+    let mut symbolizer = symbolicate.then(|| Symbolizer::new(tid));
+    // the faulting thread only stays put until we resolve the fault
+    let fault_ip = |symbolizer: &Option<Symbolizer>, thread_id: userfaultfd::ThreadId| {
+        symbolizer.as_ref()?;
+        symbols::instruction_pointer(tid, thread(thread_id)?)
+    };
+
+    loop {
+        let deadline = coalescer
+            .deadline()
+            .or_else(|| counter.as_ref()?.deadline());
+        if let Some(deadline) = deadline {
+            // only block for as long as the pending fault can wait
+            if !readable(&uffd, deadline.saturating_duration_since(Instant::now())) {
+                flush(tx, &mut coalescer, &mut counter)?;
+                continue;
+            }
+        }
+
+        let event = match uffd.read_event() {
+            Ok(event) => event.unwrap(),
+            Err(userfaultfd::Error::SystemError(nix::Error::EBADF)) => {
+                flush(tx, &mut coalescer, &mut counter)?;
+                warn!("{tid} uffd {} died! (got EBADF)", uffd.as_raw_fd());
+                let ev = MeviEvent::TraceeEvent(tid, TraceePayload::Exit);
+                tx.send(ev)?;
+                return Ok(());
+            }
+            Err(e) => {
+                panic!("uffd.read_event failed: {e:?}");
+            }
+        };
+        tracing::debug!("{tid} got {event:?}");
+        match event {
+            userfaultfd::Event::Pagefault {
+                kind: FaultKind::WriteProtected,
+                addr,
+                thread_id,
+                ..
+            } => {
+                let ip = fault_ip(&symbolizer, thread_id);
+                // the page was already there, but this is the first write
+                // since we protected it: let it through for good
+                if let Err(e) = uffd.remove_write_protection(addr, page_size as _, true) {
+                    warn!("{tid} couldn't remove write protection at {addr:p}: {e:?}");
+                    continue;
+                }
+                let addr = addr as u64;
+                report(
+                    tx,
+                    &mut coalescer,
+                    &mut counter,
+                    TraceePayload::PageFault {
+                        range: addr..addr + page_size,
+                        access: FaultAccess::Write,
+                        thread: thread(thread_id),
+                        source: FaultSource::Unprotect,
+                        site: ip.and_then(|ip| symbolizer.as_mut()?.resolve(ip)),
+                    },
+                )?;
+            }
+            userfaultfd::Event::Pagefault {
+                kind: FaultKind::Minor,
+                rw,
+                addr,
+                thread_id,
+                ..
+            } => {
+                // the page is in the page cache already (shmem or hugetlbfs),
+                // it just needs to be mapped in
+                let ip = fault_ip(&symbolizer, thread_id);
+                let addr = addr as u64;
+                let size = match minor_page_sizes.get(&addr) {
+                    Some(size) => *size,
+                    None => match smaps::kernel_page_size(tid, addr) {
+                        Ok((range, size)) => {
+                            minor_page_sizes.insert(range, size);
+                            size
+                        }
+                        Err(e) => {
+                            warn!("{tid} couldn't find page size for {addr:x}: {e}");
+                            page_size
+                        }
+                    },
+                };
+                let start = addr & !(size - 1);
+
+                if let Err(e) = uffd.r#continue(start as _, size as _, true) {
+                    warn!("{tid} UFFDIO_CONTINUE failed at {start:x}: {e:?}");
+                    // don't leave the thread hanging
+                    _ = uffd.wake(start as _, size as _);
+                    continue;
+                }
+                report(
+                    tx,
+                    &mut coalescer,
+                    &mut counter,
+                    TraceePayload::PageFault {
+                        range: start..start + size,
+                        access: access(rw),
+                        thread: thread(thread_id),
+                        source: FaultSource::PageCache,
+                        site: ip.and_then(|ip| symbolizer.as_mut()?.resolve(ip)),
+                    },
+                )?;
+            }
+            userfaultfd::Event::Pagefault {
+                rw,
+                addr,
+                thread_id,
+                ..
+            } => {
+                // in write-protect mode, read faults get a protected zero
+                // page so we hear about the first write, and we wake the
+                // faulting thread ourselves once that's done.
+                let ip = fault_ip(&symbolizer, thread_id);
+                let protect = write_protect && rw == ReadWrite::Read;
+                let res = unsafe { uffd.zeropage(addr, page_size as _, !protect) };
+                if let Err(e) = res {
+                    let errno = match e {
+                        userfaultfd::Error::ZeropageFailed(errno) => errno,
+                        _ => unreachable!(),
+                    };
+
+                    match errno as i32 {
+                        libc::EAGAIN => {
+                            // retrying often doesn't work, BUT this means the
+                            // thread wasn't awakened, so we need to do it by
+                            // hand. worst case scenario, we get another event
+                            // from the same range.
+                            debug!("zeropage({addr:p}, {page_size:x?}) = EAGAIN, breaking");
+                            uffd.wake(addr, page_size as _).unwrap();
+                        }
+                        libc::EBADF => {
+                            flush(tx, &mut coalescer, &mut counter)?;
+                            warn!("{tid} uffd {} died! (got EBADF)", uffd.as_raw_fd());
+                            return Ok(());
+                        }
+                        libc::ENOENT => {
+                            // not sure if this is fine but let's not panic?
+                            warn!("{tid} ENOENT while zeropaging {addr:?}");
+                            continue;
+                        }
+                        _ => {
+                            panic!("while doing zeropage: {e:?}");
+                        }
+                    }
+                } else if protect {
+                    if let Err(e) = uffd.write_protect(addr, page_size as _) {
+                        warn!("{tid} couldn't write-protect {addr:p}: {e:?}");
+                    }
+                    uffd.wake(addr, page_size as _).unwrap();
+                }
+                let addr = addr as u64;
+                report(
+                    tx,
+                    &mut coalescer,
+                    &mut counter,
+                    TraceePayload::PageFault {
+                        range: addr..addr + page_size,
+                        access: access(rw),
+                        thread: thread(thread_id),
+                        source: FaultSource::ZeroFill,
+                        site: ip.and_then(|ip| symbolizer.as_mut()?.resolve(ip)),
+                    },
+                )?;
+            }
+            userfaultfd::Event::Remap { from, to, len } => {
+                // faults from before the remap shouldn't land after the
+                // tracer reports it
+                flush(tx, &mut coalescer, &mut counter)?;
+                let from = from as usize;
+                let to = to as usize;
+                minor_page_sizes.remove(from as u64..(from + len) as u64);
+                if let Some(counter) = &mut counter {
+                    counter.forget(&(from as u64..(from + len) as u64));
+                }
+
+                debug!(
+                    "{} got uffd remap event {:x?}.. => {:x?}, len = {}",
+                    tid,
+                    from,
+                    to,
+                    make_format(BINARY)(len),
+                );
+            }
+            userfaultfd::Event::Remove { start, end } => {
+                flush(tx, &mut coalescer, &mut counter)?;
+                let start = start as usize;
+                let end = end as usize;
+                if let Some(counter) = &mut counter {
+                    counter.remove(&(start as u64..end as u64));
+                }
+
+                debug!(
+                    "{} got uffd remove event {:x?}, len = {}",
+                    tid,
+                    start..end,
+                    make_format(BINARY)(end - start),
+                );
+            }
+            userfaultfd::Event::Unmap { start, end } => {
+                flush(tx, &mut coalescer, &mut counter)?;
+                let start = start as usize;
+                let end = end as usize;
+                minor_page_sizes.remove(start as u64..end as u64);
+                if let Some(counter) = &mut counter {
+                    counter.forget(&(start as u64..end as u64));
+                }
+
+                debug!(
+                    "{} got uffd unmap event {:x?}, len = {}",
+                    tid,
+                    start..end,
+                    make_format(BINARY)(end - start),
+                );
+            }
+            other => {
+                warn!("unhandled uffd event: {:?}", other);
+            }
+        }
+    }
+}
+
+/// Sends a fault on (give or take coalescing), or only counts it with
+/// `--track-granularity`
+fn report(
+    tx: &Events,
+    coalescer: &mut Coalescer,
+    counter: &mut Option<Counter>,
+    payload: TraceePayload,
+) -> Result<(), mpsc::SendError<MeviEvent>> {
+    match counter {
+        Some(counter) => {
+            counter.fault(&payload);
+            Ok(())
+        }
+        None => coalescer.push(tx, payload),
+    }
+}
+
+/// Sends whatever's waiting to go out
+fn flush(
+    tx: &Events,
+    coalescer: &mut Coalescer,
+    counter: &mut Option<Counter>,
+) -> Result<(), mpsc::SendError<MeviEvent>> {
+    coalescer.flush(tx)?;
+    if let Some(counter) = counter {
+        counter.flush(tx)?;
+    }
+    Ok(())
+}
+
+/// Waits for `uffd` to have an event for us, for at most `timeout`
+fn readable(uffd: &Uffd, timeout: Duration) -> bool {
+    let mut fds = [libc::pollfd {
+        fd: uffd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    // round up, so we don't spin on sub-millisecond timeouts
+    let timeout = timeout.as_micros().div_ceil(1000) as libc::c_int;
+    let ret = unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) };
+    // on errors, let read_event find out what's wrong
+    ret != 0
+}
+
+fn access(rw: ReadWrite) -> FaultAccess {
+    match rw {
+        ReadWrite::Read => FaultAccess::Read,
+        ReadWrite::Write => FaultAccess::Write,
+    }
+}
+
+/// The kernel only fills in the faulting thread's id if we asked for
+/// UFFD_FEATURE_THREAD_ID (and it knows about it), otherwise it's zero.
+fn thread(thread_id: userfaultfd::ThreadId) -> Option<TraceeId> {
+    match thread_id.0 {
+        0 => None,
+        tid => Some(TraceeId(tid as _)),
+    }
+}
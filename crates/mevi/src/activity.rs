@@ -0,0 +1,136 @@
+//! Which mappings are busy, rather than just big: how many faults each one
+//! took, how long after being mapped it was first touched, and how much of
+//! it got faulted in again after being given back (fault, madvise, fault).
+
+use std::{collections::BTreeMap, ops::Range, time::Duration};
+
+use mevi_common::{MappingKinds, MemMap, MemState, TraceePayload};
+use rangemap::RangeSet;
+use serde::Serialize;
+
+#[derive(Default)]
+pub(crate) struct Activity {
+    /// By mapping start, as [MappingKinds] has them
+    mappings: BTreeMap<u64, MappingActivity>,
+
+    /// Pages that went from resident to not resident, which count as churn
+    /// if they're faulted in again
+    released: RangeSet<u64>,
+}
+
+#[derive(Clone, Copy, Default, Serialize)]
+pub(crate) struct MappingActivity {
+    /// When it was mapped, if we saw that happen
+    #[serde(skip)]
+    mapped_at: Option<Duration>,
+
+    pub(crate) faults: u64,
+
+    /// How long after being mapped it took its first fault, in milliseconds
+    pub(crate) first_fault_ms: Option<u64>,
+
+    /// Bytes faulted in again after being released, e.g. with
+    /// `MADV_DONTNEED`
+    pub(crate) churn: u64,
+}
+
+impl Activity {
+    /// Takes `payload` into account. `map` must be as it was before it, and
+    /// `kinds` as it is after it.
+    pub(crate) fn apply(
+        &mut self,
+        at: Duration,
+        payload: &TraceePayload,
+        map: &MemMap,
+        kinds: &MappingKinds,
+    ) {
+        match payload {
+            TraceePayload::Exec { .. } => {
+                self.mappings.clear();
+                self.released.clear();
+            }
+            TraceePayload::Map { range, .. } => {
+                remove(&mut self.released, range);
+                self.forget_gone(kinds);
+                // unless it got merged with its neighbor
+                if kinds.get_key_value(&range.start).map(|(r, _)| r.start) == Some(range.start) {
+                    self.mappings.insert(
+                        range.start,
+                        MappingActivity {
+                            mapped_at: Some(at),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+            TraceePayload::Unmap { range } => {
+                remove(&mut self.released, range);
+                self.forget_gone(kinds);
+            }
+            TraceePayload::Remap {
+                old_range,
+                new_range,
+            } => {
+                remove(&mut self.released, old_range);
+                if let Some(activity) = self.mappings.remove(&old_range.start) {
+                    self.mappings.insert(new_range.start, activity);
+                }
+                self.forget_gone(kinds);
+            }
+            TraceePayload::MemStateChange {
+                range,
+                state: MemState::NotResident,
+            } => {
+                for (r, state) in map.overlapping(range) {
+                    if state.is_resident() {
+                        let start = r.start.max(range.start);
+                        let end = r.end.min(range.end);
+                        if start < end {
+                            self.released.insert(start..end);
+                        }
+                    }
+                }
+            }
+            TraceePayload::PageFault { range, .. } => {
+                let churn = self
+                    .released
+                    .overlapping(range)
+                    .map(|r| r.end.min(range.end) - r.start.max(range.start))
+                    .sum::<u64>();
+                remove(&mut self.released, range);
+
+                let Some((mapping, _)) = kinds.get_key_value(&range.start) else {
+                    return;
+                };
+                // we may not have seen it get mapped, if it was there before
+                // we started tracing
+                let activity = self.mappings.entry(mapping.start).or_default();
+                activity.faults += 1;
+                activity.churn += churn;
+                if let (None, Some(mapped_at)) = (activity.first_fault_ms, activity.mapped_at) {
+                    activity.first_fault_ms = Some(at.saturating_sub(mapped_at).as_millis() as u64);
+                }
+            }
+            _ => {
+                // doesn't change mappings, or what's resident in them
+            }
+        }
+    }
+
+    /// What we know about the mapping that starts at `start`
+    pub(crate) fn get(&self, start: u64) -> MappingActivity {
+        self.mappings.get(&start).copied().unwrap_or_default()
+    }
+
+    /// Drops mappings that aren't in `kinds` anymore
+    fn forget_gone(&mut self, kinds: &MappingKinds) {
+        self.mappings
+            .retain(|start, _| kinds.get_key_value(start).map(|(r, _)| r.start) == Some(*start));
+    }
+}
+
+fn remove(set: &mut RangeSet<u64>, range: &Range<u64>) {
+    if range.start < range.end {
+        set.remove(range.clone());
+    }
+}
@@ -0,0 +1,320 @@
+//! `/api`: what the relay knows right now, as JSON, for scripts and
+//! dashboards that would rather poll than follow the stream.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use mevi_common::{
+    resident_in, MappingFile, MappingKind, MappingPerms, MemState, ProcessInfo, Timestamp, TraceeId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{activity::MappingActivity, hot, series, RouterState, TraceeState, CORS};
+
+#[derive(Serialize)]
+struct TraceeSummary {
+    tid: u64,
+    cmdline: Vec<String>,
+
+    /// exe, cwd, uid and gid, once the tracer has found out
+    info: Option<ProcessInfo>,
+
+    /// The traced process that forked this one, if any
+    parent: Option<u64>,
+
+    /// Thread names, by tid
+    threads: BTreeMap<u64, String>,
+    mapped: u64,
+    resident: u64,
+    heap: u64,
+    faults: u64,
+}
+
+impl From<&TraceeState> for TraceeSummary {
+    fn from(tracee: &TraceeState) -> Self {
+        let (mapped, resident) = tracee.totals();
+        Self {
+            tid: tracee.tid.0,
+            cmdline: tracee.cmdline.clone(),
+            info: tracee.info.clone(),
+            parent: tracee.parent.map(|parent| parent.0),
+            threads: tracee
+                .threads
+                .iter()
+                .map(|(thread, name)| (thread.0, name.clone()))
+                .collect(),
+            mapped,
+            resident,
+            heap: tracee.heap_size(),
+            faults: tracee.faults,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TreeNode {
+    #[serde(flatten)]
+    summary: TraceeSummary,
+
+    /// The same totals, counting every descendant too
+    subtree_mapped: u64,
+    subtree_resident: u64,
+
+    children: Vec<TreeNode>,
+}
+
+#[derive(Serialize)]
+struct Series {
+    interval_ms: u64,
+    points: Vec<series::Point>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HotQuery {
+    /// How many regions, 10 if unset
+    n: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct HotRegion {
+    tid: u64,
+    start: u64,
+    end: u64,
+
+    /// Bytes faulted in over the window, and the same per second
+    bytes: u64,
+    bytes_per_sec: u64,
+
+    /// The first mapping in the region, if it's still mapped
+    kind: Option<MappingKind>,
+    file: Option<MappingFile>,
+}
+
+#[derive(Serialize)]
+struct Mapping {
+    start: u64,
+    end: u64,
+    kind: MappingKind,
+    file: Option<MappingFile>,
+    perms: MappingPerms,
+    resident: u64,
+
+    /// Faults, first touch and churn
+    #[serde(flatten)]
+    activity: MappingActivity,
+}
+
+#[derive(Serialize)]
+struct StateRange {
+    start: u64,
+    end: u64,
+    state: MemState,
+}
+
+#[derive(Serialize)]
+struct Maps {
+    /// Mappings, as the tracer classified them
+    mappings: Vec<Mapping>,
+
+    /// What we know of every tracked range, which may span several mappings
+    ranges: Vec<StateRange>,
+}
+
+#[derive(Default, Serialize)]
+struct KindStats {
+    mapped: u64,
+    resident: u64,
+    mappings: u64,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    #[serde(flatten)]
+    summary: TraceeSummary,
+
+    /// Bytes in each state
+    by_state: BTreeMap<String, u64>,
+
+    by_kind: BTreeMap<String, KindStats>,
+
+    /// The same, grouped into broader categories: anon, heap, stacks, code,
+    /// data, file and shared
+    by_category: BTreeMap<&'static str, KindStats>,
+}
+
+impl KindStats {
+    fn add(&mut self, range: &Range<u64>, resident: u64) {
+        self.mapped += range.end - range.start;
+        self.resident += resident;
+        self.mappings += 1;
+    }
+}
+
+/// `GET /api/tracees`: every tracee, with its totals
+pub(crate) async fn tracees(State(rs): State<RouterState>) -> Response {
+    let tracees = rs.tracees.lock().unwrap();
+    let mut summaries = tracees
+        .values()
+        .map(TraceeSummary::from)
+        .collect::<Vec<_>>();
+    summaries.sort_by_key(|summary| summary.tid);
+    (CORS, Json(summaries)).into_response()
+}
+
+/// `GET /api/tree`: tracees as the process tree they form, with totals for
+/// each subtree
+pub(crate) async fn tree(State(rs): State<RouterState>) -> Response {
+    let tracees = rs.tracees.lock().unwrap();
+    let mut children = HashMap::<Option<TraceeId>, Vec<&TraceeState>>::new();
+    for tracee in tracees.values() {
+        // a process whose parent we don't trace is a root
+        let parent = tracee.parent.filter(|parent| tracees.contains_key(parent));
+        children.entry(parent).or_default().push(tracee);
+    }
+    (CORS, Json(tree_nodes(&children, None))).into_response()
+}
+
+fn tree_nodes(
+    children: &HashMap<Option<TraceeId>, Vec<&TraceeState>>,
+    parent: Option<TraceeId>,
+) -> Vec<TreeNode> {
+    let mut nodes = children
+        .get(&parent)
+        .into_iter()
+        .flatten()
+        .map(|tracee| {
+            let summary = TraceeSummary::from(*tracee);
+            let children = tree_nodes(children, Some(tracee.tid));
+            TreeNode {
+                subtree_mapped: summary.mapped
+                    + children.iter().map(|c| c.subtree_mapped).sum::<u64>(),
+                subtree_resident: summary.resident
+                    + children.iter().map(|c| c.subtree_resident).sum::<u64>(),
+                summary,
+                children,
+            }
+        })
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|node| node.summary.tid);
+    nodes
+}
+
+/// `GET /api/tracees/:tid/maps`: a tracee's mappings, and the state of every
+/// range in them
+pub(crate) async fn maps(State(rs): State<RouterState>, Path(tid): Path<u64>) -> Response {
+    let tracees = rs.tracees.lock().unwrap();
+    let Some(tracee) = tracees.get(&TraceeId(tid)) else {
+        return (StatusCode::NOT_FOUND, CORS).into_response();
+    };
+    let maps = Maps {
+        mappings: tracee
+            .kinds
+            .iter()
+            .map(|(range, kind)| Mapping {
+                start: range.start,
+                end: range.end,
+                kind: *kind,
+                file: tracee.files.get(&range.start).cloned(),
+                perms: tracee.perms.get(&range.start).copied().unwrap_or_default(),
+                resident: resident_in(&tracee.map, range),
+                activity: tracee.activity.get(range.start),
+            })
+            .collect(),
+        ranges: tracee
+            .map
+            .iter()
+            .map(|(range, state)| StateRange {
+                start: range.start,
+                end: range.end,
+                state: *state,
+            })
+            .collect(),
+    };
+    (CORS, Json(maps)).into_response()
+}
+
+/// `GET /api/tracees/:tid/stats`: a tracee's totals, broken down by state and
+/// by kind of mapping
+pub(crate) async fn stats(State(rs): State<RouterState>, Path(tid): Path<u64>) -> Response {
+    let tracees = rs.tracees.lock().unwrap();
+    let Some(tracee) = tracees.get(&TraceeId(tid)) else {
+        return (StatusCode::NOT_FOUND, CORS).into_response();
+    };
+
+    let mut by_state = BTreeMap::new();
+    for (range, state) in tracee.map.iter() {
+        *by_state.entry(format!("{state:?}")).or_default() += range.end - range.start;
+    }
+    let mut by_kind = BTreeMap::<_, KindStats>::new();
+    let mut by_category = BTreeMap::<_, KindStats>::new();
+    for (range, kind) in tracee.kinds.iter() {
+        let resident = resident_in(&tracee.map, range);
+        by_kind
+            .entry(format!("{kind:?}"))
+            .or_default()
+            .add(range, resident);
+        by_category
+            .entry(kind.category())
+            .or_default()
+            .add(range, resident);
+    }
+
+    let stats = Stats {
+        summary: tracee.into(),
+        by_state,
+        by_kind,
+        by_category,
+    };
+    (CORS, Json(stats)).into_response()
+}
+
+/// `GET /api/tracees/:tid/series`: a tracee's mapped and resident bytes over
+/// time, oldest first, sampled every `--series-interval`
+pub(crate) async fn series(State(rs): State<RouterState>, Path(tid): Path<u64>) -> Response {
+    let Some(series) = &rs.series else {
+        return (StatusCode::NOT_FOUND, CORS).into_response();
+    };
+    let Some(points) = series.get(TraceeId(tid)) else {
+        return (StatusCode::NOT_FOUND, CORS).into_response();
+    };
+    let series = Series {
+        interval_ms: series.interval.as_millis() as u64,
+        points,
+    };
+    (CORS, Json(series)).into_response()
+}
+
+/// `GET /api/hot?n=N`: the regions that took the most faults over the last
+/// few seconds, across every tracee, hottest first
+pub(crate) async fn hot(State(rs): State<RouterState>, Query(query): Query<HotQuery>) -> Response {
+    let n = query.n.unwrap_or(10);
+    let now = Timestamp::now();
+    let mut tracees = rs.tracees.lock().unwrap();
+    let mut regions = vec![];
+    for tracee in tracees.values_mut() {
+        tracee.hot.expire(now);
+        for (range, bytes) in tracee.hot.top(n) {
+            let mapping = tracee.kinds.overlapping(&range).next();
+            regions.push(HotRegion {
+                tid: tracee.tid.0,
+                start: range.start,
+                end: range.end,
+                bytes,
+                bytes_per_sec: bytes / hot::WINDOW.as_secs(),
+                kind: mapping.map(|(_, kind)| *kind),
+                file: mapping.and_then(|(r, _)| tracee.files.get(&r.start).cloned()),
+            });
+        }
+    }
+    regions.sort_by_key(|region| (std::cmp::Reverse(region.bytes), region.tid, region.start));
+    regions.truncate(n);
+    (CORS, Json(regions)).into_response()
+}
@@ -0,0 +1,48 @@
+//! The frontend, built into the binary: whatever `trunk build` left in
+//! `mevi-frontend/dist` when mevi was compiled, so there's nothing else to
+//! deploy. Debug builds read it from disk instead, so rebuilding the frontend
+//! doesn't mean rebuilding mevi.
+
+use axum::{
+    extract::OriginalUri,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+};
+use rust_embed::RustEmbed;
+
+#[derive(RustEmbed)]
+#[folder = "../mevi-frontend/dist"]
+#[allow_missing = true]
+struct Dist;
+
+/// Serves `uri` from the embedded frontend, `index.html` for `/`
+pub(crate) async fn serve(OriginalUri(original): OriginalUri, uri: Uri) -> Response {
+    // under --base-path, `/mevi` gets here as `/`, but the frontend's
+    // relative links only work from `/mevi/`
+    if uri.path() == "/" && !original.path().ends_with('/') {
+        let query = original
+            .query()
+            .map(|q| format!("?{q}"))
+            .unwrap_or_default();
+        return Redirect::permanent(&format!("{}/{query}", original.path())).into_response();
+    }
+
+    let path = match uri.path().trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    };
+    match Dist::get(path) {
+        Some(file) => (
+            [(header::CONTENT_TYPE, file.metadata.mimetype())],
+            file.data,
+        )
+            .into_response(),
+        None if path == "index.html" => (
+            StatusCode::NOT_FOUND,
+            "this mevi was built without its frontend: run `trunk build` before \
+             building it (see `just install`), or serve the frontend separately",
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
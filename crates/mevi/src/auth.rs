@@ -0,0 +1,121 @@
+//! Bearer tokens for the HTTP endpoints: served anywhere but on loopback,
+//! anyone on the network could otherwise watch what the tracee is up to.
+
+use std::{fs::File, io::Read, net::IpAddr};
+
+use axum::{
+    extract::State,
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::{eyre::WrapErr, Result};
+
+use crate::{RouterState, CORS};
+
+/// A random token, good for this session only
+pub(crate) fn generate() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .wrap_err("generating a token")?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Turns away requests without the session's token, if it has one. Browsers
+/// can't set headers on websockets, so `?token=` works as well as an
+/// `Authorization: Bearer` header.
+pub(crate) async fn require<B>(
+    State(rs): State<RouterState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(token) = &rs.token else {
+        return next.run(req).await;
+    };
+    match presented(&req) {
+        Some(presented) if same(presented.as_bytes(), token.as_bytes()) => next.run(req).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            CORS,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response(),
+    }
+}
+
+/// Turns away browsers on pages from anywhere but us (or `--allow-origin`),
+/// for what can read the tracees' memory or change what they do: without a
+/// token, as on loopback, any page that's open could otherwise ask. Browsers
+/// always say where a page came from on those requests, other clients (e.g.
+/// `mevi control`) don't have to.
+///
+/// Without a token, requests also have to be addressed to loopback: a page
+/// can point its own name at 127.0.0.1 (DNS rebinding), and then it's "us"
+/// as far as the browser's concerned, Origin and all.
+pub(crate) async fn same_origin<B>(
+    State(rs): State<RouterState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let headers = req.headers();
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    if rs.token.is_none() && !host.is_some_and(is_loopback) {
+        return (
+            StatusCode::FORBIDDEN,
+            format!(
+                "{} isn't a loopback address, and there's no token",
+                host.unwrap_or("that")
+            ),
+        )
+            .into_response();
+    }
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return next.run(req).await;
+    };
+    let origin = origin.to_str().unwrap_or_default().to_owned();
+    let ours = host.is_some() && origin.split_once("://").map(|(_, authority)| authority) == host;
+    if ours || rs.allowed_origins.iter().any(|o| *o == origin) {
+        next.run(req).await
+    } else {
+        (
+            StatusCode::FORBIDDEN,
+            format!("origin {origin} isn't allowed"),
+        )
+            .into_response()
+    }
+}
+
+/// Whether a `Host` header names loopback, port or not
+fn is_loopback(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        // [::1]:5001
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn presented<B>(req: &Request<B>) -> Option<&str> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = || {
+        req.uri()
+            .query()?
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    };
+    bearer.or_else(query)
+}
+
+/// Compares all of both, so how long it takes doesn't tell how much of the
+/// token someone got right
+pub(crate) fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
@@ -0,0 +1,63 @@
+//! `--capture-signals`: SIGUSR2 makes `mevi record` stop writing down what
+//! tracees do with their pages, and SIGUSR1 makes it start again, so a trace
+//! can bracket the interesting part of a long run. What memory looks like
+//! (mappings, processes coming and going) is written down either way, so the
+//! trace still adds up.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use color_eyre::Result;
+use mevi_common::TraceePayload;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use tracing::info;
+
+static CAPTURING: AtomicBool = AtomicBool::new(true);
+
+/// Starts listening for SIGUSR1 and SIGUSR2, capturing from the start or
+/// not
+pub(crate) fn install(capturing: bool) -> Result<()> {
+    extern "C" fn start(_: libc::c_int) {
+        CAPTURING.store(true, Ordering::SeqCst);
+    }
+    extern "C" fn stop(_: libc::c_int) {
+        CAPTURING.store(false, Ordering::SeqCst);
+    }
+
+    CAPTURING.store(capturing, Ordering::SeqCst);
+    for (signal, handler) in [
+        (Signal::SIGUSR1, start as extern "C" fn(libc::c_int)),
+        (Signal::SIGUSR2, stop),
+    ] {
+        // unlike the watchdog's, these shouldn't interrupt anything
+        let action = SigAction::new(
+            SigHandler::Handler(handler),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        unsafe { sigaction(signal, &action)? };
+    }
+    info!(
+        "kill -USR1 {pid} to start capturing, kill -USR2 {pid} to stop",
+        pid = std::process::id()
+    );
+    Ok(())
+}
+
+pub(crate) fn capturing() -> bool {
+    CAPTURING.load(Ordering::SeqCst)
+}
+
+/// Whether `payload` is about what a tracee does with its pages, which is
+/// what we leave out while we're not capturing
+pub(crate) fn is_activity(payload: &TraceePayload) -> bool {
+    matches!(
+        payload,
+        TraceePayload::PageFault { .. }
+            | TraceePayload::MemStateChange { .. }
+            | TraceePayload::ResidentPages(_)
+            | TraceePayload::OverlaySample { .. }
+            | TraceePayload::OverlayDelta { .. }
+            | TraceePayload::Sample(_)
+            | TraceePayload::Syscall { .. }
+    )
+}
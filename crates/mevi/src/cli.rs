@@ -0,0 +1,1399 @@
+use std::{
+    ffi::CString,
+    io::IsTerminal,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::{Arg, CommandFactory, FromArgMatches, Parser, Subcommand};
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use mevi_common::{SessionInfo, TraceeId};
+use mevi_tracer::{
+    channel::Backpressure,
+    evict::{Advice, Eviction},
+    track::TrackFilter,
+    Backend, Env, InjectTrigger, OnLimit, Options, RunAs, Target,
+};
+use nix::unistd::{getgrouplist, Gid, Group, Uid, User};
+
+use crate::{config::Config, control, syscalls};
+
+/// Where we serve HTTP, unless told otherwise
+pub(crate) const DEFAULT_LISTEN: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5001));
+
+/// How finely residency is tracked, or shown to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Granularity {
+    /// Every page, as the tracer reports it
+    Page,
+
+    /// Resident bytes per 64KiB bucket
+    Buckets64K,
+
+    /// Resident bytes per 2MiB bucket, the size of a huge page
+    Buckets2M,
+}
+
+impl Granularity {
+    /// How big buckets are, `None` when clients get pages
+    pub(crate) fn bucket(self) -> Option<u64> {
+        match self {
+            Self::Page => None,
+            Self::Buckets64K => Some(64 * 1024),
+            Self::Buckets2M => Some(2 * 1024 * 1024),
+        }
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "page" => Ok(Self::Page),
+            "64k" => Ok(Self::Buckets64K),
+            "2m" => Ok(Self::Buckets2M),
+            other => bail!("unknown granularity {other:?}, expected page, 64k or 2m"),
+        }
+    }
+}
+
+/// Somewhere the server writes events to, on top of streaming them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Sink {
+    Sqlite(PathBuf),
+}
+
+impl FromStr for Sink {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(Self::Sqlite(path.into())),
+            _ => bail!("unknown sink {s:?}, expected sqlite:PATH"),
+        }
+    }
+}
+
+/// What `mevi export` produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExportFormat {
+    /// One JSON object per event
+    Jsonl,
+
+    /// Trace Event Format JSON, for Perfetto and chrome://tracing
+    Chrome,
+
+    /// Resident bytes per mapping, every `slice`
+    Heatmap { slice: Duration },
+
+    /// One row per mapping, with its lifetime, peak resident bytes and faults
+    Csv,
+
+    /// A page that shows resident and mapped bytes and mappings over time,
+    /// with everything inlined
+    Html,
+
+    /// A short report for humans, per process: peaks, faults, the biggest
+    /// mappings and how the heap grew
+    Summary,
+}
+
+impl FromStr for ExportFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            "chrome" => Ok(Self::Chrome),
+            "heatmap" => Ok(Self::Heatmap {
+                slice: Duration::from_millis(100),
+            }),
+            "csv" => Ok(Self::Csv),
+            "summary" => Ok(Self::Summary),
+            "html" => Ok(Self::Html),
+            other => {
+                bail!(
+                    "unknown export format {other:?}, expected jsonl, chrome, heatmap, csv, html or summary"
+                )
+            }
+        }
+    }
+}
+
+impl ExportFormat {
+    /// What exports are called in `--output-dir`
+    fn file_name(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "events.jsonl",
+            Self::Chrome => "chrome.json",
+            Self::Heatmap { .. } => "heatmap.json",
+            Self::Csv => "mappings.csv",
+            Self::Html => "report.html",
+            Self::Summary => "summary.txt",
+        }
+    }
+}
+
+/// What to do with the events we collect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Mode {
+    /// Stream them to the frontend as they happen
+    Serve,
+
+    /// Write them to a trace file, to look at later
+    Record {
+        output: PathBuf,
+        compression_level: i32,
+        snapshot_interval: Option<Duration>,
+
+        /// What to export the trace to once it's recorded, and where
+        exports: Vec<(ExportFormat, PathBuf)>,
+
+        /// Start and stop capturing on SIGUSR1 and SIGUSR2, cf.
+        /// [crate::capture], and whether to wait for the first SIGUSR1
+        capture_signals: bool,
+        wait_for_signal: bool,
+    },
+
+    /// Don't trace anything, serve events from a trace file instead
+    Replay { input: PathBuf, speed: f64 },
+
+    /// Show them in the terminal, with the program's output going to
+    /// `output` (or nowhere) so it doesn't get in the way
+    Tui { output: Option<PathBuf> },
+
+    /// Don't trace anything, serve events from a remote mevi instead
+    Connect {
+        addr: String,
+        tls_ca: Option<PathBuf>,
+        token: Option<String>,
+    },
+
+    /// Convert them to some other format, either as they happen, or from a
+    /// trace file (`input`). Goes to stdout if there's no `output`.
+    Export {
+        format: ExportFormat,
+        input: Option<PathBuf>,
+        output: Option<PathBuf>,
+    },
+
+    /// Don't trace anything, check whether we could
+    Doctor,
+
+    /// Don't trace anything, serve whatever sessions tracers register on a
+    /// Unix socket at `accept`, cf. [crate::register]
+    Server { accept: PathBuf },
+
+    /// Send them to a server (`mevi serve`) that's already running, on the
+    /// Unix socket at `server`
+    Register { server: PathBuf },
+
+    /// Don't trace anything, tell the mevi serving on `url` what to do with
+    /// what it traces
+    Control {
+        url: String,
+        token: Option<String>,
+        action: control::Action,
+    },
+
+    /// Don't trace anything, get a range of memory from a process the mevi
+    /// serving on `url` traces
+    Dump {
+        url: String,
+        token: Option<String>,
+        tid: TraceeId,
+        range: Range<u64>,
+        output: Option<PathBuf>,
+        hexdump: bool,
+    },
+}
+
+pub(crate) struct Args {
+    pub(crate) mode: Mode,
+
+    /// What to trace, and how
+    pub(crate) tracer: Options,
+
+    pub(crate) backpressure: Backpressure,
+
+    /// What the session's called, and tagged with
+    pub(crate) session: SessionInfo,
+
+    /// If set, the base URL of an OTLP/HTTP collector to push to
+    pub(crate) otlp_endpoint: Option<String>,
+    pub(crate) otlp_interval: Duration,
+
+    /// Where to write events, besides streaming them
+    pub(crate) sinks: Vec<Sink>,
+
+    /// Where to serve HTTP, if not `$MEVI_LISTEN` or the default, cf.
+    /// [Args::http_addr]
+    pub(crate) listen: Option<SocketAddr>,
+
+    /// What HTTP clients have to show, if not `$MEVI_TOKEN` or a generated
+    /// one, cf. [Args::token]
+    pub(crate) token: Option<String>,
+
+    /// The prefix everything is served under, without a trailing slash, if
+    /// not the root
+    pub(crate) base_path: Option<String>,
+
+    /// Origins websockets are accepted from, any if empty
+    pub(crate) allowed_origins: Vec<String>,
+
+    /// If set, where to accept `mevi connect` viewers
+    pub(crate) listen_tcp: Option<SocketAddr>,
+
+    /// The certificate chain and private key to serve TLS with, if any
+    pub(crate) tls: Option<(PathBuf, PathBuf)>,
+
+    /// If set, where to accept local clients on a Unix socket
+    pub(crate) listen_unix: Option<PathBuf>,
+
+    /// Roughly how many events to keep around for clients to look back on,
+    /// cf. [crate::history::History]
+    pub(crate) history: usize,
+
+    /// If set, how often to sample resident bytes, cf.
+    /// [crate::series::Series]
+    pub(crate) series_interval: Option<Duration>,
+
+    /// How finely clients see residency
+    pub(crate) granularity: Granularity,
+
+    /// How long to keep serving once the session is over, until interrupted
+    /// if unset
+    pub(crate) linger: Option<Duration>,
+
+    /// Where our own logs go, if not stderr
+    pub(crate) log_file: Option<PathBuf>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Serve,
+            // tracing is all we do
+            tracer: Options {
+                subreaper: true,
+                ..Default::default()
+            },
+            backpressure: Backpressure::Block,
+            session: Default::default(),
+            otlp_endpoint: None,
+            otlp_interval: Duration::from_secs(10),
+            sinks: Default::default(),
+            listen: None,
+            token: None,
+            base_path: None,
+            allowed_origins: Default::default(),
+            listen_tcp: None,
+            tls: None,
+            listen_unix: None,
+            history: 1_000_000,
+            series_interval: Some(Duration::from_millis(1000)),
+            granularity: Granularity::Page,
+            linger: None,
+            log_file: None,
+        }
+    }
+}
+
+/// See what a program does with its memory, as it runs
+#[derive(Parser)]
+#[command(name = "mevi", version, arg_required_else_help = true)]
+struct Cli {
+    /// Write mevi's own logs to FILE rather than stderr. How much gets
+    /// logged is up to $MEVI_LOG (e.g. debug, or mevi_tracer::tracer=trace)
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Trace PROGRAM and serve its memory usage live. `mevi PROGRAM` is
+    /// short for this.
+    Run {
+        #[command(flatten)]
+        trace: TraceOpts,
+
+        #[command(flatten)]
+        serve: ServeOpts,
+
+        /// Don't serve anything, print a summary once PROGRAM exits (same as
+        /// export --format summary)
+        #[arg(long, conflicts_with_all = ["listen", "token", "listen_tcp", "listen_unix"])]
+        headless: bool,
+
+        /// Don't serve anything, send everything to a mevi serve that's
+        /// already running, e.g. unix:///run/mevi.sock
+        #[arg(
+            long,
+            value_name = "URL",
+            value_parser = parse_server,
+            conflicts_with_all = ["listen", "token", "listen_tcp", "listen_unix", "headless"]
+        )]
+        server: Option<PathBuf>,
+
+        #[command(flatten)]
+        spawn: SpawnOpts,
+
+        /// The program to trace, and its arguments. Put `--` before it if it
+        /// starts with a dash.
+        #[arg(required = true, trailing_var_arg = true, value_name = "PROGRAM")]
+        program: Vec<String>,
+    },
+
+    /// Trace a process that's already running, and serve its memory usage
+    /// live. With the uffd backend, it won't outlive mevi.
+    Attach {
+        #[command(flatten)]
+        trace: TraceOpts,
+
+        #[command(flatten)]
+        serve: ServeOpts,
+
+        /// The process to attach to
+        pid: u32,
+    },
+
+    /// Trace PROGRAM and write everything to a trace file instead
+    Record {
+        /// Where to write the trace
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            required_unless_present = "output_dir"
+        )]
+        output: Option<PathBuf>,
+
+        /// Make a directory for this run in DIR, named after the program and
+        /// when it started, with the trace, a summary and any --export in it
+        #[arg(long, value_name = "DIR", conflicts_with = "output")]
+        output_dir: Option<PathBuf>,
+
+        /// Also export the trace to FORMAT in the run's directory, once it's
+        /// recorded. Can be given more than once.
+        #[arg(long, value_name = "FORMAT", requires = "output_dir")]
+        export: Vec<ExportFormat>,
+
+        /// zstd level for the trace, 0 to write it uncompressed
+        #[arg(long, default_value_t = 3, value_name = "N")]
+        compression_level: i32,
+
+        /// Also write down what every process looks like this often, so
+        /// replays can seek without going through the whole trace
+        #[arg(long, value_name = "SECS")]
+        snapshot_interval: Option<u64>,
+
+        /// Stop writing down page activity when mevi gets SIGUSR2, and start
+        /// again on SIGUSR1. Mappings and processes are always written down.
+        #[arg(long)]
+        capture_signals: bool,
+
+        /// Like --capture-signals, but don't capture anything until the
+        /// first SIGUSR1
+        #[arg(long)]
+        wait_for_signal: bool,
+
+        #[command(flatten)]
+        trace: TraceOpts,
+
+        #[command(flatten)]
+        target: TargetOpts,
+    },
+
+    /// Serve a recorded trace as if it was happening now
+    Replay {
+        /// How much faster than real time to play the trace back
+        #[arg(long, default_value = "1", value_parser = parse_speed, value_name = "X")]
+        speed: f64,
+
+        #[command(flatten)]
+        serve: ServeOpts,
+
+        /// The trace to replay
+        #[arg(value_name = "FILE")]
+        input: PathBuf,
+    },
+
+    /// Serve what a remote mevi (started with --listen-tcp) is tracing,
+    /// reconnecting if the connection drops
+    Connect {
+        /// Use TLS, and only trust certificates signed by the PEM CA
+        /// certificate in FILE
+        #[arg(long, value_name = "FILE")]
+        tls_ca: Option<PathBuf>,
+
+        /// The token the remote mevi wants, if it printed one
+        #[arg(long, value_name = "TOKEN")]
+        remote_token: Option<String>,
+
+        #[command(flatten)]
+        serve: ServeOpts,
+
+        /// Where the remote mevi streams events
+        #[arg(value_name = "HOST:PORT", value_parser = parse_host_port)]
+        addr: String,
+    },
+
+    /// Trace PROGRAM and show its memory usage in the terminal, rather than
+    /// serving it
+    Tui {
+        /// Where PROGRAM's output goes [default: nowhere]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        trace: TraceOpts,
+
+        #[command(flatten)]
+        target: TargetOpts,
+    },
+
+    /// Convert events (live, or from a recorded trace) to another format
+    Export {
+        /// What to convert to: jsonl, chrome, heatmap, csv, html or summary
+        #[arg(long)]
+        format: ExportFormat,
+
+        /// Heatmap resolution, in milliseconds [default: 100]
+        #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+        slice: Option<u64>,
+
+        /// Read events from a recorded trace, rather than tracing anything
+        #[arg(long, value_name = "TRACE", conflicts_with_all = ["pid", "program"])]
+        from: Option<PathBuf>,
+
+        /// Where to write [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        trace: TraceOpts,
+
+        #[command(flatten)]
+        target: TargetOpts,
+    },
+
+    /// Check whether this machine lets mevi trace things, and what to change
+    /// if it doesn't
+    Doctor,
+
+    /// Serve a frontend for as long as it's up, showing whatever sessions
+    /// `mevi run --server` registers with it
+    Serve {
+        /// Where tracers register, as a Unix socket
+        #[arg(long, default_value = "/run/mevi.sock", value_name = "PATH")]
+        accept: PathBuf,
+
+        #[command(flatten)]
+        serve: ServeOpts,
+    },
+
+    /// Freeze every process a running mevi traces, to look at their memory
+    /// as it is
+    Pause(ControlOpts),
+
+    /// Let the processes a running mevi traces go on after mevi pause
+    Resume(ControlOpts),
+
+    /// Have a process a running mevi traces evict a range of its memory,
+    /// and watch it come back as it's touched again
+    Evict {
+        /// pageout (to swap, contents kept) or dontneed (anonymous memory
+        /// reads back as zeroes)
+        #[arg(long, default_value = "pageout")]
+        advice: Advice,
+
+        /// The process
+        pid: u64,
+
+        /// What to evict, in hex, e.g. 7f12a0000000-7f12a4000000
+        #[arg(value_name = "START-END", value_parser = parse_range)]
+        range: Range<u64>,
+
+        #[command(flatten)]
+        control: ControlOpts,
+    },
+
+    /// Write what's in a range of a process a running mevi traces to a file,
+    /// or as a hexdump
+    Dump {
+        /// Where to write it [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Write a hexdump, rather than the bytes themselves
+        #[arg(long)]
+        hexdump: bool,
+
+        /// The process
+        pid: u64,
+
+        /// What to dump, in hex, e.g. 7f12a0000000-7f12a0001000
+        #[arg(value_name = "START-END", value_parser = parse_range)]
+        range: Range<u64>,
+
+        #[command(flatten)]
+        control: ControlOpts,
+    },
+}
+
+/// How to trace, for commands that do
+#[derive(clap::Args)]
+struct TraceOpts {
+    /// How to learn about resident memory: uffd or pagemap
+    #[arg(long, default_value = "uffd", value_name = "BACKEND")]
+    backend: Backend,
+
+    /// How often the pagemap backend scans, in milliseconds
+    #[arg(long, default_value_t = 250, value_name = "MS")]
+    poll_interval: u64,
+
+    /// When to start tracking a process: on its first syscall
+    /// (first-syscall), or once it maps anonymous memory or grows its heap
+    /// (first-mapping), which leaves alone processes that exec right away
+    #[arg(long, default_value = "first-mapping", value_name = "WHEN")]
+    inject_on: InjectTrigger,
+
+    /// What to do when events come in faster than mevi can relay them:
+    /// block the tracee, drop faults and samples, or coalesce them
+    #[arg(long, default_value = "block", value_name = "POLICY")]
+    backpressure: Backpressure,
+
+    /// (uffd) Also track the first write to each page
+    #[arg(long)]
+    write_protect: bool,
+
+    /// (uffd) Merge faults on contiguous pages that happen within that many
+    /// milliseconds of each other, 0 to report every page on its own
+    #[arg(long, default_value_t = 5, value_name = "MS")]
+    coalesce_window: u64,
+
+    /// (uffd) Tell which function took each fault, at the cost of a procfs
+    /// read per fault
+    #[arg(long)]
+    symbolicate: bool,
+
+    /// (uffd) Count faults per bucket and only send resident bytes per
+    /// bucket, rather than every fault: page, 64k or 2m. Much less traffic
+    /// from processes with huge heaps, but only as precise as the buckets.
+    #[arg(
+        long,
+        default_value = "page",
+        value_name = "G",
+        conflicts_with = "symbolicate"
+    )]
+    track_granularity: Granularity,
+
+    /// (uffd) How often to look for swapped pages, in milliseconds, 0 to
+    /// never look
+    #[arg(long, default_value_t = 1000, value_name = "MS")]
+    swap_interval: u64,
+
+    /// Report pages written to in each interval
+    #[arg(long, value_name = "MS")]
+    soft_dirty_interval: Option<u64>,
+
+    /// Report resident pages not accessed in each interval
+    #[arg(long, value_name = "MS")]
+    idle_interval: Option<u64>,
+
+    /// Report transparent huge page coverage
+    #[arg(long, value_name = "MS")]
+    thp_interval: Option<u64>,
+
+    /// Report pages merged by KSM
+    #[arg(long, value_name = "MS")]
+    ksm_interval: Option<u64>,
+
+    /// Report which NUMA nodes memory lives on
+    #[arg(long, value_name = "MS")]
+    numa_interval: Option<u64>,
+
+    /// Report memory cgroup usage, limits and events
+    #[arg(long, value_name = "MS")]
+    cgroup_interval: Option<u64>,
+
+    /// Report RSS/PSS/swap as accounted by the kernel
+    #[arg(long, value_name = "MS")]
+    rollup_interval: Option<u64>,
+
+    /// Report minor and major fault counts
+    #[arg(long, value_name = "MS")]
+    faults_interval: Option<u64>,
+
+    /// Only track memory between these addresses, e.g.
+    /// 0x7f0000000000-0x7fffffffffff. Can be given more than once.
+    #[arg(long, value_name = "START-END", value_parser = parse_range)]
+    track_range: Vec<Range<u64>>,
+
+    /// Don't track mappings smaller than this (other than the heap), e.g. 1M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_map_size: Option<u64>,
+
+    /// Don't track mappings bigger than this (other than the heap), e.g. 1T
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_map_size: Option<u64>,
+
+    /// Only track mappings whose path (or file name) matches GLOB, e.g.
+    /// 'libfoo*'. Others are [heap], [stack], [anon], [vdso], [vvar] and
+    /// [vsyscall]. Can be given more than once.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Don't track mappings whose path (or file name) matches GLOB, e.g.
+    /// '[stack]'. Can be given more than once.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Only trace the process we started (or attached to) and its threads,
+    /// not the processes it forks
+    #[arg(long)]
+    no_follow_children: bool,
+
+    /// Report every call to these syscalls (and what they returned), e.g.
+    /// mmap,madvise,openat. Names or numbers, comma-separated.
+    #[arg(long, value_name = "SYSCALLS", value_delimiter = ',', value_parser = parse_syscall)]
+    watch_syscalls: Vec<(i64, String)>,
+
+    /// A name for the session, shown by the frontend and kept in traces and
+    /// exports
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
+
+    /// Tag the session with KEY=VALUE, e.g. git=abc123 (can be repeated)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env)]
+    tag: Vec<(String, String)>,
+
+    /// Stop tracing after SECS seconds
+    #[arg(long, value_name = "SECS")]
+    max_duration: Option<u64>,
+
+    /// Stop tracing after N events
+    #[arg(long, value_name = "N")]
+    max_events: Option<u64>,
+
+    /// What to do with the traced processes once a limit is hit: detach
+    /// (they go on without mevi) or kill
+    #[arg(long, default_value = "detach", value_name = "POLICY")]
+    on_limit: OnLimit,
+}
+
+/// How to serve, for commands that do
+#[derive(clap::Args)]
+struct ServeOpts {
+    /// Where to serve the frontend, its stream, the API and metrics, port 0
+    /// for any free port [default: $MEVI_LISTEN, or 127.0.0.1:5001]
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<SocketAddr>,
+
+    /// Require TOKEN from clients, as a bearer token or ?token=TOKEN over
+    /// HTTP [default: $MEVI_TOKEN, or a random one when --listen or
+    /// --listen-tcp isn't a loopback address]
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Serve everything under PATH (e.g. /mevi), behind reverse proxies that
+    /// don't strip it
+    #[arg(long, value_name = "PATH", value_parser = parse_base_path)]
+    base_path: Option<String>,
+
+    /// Only accept websockets from pages served from ORIGIN (e.g.
+    /// https://debug.example.com), can be given more than once [default: any
+    /// origin]. Pausing, evicting and dumping only ever take requests from
+    /// mevi's own pages, and those.
+    #[arg(long = "allow-origin", value_name = "ORIGIN")]
+    allowed_origins: Vec<String>,
+
+    /// Also stream events over TCP on ADDR (e.g. 0.0.0.0:5002), for mevi
+    /// connect
+    #[arg(long, value_name = "ADDR")]
+    listen_tcp: Option<SocketAddr>,
+
+    /// Serve HTTPS (and --listen-tcp over TLS), with the PEM certificate
+    /// chain in FILE
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// The PEM private key for --tls-cert
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Also stream events on a Unix socket at PATH, same protocol as
+    /// --listen-tcp
+    #[arg(long, value_name = "PATH")]
+    listen_unix: Option<PathBuf>,
+
+    /// Keep about N events' worth of history, so the frontend can look back,
+    /// 0 to keep none
+    #[arg(long, default_value_t = 1_000_000, value_name = "N")]
+    history: usize,
+
+    /// How often to sample resident bytes for /api/tracees/TID/series, in
+    /// milliseconds, 0 to never sample
+    #[arg(long, default_value_t = 1000, value_name = "MS")]
+    series_interval: u64,
+
+    /// Send clients resident bytes per bucket rather than page by page:
+    /// page, 64k or 2m
+    #[arg(long, default_value = "page", value_name = "G")]
+    granularity: Granularity,
+
+    /// Once every traced process is gone, keep serving for SECS more
+    /// [default: until interrupted, or not at all without a terminal]
+    #[arg(long, value_name = "SECS")]
+    linger: Option<u64>,
+
+    /// Push metrics and process spans to an OpenTelemetry collector over
+    /// OTLP/HTTP, e.g. http://localhost:4318
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// How often to push to the collector, in milliseconds
+    #[arg(long, default_value_t = 10_000, value_name = "MS")]
+    otlp_interval: u64,
+
+    /// Also write every event to a SQLite database, with processes,
+    /// mappings, events and samples tables: sqlite:PATH
+    #[arg(long = "sink", value_name = "SINK")]
+    sinks: Vec<Sink>,
+}
+
+/// What to trace, for commands that can attach as well as start a program
+#[derive(clap::Args)]
+struct TargetOpts {
+    /// Attach to a process that's already running instead
+    #[arg(long, conflicts_with = "program")]
+    pid: Option<u32>,
+
+    #[command(flatten)]
+    spawn: SpawnOpts,
+
+    /// The program to trace, and its arguments. Put `--` before it if it
+    /// starts with a dash.
+    #[arg(trailing_var_arg = true, value_name = "PROGRAM")]
+    program: Vec<String>,
+}
+
+/// How to start a program
+#[derive(clap::Args)]
+struct SpawnOpts {
+    /// Set an environment variable for PROGRAM, e.g.
+    /// MALLOC_CONF=dirty_decay_ms:0
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// Leave an environment variable out of PROGRAM's environment
+    #[arg(long, value_name = "KEY")]
+    env_remove: Vec<String>,
+
+    /// Start PROGRAM with only what --env sets, rather than mevi's whole
+    /// environment
+    #[arg(long)]
+    clear_env: bool,
+
+    /// What PROGRAM sees as its argv[0] [default: PROGRAM, as given]
+    #[arg(long, value_name = "NAME")]
+    argv0: Option<String>,
+
+    /// Run PROGRAM in a pseudo-terminal, so it acts like it would in a
+    /// terminal (colors, line editing, etc.) even though mevi's in the way
+    #[arg(long)]
+    pty: bool,
+
+    /// Write PROGRAM's stdout to FILE (it can be the same as --stderr)
+    #[arg(long, value_name = "FILE", conflicts_with = "pty")]
+    stdout: Option<PathBuf>,
+
+    /// Write PROGRAM's stderr to FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "pty")]
+    stderr: Option<PathBuf>,
+
+    /// Run PROGRAM as USER (a name or a UID), with their groups, while mevi
+    /// keeps its privileges
+    #[arg(long, value_name = "USER")]
+    user: Option<String>,
+
+    /// Run PROGRAM with GROUP (a name or a GID) as its group [default:
+    /// USER's]
+    #[arg(long, value_name = "GROUP")]
+    group: Option<String>,
+
+    /// Run PROGRAM in a new cgroup that can only use SIZE bytes of memory,
+    /// e.g. 512M, to see how it copes with reclaim and OOMs. Implies
+    /// --cgroup-interval 1000 unless given.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memory_limit: Option<u64>,
+}
+
+/// Which mevi to tell what to do
+#[derive(clap::Args)]
+struct ControlOpts {
+    /// Where it serves its frontend
+    #[arg(default_value = "http://127.0.0.1:5001", value_name = "URL")]
+    url: String,
+
+    /// The token it wants from HTTP clients, if any [default: $MEVI_TOKEN]
+    #[arg(long)]
+    token: Option<String>,
+}
+
+impl TraceOpts {
+    fn apply(self, args: &mut Args) {
+        args.tracer.backend = self.backend;
+        args.tracer.poll_interval = Duration::from_millis(self.poll_interval);
+        args.tracer.inject_on = self.inject_on;
+        args.backpressure = self.backpressure;
+        args.tracer.write_protect = self.write_protect;
+        args.tracer.coalesce_window = Duration::from_millis(self.coalesce_window);
+        args.tracer.symbolicate = self.symbolicate;
+        args.tracer.bucket = self.track_granularity.bucket();
+        args.tracer.swap_interval = (self.swap_interval > 0).then(|| ms(self.swap_interval));
+        args.tracer.soft_dirty_interval = self.soft_dirty_interval.map(ms);
+        args.tracer.idle_interval = self.idle_interval.map(ms);
+        args.tracer.thp_interval = self.thp_interval.map(ms);
+        args.tracer.ksm_interval = self.ksm_interval.map(ms);
+        args.tracer.numa_interval = self.numa_interval.map(ms);
+        args.tracer.cgroup_interval = self.cgroup_interval.map(ms);
+        args.tracer.rollup_interval = self.rollup_interval.map(ms);
+        args.tracer.faults_interval = self.faults_interval.map(ms);
+        args.tracer.track = TrackFilter {
+            ranges: self.track_range.into_iter().collect(),
+            min_size: self.min_map_size.unwrap_or_default(),
+            max_size: self.max_map_size,
+            include: self.include,
+            exclude: self.exclude,
+        };
+        args.tracer.follow_children = !self.no_follow_children;
+        args.tracer.watch_syscalls = self.watch_syscalls.into_iter().collect();
+        args.session = SessionInfo {
+            name: self.name,
+            tags: self.tag,
+        };
+        args.tracer.max_duration = self.max_duration.map(Duration::from_secs);
+        args.tracer.max_events = self.max_events;
+        args.tracer.on_limit = self.on_limit;
+    }
+}
+
+impl ServeOpts {
+    fn apply(self, args: &mut Args) {
+        args.listen = self.listen;
+        args.token = self.token;
+        // `/` is the same as nothing at all
+        args.base_path = self.base_path.filter(|path| !path.is_empty());
+        args.allowed_origins = self.allowed_origins;
+        args.listen_tcp = self.listen_tcp;
+        // clap makes sure it's both or neither
+        args.tls = self.tls_cert.zip(self.tls_key);
+        args.listen_unix = self.listen_unix;
+        args.history = self.history;
+        args.series_interval = (self.series_interval > 0).then(|| ms(self.series_interval));
+        args.granularity = self.granularity;
+        args.linger = self.linger.map(Duration::from_secs);
+        args.otlp_endpoint = self.otlp_endpoint;
+        args.otlp_interval = ms(self.otlp_interval);
+        args.sinks = self.sinks;
+    }
+}
+
+impl SpawnOpts {
+    fn is_empty(&self) -> bool {
+        self.env.is_empty()
+            && self.env_remove.is_empty()
+            && !self.clear_env
+            && self.argv0.is_none()
+            && !self.pty
+            && self.stdout.is_none()
+            && self.stderr.is_none()
+            && self.user.is_none()
+            && self.group.is_none()
+            && self.memory_limit.is_none()
+    }
+
+    fn apply(self, args: &mut Args) -> Result<()> {
+        args.tracer.env = Env {
+            clear: self.clear_env,
+            remove: self.env_remove,
+            set: self.env,
+        };
+        args.tracer.argv0 = self.argv0;
+        args.tracer.pty = self.pty;
+        args.tracer.stdout = self.stdout;
+        args.tracer.stderr = self.stderr;
+        args.tracer.run_as = resolve_run_as(self.user.as_deref(), self.group.as_deref())?;
+        args.tracer.memory_limit = self.memory_limit;
+        if args.tracer.memory_limit.is_some() && args.tracer.cgroup_interval.is_none() {
+            // that's the point of it
+            args.tracer.cgroup_interval = Some(Duration::from_secs(1));
+        }
+        Ok(())
+    }
+}
+
+impl TargetOpts {
+    fn apply(self, args: &mut Args) -> Result<()> {
+        if self.pid.is_some() && !self.spawn.is_empty() {
+            bail!("--pid doesn't go with options for starting a program (--env, --pty, etc.)")
+        }
+        self.spawn.apply(args)?;
+        args.tracer.target = match self.pid {
+            Some(pid) => Target::Attach(pid),
+            None if self.program.is_empty() => {
+                bail!("missing the program to trace (or a --pid to attach to)")
+            }
+            None => Target::Spawn(self.program),
+        };
+        Ok(())
+    }
+}
+
+impl ControlOpts {
+    fn apply(self, args: &mut Args, action: control::Action) {
+        args.mode = Mode::Control {
+            token: self.token(),
+            url: self.url,
+            action,
+        };
+    }
+
+    fn token(&self) -> Option<String> {
+        self.token
+            .clone()
+            .or_else(|| std::env::var("MEVI_TOKEN").ok())
+    }
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// Looks up `--user` and `--group`, if there's anything to look up
+fn resolve_run_as(user: Option<&str>, group: Option<&str>) -> Result<Option<RunAs>> {
+    let user = match user {
+        Some(name) => {
+            let found = match name.parse() {
+                Ok(uid) => User::from_uid(Uid::from_raw(uid))?,
+                Err(_) => User::from_name(name)?,
+            };
+            Some(found.ok_or_else(|| eyre!("there's no user {name:?}"))?)
+        }
+        None => None,
+    };
+    let gid = match group {
+        Some(name) => {
+            let found = match name.parse() {
+                Ok(gid) => Group::from_gid(Gid::from_raw(gid))?,
+                Err(_) => Group::from_name(name)?,
+            };
+            Some(found.ok_or_else(|| eyre!("there's no group {name:?}"))?.gid)
+        }
+        None => None,
+    };
+
+    Ok(match (user, gid) {
+        (None, None) => None,
+        (Some(user), gid) => {
+            let gid = gid.unwrap_or(user.gid);
+            let name = CString::new(user.name.as_str())?;
+            Some(RunAs {
+                uid: user.uid,
+                gid,
+                groups: getgrouplist(&name, gid)?,
+            })
+        }
+        (None, Some(gid)) => Some(RunAs {
+            uid: Uid::current(),
+            gid,
+            groups: vec![gid],
+        }),
+    })
+}
+
+impl Args {
+    pub(crate) fn parse() -> Result<Self> {
+        let mut args = std::env::args_os().collect::<Vec<_>>();
+
+        // `--config` comes before the command, so we know where to get
+        // defaults from before parsing the rest
+        let mut config = None;
+        let mut command_at = 1;
+        while let Some(arg) = args.get(command_at).and_then(|arg| arg.to_str()) {
+            if arg == "--config" {
+                config = args.get(command_at + 1).map(PathBuf::from);
+                command_at += 2;
+            } else if let Some(path) = arg.strip_prefix("--config=") {
+                config = Some(PathBuf::from(path));
+                command_at += 1;
+            } else {
+                break;
+            }
+        }
+
+        // `mevi PROGRAM` (or `mevi --some-option PROGRAM`) means `mevi run`
+        let first = args.get(command_at).and_then(|arg| arg.to_str());
+        if let Some(first) = first {
+            let ours = matches!(first, "help" | "-h" | "--help" | "-V" | "--version")
+                || Cli::command().get_subcommands().any(|sub| {
+                    sub.get_name() == first || sub.get_all_aliases().any(|a| a == first)
+                });
+            if !ours {
+                args.insert(command_at, "run".into());
+            }
+        }
+
+        let mut cmd = Cli::command().arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Where to read default options from [default: ~/.config/mevi/config.toml]"),
+        );
+        if let Some(config) = Config::load(config.as_deref())? {
+            cmd = config.apply(cmd)?;
+        }
+        let matches = cmd.get_matches_from(args);
+        let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        Self::from_cli(cli)
+    }
+
+    fn from_cli(cli: Cli) -> Result<Self> {
+        let mut args = Self {
+            log_file: cli.log_file,
+            ..Self::default()
+        };
+        match cli.command {
+            Command::Run {
+                trace,
+                serve,
+                headless,
+                server,
+                spawn,
+                program,
+            } => {
+                trace.apply(&mut args);
+                serve.apply(&mut args);
+                args.tracer.target = Target::Spawn(program);
+                spawn.apply(&mut args)?;
+                if headless {
+                    args.mode = Mode::Export {
+                        format: ExportFormat::Summary,
+                        input: None,
+                        output: None,
+                    };
+                }
+                if let Some(server) = server {
+                    args.mode = Mode::Register { server };
+                }
+            }
+            Command::Attach { trace, serve, pid } => {
+                trace.apply(&mut args);
+                serve.apply(&mut args);
+                args.tracer.target = Target::Attach(pid);
+            }
+            Command::Record {
+                output,
+                output_dir,
+                export,
+                compression_level,
+                snapshot_interval,
+                capture_signals,
+                wait_for_signal,
+                trace,
+                target,
+            } => {
+                trace.apply(&mut args);
+                target.apply(&mut args)?;
+                let (output, exports) = match output_dir {
+                    Some(dir) => {
+                        let dir = dir.join(run_dir_name(&args.tracer.target));
+                        let mut exports = vec![ExportFormat::Summary];
+                        exports.extend(export.into_iter().filter(|f| *f != ExportFormat::Summary));
+                        let exports = exports
+                            .into_iter()
+                            .map(|format| (format, dir.join(format.file_name())))
+                            .collect();
+                        (dir.join("trace.mevi"), exports)
+                    }
+                    None => (output.unwrap(), vec![]),
+                };
+                args.mode = Mode::Record {
+                    output,
+                    compression_level,
+                    snapshot_interval: snapshot_interval.map(Duration::from_secs),
+                    exports,
+                    capture_signals: capture_signals || wait_for_signal,
+                    wait_for_signal,
+                };
+            }
+            Command::Replay {
+                speed,
+                serve,
+                input,
+            } => {
+                serve.apply(&mut args);
+                args.mode = Mode::Replay { input, speed };
+            }
+            Command::Connect {
+                tls_ca,
+                remote_token,
+                serve,
+                addr,
+            } => {
+                serve.apply(&mut args);
+                args.mode = Mode::Connect {
+                    addr,
+                    tls_ca,
+                    token: remote_token,
+                };
+            }
+            Command::Tui {
+                output,
+                trace,
+                target,
+            } => {
+                trace.apply(&mut args);
+                target.apply(&mut args)?;
+                if args.tracer.pty {
+                    bail!("the TUI needs the terminal to itself, --pty doesn't work with it");
+                }
+                // the terminal belongs to the TUI, unless told otherwise the
+                // program's output goes to `output`, or nowhere
+                let out = output.clone().unwrap_or_else(|| "/dev/null".into());
+                args.tracer.stdout.get_or_insert_with(|| out.clone());
+                args.tracer.stderr.get_or_insert(out);
+                args.tracer.null_stdin = true;
+                args.mode = Mode::Tui { output };
+            }
+            Command::Export {
+                format,
+                slice,
+                from,
+                output,
+                trace,
+                target,
+            } => {
+                let format = match (format, slice) {
+                    (ExportFormat::Heatmap { .. }, Some(slice)) => {
+                        ExportFormat::Heatmap { slice: ms(slice) }
+                    }
+                    (_, Some(_)) => bail!("--slice only applies to heatmaps"),
+                    (format, None) => format,
+                };
+                trace.apply(&mut args);
+                if from.is_none() {
+                    target.apply(&mut args)?;
+                }
+                args.mode = Mode::Export {
+                    format,
+                    input: from,
+                    output,
+                };
+            }
+            Command::Doctor => args.mode = Mode::Doctor,
+            Command::Serve { accept, serve } => {
+                serve.apply(&mut args);
+                args.mode = Mode::Server { accept };
+            }
+            Command::Pause(opts) => opts.apply(&mut args, control::Action::Pause),
+            Command::Resume(opts) => opts.apply(&mut args, control::Action::Resume),
+            Command::Evict {
+                advice,
+                pid,
+                range,
+                control: opts,
+            } => opts.apply(
+                &mut args,
+                control::Action::Evict(Eviction {
+                    tid: TraceeId(pid),
+                    range,
+                    advice,
+                }),
+            ),
+            Command::Dump {
+                output,
+                hexdump,
+                pid,
+                range,
+                control: opts,
+            } => {
+                if output.is_none() && !hexdump && std::io::stdout().is_terminal() {
+                    bail!("that'd be binary all over the terminal, pass -o FILE or --hexdump");
+                }
+                args.mode = Mode::Dump {
+                    token: opts.token(),
+                    url: opts.url,
+                    tid: TraceeId(pid),
+                    range,
+                    output,
+                    hexdump,
+                };
+            }
+        }
+        Ok(args)
+    }
+
+    /// Where to serve HTTP: `--listen`, or `$MEVI_LISTEN`, or the default
+    pub(crate) fn http_addr(&self) -> Result<SocketAddr> {
+        if let Some(addr) = self.listen {
+            return Ok(addr);
+        }
+        match std::env::var("MEVI_LISTEN") {
+            Ok(addr) => addr
+                .parse()
+                .map_err(|e| eyre!("MEVI_LISTEN={addr:?} isn't an address: {e}")),
+            Err(_) => Ok(DEFAULT_LISTEN),
+        }
+    }
+
+    /// What clients (HTTP ones served on `addr`, and the `--listen-tcp` and
+    /// `--listen-unix` ones) have to show: `--token`, or `$MEVI_TOKEN`, or a
+    /// fresh one if anyone else on the network could connect. `None` if
+    /// anyone on this machine can connect.
+    pub(crate) fn token(&self, addr: SocketAddr) -> Result<Option<String>> {
+        if let Some(token) = &self.token {
+            return Ok(Some(token.clone()));
+        }
+        if let Ok(token) = std::env::var("MEVI_TOKEN") {
+            return Ok(Some(token));
+        }
+        let tcp_is_public = self.listen_tcp.is_some_and(|tcp| !tcp.ip().is_loopback());
+        if addr.ip().is_loopback() && !tcp_is_public {
+            return Ok(None);
+        }
+        crate::auth::generate().map(Some)
+    }
+}
+
+/// Playback speeds are multipliers, so they have to be positive
+pub(crate) fn parse_speed(s: &str) -> Result<f64> {
+    let speed: f64 = s.parse()?;
+    if !(speed.is_finite() && speed > 0.0) {
+        bail!("speed must be a positive number, got {s}");
+    }
+    Ok(speed)
+}
+
+/// `START-END`, in hex (`0x` is optional), rounded out to whole pages
+pub(crate) fn parse_range(s: &str) -> Result<Range<u64>> {
+    let Some((start, end)) = s.split_once('-') else {
+        bail!("expected START-END, got {s:?}");
+    };
+    let parse = |addr: &str| {
+        let addr = addr.trim_start_matches("0x");
+        u64::from_str_radix(addr, 16).map_err(|e| eyre!("{addr:?} isn't a hex address: {e}"))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start >= end {
+        bail!("{s:?} is empty");
+    }
+    let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?.unwrap() as u64;
+    let Some(end) = end.div_ceil(page_size).checked_mul(page_size) else {
+        bail!("{s:?} ends past the address space");
+    };
+    Ok(start / page_size * page_size..end)
+}
+
+/// A number of bytes, optionally with a k, m, g or t suffix (powers of 1024)
+fn parse_size(s: &str) -> Result<u64> {
+    let lower = s.to_ascii_lowercase();
+    let (digits, shift) = match lower.trim_end_matches('b').as_bytes() {
+        [digits @ .., b'k'] => (digits, 10),
+        [digits @ .., b'm'] => (digits, 20),
+        [digits @ .., b'g'] => (digits, 30),
+        [digits @ .., b't'] => (digits, 40),
+        digits => (digits, 0),
+    };
+    let n: u64 = std::str::from_utf8(digits)?
+        .parse()
+        .map_err(|e| eyre!("{s:?} isn't a size: {e}"))?;
+    n.checked_shl(shift)
+        .filter(|size| size >> shift == n)
+        .ok_or_else(|| eyre!("{s:?} is too big"))
+}
+
+/// `mevi-{program}-{YYYYmmdd-HHMMSS}`, in local time, for `--output-dir`
+fn run_dir_name(target: &Target) -> String {
+    let program = match target {
+        Target::Spawn(command) => Path::new(&command[0])
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        Target::Attach(pid) => std::fs::read_to_string(format!("/proc/{pid}/comm"))
+            .map(|comm| comm.trim().to_owned())
+            .unwrap_or_else(|_| pid.to_string()),
+    };
+    // it's going in a path
+    let program: String = program
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    format!(
+        "mevi-{program}-{:04}{:02}{:02}-{:02}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+fn parse_syscall(s: &str) -> Result<(i64, String)> {
+    syscalls::parse(s).ok_or_else(|| eyre!("unknown syscall {s:?}, give its number instead"))
+}
+
+/// `unix:///path/to/socket`, the only kind of server there is for now
+fn parse_server(s: &str) -> Result<PathBuf> {
+    match s.strip_prefix("unix://") {
+        Some(path) if path.starts_with('/') => Ok(PathBuf::from(path)),
+        _ => bail!("expected unix:///path/to/socket, got {s:?}"),
+    }
+}
+
+/// `KEY=VALUE`, where VALUE may be empty
+fn parse_env(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => bail!("expected KEY=VALUE, got {s:?}"),
+    }
+}
+
+/// `/mevi/` and `/mevi` both mean `/mevi`, and `/` means no prefix at all,
+/// i.e. an empty one
+fn parse_base_path(s: &str) -> Result<String> {
+    if !s.starts_with('/') {
+        bail!("base path must start with a slash, got {s:?}");
+    }
+    Ok(s.trim_end_matches('/').to_owned())
+}
+
+fn parse_host_port(s: &str) -> Result<String> {
+    if !s.contains(':') {
+        bail!("{s:?} is missing a port");
+    }
+    Ok(s.to_owned())
+}
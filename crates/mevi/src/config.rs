@@ -0,0 +1,145 @@
+//! `~/.config/mevi/config.toml`, or `--config FILE`: defaults for any
+//! option, so they don't have to be spelled out every time. Keys are long
+//! option names, and a table named after a command only applies to it:
+//!
+//! ```toml
+//! listen = "0.0.0.0:5001"
+//! backend = "pagemap"
+//! granularity = "64k"
+//! sink = ["sqlite:mevi.db"]
+//!
+//! [record]
+//! compression-level = 9
+//! ```
+//!
+//! Options given on the command line win.
+
+use std::path::{Path, PathBuf};
+
+use clap::ArgAction;
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+
+pub(crate) struct Config {
+    path: PathBuf,
+    table: toml::Table,
+}
+
+impl Config {
+    /// Reads `path`, or the default config file if there is one
+    pub(crate) fn load(path: Option<&Path>) -> Result<Option<Self>> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(None),
+            },
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => return Ok(None),
+            Err(e) => return Err(e).wrap_err_with(|| format!("reading {}", path.display())),
+        };
+        let table = text
+            .parse()
+            .wrap_err_with(|| format!("parsing {}", path.display()))?;
+        Ok(Some(Self { path, table }))
+    }
+
+    /// Makes what's in the config the default for every subcommand of `cmd`
+    /// that has those options
+    pub(crate) fn apply(&self, mut cmd: clap::Command) -> Result<clap::Command> {
+        let commands = cmd
+            .get_subcommands()
+            .map(|sub| sub.get_name().to_owned())
+            .collect::<Vec<_>>();
+        for (key, value) in &self.table {
+            if let toml::Value::Table(table) = value {
+                if !commands.contains(key) {
+                    bail!("{}: there's no {key} command", self.path.display());
+                }
+                for (option, value) in table {
+                    cmd = self.set_default(cmd, &[key.clone()], option, value)?;
+                }
+            } else if let Some(arg) = cmd
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+            {
+                // options that come before the command, like --log-file
+                let id = arg.get_id().clone();
+                let value = self.scalar(key, value)?;
+                cmd = cmd.mut_arg(id, |arg| arg.default_value(value));
+            } else {
+                cmd = self.set_default(cmd, &commands, key, value)?;
+            }
+        }
+        Ok(cmd)
+    }
+
+    /// Sets the default of `--option` to `value`, in whichever of `commands`
+    /// have it
+    fn set_default(
+        &self,
+        mut cmd: clap::Command,
+        commands: &[String],
+        option: &str,
+        value: &toml::Value,
+    ) -> Result<clap::Command> {
+        let values = match value {
+            toml::Value::Array(values) => values
+                .iter()
+                .map(|value| self.scalar(option, value))
+                .collect::<Result<Vec<_>>>()?,
+            value => vec![self.scalar(option, value)?],
+        };
+
+        let mut found = false;
+        for name in commands {
+            let sub = cmd.find_subcommand(name).unwrap();
+            let Some(arg) = sub
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(option))
+            else {
+                continue;
+            };
+            if values.len() != 1 && !matches!(arg.get_action(), ArgAction::Append) {
+                bail!("{}: {option} takes a single value", self.path.display());
+            }
+            let id = arg.get_id().clone();
+            let values = values.clone();
+            cmd = cmd.mut_subcommand(name, |sub| {
+                sub.mut_arg(id, |arg| arg.default_values(values))
+            });
+            found = true;
+        }
+        if !found {
+            bail!("{}: unknown option {option}", self.path.display());
+        }
+        Ok(cmd)
+    }
+
+    fn scalar(&self, option: &str, value: &toml::Value) -> Result<String> {
+        Ok(match value {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Float(f) => f.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            _ => bail!(
+                "{}: {option} can't be a {}",
+                self.path.display(),
+                value.type_str()
+            ),
+        })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mevi/config.toml`, or `~/.config/mevi/config.toml`
+fn default_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("mevi").join("config.toml"))
+}
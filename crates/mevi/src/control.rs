@@ -0,0 +1,109 @@
+//! Telling the tracer of a live session what to do, from another mevi: the
+//! tracer's side of it is [mevi_tracer::Control], served as `/tracer`.
+//!
+//! Mevis that aren't tracing anything talk to one that is with [send] and
+//! [dump].
+
+use std::ops::Range;
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use hyper::{body::Bytes, header, Body, Client, Method, Request, Response};
+use mevi_common::TraceeId;
+use mevi_tracer::evict::Eviction;
+use tracing::info;
+
+/// What `mevi pause`, `mevi resume` and `mevi evict` ask for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Action {
+    Pause,
+    Resume,
+    Evict(Eviction),
+}
+
+impl Action {
+    /// Where it's posted, under `/tracer`
+    fn path(&self) -> String {
+        match self {
+            Action::Pause => "pause".to_owned(),
+            Action::Resume => "resume".to_owned(),
+            Action::Evict(Eviction { tid, range, advice }) => format!(
+                "evict?tid={}&range={:x}-{:x}&advice={}",
+                tid.0,
+                range.start,
+                range.end,
+                advice.name()
+            ),
+        }
+    }
+}
+
+/// Asks the mevi serving on `url` to do `action`
+pub(crate) async fn send(url: &str, token: Option<&str>, action: Action) -> Result<()> {
+    request(url, token, Method::POST, &action.path()).await?;
+    match action {
+        Action::Pause => info!("paused"),
+        Action::Resume => info!("resumed"),
+        // it happens on the tracee's next syscall, and takes however long it
+        // takes to come back
+        Action::Evict(_) => info!("asked to evict, that mevi's log says how it goes"),
+    }
+    Ok(())
+}
+
+/// How `/tracer/dump` says how many bytes it couldn't read
+pub(crate) const UNREADABLE: &str = "x-mevi-unreadable";
+
+/// What's in `range` of `tid`'s memory, according to the mevi serving on
+/// `url`, and how many bytes of it couldn't be read, cf. [crate::dump]
+pub(crate) async fn dump(
+    url: &str,
+    token: Option<&str>,
+    tid: TraceeId,
+    range: &Range<u64>,
+) -> Result<(Bytes, u64)> {
+    let path = format!("dump?tid={}&range={:x}-{:x}", tid.0, range.start, range.end);
+    let res = request(url, token, Method::GET, &path).await?;
+    let unreadable = res
+        .headers()
+        .get(UNREADABLE)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .unwrap_or_default();
+    Ok((hyper::body::to_bytes(res.into_body()).await?, unreadable))
+}
+
+/// Makes a request to `/tracer/{path}`, and makes sense of errors
+async fn request(
+    url: &str,
+    token: Option<&str>,
+    method: Method,
+    path: &str,
+) -> Result<Response<Body>> {
+    if !url.starts_with("http://") {
+        bail!("expected an http:// URL, got {url}");
+    }
+    let url = format!("{}/tracer/{path}", url.trim_end_matches('/'));
+    let mut req = Request::builder().method(method).uri(&url);
+    if let Some(token) = token {
+        req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    let res = Client::new()
+        .request(req.body(Body::empty())?)
+        .await
+        .wrap_err_with(|| format!("reaching {url}"))?;
+    match res.status().as_u16() {
+        200..=299 => Ok(res),
+        400 => {
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            bail!(
+                "that mevi didn't like it: {}",
+                String::from_utf8_lossy(&body)
+            )
+        }
+        401 => bail!("that mevi wants a token, pass --token or set $MEVI_TOKEN"),
+        404 => bail!("that mevi isn't tracing that"),
+        status => bail!("that mevi said {status}"),
+    }
+}
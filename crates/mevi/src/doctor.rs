@@ -0,0 +1,308 @@
+//! `mevi doctor`: checks whether this machine lets mevi do its thing, and
+//! says what to change if it doesn't, rather than having a run fail halfway
+//! through with some errno.
+
+use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+use color_eyre::{eyre::bail, Result};
+use mevi_tracer::cgroup;
+use userfaultfd::FeatureFlags;
+
+enum Status {
+    Ok,
+    /// Works, but not everything mevi can do
+    Warning,
+    /// Tracing (the default way, anyway) won't work
+    Problem,
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+
+    /// What to do about it
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warning(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warning,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn problem(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Problem,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Runs every check and prints how it went. Fails if any of them found a
+/// problem, so it can gate scripts.
+pub(crate) fn run() -> Result<()> {
+    let checks = [
+        kernel(),
+        unprivileged_userfaultfd(),
+        ptrace_scope(),
+        userfaultfd_features(),
+        cgroups(),
+    ];
+
+    let mut problems = 0;
+    for check in &checks {
+        let tag = match check.status {
+            Status::Ok => "ok     ",
+            Status::Warning => "warning",
+            Status::Problem => {
+                problems += 1;
+                "problem"
+            }
+        };
+        println!("[{tag}] {}: {}", check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            for line in fix.lines() {
+                println!("          {line}");
+            }
+        }
+    }
+
+    match problems {
+        0 => Ok(()),
+        1 => bail!("found a problem, see above"),
+        n => bail!("found {n} problems, see above"),
+    }
+}
+
+fn is_root() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+/// A sysctl, if the kernel has it
+fn sysctl(path: &str) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_owned())
+}
+
+fn kernel() -> Check {
+    const NAME: &str = "kernel";
+    let Some(release) = sysctl("/proc/sys/kernel/osrelease") else {
+        return Check::warning(
+            NAME,
+            "couldn't read /proc/sys/kernel/osrelease",
+            "is /proc mounted?",
+        );
+    };
+    // e.g. 6.1.0-13-amd64
+    let mut numbers = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse::<u32>().unwrap_or_default());
+    let version = (
+        numbers.next().unwrap_or_default(),
+        numbers.next().unwrap_or_default(),
+    );
+
+    if version < (4, 14) {
+        Check::problem(
+            NAME,
+            format!("{release} is too old for the uffd backend, which needs 4.14"),
+            "upgrade the kernel, or use --backend pagemap",
+        )
+    } else if version < (5, 7) {
+        Check::warning(
+            NAME,
+            format!("{release} is older than 5.7, --write-protect won't do anything"),
+            "upgrade the kernel to see first writes",
+        )
+    } else if version < (5, 14) {
+        Check::warning(
+            NAME,
+            format!("{release} is older than 5.14, some shared memory goes unnoticed"),
+            "upgrade the kernel to see pages already in the page cache get mapped",
+        )
+    } else {
+        Check::ok(NAME, release)
+    }
+}
+
+fn unprivileged_userfaultfd() -> Check {
+    const NAME: &str = "vm.unprivileged_userfaultfd";
+    match sysctl("/proc/sys/vm/unprivileged_userfaultfd").as_deref() {
+        // kernels before 5.2 don't have it, and let anyone have one
+        None => Check::ok(NAME, "not there, so it's allowed"),
+        Some("1") => Check::ok(NAME, "1"),
+        Some(value) if is_root() => Check::warning(
+            NAME,
+            format!("{value}, so only programs running as root can be traced with uffd"),
+            "that's fine unless you use --user, otherwise:\n\
+             sudo sysctl -w vm.unprivileged_userfaultfd=1",
+        ),
+        Some(value) => Check::problem(
+            NAME,
+            format!("{value}, so the uffd backend won't work without root"),
+            "sudo sysctl -w vm.unprivileged_userfaultfd=1\n\
+             (in /etc/sysctl.d/ to keep it after a reboot), or use --backend pagemap",
+        ),
+    }
+}
+
+fn ptrace_scope() -> Check {
+    const NAME: &str = "kernel.yama.ptrace_scope";
+    match sysctl("/proc/sys/kernel/yama/ptrace_scope").as_deref() {
+        None => Check::ok(NAME, "no Yama, nothing to worry about"),
+        Some("0") => Check::ok(NAME, "0"),
+        Some(_) if is_root() => Check::ok(NAME, "doesn't apply to root"),
+        Some("1") => Check::warning(
+            NAME,
+            "1, so mevi can trace programs it starts, but not attach to others",
+            "to use `mevi attach`, run it as root, or:\nsudo sysctl -w kernel.yama.ptrace_scope=0",
+        ),
+        Some("2") => Check::problem(
+            NAME,
+            "2, so only root can trace anything",
+            "run mevi as root, or:\nsudo sysctl -w kernel.yama.ptrace_scope=0",
+        ),
+        Some(value) => Check::problem(
+            NAME,
+            format!("{value}, so nobody can trace anything until the next reboot"),
+            "set kernel.yama.ptrace_scope to 0 (or 1) in /etc/sysctl.d/, and reboot",
+        ),
+    }
+}
+
+fn userfaultfd_features() -> Check {
+    const NAME: &str = "userfaultfd features";
+    let features = mevi_tracer::supported_features();
+    if features.is_empty() {
+        return Check::problem(
+            NAME,
+            "couldn't get a userfaultfd to ask",
+            "see vm.unprivileged_userfaultfd above, or use --backend pagemap",
+        );
+    }
+
+    let required = [
+        (FeatureFlags::EVENT_REMAP, "EVENT_REMAP"),
+        (FeatureFlags::EVENT_REMOVE, "EVENT_REMOVE"),
+        (FeatureFlags::EVENT_UNMAP, "EVENT_UNMAP"),
+        (FeatureFlags::THREAD_ID, "THREAD_ID"),
+        (FeatureFlags::MISSING_SHMEM, "MISSING_SHMEM"),
+    ];
+    let missing = required
+        .iter()
+        .filter(|(flag, _)| !features.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Check::problem(
+            NAME,
+            format!("missing {}", missing.join(", ")),
+            "upgrade the kernel, or use --backend pagemap",
+        );
+    }
+
+    let optional = [
+        (
+            FeatureFlags::PAGEFAULT_FLAG_WP,
+            "PAGEFAULT_FLAG_WP",
+            "--write-protect",
+        ),
+        (
+            FeatureFlags::MINOR_SHMEM,
+            "MINOR_SHMEM",
+            "shared memory that's already in the page cache",
+        ),
+        (
+            FeatureFlags::MINOR_HUGETLBFS,
+            "MINOR_HUGETLBFS",
+            "hugetlbfs mappings",
+        ),
+    ];
+    let missing = optional
+        .iter()
+        .filter(|(flag, ..)| !features.contains(*flag))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Check::ok(NAME, "everything mevi uses");
+    }
+    Check::warning(
+        NAME,
+        format!(
+            "missing {}",
+            missing
+                .iter()
+                .map(|(_, name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        format!(
+            "upgrade the kernel to get: {}",
+            missing
+                .iter()
+                .map(|(_, _, what)| *what)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    )
+}
+
+fn cgroups() -> Check {
+    const NAME: &str = "cgroups";
+    let parent = match cgroup::transient_parent() {
+        Ok(parent) => parent,
+        Err(e) => {
+            return Check::warning(
+                NAME,
+                format!("{e}"),
+                "--cgroup-interval and --memory-limit need cgroup v2\n\
+                 (systemd.unified_cgroup_hierarchy=1 on the kernel command line)",
+            )
+        }
+    };
+
+    let controllers =
+        std::fs::read_to_string(parent.join("cgroup.controllers")).unwrap_or_default();
+    if !controllers.split_whitespace().any(|c| c == "memory") {
+        return Check::warning(
+            NAME,
+            format!(
+                "the memory controller isn't available in {}",
+                parent.display()
+            ),
+            "--memory-limit won't work, enable it in the parent's cgroup.subtree_control",
+        );
+    }
+
+    let writable = CString::new(parent.as_os_str().as_bytes())
+        .map(|path| unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0)
+        .unwrap_or_default();
+    if !writable {
+        return Check::warning(
+            NAME,
+            format!("can't create cgroups in {}", parent.display()),
+            "--memory-limit needs root, or a delegated cgroup, e.g.:\n\
+             systemd-run --user --scope -p Delegate=yes mevi ...",
+        );
+    }
+    Check::ok(
+        NAME,
+        format!("v2, --memory-limit cgroups go in {}", parent.display()),
+    )
+}
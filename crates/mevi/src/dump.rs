@@ -0,0 +1,84 @@
+//! `mevi dump`: what's actually in a range of a traced process's memory,
+//! read with `process_vm_readv` by the mevi tracing it, and written to a
+//! file (or as a hexdump) by whoever asked.
+
+use std::{
+    io::{IoSliceMut, Write},
+    ops::Range,
+};
+
+use color_eyre::{eyre::bail, Result};
+use mevi_common::TraceeId;
+use nix::{
+    sys::uio::{process_vm_readv, RemoteIoVec},
+    unistd::{Pid, SysconfVar},
+};
+
+/// The most we'll read in one go
+pub(crate) const MAX_LEN: u64 = 1 << 30;
+
+/// How much we try to read at once, before going page by page
+const CHUNK_LEN: usize = 1 << 20;
+
+/// Reads `range` of `tid`'s memory. Pages that can't be read (not mapped,
+/// or `PROT_NONE`) read as zeroes: also returns how many bytes that was.
+pub(crate) fn read(tid: TraceeId, range: Range<u64>) -> Result<(Vec<u8>, u64)> {
+    let len = range.end - range.start;
+    if len > MAX_LEN {
+        bail!("that's more than {MAX_LEN} bytes, dump it in parts");
+    }
+    let pid = Pid::from_raw(tid.0 as _);
+    let page_size = nix::unistd::sysconf(SysconfVar::PAGE_SIZE)?.unwrap() as usize;
+
+    let mut buf = vec![0u8; len as usize];
+    let mut unreadable = 0;
+    for (i, chunk) in buf.chunks_mut(CHUNK_LEN).enumerate() {
+        let base = range.start as usize + i * CHUNK_LEN;
+        if read_into(pid, base, chunk) == chunk.len() {
+            continue;
+        }
+        // somewhere in there is a page we can't read, and everything after
+        // it in the chunk went unread too
+        for (j, page) in chunk.chunks_mut(page_size).enumerate() {
+            let read = read_into(pid, base + j * page_size, page);
+            page[read..].fill(0);
+            unreadable += (page.len() - read) as u64;
+        }
+    }
+    Ok((buf, unreadable))
+}
+
+/// How many bytes of `buf` could be read from `base` on
+fn read_into(pid: Pid, base: usize, buf: &mut [u8]) -> usize {
+    let remote = [RemoteIoVec {
+        base,
+        len: buf.len(),
+    }];
+    process_vm_readv(pid, &mut [IoSliceMut::new(buf)], &remote).unwrap_or(0)
+}
+
+/// Like `xxd`, with the addresses the bytes were at
+pub(crate) fn hexdump(mut w: impl Write, start: u64, bytes: &[u8]) -> std::io::Result<()> {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        write!(w, "{:016x}: ", start + i as u64 * 16)?;
+        for pair in line.chunks(2) {
+            for byte in pair {
+                write!(w, "{byte:02x}")?;
+            }
+            write!(w, " ")?;
+        }
+        // short last lines keep the text column where it is
+        let missing = 16 - line.len();
+        write!(w, "{:width$} ", "", width = missing * 2 + missing / 2)?;
+        for byte in line {
+            let c = if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            };
+            write!(w, "{c}")?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,1298 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufReader, Write},
+    ops::Range,
+    path::Path,
+    time::Duration,
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use humansize::{make_format, BINARY};
+use mevi_common::{
+    resident_in,
+    trace::{self, TraceReader, TraceRecord},
+    AnnotationKind, MappingKind, MappingKinds, MemMap, MeviEvent, SessionInfo, TraceeId,
+    TraceePayload, TraceeSnapshot,
+};
+use mevi_tracer::channel::EventRx;
+use rangemap::RangeSet;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    activity::{Activity, MappingActivity},
+    cli::ExportFormat,
+};
+
+/// Turns a stream of events into something other tools understand
+trait Exporter {
+    fn event(&mut self, at: Duration, ev: &MeviEvent) -> Result<()>;
+
+    /// Called once there are no more events, for formats that can only be
+    /// written out at the end
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Events as they're happening, timestamped relative to when we started
+/// listening. Ends when all senders are gone.
+pub(crate) fn live(rx: EventRx) -> impl Iterator<Item = Result<TraceRecord>> {
+    let start = rx.anchor().monotonic;
+    std::iter::from_fn(move || rx.recv().ok()).map(move |ev| {
+        Ok(TraceRecord {
+            at: ev.at.since(start),
+            event: ev.event,
+        })
+    })
+}
+
+/// Events from a recorded trace
+pub(crate) fn from_trace(path: &Path) -> Result<impl Iterator<Item = Result<TraceRecord>>> {
+    let file = File::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+    let path = path.to_owned();
+    Ok(
+        TraceReader::new(BufReader::new(file))?.map_while(move |record| match record {
+            Ok(record) => Some(Ok(record)),
+            // that's what a recording that got interrupted looks like
+            Err(trace::Error::Truncated) => {
+                warn!("{} ends with a truncated record", path.display());
+                None
+            }
+            Err(e) => Some(Err(e.into())),
+        }),
+    )
+}
+
+pub(crate) fn export(
+    records: impl Iterator<Item = Result<TraceRecord>>,
+    format: ExportFormat,
+    w: Box<dyn Write + Send>,
+) -> Result<()> {
+    let mut exporter: Box<dyn Exporter> = match format {
+        ExportFormat::Jsonl => Box::new(Jsonl { w }),
+        ExportFormat::Chrome => Box::new(Chrome::new(w)?),
+        ExportFormat::Heatmap { slice } => Box::new(Heatmap::new(w, slice)),
+        ExportFormat::Csv => Box::new(Csv::new(w)),
+        ExportFormat::Summary => Box::new(Summary::new(w)),
+        ExportFormat::Html => Box::new(Html::new(w)),
+    };
+
+    for record in records {
+        let record = record?;
+        exporter.event(record.at, &record.event)?;
+    }
+    exporter.finish()
+}
+
+/// One JSON object per line, per event
+struct Jsonl {
+    w: Box<dyn Write + Send>,
+}
+
+#[derive(Serialize)]
+struct JsonlEvent<'a> {
+    /// Microseconds since the start of the trace
+    at_us: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tid: Option<TraceeId>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<&'a TraceePayload>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot: Option<&'a [TraceeSnapshot]>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<&'a SessionInfo>,
+}
+
+impl Exporter for Jsonl {
+    fn event(&mut self, at: Duration, ev: &MeviEvent) -> Result<()> {
+        let mut line = JsonlEvent {
+            at_us: at.as_micros() as u64,
+            tid: None,
+            payload: None,
+            snapshot: None,
+            session: None,
+        };
+        match ev {
+            MeviEvent::Snapshot(snapshot) => line.snapshot = Some(snapshot),
+            MeviEvent::SessionInfo(info) => line.session = Some(info),
+            MeviEvent::TraceeEvent(tid, payload) => {
+                line.tid = Some(*tid);
+                line.payload = Some(payload);
+            }
+            // only the relay sends those, traces don't have them
+            MeviEvent::SessionEnd(_) => return Ok(()),
+        }
+        serde_json::to_writer(&mut self.w, &line)?;
+        self.w.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(self.w.flush()?)
+    }
+}
+
+/// Don't emit counters for a process more often than this, a memset can
+/// easily produce thousands of faults per millisecond
+const COUNTER_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The Trace Event Format that chrome://tracing and Perfetto read, cf.
+/// https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+///
+/// Each tracee shows up as a process, with counters for its resident and
+/// mapped bytes, and instant events for mappings coming and going.
+struct Chrome {
+    w: Box<dyn Write + Send>,
+    tracees: HashMap<TraceeId, ChromeTracee>,
+
+    /// Whether we've written an event yet, to know whether we need a comma
+    started: bool,
+
+    /// When the latest event happened
+    last_at: Duration,
+}
+
+#[derive(Default)]
+struct ChromeTracee {
+    map: MemMap,
+
+    /// Bytes faulted in since the start of the trace
+    faulted: u64,
+
+    /// When we last emitted counters, and whether anything changed since
+    last_counters: Option<Duration>,
+    changed: bool,
+}
+
+#[derive(Serialize)]
+struct ChromeEvent<'a> {
+    name: &'a str,
+    ph: &'static str,
+
+    /// In microseconds
+    ts: u64,
+    pid: u64,
+    tid: u64,
+
+    /// Scope, for instant events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+
+    args: serde_json::Value,
+}
+
+impl Chrome {
+    fn new(mut w: Box<dyn Write + Send>) -> Result<Self> {
+        // the format allows leaving the array unterminated, so even a partial
+        // export can be loaded
+        w.write_all(b"{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n")?;
+        Ok(Self {
+            w,
+            tracees: Default::default(),
+            started: false,
+            last_at: Duration::ZERO,
+        })
+    }
+
+    fn emit(&mut self, ev: ChromeEvent) -> Result<()> {
+        if self.started {
+            self.w.write_all(b",\n")?;
+        }
+        self.started = true;
+        serde_json::to_writer(&mut self.w, &ev)?;
+        Ok(())
+    }
+
+    fn instant(
+        &mut self,
+        at: Duration,
+        tid: TraceeId,
+        name: &str,
+        args: serde_json::Value,
+    ) -> Result<()> {
+        self.emit(ChromeEvent {
+            name,
+            ph: "i",
+            ts: at.as_micros() as u64,
+            pid: tid.0,
+            tid: tid.0,
+            s: Some("p"),
+            args,
+        })
+    }
+
+    fn counters(&mut self, at: Duration, tid: TraceeId) -> Result<()> {
+        let Some(tracee) = self.tracees.get_mut(&tid) else {
+            return Ok(());
+        };
+        tracee.last_counters = Some(at);
+        tracee.changed = false;
+
+        let mut mapped = 0;
+        let mut resident = 0;
+        for (range, state) in tracee.map.iter() {
+            let size = range.end - range.start;
+            mapped += size;
+            if state.is_resident() {
+                resident += size;
+            }
+        }
+        let faulted = tracee.faulted;
+
+        for (name, args) in [
+            (
+                "memory",
+                serde_json::json!({ "resident": resident, "mapped": mapped }),
+            ),
+            ("faulted", serde_json::json!({ "faulted": faulted })),
+        ] {
+            self.emit(ChromeEvent {
+                name,
+                ph: "C",
+                ts: at.as_micros() as u64,
+                pid: tid.0,
+                tid: tid.0,
+                s: None,
+                args,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for Chrome {
+    fn event(&mut self, at: Duration, ev: &MeviEvent) -> Result<()> {
+        let MeviEvent::TraceeEvent(tid, payload) = ev else {
+            return Ok(());
+        };
+        let tid = *tid;
+        self.last_at = at;
+
+        let tracee = self.tracees.entry(tid).or_default();
+        payload.apply_to_memmap(&mut tracee.map);
+        tracee.changed = true;
+        if let TraceePayload::PageFault { range, .. } = payload {
+            tracee.faulted += range.end - range.start;
+        }
+
+        let range_args = |range: &Range<u64>| {
+            serde_json::json!({
+                "start": format!("{:#x}", range.start),
+                "end": format!("{:#x}", range.end),
+                "size": range.end - range.start,
+            })
+        };
+        match payload {
+            TraceePayload::Map {
+                range,
+                kind,
+                file,
+                perms,
+                raw,
+                ..
+            } => {
+                // the heap only ever grows with brk
+                let name = match kind {
+                    MappingKind::Heap => "brk",
+                    _ => "map",
+                };
+                let mut args = range_args(range);
+                args["kind"] = format!("{kind:?}").into();
+                args["perms"] = perms.to_string().into();
+                if let Some(file) = file {
+                    args["file"] = file.path.clone().into();
+                }
+                if let Some(raw) = raw {
+                    args["prot"] = format!("{:#x}", raw.prot).into();
+                    args["flags"] = format!("{:#x}", raw.flags).into();
+                }
+                self.instant(at, tid, name, args)?;
+            }
+            TraceePayload::Unmap { range } => self.instant(at, tid, "unmap", range_args(range))?,
+            TraceePayload::Remap {
+                old_range,
+                new_range,
+            } => self.instant(
+                at,
+                tid,
+                "remap",
+                serde_json::json!({ "old": range_args(old_range), "new": range_args(new_range) }),
+            )?,
+            TraceePayload::Exec {
+                exe,
+                argv,
+                interpreter,
+            } => self.instant(
+                at,
+                tid,
+                "exec",
+                serde_json::json!({ "exe": exe, "argv": argv, "interpreter": interpreter }),
+            )?,
+            TraceePayload::OomKilled { rss } => {
+                self.instant(at, tid, "oom killed", serde_json::json!({ "rss": rss }))?
+            }
+            TraceePayload::Annotation { name, kind } => {
+                // phases become slices, stacked like the tracee stacked them
+                let ph = match kind {
+                    AnnotationKind::Mark => "i",
+                    AnnotationKind::Begin => "B",
+                    AnnotationKind::End => "E",
+                };
+                self.emit(ChromeEvent {
+                    name,
+                    ph,
+                    ts: at.as_micros() as u64,
+                    pid: tid.0,
+                    tid: tid.0,
+                    s: (*kind == AnnotationKind::Mark).then_some("p"),
+                    args: serde_json::json!({}),
+                })?;
+            }
+            TraceePayload::Syscall {
+                thread,
+                name,
+                args,
+                ret,
+            } => self.instant(
+                at,
+                tid,
+                name,
+                serde_json::json!({
+                    "thread": thread.0,
+                    "args": args.map(|arg| format!("{arg:#x}")),
+                    "ret": ret,
+                }),
+            )?,
+            TraceePayload::CmdLineChange { cmdline } => {
+                self.emit(ChromeEvent {
+                    name: "process_name",
+                    ph: "M",
+                    ts: 0,
+                    pid: tid.0,
+                    tid: tid.0,
+                    s: None,
+                    args: serde_json::json!({ "name": cmdline.join(" ") }),
+                })?;
+            }
+            TraceePayload::Exit => {
+                self.counters(at, tid)?;
+                self.instant(at, tid, "exit", serde_json::json!({}))?;
+                self.tracees.remove(&tid);
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let due = match self.tracees[&tid].last_counters {
+            Some(last) => at.saturating_sub(last) >= COUNTER_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.counters(at, tid)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // whatever changed since the last counters we emitted
+        let pending: Vec<TraceeId> = self
+            .tracees
+            .iter()
+            .filter(|(_, tracee)| tracee.changed)
+            .map(|(tid, _)| *tid)
+            .collect();
+        for tid in pending {
+            self.counters(self.last_at, tid)?;
+        }
+
+        self.w.write_all(b"\n]}\n")?;
+        Ok(self.w.flush()?)
+    }
+}
+
+/// Mapped and resident bytes in each category of mapping, cf.
+/// [MappingKind::category]
+pub(crate) fn by_category(
+    map: &MemMap,
+    kinds: &MappingKinds,
+) -> BTreeMap<&'static str, (u64, u64)> {
+    let mut categories = BTreeMap::<_, (u64, u64)>::new();
+    for (range, kind) in kinds.iter() {
+        let (mapped, resident) = categories.entry(kind.category()).or_default();
+        *mapped += range.end - range.start;
+        *resident += resident_in(map, range);
+    }
+    categories
+}
+
+/// Resident bytes per mapping, per time slice, as JSON: a grid that's easy
+/// to plot as a heatmap (mappings on one axis, time on the other).
+///
+/// Mappings are identified by their address range and kind: if a mapping
+/// grows, it shows up as a new row.
+struct Heatmap {
+    w: Box<dyn Write + Send>,
+    slice: Duration,
+
+    /// How many slices we've taken so far
+    slices: usize,
+    tracees: HashMap<TraceeId, HeatmapTracee>,
+    exited: Vec<(TraceeId, HeatmapTracee)>,
+}
+
+#[derive(Default)]
+struct HeatmapTracee {
+    cmdline: Vec<String>,
+    map: MemMap,
+    kinds: MappingKinds,
+    rows: HashMap<Range<u64>, HeatmapRow>,
+}
+
+#[derive(Serialize)]
+struct HeatmapRow {
+    start: String,
+    end: String,
+    kind: MappingKind,
+
+    /// One entry per slice, zero when the mapping didn't exist
+    resident: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct HeatmapProcess {
+    tid: TraceeId,
+    cmdline: Vec<String>,
+    mappings: Vec<HeatmapRow>,
+}
+
+#[derive(Serialize)]
+struct HeatmapOutput {
+    slice_ms: u64,
+    slices: usize,
+    processes: Vec<HeatmapProcess>,
+}
+
+impl Heatmap {
+    fn new(w: Box<dyn Write + Send>, slice: Duration) -> Self {
+        Self {
+            w,
+            slice,
+            slices: 0,
+            tracees: Default::default(),
+            exited: Default::default(),
+        }
+    }
+
+    /// Records how much of each mapping is resident right now
+    fn take_slice(&mut self) {
+        let idx = self.slices;
+        for tracee in self.tracees.values_mut() {
+            for (range, kind) in tracee.kinds.iter() {
+                let resident = resident_in(&tracee.map, range);
+                let row = tracee
+                    .rows
+                    .entry(range.clone())
+                    .or_insert_with(|| HeatmapRow {
+                        start: format!("{:#x}", range.start),
+                        end: format!("{:#x}", range.end),
+                        kind: *kind,
+                        resident: vec![],
+                    });
+                row.resident.resize(idx, 0);
+                row.resident.push(resident);
+            }
+        }
+        self.slices += 1;
+    }
+}
+
+impl Exporter for Heatmap {
+    fn event(&mut self, at: Duration, ev: &MeviEvent) -> Result<()> {
+        // slice `n` is what things looked like at the end of it
+        while at >= self.slice * (self.slices as u32 + 1) {
+            self.take_slice();
+        }
+
+        let MeviEvent::TraceeEvent(tid, payload) = ev else {
+            return Ok(());
+        };
+        if let TraceePayload::Exit = payload {
+            if let Some(tracee) = self.tracees.remove(tid) {
+                self.exited.push((*tid, tracee));
+            }
+            return Ok(());
+        }
+
+        let tracee = self.tracees.entry(*tid).or_default();
+        payload.apply_to_memmap(&mut tracee.map);
+        payload.apply_to_kinds(&mut tracee.kinds);
+        if let TraceePayload::CmdLineChange { cmdline } = payload {
+            tracee.cmdline = cmdline.clone();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.take_slice();
+
+        let slices = self.slices;
+        let tracees = std::mem::take(&mut self.tracees)
+            .into_iter()
+            .chain(std::mem::take(&mut self.exited));
+        let processes = tracees
+            .map(|(tid, tracee)| {
+                let mut mappings: Vec<_> = tracee
+                    .rows
+                    .into_iter()
+                    .map(|(range, mut row)| {
+                        row.resident.resize(slices, 0);
+                        (range.start, row)
+                    })
+                    .collect();
+                mappings.sort_by_key(|(start, _)| *start);
+                HeatmapProcess {
+                    tid,
+                    cmdline: tracee.cmdline,
+                    mappings: mappings.into_iter().map(|(_, row)| row).collect(),
+                }
+            })
+            .collect();
+
+        serde_json::to_writer(
+            &mut self.w,
+            &HeatmapOutput {
+                slice_ms: self.slice.as_millis() as u64,
+                slices,
+                processes,
+            },
+        )?;
+        self.w.write_all(b"\n")?;
+        Ok(self.w.flush()?)
+    }
+}
+
+/// One row per mapping, written out at the end: when it was mapped and
+/// unmapped, the most of it that was ever resident, and how many page faults
+/// it took.
+///
+/// Like for heatmaps, a mapping is an address range and a kind, so a mapping
+/// that grows or shrinks ends and a new one starts.
+struct Csv {
+    w: Box<dyn Write + Send>,
+    tracees: HashMap<TraceeId, CsvTracee>,
+
+    /// Rows for mappings that are gone
+    done: Vec<CsvRow>,
+
+    /// When the latest event happened
+    last_at: Duration,
+}
+
+#[derive(Default)]
+struct CsvTracee {
+    cmdline: Vec<String>,
+    map: MemMap,
+    kinds: MappingKinds,
+
+    /// Rows for current mappings, by start address (mappings don't overlap)
+    live: BTreeMap<u64, CsvRow>,
+
+    activity: Activity,
+}
+
+struct CsvRow {
+    tid: TraceeId,
+    cmdline: Vec<String>,
+    range: Range<u64>,
+    kind: MappingKind,
+    mapped_at: Duration,
+    unmapped_at: Option<Duration>,
+    peak_resident: u64,
+    activity: MappingActivity,
+}
+
+impl Csv {
+    fn new(w: Box<dyn Write + Send>) -> Self {
+        Self {
+            w,
+            tracees: Default::default(),
+            done: Default::default(),
+            last_at: Duration::ZERO,
+        }
+    }
+
+    /// Ends all rows, and returns them by tracee, then by when they started
+    fn rows(&mut self) -> Vec<CsvRow> {
+        for (_, tracee) in std::mem::take(&mut self.tracees) {
+            tracee.close(None, &mut self.done);
+        }
+        let mut rows = std::mem::take(&mut self.done);
+        rows.sort_by_key(|row| (row.tid.0, row.mapped_at, row.range.start));
+        rows
+    }
+}
+
+/// Rows for the mappings that overlap `range`
+fn overlapping_rows<'a>(
+    live: &'a mut BTreeMap<u64, CsvRow>,
+    range: &Range<u64>,
+) -> impl Iterator<Item = &'a mut CsvRow> {
+    let start = range.start;
+    live.range_mut(..range.end)
+        .rev()
+        .map(|(_, row)| row)
+        .take_while(move |row| row.range.end > start)
+}
+
+impl CsvTracee {
+    /// Ends rows for mappings that went away, and starts rows for new ones
+    fn reconcile(&mut self, tid: TraceeId, at: Duration, done: &mut Vec<CsvRow>) {
+        let kinds = &self.kinds;
+        let (keep, gone): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut self.live)
+            .into_iter()
+            .partition(|(start, row)| kinds.get_key_value(start) == Some((&row.range, &row.kind)));
+        self.live = keep;
+        for (_, mut row) in gone {
+            row.unmapped_at = Some(at);
+            row.cmdline = self.cmdline.clone();
+            done.push(row);
+        }
+
+        for (range, kind) in self.kinds.iter() {
+            if self.live.contains_key(&range.start) {
+                continue;
+            }
+            self.live.insert(
+                range.start,
+                CsvRow {
+                    tid,
+                    cmdline: vec![],
+                    range: range.clone(),
+                    kind: *kind,
+                    mapped_at: at,
+                    unmapped_at: None,
+                    peak_resident: resident_in(&self.map, range),
+                    activity: self.activity.get(range.start),
+                },
+            );
+        }
+    }
+
+    /// Ends all rows, because the tracee exited or the trace is over
+    fn close(self, at: Option<Duration>, done: &mut Vec<CsvRow>) {
+        for (_, mut row) in self.live {
+            row.unmapped_at = at;
+            row.cmdline = self.cmdline.clone();
+            done.push(row);
+        }
+    }
+}
+
+impl Exporter for Csv {
+    fn event(&mut self, at: Duration, ev: &MeviEvent) -> Result<()> {
+        let MeviEvent::TraceeEvent(tid, payload) = ev else {
+            return Ok(());
+        };
+        self.last_at = at;
+
+        if let TraceePayload::Exit = payload {
+            if let Some(tracee) = self.tracees.remove(tid) {
+                tracee.close(Some(at), &mut self.done);
+            }
+            return Ok(());
+        }
+
+        let tracee = self.tracees.entry(*tid).or_default();
+        payload.apply_to_kinds(&mut tracee.kinds);
+        tracee
+            .activity
+            .apply(at, payload, &tracee.map, &tracee.kinds);
+        payload.apply_to_memmap(&mut tracee.map);
+
+        let touched = match payload {
+            TraceePayload::CmdLineChange { cmdline } => {
+                tracee.cmdline = cmdline.clone();
+                None
+            }
+            TraceePayload::Exec { .. } | TraceePayload::Unmap { .. } => {
+                tracee.reconcile(*tid, at, &mut self.done);
+                None
+            }
+            TraceePayload::Map { range, .. } => {
+                tracee.reconcile(*tid, at, &mut self.done);
+                Some(range)
+            }
+            TraceePayload::Remap { new_range, .. } => {
+                tracee.reconcile(*tid, at, &mut self.done);
+                Some(new_range)
+            }
+            TraceePayload::PageFault { range, .. } => {
+                for row in overlapping_rows(&mut tracee.live, range) {
+                    row.activity = tracee.activity.get(row.range.start);
+                }
+                Some(range)
+            }
+            TraceePayload::MemStateChange { range, .. } => Some(range),
+            _ => None,
+        };
+
+        if let Some(range) = touched {
+            for row in overlapping_rows(&mut tracee.live, range) {
+                let resident = resident_in(&tracee.map, &row.range);
+                row.peak_resident = row.peak_resident.max(resident);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let rows = self.rows();
+        writeln!(
+            self.w,
+            "tid,cmdline,start,end,kind,mapped_at_ms,unmapped_at_ms,lifetime_ms,peak_resident_bytes,faults,first_fault_ms,churn_bytes"
+        )?;
+        for row in rows {
+            let unmapped_at = row
+                .unmapped_at
+                .map(|at| at.as_millis().to_string())
+                .unwrap_or_default();
+            let lifetime = row
+                .unmapped_at
+                .unwrap_or(self.last_at)
+                .saturating_sub(row.mapped_at);
+            let first_fault = row
+                .activity
+                .first_fault_ms
+                .map(|ms| ms.to_string())
+                .unwrap_or_default();
+            writeln!(
+                self.w,
+                "{},{},{:#x},{:#x},{:?},{},{},{},{},{},{},{}",
+                row.tid.0,
+                csv_field(&row.cmdline.join(" ")),
+                row.range.start,
+                row.range.end,
+                row.kind,
+                row.mapped_at.as_millis(),
+                unmapped_at,
+                lifetime.as_millis(),
+                row.peak_resident,
+                row.activity.faults,
+                first_fault,
+                row.activity.churn,
+            )?;
+        }
+        Ok(self.w.flush()?)
+    }
+}
+
+/// Quotes a field if it needs it, doubling any quotes inside
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Mapped and resident bytes, kept up to date as events come in
+#[derive(Default, Clone, Copy)]
+pub(crate) struct Totals {
+    pub(crate) mapped: u64,
+    pub(crate) resident: u64,
+}
+
+impl Totals {
+    /// Applies `payload` to `map`, only looking at the ranges it touches:
+    /// finding what overlaps a range is logarithmic in the size of the map,
+    /// going over all of it for every event is not
+    pub(crate) fn apply(&mut self, map: &mut MemMap, payload: &TraceePayload) {
+        let mut touched = RangeSet::new();
+        let mut touch = |range: &Range<u64>| {
+            if range.start < range.end {
+                touched.insert(range.clone());
+            }
+        };
+        match payload {
+            TraceePayload::Map { range, .. }
+            | TraceePayload::MemStateChange { range, .. }
+            | TraceePayload::PageFault { range, .. }
+            | TraceePayload::Unmap { range } => touch(range),
+            TraceePayload::Remap {
+                old_range,
+                new_range,
+            } => {
+                touch(old_range);
+                touch(new_range);
+            }
+            TraceePayload::ResidentPages(pages) => {
+                for (range, _) in &pages.runs {
+                    touch(range);
+                }
+            }
+            TraceePayload::Exec { .. } => {
+                payload.apply_to_memmap(map);
+                *self = Totals::default();
+                return;
+            }
+            _ => {
+                // doesn't change the map
+            }
+        }
+
+        let before = Totals::within(map, &touched);
+        payload.apply_to_memmap(map);
+        let after = Totals::within(map, &touched);
+        self.mapped = self.mapped - before.mapped + after.mapped;
+        self.resident = self.resident - before.resident + after.resident;
+    }
+
+    fn within(map: &MemMap, ranges: &RangeSet<u64>) -> Self {
+        let mut totals = Totals::default();
+        for range in ranges.iter() {
+            for (r, state) in map.overlapping(range) {
+                let size = r.end.min(range.end) - r.start.max(range.start);
+                totals.mapped += size;
+                if state.is_resident() {
+                    totals.resident += size;
+                }
+            }
+        }
+        totals
+    }
+}
+
+/// How many mappings the summary lists, per process
+const SUMMARY_TOP_MAPPINGS: usize = 10;
+
+/// How many characters the heap curve is drawn with
+const HEAP_CURVE_WIDTH: usize = 40;
+
+/// A short, human-readable report, written out at the end: peaks, faults,
+/// the mappings that got the most resident, and how the heap grew.
+struct Summary {
+    w: Box<dyn Write + Send>,
+    tracees: BTreeMap<TraceeId, SummaryTracee>,
+
+    /// Keeps track of mappings for us, and doesn't write anything
+    mappings: Csv,
+
+    /// When the latest event happened
+    last_at: Duration,
+
+    /// What the session was called, if anything
+    session: Option<SessionInfo>,
+}
+
+#[derive(Default)]
+struct SummaryTracee {
+    cmdline: Vec<String>,
+    map: MemMap,
+    kinds: MappingKinds,
+    first_at: Duration,
+    exited_at: Option<Duration>,
+    totals: Totals,
+    peak_resident: u64,
+    peak_mapped: u64,
+
+    /// Pages faulted in
+    faulted: u64,
+
+    /// Bytes faulted in by each function, with `--symbolicate`
+    faulted_by_site: HashMap<String, u64>,
+
+    /// The heap's size every time it changed
+    heap: Vec<(Duration, u64)>,
+}
+
+impl Summary {
+    fn new(w: Box<dyn Write + Send>) -> Self {
+        Self {
+            w,
+            tracees: Default::default(),
+            mappings: Csv::new(Box::new(std::io::sink())),
+            last_at: Duration::ZERO,
+            session: None,
+        }
+    }
+}
+
+impl Exporter for Summary {
+    fn event(&mut self, at: Duration, ev: &MeviEvent) -> Result<()> {
+        self.mappings.event(at, ev)?;
+        let (tid, payload) = match ev {
+            MeviEvent::TraceeEvent(tid, payload) => (tid, payload),
+            MeviEvent::SessionInfo(info) => {
+                self.session = Some(info.clone());
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        self.last_at = at;
+
+        let tracee = self.tracees.entry(*tid).or_insert_with(|| SummaryTracee {
+            first_at: at,
+            ..Default::default()
+        });
+        match payload {
+            TraceePayload::Exit => {
+                tracee.exited_at = Some(at);
+                return Ok(());
+            }
+            TraceePayload::CmdLineChange { cmdline } => tracee.cmdline = cmdline.clone(),
+            TraceePayload::PageFault { range, site, .. } => {
+                tracee.faulted += (range.end - range.start) / PAGE_SIZE;
+                if let Some(site) = site {
+                    *tracee.faulted_by_site.entry(site.clone()).or_default() +=
+                        range.end - range.start;
+                }
+            }
+            _ => {}
+        }
+
+        tracee.totals.apply(&mut tracee.map, payload);
+        tracee.peak_resident = tracee.peak_resident.max(tracee.totals.resident);
+        tracee.peak_mapped = tracee.peak_mapped.max(tracee.totals.mapped);
+
+        payload.apply_to_kinds(&mut tracee.kinds);
+        if let TraceePayload::Exec { .. }
+        | TraceePayload::Map { .. }
+        | TraceePayload::Unmap { .. }
+        | TraceePayload::Remap { .. } = payload
+        {
+            let heap = tracee
+                .kinds
+                .iter()
+                .filter(|(_, kind)| **kind == MappingKind::Heap)
+                .map(|(range, _)| range.end - range.start)
+                .sum();
+            if tracee.heap.last().map(|(_, size)| *size) != Some(heap) {
+                tracee.heap.push((at, heap));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let formatter = make_format(BINARY);
+        let rows = self.mappings.rows();
+
+        if let Some(session) = &self.session {
+            writeln!(self.w, "session {session}")?;
+            writeln!(self.w)?;
+        }
+        for (tid, tracee) in &self.tracees {
+            let end = tracee.exited_at.unwrap_or(self.last_at);
+            writeln!(self.w, "{tid} {}", tracee.cmdline.join(" "))?;
+            writeln!(
+                self.w,
+                "  ran for {:.1}s{}",
+                end.saturating_sub(tracee.first_at).as_secs_f64(),
+                if tracee.exited_at.is_some() {
+                    ""
+                } else {
+                    ", still running at the end"
+                }
+            )?;
+            writeln!(
+                self.w,
+                "  peak resident {}, peak mapped {}, {} pages faulted in",
+                formatter(tracee.peak_resident),
+                formatter(tracee.peak_mapped),
+                tracee.faulted,
+            )?;
+
+            let categories = by_category(&tracee.map, &tracee.kinds);
+            if !categories.is_empty() {
+                writeln!(self.w, "  by category, at the end:")?;
+            }
+            for (category, (mapped, resident)) in categories {
+                writeln!(
+                    self.w,
+                    "    {category:<8} {:>10} resident of {:>10}",
+                    formatter(resident).to_string(),
+                    formatter(mapped).to_string(),
+                )?;
+            }
+
+            let mut top = rows
+                .iter()
+                .filter(|row| row.tid == *tid && row.peak_resident > 0)
+                .collect::<Vec<_>>();
+            top.sort_by_key(|row| std::cmp::Reverse(row.peak_resident));
+            if !top.is_empty() {
+                writeln!(self.w, "  top mappings by peak resident:")?;
+            }
+            for row in top.into_iter().take(SUMMARY_TOP_MAPPINGS) {
+                writeln!(
+                    self.w,
+                    "    {:#014x}-{:#014x} {:<12} {:>10} of {:>10}",
+                    row.range.start,
+                    row.range.end,
+                    format!("{:?}", row.kind),
+                    formatter(row.peak_resident).to_string(),
+                    formatter(row.range.end - row.range.start).to_string(),
+                )?;
+            }
+
+            let mut busiest = rows
+                .iter()
+                .filter(|row| row.tid == *tid && row.activity.faults > 0)
+                .collect::<Vec<_>>();
+            busiest.sort_by_key(|row| std::cmp::Reverse(row.activity.faults));
+            if !busiest.is_empty() {
+                writeln!(self.w, "  top mappings by faults:")?;
+            }
+            for row in busiest.into_iter().take(SUMMARY_TOP_MAPPINGS) {
+                let first_fault = match row.activity.first_fault_ms {
+                    Some(ms) => format!("first touched after {ms}ms"),
+                    None => "mapped before tracing".to_string(),
+                };
+                writeln!(
+                    self.w,
+                    "    {:#014x}-{:#014x} {:<12} {:>8} faults, {:>10} churn, {first_fault}",
+                    row.range.start,
+                    row.range.end,
+                    format!("{:?}", row.kind),
+                    row.activity.faults,
+                    formatter(row.activity.churn).to_string(),
+                )?;
+            }
+
+            let mut sites = tracee.faulted_by_site.iter().collect::<Vec<_>>();
+            sites.sort_by_key(|(_, faulted)| std::cmp::Reverse(**faulted));
+            if !sites.is_empty() {
+                writeln!(self.w, "  top fault sites:")?;
+            }
+            for (site, faulted) in sites.into_iter().take(SUMMARY_TOP_MAPPINGS) {
+                writeln!(self.w, "    {:>10} {site}", formatter(*faulted).to_string())?;
+            }
+
+            if let Some(curve) = heap_curve(&tracee.heap, tracee.first_at, end) {
+                let peak = tracee.heap.iter().map(|(_, size)| *size).max().unwrap_or(0);
+                let last = tracee.heap.last().map(|(_, size)| *size).unwrap_or(0);
+                writeln!(
+                    self.w,
+                    "  heap: {curve} {} at the end, {} at most",
+                    formatter(last),
+                    formatter(peak),
+                )?;
+            }
+            writeln!(self.w)?;
+        }
+        Ok(self.w.flush()?)
+    }
+}
+
+/// The heap's size over `start..end`, as a row of block characters, each
+/// showing the biggest it got in its slice of time
+fn heap_curve(heap: &[(Duration, u64)], start: Duration, end: Duration) -> Option<String> {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let peak = heap.iter().map(|(_, size)| *size).max()?;
+    if peak == 0 {
+        return None;
+    }
+    let span = end.saturating_sub(start).max(Duration::from_millis(1));
+    let mut slices = vec![None; HEAP_CURVE_WIDTH];
+    for (at, size) in heap {
+        let i = (at.saturating_sub(start).as_secs_f64() / span.as_secs_f64()
+            * HEAP_CURVE_WIDTH as f64) as usize;
+        let slice = &mut slices[i.min(HEAP_CURVE_WIDTH - 1)];
+        *slice = Some(slice.unwrap_or(0).max(*size));
+    }
+
+    // slices without changes keep whatever size the heap had before
+    let mut size = 0;
+    let curve = slices
+        .into_iter()
+        .map(|slice| {
+            size = slice.unwrap_or(size);
+            match size {
+                0 => ' ',
+                size => BLOCKS[((size * 8 - 1) / peak) as usize],
+            }
+        })
+        .collect();
+    Some(curve)
+}
+
+/// How often the HTML export samples resident and mapped bytes, at most
+const HTML_POINT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The page the HTML export fills in, data goes where `/*DATA*/null` is
+const HTML_TEMPLATE: &str = include_str!("export.html");
+
+/// A single HTML page with the data inlined, that shows resident and mapped
+/// bytes over time, and when each mapping was around. Opens in any browser,
+/// without mevi.
+struct Html {
+    w: Box<dyn Write + Send>,
+    tracees: BTreeMap<TraceeId, HtmlTracee>,
+
+    /// Keeps track of mappings for us, and doesn't write anything
+    mappings: Csv,
+
+    /// When the latest event happened
+    last_at: Duration,
+
+    /// What the session was called, if anything
+    session: Option<SessionInfo>,
+}
+
+#[derive(Default)]
+struct HtmlTracee {
+    cmdline: Vec<String>,
+    map: MemMap,
+    totals: Totals,
+    exited_at: Option<Duration>,
+
+    /// Milliseconds, resident and mapped bytes
+    points: Vec<(f64, u64, u64)>,
+
+    /// When we last added a point, and whether anything changed since
+    last_point: Option<Duration>,
+    changed: bool,
+}
+
+impl HtmlTracee {
+    fn point(&mut self, at: Duration) {
+        self.points.push((
+            at.as_secs_f64() * 1000.0,
+            self.totals.resident,
+            self.totals.mapped,
+        ));
+        self.last_point = Some(at);
+        self.changed = false;
+    }
+}
+
+#[derive(Serialize)]
+struct HtmlData<'a> {
+    session: Option<String>,
+    duration_ms: f64,
+    tracees: Vec<HtmlTraceeData<'a>>,
+}
+
+#[derive(Serialize)]
+struct HtmlTraceeData<'a> {
+    tid: u64,
+    cmdline: String,
+    exited_ms: Option<f64>,
+    points: &'a [(f64, u64, u64)],
+    mappings: Vec<HtmlMapping>,
+}
+
+#[derive(Serialize)]
+struct HtmlMapping {
+    start: u64,
+    end: u64,
+    kind: String,
+    mapped_ms: f64,
+    unmapped_ms: Option<f64>,
+    peak_resident: u64,
+    faults: u64,
+}
+
+impl Html {
+    fn new(w: Box<dyn Write + Send>) -> Self {
+        Self {
+            w,
+            tracees: Default::default(),
+            mappings: Csv::new(Box::new(std::io::sink())),
+            last_at: Duration::ZERO,
+            session: None,
+        }
+    }
+}
+
+impl Exporter for Html {
+    fn event(&mut self, at: Duration, ev: &MeviEvent) -> Result<()> {
+        self.mappings.event(at, ev)?;
+        let (tid, payload) = match ev {
+            MeviEvent::TraceeEvent(tid, payload) => (tid, payload),
+            MeviEvent::SessionInfo(info) => {
+                self.session = Some(info.clone());
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        self.last_at = at;
+
+        let tracee = self.tracees.entry(*tid).or_default();
+        match payload {
+            TraceePayload::Exit => {
+                tracee.point(at);
+                tracee.exited_at = Some(at);
+                return Ok(());
+            }
+            TraceePayload::CmdLineChange { cmdline } => tracee.cmdline = cmdline.clone(),
+            _ => {}
+        }
+
+        tracee.totals.apply(&mut tracee.map, payload);
+        tracee.changed = true;
+        let due = match tracee.last_point {
+            Some(last) => at.saturating_sub(last) >= HTML_POINT_INTERVAL,
+            None => true,
+        };
+        if due {
+            tracee.point(at);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let ms = |at: Duration| at.as_secs_f64() * 1000.0;
+        let rows = self.mappings.rows();
+        for tracee in self.tracees.values_mut() {
+            if tracee.changed && tracee.exited_at.is_none() {
+                tracee.point(self.last_at);
+            }
+        }
+
+        let data = HtmlData {
+            session: self.session.as_ref().map(|info| info.to_string()),
+            duration_ms: ms(self.last_at),
+            tracees: self
+                .tracees
+                .iter()
+                .map(|(tid, tracee)| HtmlTraceeData {
+                    tid: tid.0,
+                    cmdline: tracee.cmdline.join(" "),
+                    exited_ms: tracee.exited_at.map(ms),
+                    points: &tracee.points,
+                    mappings: rows
+                        .iter()
+                        .filter(|row| row.tid == *tid)
+                        .map(|row| HtmlMapping {
+                            start: row.range.start,
+                            end: row.range.end,
+                            kind: format!("{:?}", row.kind),
+                            mapped_ms: ms(row.mapped_at),
+                            unmapped_ms: row.unmapped_at.map(ms),
+                            peak_resident: row.peak_resident,
+                            faults: row.activity.faults,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        // JSON can hold `</script>`, HTML can't
+        let json = serde_json::to_string(&data)?.replace("</", "<\\/");
+        let (before, after) = HTML_TEMPLATE
+            .split_once("/*DATA*/null")
+            .expect("the HTML template has a spot for data");
+        self.w.write_all(before.as_bytes())?;
+        self.w.write_all(json.as_bytes())?;
+        self.w.write_all(after.as_bytes())?;
+        Ok(self.w.flush()?)
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use mevi_common::{MeviEvent, TimedEvent, TraceeId};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{info, warn};
+
+/// How many events a client can be behind before it starts missing some
+const CLIENT_QUEUE: usize = 16 * 1024;
+
+/// How many of the latest events we keep around for clients that reconnect.
+/// Half a queue, so there's still room after replaying all of them.
+const HISTORY_LEN: usize = CLIENT_QUEUE / 2;
+
+/// Everyone connected to `/stream`, each with its own queue, so that one
+/// slow client can't hold up the relay (and through it, the tracer).
+#[derive(Clone, Default)]
+pub(crate) struct Clients {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    clients: Vec<Client>,
+    next_id: u64,
+    history: VecDeque<TimedEvent>,
+
+    /// Once the session is over, the [MeviEvent::SessionEnd] that said so
+    ended: Option<TimedEvent>,
+
+    /// The [MeviEvent::SessionInfo] every client gets first, if there is one
+    info: Option<TimedEvent>,
+}
+
+struct Client {
+    id: u64,
+    tx: mpsc::Sender<TimedEvent>,
+
+    /// Events this client missed since it last had room
+    missed: u64,
+}
+
+impl Clients {
+    /// Starts a client off with `first` (a snapshot, or what it missed),
+    /// events come after that
+    pub(crate) fn subscribe(&self, first: Vec<TimedEvent>) -> mpsc::Receiver<TimedEvent> {
+        let (tx, rx) = mpsc::channel(CLIENT_QUEUE);
+        let mut inner = self.inner.lock().unwrap();
+        // a client that shows up late still gets to know what the session
+        // was, and how it all ended
+        let events = inner
+            .info
+            .clone()
+            .into_iter()
+            .chain(first)
+            .chain(inner.ended.clone());
+        for ev in events {
+            // a fresh queue has room
+            _ = tx.try_send(ev);
+        }
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.clients.push(Client { id, tx, missed: 0 });
+        rx
+    }
+
+    /// Never waits: a client whose queue is full misses events. Once it
+    /// has caught up with a good part of its queue, it gets `snapshot()`
+    /// instead of `ev`, so `snapshot` must already account for `ev`. Clients
+    /// that went away are forgotten.
+    pub(crate) fn send(&self, ev: &TimedEvent, snapshot: impl Fn() -> TimedEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.history.len() == HISTORY_LEN {
+            inner.history.pop_front();
+        }
+        inner.history.push_back(ev.clone());
+
+        inner.clients.retain_mut(|client| {
+            if client.tx.is_closed() {
+                return false;
+            }
+
+            if client.missed > 0 {
+                // a snapshot can be big, don't make one just to miss more
+                // events right after
+                if client.tx.capacity() < CLIENT_QUEUE / 2 {
+                    client.missed += 1;
+                    return true;
+                }
+                info!(
+                    "client {} caught up, after missing {} events",
+                    client.id, client.missed
+                );
+                client.missed = 0;
+                // we're the only ones sending, so there's room
+                return client.tx.try_send(snapshot()).is_ok();
+            }
+
+            match client.tx.try_send(ev.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("client {} fell behind, it'll miss events", client.id);
+                    client.missed += 1;
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    /// Sends everyone `ev`, a [MeviEvent::SessionEnd], and every client
+    /// that subscribes from now on too. Clients that missed events get
+    /// `snapshot()` first, nothing else would catch them up.
+    pub(crate) fn end(&self, ev: TimedEvent, snapshot: impl Fn() -> TimedEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clients.retain(|client| !client.tx.is_closed());
+        for client in &mut inner.clients {
+            if client.missed > 0 {
+                _ = client.tx.try_send(snapshot());
+            }
+            if client.tx.try_send(ev.clone()).is_err() {
+                warn!(
+                    "client {} is too far behind to hear the session ended",
+                    client.id
+                );
+            }
+        }
+        inner.ended = Some(ev);
+    }
+
+    /// Sends everyone `ev`, a [MeviEvent::SessionInfo], and every client
+    /// that subscribes from now on too, before anything else
+    pub(crate) fn describe(&self, ev: TimedEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clients.retain(|client| !client.tx.is_closed());
+        for client in &inner.clients {
+            // it's early on, queues have room
+            _ = client.tx.try_send(ev.clone());
+        }
+        inner.info = Some(ev);
+    }
+
+    /// What a reconnecting client missed, if we still have all of it. `from`
+    /// is the next sequence number it expects for each tracee it knows about,
+    /// `next_seqs` the one we'll give each live tracee next.
+    pub(crate) fn replay(
+        &self,
+        from: &[(TraceeId, u64)],
+        next_seqs: &HashMap<TraceeId, u64>,
+    ) -> Option<Vec<TimedEvent>> {
+        if from.is_empty() {
+            return None;
+        }
+        let from: HashMap<TraceeId, u64> = from.iter().copied().collect();
+        let inner = self.inner.lock().unwrap();
+
+        // the first event we still have for each tracee
+        let mut oldest = HashMap::new();
+        for ev in &inner.history {
+            if let MeviEvent::TraceeEvent(tid, _) = ev.event {
+                oldest.entry(tid).or_insert(ev.seq);
+            }
+        }
+        let kept_since = |tid: &TraceeId, seq: u64| oldest.get(tid).is_some_and(|&o| o <= seq);
+
+        for (tid, &next) in next_seqs {
+            let seen = from.get(tid).copied().unwrap_or_default();
+            // a client that's ahead of us must have been talking to another
+            // mevi, one that's behind needs everything it missed
+            if seen > next || (seen < next && !kept_since(tid, seen)) {
+                return None;
+            }
+        }
+        // tracees that went away since, we need to tell the client that
+        for (tid, &seen) in &from {
+            if !next_seqs.contains_key(tid) && !kept_since(tid, seen) {
+                return None;
+            }
+        }
+
+        let missed = inner
+            .history
+            .iter()
+            .filter(|ev| match &ev.event {
+                MeviEvent::TraceeEvent(tid, _) => ev.seq >= from.get(tid).copied().unwrap_or(0),
+                MeviEvent::Snapshot(_) | MeviEvent::SessionEnd(_) | MeviEvent::SessionInfo(_) => {
+                    false
+                }
+            })
+            .cloned()
+            .collect();
+        Some(missed)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().unwrap().clients.len()
+    }
+}
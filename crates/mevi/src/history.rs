@@ -0,0 +1,126 @@
+//! The recent past of a live session, so the frontend can scrub back through
+//! it rather than only watch the present: every so often a keyframe (a
+//! snapshot of every tracee), and the events that came after it.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use mevi_common::{MeviEvent, TimedEvent, Timestamp};
+
+/// A keyframe is taken after that many events...
+const KEYFRAME_EVENTS: usize = 16 * 1024;
+
+/// ...or once that much time has passed, whichever comes first, so seeking
+/// never has to go through too many events
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub(crate) struct History {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    keyframes: VecDeque<Keyframe>,
+
+    /// What all the keyframes cost, cf. [Keyframe::cost]
+    cost: usize,
+
+    /// How much they may cost before we forget the oldest
+    capacity: usize,
+}
+
+struct Keyframe {
+    /// A [MeviEvent::Snapshot]
+    snapshot: TimedEvent,
+
+    /// What happened since, oldest first
+    events: Vec<TimedEvent>,
+}
+
+impl Keyframe {
+    /// Roughly how much memory this holds on to, counting one per event,
+    /// and one per range in the snapshot
+    fn cost(&self) -> usize {
+        let snapshot = match &self.snapshot.event {
+            MeviEvent::Snapshot(tracees) => tracees
+                .iter()
+                .map(|tracee| 1 + tracee.map.iter().count() + tracee.kinds.iter().count())
+                .sum(),
+            MeviEvent::TraceeEvent(..) | MeviEvent::SessionEnd(_) | MeviEvent::SessionInfo(_) => 1,
+        };
+        snapshot + self.events.len()
+    }
+}
+
+impl History {
+    /// Keeps about `capacity` events' worth of history
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                keyframes: Default::default(),
+                cost: 0,
+                capacity,
+            })),
+        }
+    }
+
+    /// Remembers `ev`. When it's time for a keyframe, it's `snapshot()`
+    /// instead, which must already account for `ev`.
+    pub(crate) fn push(&self, ev: &TimedEvent, snapshot: impl FnOnce() -> TimedEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        let due = match inner.keyframes.back() {
+            Some(last) => {
+                last.events.len() >= KEYFRAME_EVENTS
+                    || (!last.events.is_empty()
+                        && ev.at.since(last.snapshot.at) >= KEYFRAME_INTERVAL)
+            }
+            None => true,
+        };
+
+        if due {
+            let keyframe = Keyframe {
+                snapshot: TimedEvent {
+                    at: ev.at,
+                    ..snapshot()
+                },
+                events: vec![],
+            };
+            inner.cost += keyframe.cost();
+            inner.keyframes.push_back(keyframe);
+        } else {
+            inner.cost += 1;
+            inner.keyframes.back_mut().unwrap().events.push(ev.clone());
+        }
+
+        while inner.cost > inner.capacity && inner.keyframes.len() > 1 {
+            let oldest = inner.keyframes.pop_front().unwrap();
+            inner.cost -= oldest.cost();
+        }
+    }
+
+    /// The first and last moments we can show, if any
+    pub(crate) fn span(&self) -> Option<(Timestamp, Timestamp)> {
+        let inner = self.inner.lock().unwrap();
+        let first = inner.keyframes.front()?;
+        let last = inner.keyframes.back()?;
+        let end = last.events.last().unwrap_or(&last.snapshot).at;
+        Some((first.snapshot.at, end))
+    }
+
+    /// What it takes to show things as they were `at`: the keyframe before
+    /// it, and the events since. `None` if that's before what we remember.
+    pub(crate) fn at(&self, at: Timestamp) -> Option<Vec<TimedEvent>> {
+        let inner = self.inner.lock().unwrap();
+        let keyframe = inner
+            .keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| keyframe.snapshot.at <= at)?;
+        let mut events = vec![keyframe.snapshot.clone()];
+        events.extend(keyframe.events.iter().take_while(|ev| ev.at <= at).cloned());
+        Some(events)
+    }
+}
@@ -0,0 +1,86 @@
+//! Where a tracee is faulting right now, for `/api/hot` and the TUI: bytes
+//! faulted in over the last [WINDOW], per [REGION_SIZE]-aligned region, so a
+//! hot loop stands out from a big mapping that got faulted in once.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    ops::Range,
+    time::Duration,
+};
+
+use mevi_common::Timestamp;
+
+/// Regions are that big, and aligned to that
+pub(crate) const REGION_SIZE: u64 = 2 * 1024 * 1024;
+
+/// How far back faults count
+pub(crate) const WINDOW: Duration = Duration::from_secs(10);
+
+/// Faults that close together in the same region share an entry, so a
+/// tracee faulting like crazy doesn't cost one entry per fault
+const MERGE_WITHIN: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+pub(crate) struct Hot {
+    /// When, where and how many bytes got faulted in, oldest first
+    recent: VecDeque<(Timestamp, u64, u64)>,
+
+    /// The same, summed per region
+    by_region: HashMap<u64, u64>,
+}
+
+impl Hot {
+    pub(crate) fn fault(&mut self, at: Timestamp, range: &Range<u64>) {
+        self.expire(at);
+        // faults are at most a huge page, but that may straddle regions
+        let mut start = range.start;
+        while start < range.end {
+            let region = start / REGION_SIZE * REGION_SIZE;
+            let end = range.end.min(region + REGION_SIZE);
+            *self.by_region.entry(region).or_default() += end - start;
+            match self.recent.back_mut() {
+                Some((last_at, last_region, bytes))
+                    if *last_region == region && at.since(*last_at) < MERGE_WITHIN =>
+                {
+                    *bytes += end - start
+                }
+                _ => self.recent.push_back((at, region, end - start)),
+            }
+            start = end;
+        }
+    }
+
+    /// Forgets what happened more than [WINDOW] before `now`
+    pub(crate) fn expire(&mut self, now: Timestamp) {
+        while let Some(&(at, region, bytes)) = self.recent.front() {
+            if now.since(at) < WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+            if let Entry::Occupied(mut entry) = self.by_region.entry(region) {
+                *entry.get_mut() -= bytes;
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.recent.clear();
+        self.by_region.clear();
+    }
+
+    /// The `n` regions that got the most bytes faulted in over the window,
+    /// hottest first, with how many. Call [Hot::expire] first.
+    pub(crate) fn top(&self, n: usize) -> Vec<(Range<u64>, u64)> {
+        let mut regions = self
+            .by_region
+            .iter()
+            .map(|(start, bytes)| (*start..start + REGION_SIZE, *bytes))
+            .collect::<Vec<_>>();
+        regions.sort_by_key(|(range, bytes)| (std::cmp::Reverse(*bytes), range.start));
+        regions.truncate(n);
+        regions
+    }
+}
@@ -0,0 +1,73 @@
+//! Serving the frontend, the stream and the API over TLS, with `--tls-cert`:
+//! enough to expose mevi on a remote machine without putting a reverse proxy
+//! in front of it.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use hyper::server::accept::Accept;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::debug;
+
+/// How long a client gets to finish its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections accepted on a listener, once they're done with the TLS
+/// handshake. Handshakes happen on their own tasks, so a slow client doesn't
+/// hold up everyone else.
+pub(crate) struct Incoming {
+    rx: mpsc::Receiver<TlsStream<TcpStream>>,
+}
+
+impl Incoming {
+    pub(crate) fn new(listener: TcpListener, tls: TlsAcceptor) -> Self {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("accepting HTTPS client: {e}");
+                        continue;
+                    }
+                };
+                if tx.is_closed() {
+                    // the server is gone
+                    return;
+                }
+                let tls = tls.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            // the server may be gone by now
+                            _ = tx.send(stream).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake with {peer} failed: {e}"),
+                        Err(_) => debug!("TLS handshake with {peer} timed out"),
+                    }
+                });
+            }
+        });
+        Self { rx }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = TlsStream<TcpStream>;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.rx.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
@@ -1,184 +1,1035 @@
-use std::{collections::HashMap, os::unix::net::UnixListener, sync::mpsc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    future::Future,
+    io::{BufWriter, IsTerminal, Write},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use axum::{
-    extract::{
-        ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
-    },
-    response::IntoResponse,
+    extract::{Path, Query, State, WebSocketUpgrade},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
 };
-use color_eyre::Result;
+use color_eyre::{eyre::WrapErr, Result};
 use humansize::{make_format, BINARY};
-use mevi_common::{MemMap, MemState, MeviEvent, TraceeId, TraceePayload, TraceeSnapshot};
-use postage::{broadcast, sink::Sink, stream::Stream};
-use tokio::time::Instant;
-use tracer::Tracer;
-use tracing::debug;
-use tracing_subscriber::EnvFilter;
+use mevi_common::{
+    protocol, ClockAnchor, HistoryStatus, MappingFiles, MappingKind, MappingKinds, MappingPermsMap,
+    MemMap, MeviEvent, ProcessInfo, SessionSummary, TimedEvent, Timestamp, TraceeId, TraceePayload,
+    TraceeSnapshot, TraceeTotals, TracerStatus,
+};
+use mevi_tracer::{
+    buckets,
+    channel::{self, ChannelStats, EventRx},
+    evict, Control, Tracer,
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
 
-mod tracer;
-mod userfault;
+mod activity;
+mod api;
+mod assets;
+mod auth;
+mod capture;
+mod cli;
+mod config;
+mod control;
+mod doctor;
+mod dump;
+mod export;
+mod fanout;
+mod history;
+mod hot;
+mod https;
+mod metrics;
+mod otlp;
+mod record;
+mod register;
+mod remote;
+mod replay;
+mod series;
+mod session;
+mod sink;
+mod syscalls;
+mod tui;
 
-const SOCK_PATH: &str = "/tmp/mevi.sock";
+/// How many events can be in flight between the tracer and the relay before
+/// backpressure kicks in
+const RING_CAPACITY: usize = 4096;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
 
+    let args = cli::Args::parse()?;
+
+    // stdout is for exports (and the tracee), and the TUI has the terminal
+    // to itself
+    let writer = match &args.log_file {
+        Some(path) => {
+            let file =
+                File::create(path).wrap_err_with(|| format!("creating {}", path.display()))?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None if matches!(args.mode, cli::Mode::Tui { .. }) => BoxMakeWriter::new(std::io::sink),
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
     tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_ansi(args.log_file.is_none())
         .with_env_filter(
-            EnvFilter::try_from_default_env()
+            // `$RUST_LOG` is also seen by the tracee, which may well be
+            // written in Rust
+            EnvFilter::try_from_env("MEVI_LOG")
+                .or_else(|_| EnvFilter::try_from_default_env())
                 .unwrap_or_else(|_| EnvFilter::try_from("info").unwrap()),
         )
         .init();
 
-    std::fs::remove_file(SOCK_PATH).ok();
-    let listener = UnixListener::bind(SOCK_PATH).unwrap();
+    let (tx, rx) = channel::channel(RING_CAPACITY, args.backpressure);
+    // the tracer takes `args`, and only the server pushes to a collector
+    let otlp_settings = args
+        .otlp_endpoint
+        .clone()
+        .map(|endpoint| (endpoint, args.otlp_interval));
+    let sinks = args.sinks.clone();
+    let http_addr = args.http_addr()?;
+    let token = args.token(http_addr)?;
+    let tls = args
+        .tls
+        .as_ref()
+        .map(|(cert, key)| remote::server_tls(cert, key))
+        .transpose()?;
+    let listen_tcp = args.listen_tcp;
+    let base_path = args.base_path.clone().unwrap_or_default();
+    let allowed_origins = Arc::from(args.allowed_origins.clone());
+    let listen_unix = args.listen_unix.clone();
+    let history_len = args.history;
+    let series_interval = args.series_interval;
+    let linger = args.linger;
+    let bucket = args.granularity.bucket();
 
-    let (tx, rx) = mpsc::sync_channel::<MeviEvent>(16);
-    let tx2 = tx.clone();
+    // first thing in the stream, so recordings and exports have it too
+    if !args.session.is_empty() {
+        _ = tx.send(MeviEvent::SessionInfo(args.session.clone()));
+    }
 
-    std::thread::spawn(move || Tracer::new(tx2, listener).unwrap().run().unwrap());
+    let mut tracer = None;
+    let mut control = None;
+    let replay = match args.mode.clone() {
+        cli::Mode::Serve => {
+            let tracer_control = Control::default();
+            control = Some(tracer_control.clone());
+            // the tracer gets the only sender, so the relay finds out when
+            // it's done
+            tracer = Some(std::thread::spawn(move || {
+                Tracer::new(tx, args.tracer)?
+                    .controlled(tracer_control)
+                    .run()
+            }));
+            None
+        }
+        cli::Mode::Record {
+            output,
+            compression_level,
+            snapshot_interval,
+            exports,
+            capture_signals,
+            wait_for_signal,
+        } => {
+            if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)
+                    .wrap_err_with(|| format!("creating {}", dir.display()))?;
+            }
+            if capture_signals {
+                capture::install(!wait_for_signal)?;
+            }
+            let recorder = std::thread::spawn({
+                let output = output.clone();
+                move || {
+                    record::record(
+                        rx,
+                        &output,
+                        compression_level,
+                        snapshot_interval,
+                        capture_signals,
+                    )
+                }
+            });
+            // the tracer owns the last sender, so the recorder stops once it's
+            // done (and its samplers have noticed)
+            let exit_code = Tracer::new(tx, args.tracer)?.run()?;
+            recorder.join().unwrap()?;
 
-    let (payload_tx, _) = broadcast::channel(16);
+            for (format, path) in exports {
+                let file =
+                    File::create(&path).wrap_err_with(|| format!("creating {}", path.display()))?;
+                export::export(
+                    export::from_trace(&output)?,
+                    format,
+                    Box::new(BufWriter::new(file)),
+                )?;
+                info!("exported to {}", path.display());
+            }
+            return exit_like(exit_code);
+        }
+        cli::Mode::Tui { .. } => {
+            let tracer = std::thread::spawn(move || Tracer::new(tx, args.tracer)?.run());
+            tui::run(rx)?;
+            // if the user quit first, the tracees die with us
+            if tracer.is_finished() {
+                return exit_like(tracer.join().unwrap()?);
+            }
+            return Ok(());
+        }
+        cli::Mode::Replay { input, speed } => Some(replay::spawn(&input, tx.clone(), speed)?),
+        cli::Mode::Connect {
+            addr,
+            tls_ca,
+            token,
+        } => {
+            let tls = tls_ca.map(|ca| remote::client_tls(&ca)).transpose()?;
+            remote::spawn_viewer(addr, tls, token, tx.clone(), rx.anchor());
+            None
+        }
+        cli::Mode::Export {
+            format,
+            input,
+            output,
+        } => {
+            let w: Box<dyn Write + Send> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(std::io::stdout()),
+            };
+            if let Some(input) = input {
+                return export::export(export::from_trace(&input)?, format, w);
+            }
+
+            let exporter = std::thread::spawn(move || export::export(export::live(rx), format, w));
+            let exit_code = Tracer::new(tx, args.tracer)?.run()?;
+            exporter.join().unwrap()?;
+            return exit_like(exit_code);
+        }
+        cli::Mode::Doctor => return doctor::run(),
+        cli::Mode::Control { url, token, action } => {
+            return control::send(&url, token.as_deref(), action).await;
+        }
+        cli::Mode::Dump {
+            url,
+            token,
+            tid,
+            range,
+            output,
+            hexdump,
+        } => {
+            let (bytes, unreadable) = control::dump(&url, token.as_deref(), tid, &range).await?;
+            if unreadable > 0 {
+                warn!("{unreadable} bytes of that couldn't be read, they're zeroes");
+            }
+            let mut w: Box<dyn Write> = match &output {
+                Some(path) => Box::new(BufWriter::new(
+                    File::create(path).wrap_err_with(|| format!("creating {}", path.display()))?,
+                )),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            if hexdump {
+                dump::hexdump(&mut w, range.start, &bytes)?;
+            } else {
+                w.write_all(&bytes)?;
+            }
+            w.flush()?;
+            return Ok(());
+        }
+        cli::Mode::Register { server } => {
+            let registered = std::thread::spawn(move || register::register(rx, &server));
+            let exit_code = Tracer::new(tx, args.tracer)?.run()?;
+            registered.join().unwrap()?;
+            return exit_like(exit_code);
+        }
+        cli::Mode::Server { accept } => {
+            // tracers come and go, the relay keeps going
+            register::listen(&accept, tx)?;
+            None
+        }
+    };
+
+    let clients = fanout::Clients::default();
+    let tracees = Tracees::default();
+    // a replay can seek already, and has all of its history on disk
+    let history = (replay.is_none() && history_len > 0).then(|| history::History::new(history_len));
+
+    let series = series_interval.map(|interval| series::Series::spawn(interval, tracees.clone()));
 
     let rs = RouterState {
-        payload_tx: payload_tx.clone(),
-        ev_tx: tx.clone(),
+        clients: clients.clone(),
+        anchor: rx.anchor(),
+        replay,
+        control,
+        tracees: tracees.clone(),
+        history: history.clone(),
+        series,
+        stats: rx.stats(),
+        token: token.as_deref().map(Arc::from),
+        allowed_origins,
     };
+    // what can read the tracees' memory, or change what they do
+    let tracer = axum::Router::new()
+        .route("/tracer/dump", axum::routing::get(tracer_dump))
+        .route("/tracer/:action", axum::routing::post(tracer_control))
+        .route_layer(axum::middleware::from_fn_with_state(
+            rs.clone(),
+            auth::same_origin,
+        ));
     let router = axum::Router::new()
         .route("/stream", axum::routing::get(stream))
+        .route("/metrics", axum::routing::get(metrics))
+        .route("/replay", axum::routing::get(replay_status))
+        .route("/replay/:action", axum::routing::post(replay_control))
+        .route("/tracer", axum::routing::get(tracer_status))
+        .route("/history", axum::routing::get(history_status))
+        .route("/history/at", axum::routing::get(history_at))
+        .route("/api/tracees", axum::routing::get(api::tracees))
+        .route("/api/tree", axum::routing::get(api::tree))
+        .route("/api/hot", axum::routing::get(api::hot))
+        .route("/api/tracees/:tid/maps", axum::routing::get(api::maps))
+        .route("/api/tracees/:tid/stats", axum::routing::get(api::stats))
+        .route("/api/tracees/:tid/series", axum::routing::get(api::series))
+        .merge(tracer)
+        .route_layer(axum::middleware::from_fn_with_state(
+            rs.clone(),
+            auth::require,
+        ))
+        // the frontend itself doesn't need a token, only what it shows does
+        .fallback(assets::serve)
         .with_state(rs);
-    let addr = "127.0.0.1:5001".parse().unwrap();
-    let server = axum::Server::bind(&addr).serve(router.into_make_service());
+    let router = if base_path.is_empty() {
+        router
+    } else {
+        // anything outside of it is someone else's
+        axum::Router::new().nest(&base_path, router)
+    };
+    let (http_addr, server): (_, Pin<Box<dyn Future<Output = hyper::Result<()>> + Send>>) =
+        match tls.clone() {
+            Some(tls) => {
+                let listener = tokio::net::TcpListener::bind(http_addr)
+                    .await
+                    .wrap_err_with(|| format!("listening on {http_addr}"))?;
+                // with port 0, that's where we actually ended up
+                let http_addr = listener.local_addr()?;
+                let server = axum::Server::builder(https::Incoming::new(listener, tls))
+                    .serve(router.into_make_service());
+                (http_addr, Box::pin(server))
+            }
+            None => {
+                let server = axum::Server::try_bind(&http_addr)
+                    .wrap_err_with(|| format!("listening on {http_addr}"))?
+                    .serve(router.into_make_service());
+                (server.local_addr(), Box::pin(server))
+            }
+        };
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    match &token {
+        Some(token) => {
+            info!("serving on {scheme}://{http_addr}{base_path}/?token={token}");
+            info!("clients need the token {token}");
+        }
+        None => info!("serving on {scheme}://{http_addr}{base_path}/"),
+    }
+
+    if let Some(path) = listen_unix {
+        session::listen_unix(
+            &path,
+            token.as_deref().map(Arc::from),
+            clients.clone(),
+            tracees.clone(),
+            rx.anchor(),
+        )?;
+    }
+    if let Some(addr) = listen_tcp {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .wrap_err_with(|| format!("listening on {addr}"))?;
+        info!(
+            "streaming events on {addr}{}",
+            if tls.is_some() { " over TLS" } else { "" }
+        );
+        tokio::spawn(remote::serve(
+            listener,
+            tls,
+            token.as_deref().map(Arc::from),
+            clients.clone(),
+            tracees.clone(),
+            rx.anchor(),
+        ));
+    }
 
-    std::thread::spawn(move || relay(rx, payload_tx));
+    let otlp =
+        otlp_settings.map(|(endpoint, interval)| otlp::spawn(endpoint, interval, tracees.clone()));
+    let sinks = sinks
+        .iter()
+        .map(|sink| sink::spawn(sink, rx.anchor()))
+        .collect::<Result<Vec<_>>>()?;
+    let (done_tx, done_rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        relay(rx, clients, tracees, history, bucket, otlp, sinks);
+        _ = done_tx.send(());
+    });
 
-    server.await.unwrap();
-    Ok(())
+    let done = async {
+        // only a tracer ever gets done, replays, viewers and servers go on
+        _ = done_rx.await;
+        // either way, Ctrl-C stops lingering, and we still exit the way the
+        // program did
+        match linger {
+            Some(linger) => {
+                info!("the session is over, serving for {linger:?} more");
+                tokio::select! {
+                    _ = tokio::time::sleep(linger) => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            // e.g. in CI, where nobody's going to interrupt us
+            None if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() => {
+                info!("the session is over, and there's no terminal to wait on");
+            }
+            None => {
+                info!("the session is over, still serving until interrupted");
+                _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    };
+    tokio::select! {
+        res = server => res.unwrap(),
+        _ = done => {}
+    }
+    match tracer {
+        // the relay is done, so the tracer is too
+        Some(tracer) => exit_like(tracer.join().unwrap()?),
+        None => Ok(()),
+    }
 }
 
+/// Exits the way the program we traced did, if it didn't succeed, so that
+/// scripts can tell
+fn exit_like(exit_code: Option<i32>) -> Result<()> {
+    match exit_code {
+        Some(code) if code != 0 => {
+            info!("the program exited with {code}, so do we");
+            std::process::exit(code)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// What the relay knows about one traced process, from the events it got so
+/// far: what new clients get a snapshot of, and what `/api` and `/metrics`
+/// answer from
 struct TraceeState {
     tid: TraceeId,
     cmdline: Vec<String>,
     map: MemMap,
-    w_tx: broadcast::Sender<MeviEvent>,
+
+    /// What's mapped and resident in `map`, kept up to date as it changes
+    sums: export::Totals,
+    kinds: MappingKinds,
+    files: MappingFiles,
+    perms: MappingPermsMap,
+
+    /// Who the process is, once the tracer has told us
+    info: Option<ProcessInfo>,
+
+    /// The traced process that forked this one, if it's still around
+    parent: Option<TraceeId>,
+
+    /// What each thread calls itself, including threads that are gone
+    threads: HashMap<TraceeId, String>,
+
+    /// Faults, first touches and churn, per mapping
+    activity: activity::Activity,
+
+    /// Where it's been faulting lately
+    hot: hot::Hot,
+
+    /// Page faults seen so far
+    faults: u64,
+
+    /// The most it ever had resident at once
+    peak_resident: u64,
+
+    /// The sequence number of the next event. Events dropped upstream don't
+    /// get one, the channel sends a `Lost` marker for those instead.
+    next_seq: u64,
+
+    /// How big the buckets clients see are, if they don't see pages, cf.
+    /// [buckets]
+    bucket: Option<u64>,
 }
 
 impl TraceeState {
-    fn send_ev(&mut self, payload: TraceePayload) {
-        let ev = MeviEvent::TraceeEvent(self.tid, payload);
-        _ = self.w_tx.blocking_send(ev);
+    /// How many bytes are mapped, and how many of those are resident
+    fn totals(&self) -> (u64, u64) {
+        (self.sums.mapped, self.sums.resident)
+    }
+
+    /// What [export::by_category] says about this tracee, as text
+    fn categories(&self) -> String {
+        let formatter = make_format(BINARY);
+        export::by_category(&self.map, &self.kinds)
+            .into_iter()
+            .map(|(category, (_, resident))| format!("{category} {}", formatter(resident)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// How it's doing, for the [SessionSummary]
+    fn final_totals(&self, exited_at: Option<Timestamp>) -> TraceeTotals {
+        let (mapped, resident) = self.totals();
+        TraceeTotals {
+            tid: self.tid,
+            cmdline: self.cmdline.clone(),
+            exited_at,
+            mapped,
+            resident,
+            peak_resident: self.peak_resident,
+            faults: self.faults,
+            by_category: export::by_category(&self.map, &self.kinds)
+                .into_iter()
+                .map(|(category, (_, resident))| (category.to_owned(), resident))
+                .collect(),
+        }
+    }
+
+    fn heap_size(&self) -> u64 {
+        self.kinds
+            .iter()
+            .filter(|(_, kind)| **kind == MappingKind::Heap)
+            .map(|(range, _)| range.end - range.start)
+            .sum()
     }
 }
 
-fn relay(ev_rx: mpsc::Receiver<MeviEvent>, mut payload_tx: broadcast::Sender<MeviEvent>) {
-    let mut tracees: HashMap<TraceeId, TraceeState> = Default::default();
+/// What the relay knows about every tracee, shared with whoever wants to
+/// take a look (like `/metrics`)
+type Tracees = Arc<Mutex<HashMap<TraceeId, TraceeState>>>;
 
-    loop {
-        let ev = ev_rx.recv().unwrap();
-        debug!("{:?}", ev);
+fn relay(
+    ev_rx: EventRx,
+    clients: fanout::Clients,
+    tracees: Tracees,
+    history: Option<history::History>,
+    bucket: Option<u64>,
+    mut otlp: Option<otlp::Handle>,
+    sinks: Vec<sink::Handle>,
+) {
+    let mut summary = SessionSummary::default();
+    // events can still trickle in after a tracee's exit (late samples, its
+    // uffd thread noticing), they'd bring it back from the dead
+    let mut exited = HashSet::new();
+    // once every sender is gone, so is every tracee
+    while let Ok(TimedEvent { at, event, .. }) = ev_rx.recv() {
+        debug!("{:?}", event);
+        summary.events += 1;
 
-        let (tid, payload) = match ev {
-            MeviEvent::Snapshot(mut snap_tracees) => {
-                for tracee in tracees.values_mut() {
-                    snap_tracees.push(TraceeSnapshot {
-                        tid: tracee.tid,
-                        cmdline: tracee.cmdline.clone(),
-                        map: tracee.map.clone(),
-                    });
-                }
-                _ = payload_tx.blocking_send(MeviEvent::Snapshot(snap_tracees));
+        let (tid, payload) = match event {
+            MeviEvent::TraceeEvent(tid, payload) => (tid, payload),
+            MeviEvent::SessionInfo(_) => {
+                clients.describe(TimedEvent { at, seq: 0, event });
                 continue;
             }
-            MeviEvent::TraceeEvent(tid, ev) => (tid, ev),
+            // snapshots are ours to make, nothing upstream sends them
+            _ => continue,
         };
+        match &payload {
+            // the tid got reused by a new process
+            TraceePayload::Exec { .. } | TraceePayload::ParentChange { parent: Some(_) } => {
+                exited.remove(&tid);
+            }
+            _ if exited.contains(&tid) => {
+                debug!("{tid} exited already, dropping {payload:?}");
+                continue;
+            }
+            TraceePayload::Exit => {
+                exited.insert(tid);
+            }
+            _ => {}
+        }
 
-        let tracee = tracees.entry(tid).or_insert_with(|| TraceeState {
-            tid,
-            cmdline: Default::default(),
-            map: Default::default(),
-            w_tx: payload_tx.clone(),
-        });
+        if let Some(otlp) = &mut otlp {
+            otlp.observe(tid, &payload);
+        }
+        for sink in &sinks {
+            sink.send(at, tid, &payload);
+        }
 
-        payload.apply_to_memmap(&mut tracee.map);
-        tracee.send_ev(payload.clone());
+        // clients are sent the event while we hold the lock, so one that
+        // subscribes with a snapshot gets either the event or its effects,
+        // not both
+        let mut tracees = tracees.lock().unwrap();
+        if let (TraceePayload::Exit, Some(tracee)) = (&payload, tracees.get(&tid)) {
+            summary.tracees.push(tracee.final_totals(Some(at)));
+        }
+        let seq = apply_payload(&mut tracees, tid, at, payload.clone(), bucket);
+        // we keep track of every page, clients may only get buckets
+        let payload = match (bucket, tracees.get(&tid)) {
+            (Some(bucket), Some(tracee)) => buckets::coarsen(payload, &tracee.map, bucket),
+            _ => payload,
+        };
+        let ev = TimedEvent {
+            at,
+            seq,
+            event: MeviEvent::TraceeEvent(tid, payload),
+        };
+        if let Some(history) = &history {
+            history.push(&ev, || snapshot(&tracees));
+        }
+        clients.send(&ev, || snapshot(&tracees));
+    }
 
-        match payload {
-            TraceePayload::Exit => {
-                if let Some(tracee) = tracees.get(&tid) {
-                    let mut total_vsz = 0;
-                    let mut total_rss = 0;
-                    for (range, state) in tracee.map.iter() {
-                        let size = range.end - range.start;
-                        total_vsz += size;
-                        if let MemState::Resident = state {
-                            total_rss += size;
-                        }
-                    }
-                    let formatter = make_format(BINARY);
-                    tracing::warn!(
-                        "{tid} exiting with {} vsz, {} rss, cmdline was {:?}",
-                        formatter(total_vsz),
-                        formatter(total_rss),
-                        tracee.cmdline,
-                    );
-                }
+    // whatever's been handed to sinks and the collector makes it out
+    for sink in sinks {
+        sink.finish();
+    }
+    if let Some(otlp) = otlp {
+        otlp.finish();
+    }
 
-                tracees.remove(&tid);
-            }
-            TraceePayload::CmdLineChange { cmdline } => {
-                tracee.cmdline = cmdline;
+    let tracees = tracees.lock().unwrap();
+    // we may not have seen everyone exit, if the tracer gave up on them
+    let mut left = tracees.values().collect::<Vec<_>>();
+    left.sort_by_key(|tracee| tracee.tid.0);
+    summary
+        .tracees
+        .extend(left.into_iter().map(|tracee| tracee.final_totals(None)));
+    let formatter = make_format(BINARY);
+    for totals in &summary.tracees {
+        info!(
+            "{} peaked at {} rss, took {} faults, cmdline was {:?}",
+            totals.tid,
+            formatter(totals.peak_resident),
+            totals.faults,
+            totals.cmdline,
+        );
+    }
+    let ev = TimedEvent {
+        at: Timestamp::now(),
+        seq: 0,
+        event: MeviEvent::SessionEnd(summary),
+    };
+    clients.end(ev, || snapshot(&tracees));
+}
+
+/// Starts a client off with what it missed since `resume`, or a snapshot if
+/// we don't have all of that. Under the lock, so either lines up with the
+/// events that follow.
+fn subscribe(
+    clients: &fanout::Clients,
+    tracees: &Tracees,
+    resume: &[(TraceeId, u64)],
+) -> mpsc::Receiver<TimedEvent> {
+    let tracees = tracees.lock().unwrap();
+    let next_seqs = tracees
+        .values()
+        .map(|tracee| (tracee.tid, tracee.next_seq))
+        .collect();
+    let first = match clients.replay(resume, &next_seqs) {
+        Some(missed) => {
+            debug!("client resumed, replaying {} events", missed.len());
+            missed
+        }
+        None => vec![snapshot(&tracees)],
+    };
+    clients.subscribe(first)
+}
+
+/// Everything we know about every tracee, for clients that just connected or
+/// that missed events
+fn snapshot(tracees: &HashMap<TraceeId, TraceeState>) -> TimedEvent {
+    let snap_tracees = tracees
+        .values()
+        .map(|tracee| {
+            let (map, resident_pages) = match tracee.bucket {
+                Some(bucket) => {
+                    let (map, pages) = buckets::snapshot(&tracee.map, bucket);
+                    (map, Some(pages))
+                }
+                None => (tracee.map.clone(), None),
+            };
+            TraceeSnapshot {
+                tid: tracee.tid,
+                cmdline: tracee.cmdline.clone(),
+                map,
+                kinds: tracee.kinds.clone(),
+                files: tracee.files.clone(),
+                perms: tracee.perms.clone(),
+                seq: tracee.next_seq,
+                resident_pages,
+                info: tracee.info.clone(),
+                parent: tracee.parent,
+                threads: tracee
+                    .threads
+                    .iter()
+                    .map(|(thread, name)| (*thread, name.clone()))
+                    .collect(),
             }
-            _ => {
-                // ignore
+        })
+        .collect();
+    TimedEvent {
+        at: Timestamp::now(),
+        seq: 0,
+        event: MeviEvent::Snapshot(snap_tracees),
+    }
+}
+
+/// Returns the event's sequence number
+fn apply_payload(
+    tracees: &mut HashMap<TraceeId, TraceeState>,
+    tid: TraceeId,
+    at: Timestamp,
+    payload: TraceePayload,
+    bucket: Option<u64>,
+) -> u64 {
+    let tracee = tracees.entry(tid).or_insert_with(|| TraceeState {
+        tid,
+        cmdline: Default::default(),
+        map: Default::default(),
+        sums: Default::default(),
+        kinds: Default::default(),
+        files: Default::default(),
+        perms: Default::default(),
+        info: None,
+        parent: None,
+        threads: Default::default(),
+        activity: Default::default(),
+        hot: Default::default(),
+        faults: 0,
+        peak_resident: 0,
+        next_seq: 0,
+        bucket,
+    });
+    let seq = tracee.next_seq;
+    tracee.next_seq += 1;
+
+    payload.apply_to_kinds(&mut tracee.kinds);
+    payload.apply_to_files(&mut tracee.files);
+    payload.apply_to_perms(&mut tracee.perms);
+    // before the map changes, it needs to know what was resident
+    tracee.activity.apply(
+        Duration::from_nanos(at.0),
+        &payload,
+        &tracee.map,
+        &tracee.kinds,
+    );
+    tracee.sums.apply(&mut tracee.map, &payload);
+    tracee.peak_resident = tracee.peak_resident.max(tracee.sums.resident);
+
+    match payload {
+        TraceePayload::Exit => {
+            if let Some(tracee) = tracees.get(&tid) {
+                let (total_vsz, total_rss) = tracee.totals();
+                let formatter = make_format(BINARY);
+                tracing::warn!(
+                    "{tid} exiting with {} vsz, {} rss ({}), cmdline was {:?}",
+                    formatter(total_vsz),
+                    formatter(total_rss),
+                    tracee.categories(),
+                    tracee.cmdline,
+                );
             }
+
+            tracees.remove(&tid);
+        }
+        TraceePayload::CmdLineChange { cmdline } => {
+            tracee.cmdline = cmdline;
+        }
+        TraceePayload::ProcessInfo(info) => {
+            tracee.info = Some(info);
+        }
+        TraceePayload::ParentChange { parent } => {
+            tracee.parent = parent;
+        }
+        TraceePayload::ThreadName { thread, name } => {
+            tracee.threads.insert(thread, name);
+        }
+        TraceePayload::OomKilled { rss } => {
+            let formatter = make_format(BINARY);
+            tracing::warn!(
+                "{tid} was killed by the OOM killer with {} rss, cmdline was {:?}",
+                formatter(rss),
+                tracee.cmdline,
+            );
+        }
+        TraceePayload::PageFault { range, .. } => {
+            tracee.faults += 1;
+            tracee.hot.fault(at, &range);
+        }
+        TraceePayload::Exec { .. } => {
+            tracee.hot.clear();
+        }
+        _ => {
+            // ignore
         }
     }
+    seq
 }
 
 #[derive(Clone)]
 struct RouterState {
-    payload_tx: broadcast::Sender<MeviEvent>,
-    ev_tx: mpsc::SyncSender<MeviEvent>,
+    clients: fanout::Clients,
+    anchor: ClockAnchor,
+    replay: Option<replay::Handle>,
+
+    /// Tells the tracer what to do, if we're tracing anything
+    control: Option<Control>,
+
+    tracees: Tracees,
+    history: Option<history::History>,
+    series: Option<series::Series>,
+    stats: Arc<ChannelStats>,
+
+    /// What clients have to show, cf. [auth::require]
+    token: Option<Arc<str>>,
+
+    /// Where websockets may come from, anywhere if empty
+    allowed_origins: Arc<[String]>,
 }
 
-async fn stream(State(rs): State<RouterState>, upgrade: WebSocketUpgrade) -> impl IntoResponse {
-    upgrade.on_upgrade(move |ws| {
-        let payload_rx = rs.payload_tx.subscribe();
-        _ = rs.ev_tx.send(MeviEvent::Snapshot(vec![]));
-        handle_ws(payload_rx, ws)
-    })
+#[derive(Deserialize)]
+struct StreamQuery {
+    #[serde(default)]
+    encoding: session::Encoding,
 }
 
-lazy_static::lazy_static! {
-    static ref MEVI_INTERVAL: Duration = Duration::from_millis(
-        std::env::var("MEVI_INTERVAL").unwrap_or_else(|_| "32".to_string()).parse().unwrap()
-    );
+async fn stream(
+    State(rs): State<RouterState>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    // browsers always say where the page came from, other clients don't
+    // have to
+    let origin = headers
+        .get(header::ORIGIN)
+        .map(|origin| origin.to_str().unwrap_or_default());
+    if let Some(origin) = origin {
+        if !rs.allowed_origins.is_empty() && !rs.allowed_origins.iter().any(|o| o == origin) {
+            return (
+                StatusCode::FORBIDDEN,
+                format!("origin {origin} isn't allowed"),
+            )
+                .into_response();
+        }
+    }
+
+    upgrade
+        .on_upgrade(move |ws| {
+            let conn = session::Conn::Ws(ws);
+            // the token's been checked already, if there is one
+            session::serve(
+                conn,
+                query.encoding,
+                None,
+                rs.clients,
+                rs.tracees,
+                rs.anchor,
+            )
+        })
+        .into_response()
+}
+
+async fn metrics(State(rs): State<RouterState>) -> impl IntoResponse {
+    let body = metrics::render(&rs.tracees.lock().unwrap(), &rs.stats, rs.clients.len());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
 }
 
-async fn handle_ws(mut payload_rx: broadcast::Receiver<MeviEvent>, mut ws: WebSocket) {
-    let interval = *MEVI_INTERVAL;
-    let mut next_flush = Instant::now() + interval;
-    let mut queue = vec![];
+// the frontend is served from elsewhere, and fetch cares about that, unlike
+// websockets
+const CORS: [(header::HeaderName, &str); 1] = [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")];
 
-    loop {
-        match tokio::time::timeout_at(next_flush, payload_rx.recv()).await {
-            Ok(ev) => {
-                let ev = ev.unwrap();
-                queue.push(ev);
-            }
-            Err(_elapsed) => {
-                if !queue.is_empty() {
-                    ws.send(Message::Binary(
-                        mevi_common::serialize_many(&queue[..]).unwrap(),
-                    ))
-                    .await
-                    .unwrap();
-                    queue.clear();
-                }
-                next_flush += interval;
+/// Where the replay is at, if we're replaying: that's how the frontend knows
+/// to show replay controls.
+async fn replay_status(State(rs): State<RouterState>) -> Response {
+    match &rs.replay {
+        Some(replay) => (CORS, Json(replay.status())).into_response(),
+        None => (StatusCode::NOT_FOUND, CORS).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReplayQuery {
+    /// Where to seek to
+    ms: Option<u64>,
+
+    /// The new playback speed
+    x: Option<String>,
+}
+
+async fn replay_control(
+    State(rs): State<RouterState>,
+    Path(action): Path<String>,
+    Query(query): Query<ReplayQuery>,
+) -> Response {
+    let Some(replay) = &rs.replay else {
+        return (StatusCode::NOT_FOUND, CORS).into_response();
+    };
+
+    let ctl = match (action.as_str(), query) {
+        ("play", _) => replay::Control::Play,
+        ("pause", _) => replay::Control::Pause,
+        ("seek", ReplayQuery { ms: Some(ms), .. }) => {
+            replay::Control::Seek(Duration::from_millis(ms))
+        }
+        ("speed", ReplayQuery { x: Some(x), .. }) => match cli::parse_speed(&x) {
+            Ok(speed) => replay::Control::Speed(speed),
+            Err(e) => return (StatusCode::BAD_REQUEST, CORS, e.to_string()).into_response(),
+        },
+        _ => return (StatusCode::BAD_REQUEST, CORS).into_response(),
+    };
+    replay.send(ctl);
+    (StatusCode::NO_CONTENT, CORS).into_response()
+}
+
+/// Whether the tracees are frozen: that's how the frontend knows to show the
+/// pause button
+async fn tracer_status(State(rs): State<RouterState>) -> Response {
+    match &rs.control {
+        Some(control) => {
+            let status = TracerStatus {
+                paused: control.paused(),
+            };
+            (CORS, Json(status)).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, CORS).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct TracerQuery {
+    /// The process to evict or dump a range of, what range, and how to
+    /// evict it
+    tid: Option<u64>,
+    range: Option<String>,
+    advice: Option<String>,
+}
+
+async fn tracer_control(
+    State(rs): State<RouterState>,
+    Path(action): Path<String>,
+    Query(query): Query<TracerQuery>,
+) -> Response {
+    let Some(control) = &rs.control else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match (action.as_str(), query) {
+        ("pause", _) => control.set_paused(true),
+        ("resume", _) => control.set_paused(false),
+        (
+            "evict",
+            TracerQuery {
+                tid: Some(tid),
+                range: Some(range),
+                advice,
+            },
+        ) => {
+            let tid = TraceeId(tid);
+            if !rs.tracees.lock().unwrap().contains_key(&tid) {
+                return StatusCode::NOT_FOUND.into_response();
             }
-        };
+            let range = match cli::parse_range(&range) {
+                Ok(range) => range,
+                Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            };
+            let advice = match advice.as_deref().unwrap_or("pageout").parse() {
+                Ok(advice) => advice,
+                Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            };
+            control.evict(evict::Eviction { tid, range, advice });
+            // it happens once the process makes a syscall
+            return StatusCode::ACCEPTED.into_response();
+        }
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// What's in a range of a traced process's memory, as is
+async fn tracer_dump(State(rs): State<RouterState>, Query(query): Query<TracerQuery>) -> Response {
+    if rs.control.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let (Some(tid), Some(range)) = (query.tid, query.range) else {
+        return (StatusCode::BAD_REQUEST, "expected ?tid=&range=").into_response();
+    };
+    let tid = TraceeId(tid);
+    if !rs.tracees.lock().unwrap().contains_key(&tid) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let range = match cli::parse_range(&range) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    match tokio::task::spawn_blocking(move || dump::read(tid, range)).await {
+        Ok(Ok((bytes, unreadable))) => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            [(control::UNREADABLE, unreadable.to_string())],
+            bytes,
+        )
+            .into_response(),
+        Ok(Err(e)) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// How far back a live session can be scrubbed: that's how the frontend
+/// knows to show the history bar
+async fn history_status(State(rs): State<RouterState>) -> Response {
+    match rs.history.as_ref().and_then(|history| history.span()) {
+        Some((start, end)) => {
+            let ms = |at: Timestamp| at.since(rs.anchor.monotonic).as_millis() as u64;
+            let status = HistoryStatus {
+                start_ms: ms(start),
+                end_ms: ms(end),
+            };
+            (CORS, Json(status)).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, CORS).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Milliseconds since the session started, like [HistoryStatus]
+    ms: u64,
+}
+
+/// What things looked like `?ms=` into the session, as an events frame: a
+/// snapshot, and the events that followed it until then
+async fn history_at(State(rs): State<RouterState>, Query(query): Query<HistoryQuery>) -> Response {
+    let Some(history) = &rs.history else {
+        return (StatusCode::NOT_FOUND, CORS).into_response();
+    };
+    let Some(at) = query
+        .ms
+        .checked_mul(1_000_000)
+        .and_then(|nanos| rs.anchor.monotonic.0.checked_add(nanos))
+    else {
+        return (StatusCode::BAD_REQUEST, CORS, "that's too far ahead").into_response();
+    };
+    let Some(events) = history.at(Timestamp(at)) else {
+        return (StatusCode::NOT_FOUND, CORS, "that's older than we remember").into_response();
+    };
+    match protocol::encode_events(&events) {
+        Ok(frame) => (
+            CORS,
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            frame,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, CORS, e.to_string()).into_response(),
     }
 }
@@ -0,0 +1,119 @@
+use std::{collections::HashMap, fmt::Write};
+
+use mevi_common::TraceeId;
+use mevi_tracer::channel::ChannelStats;
+
+use crate::{export, TraceeState};
+
+/// Renders what we know in the Prometheus text exposition format, cf.
+/// https://prometheus.io/docs/instrumenting/exposition_formats/
+pub(crate) fn render(
+    tracees: &HashMap<TraceeId, TraceeState>,
+    stats: &ChannelStats,
+    clients: usize,
+) -> String {
+    let mut resident = vec![];
+    let mut mapped = vec![];
+    let mut heap = vec![];
+    let mut faults = vec![];
+    let mut categories = vec![];
+
+    for tracee in tracees.values() {
+        let labels = labels(tracee);
+
+        let (tracee_mapped, tracee_resident) = tracee.totals();
+        resident.push((labels.clone(), tracee_resident));
+        mapped.push((labels.clone(), tracee_mapped));
+        heap.push((labels.clone(), tracee.heap_size()));
+        faults.push((labels, tracee.faults));
+        for (category, (_, resident)) in export::by_category(&tracee.map, &tracee.kinds) {
+            categories.push((category_labels(tracee, category), resident));
+        }
+    }
+
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+        _ = writeln!(out, "# HELP {name} {help}");
+        _ = writeln!(out, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            _ = writeln!(out, "{name}{labels} {value}");
+        }
+    };
+
+    family(
+        "mevi_resident_bytes",
+        "gauge",
+        "Bytes of tracked memory that are resident",
+        &resident,
+    );
+    family(
+        "mevi_mapped_bytes",
+        "gauge",
+        "Bytes of tracked memory that are mapped",
+        &mapped,
+    );
+    family(
+        "mevi_category_resident_bytes",
+        "gauge",
+        "Bytes of tracked memory that are resident, per category of mapping",
+        &categories,
+    );
+    family("mevi_heap_bytes", "gauge", "Size of the brk heap", &heap);
+    family(
+        "mevi_page_faults_total",
+        "counter",
+        "Page faults handled by mevi",
+        &faults,
+    );
+    family(
+        "mevi_events_total",
+        "counter",
+        "Events that went through the relay",
+        &[(String::new(), stats.received())],
+    );
+    family(
+        "mevi_events_dropped_total",
+        "counter",
+        "Events dropped because the relay couldn't keep up",
+        &[(String::new(), stats.dropped())],
+    );
+    family(
+        "mevi_channel_depth",
+        "gauge",
+        "Events waiting to be relayed",
+        &[(String::new(), stats.depth())],
+    );
+    family(
+        "mevi_clients",
+        "gauge",
+        "Clients connected to the event stream",
+        &[(String::new(), clients as u64)],
+    );
+
+    out
+}
+
+fn labels(tracee: &TraceeState) -> String {
+    format!("{{{}}}", tracee_labels(tracee))
+}
+
+fn category_labels(tracee: &TraceeState, category: &str) -> String {
+    format!("{{{},category=\"{category}\"}}", tracee_labels(tracee))
+}
+
+fn tracee_labels(tracee: &TraceeState) -> String {
+    let comm = tracee
+        .cmdline
+        .first()
+        .map(|arg0| arg0.rsplit('/').next().unwrap_or(arg0))
+        .unwrap_or_default();
+    format!("tid=\"{}\",comm=\"{}\"", tracee.tid.0, escape(comm))
+}
+
+/// Label values are double-quoted, with backslashes, quotes and newlines
+/// escaped
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
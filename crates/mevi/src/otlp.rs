@@ -0,0 +1,310 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::{BuildHasher, Hasher},
+    sync::mpsc as std_mpsc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{Body, Client, Method, Request};
+use mevi_common::{TraceeId, TraceePayload};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::Tracees;
+
+/// How long the relay waits for the last spans to reach the collector
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the exporter needs to hear about from the relay to build spans
+enum Lifecycle {
+    Start(TraceeId),
+    /// With the new executable and its arguments
+    Exec(TraceeId, Option<String>, Vec<String>),
+    CmdLine(TraceeId, Vec<String>),
+    Exit(TraceeId),
+}
+
+/// Lets the relay tell the exporter about tracees coming and going
+pub(crate) struct Handle {
+    tx: mpsc::UnboundedSender<(SystemTime, Lifecycle)>,
+    known: HashSet<TraceeId>,
+
+    /// Disconnects once the exporter is done, cf. [Handle::finish]
+    flushed: std_mpsc::Receiver<()>,
+}
+
+impl Handle {
+    pub(crate) fn observe(&mut self, tid: TraceeId, payload: &TraceePayload) {
+        let now = SystemTime::now();
+        if self.known.insert(tid) {
+            _ = self.tx.send((now, Lifecycle::Start(tid)));
+        }
+        let ev = match payload {
+            TraceePayload::Exec { exe, argv, .. } => {
+                Lifecycle::Exec(tid, exe.clone(), argv.clone())
+            }
+            TraceePayload::CmdLineChange { cmdline } => Lifecycle::CmdLine(tid, cmdline.clone()),
+            TraceePayload::Exit => {
+                self.known.remove(&tid);
+                Lifecycle::Exit(tid)
+            }
+            _ => return,
+        };
+        _ = self.tx.send((now, ev));
+    }
+
+    /// Ends the spans that are still open and sends whatever hasn't been
+    /// sent yet, waiting a bit for that to happen
+    pub(crate) fn finish(self) {
+        drop(self.tx);
+        _ = self.flushed.recv_timeout(FLUSH_TIMEOUT);
+    }
+}
+
+/// Periodically pushes memory metrics, and the lifetime of every tracee as a
+/// span, to an OpenTelemetry collector over OTLP/HTTP (JSON encoding), cf.
+/// https://opentelemetry.io/docs/specs/otlp/#otlphttp
+pub(crate) fn spawn(endpoint: String, interval: Duration, tracees: Tracees) -> Handle {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (flushed_tx, flushed) = std_mpsc::channel();
+    tokio::spawn(run(
+        endpoint.trim_end_matches('/').to_owned(),
+        interval,
+        tracees,
+        rx,
+        flushed_tx,
+    ));
+    Handle {
+        tx,
+        known: Default::default(),
+        flushed,
+    }
+}
+
+struct Span {
+    span_id: u64,
+    name: String,
+    start: SystemTime,
+    events: Vec<Value>,
+}
+
+async fn run(
+    endpoint: String,
+    interval: Duration,
+    tracees: Tracees,
+    mut rx: mpsc::UnboundedReceiver<(SystemTime, Lifecycle)>,
+    // dropped once we're done, which is what `Handle::finish` waits for
+    _flushed: std_mpsc::Sender<()>,
+) {
+    let client = Client::new();
+    let started = SystemTime::now();
+
+    // one trace per mevi session, one span per tracee
+    let ids = RandomState::new();
+    let random = |n: u64| {
+        let mut h = ids.build_hasher();
+        h.write_u64(n);
+        h.finish()
+    };
+    let trace_id = format!("{:016x}{:016x}", random(0), random(1));
+
+    let mut open: HashMap<TraceeId, Span> = HashMap::new();
+    let mut done: Vec<Value> = vec![];
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            ev = rx.recv() => {
+                let Some((at, ev)) = ev else {
+                    // the session is over, whatever's still around ends with it
+                    let now = SystemTime::now();
+                    for (tid, span) in open.drain() {
+                        done.push(span_json(&trace_id, tid, span, now));
+                    }
+                    post_spans(&client, &endpoint, std::mem::take(&mut done)).await;
+                    return;
+                };
+                match ev {
+                    Lifecycle::Start(tid) => {
+                        open.insert(tid, Span {
+                            span_id: random(tid.0 + 2),
+                            name: format!("pid {}", tid.0),
+                            start: at,
+                            events: vec![],
+                        });
+                    }
+                    Lifecycle::Exec(tid, exe, argv) => {
+                        if let Some(span) = open.get_mut(&tid) {
+                            span.events.push(json!({
+                                "timeUnixNano": nanos(at),
+                                "name": "execve",
+                                "attributes": [
+                                    { "key": "process.executable.path", "value": { "stringValue": exe.unwrap_or_default() } },
+                                    { "key": "process.command_line", "value": { "stringValue": argv.join(" ") } },
+                                ],
+                            }));
+                        }
+                    }
+                    Lifecycle::CmdLine(tid, cmdline) => {
+                        if let Some(span) = open.get_mut(&tid) {
+                            span.name = cmdline.join(" ");
+                        }
+                    }
+                    Lifecycle::Exit(tid) => {
+                        if let Some(span) = open.remove(&tid) {
+                            done.push(span_json(&trace_id, tid, span, at));
+                        }
+                    }
+                }
+            }
+            _ = ticker.tick() => {
+                let metrics = metrics_json(&tracees, started);
+                post(&client, &format!("{endpoint}/v1/metrics"), metrics).await;
+
+                post_spans(&client, &endpoint, std::mem::take(&mut done)).await;
+            }
+        }
+    }
+}
+
+async fn post_spans(
+    client: &Client<hyper::client::HttpConnector>,
+    endpoint: &str,
+    spans: Vec<Value>,
+) {
+    if spans.is_empty() {
+        return;
+    }
+    let spans = json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{
+                "scope": { "name": "mevi" },
+                "spans": spans,
+            }],
+        }],
+    });
+    post(client, &format!("{endpoint}/v1/traces"), spans).await;
+}
+
+async fn post(client: &Client<hyper::client::HttpConnector>, url: &str, body: Value) {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()));
+    let req = match req {
+        Ok(req) => req,
+        Err(e) => {
+            warn!("bad OTLP endpoint {url}: {e}");
+            return;
+        }
+    };
+    match client.request(req).await {
+        Ok(res) if res.status().is_success() => {}
+        Ok(res) => debug!("OTLP collector said {} for {url}", res.status()),
+        Err(e) => debug!("couldn't reach OTLP collector at {url}: {e}"),
+    }
+}
+
+/// OTLP/JSON wants 64-bit integers as strings
+fn nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn resource() -> Value {
+    json!({
+        "attributes": [
+            { "key": "service.name", "value": { "stringValue": "mevi" } },
+        ],
+    })
+}
+
+fn process_attributes(tid: TraceeId, cmdline: &[String]) -> Value {
+    json!([
+        { "key": "process.pid", "value": { "intValue": tid.0.to_string() } },
+        { "key": "process.command_line", "value": { "stringValue": cmdline.join(" ") } },
+    ])
+}
+
+fn span_json(trace_id: &str, tid: TraceeId, span: Span, end: SystemTime) -> Value {
+    json!({
+        "traceId": trace_id,
+        "spanId": format!("{:016x}", span.span_id),
+        "name": span.name,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        "startTimeUnixNano": nanos(span.start),
+        "endTimeUnixNano": nanos(end),
+        "attributes": [{ "key": "process.pid", "value": { "intValue": tid.0.to_string() } }],
+        "events": span.events,
+    })
+}
+
+fn metrics_json(tracees: &Tracees, started: SystemTime) -> Value {
+    let now = nanos(SystemTime::now());
+    let start = nanos(started);
+
+    let mut resident = vec![];
+    let mut mapped = vec![];
+    let mut heap = vec![];
+    let mut faults = vec![];
+    for tracee in tracees.lock().unwrap().values() {
+        let attributes = process_attributes(tracee.tid, &tracee.cmdline);
+
+        let (tracee_mapped, tracee_resident) = tracee.totals();
+
+        let point = |value: u64| {
+            json!({
+                "asInt": value.to_string(),
+                "timeUnixNano": now,
+                "attributes": attributes,
+            })
+        };
+        resident.push(point(tracee_resident));
+        mapped.push(point(tracee_mapped));
+        heap.push(point(tracee.heap_size()));
+
+        let mut fault_point = point(tracee.faults);
+        fault_point["startTimeUnixNano"] = start.clone().into();
+        faults.push(fault_point);
+    }
+
+    let gauge = |name: &str, description: &str, points: Vec<Value>| {
+        json!({
+            "name": name,
+            "description": description,
+            "unit": "By",
+            "gauge": { "dataPoints": points },
+        })
+    };
+
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{
+                "scope": { "name": "mevi" },
+                "metrics": [
+                    gauge("mevi.memory.resident", "Bytes of tracked memory that are resident", resident),
+                    gauge("mevi.memory.mapped", "Bytes of tracked memory that are mapped", mapped),
+                    gauge("mevi.memory.heap", "Size of the brk heap", heap),
+                    {
+                        "name": "mevi.page_faults",
+                        "description": "Page faults handled by mevi",
+                        "unit": "{fault}",
+                        "sum": {
+                            // AGGREGATION_TEMPORALITY_CUMULATIVE
+                            "aggregationTemporality": 2,
+                            "isMonotonic": true,
+                            "dataPoints": faults,
+                        },
+                    },
+                ],
+            }],
+        }],
+    })
+}
@@ -0,0 +1,135 @@
+use std::{collections::HashMap, fs::File, io::BufWriter, path::Path, time::Duration};
+
+use color_eyre::{eyre::WrapErr, Result};
+use mevi_common::{trace::TraceWriter, MeviEvent, TraceeId};
+use mevi_tracer::channel::EventRx;
+use tracing::info;
+
+use crate::{capture, replay};
+
+/// Writes every event to `path` until all senders are gone, i.e. until the
+/// tracer and its samplers are done. A `compression_level` of 0 means no
+/// compression at all.
+///
+/// With a `snapshot_interval`, a snapshot of every tracee goes in that
+/// often (as long as anything happens), so replaying can seek from there
+/// rather than from the start.
+///
+/// With `capture_signals`, page activity only gets written down while
+/// [capture::capturing].
+pub(crate) fn record(
+    rx: EventRx,
+    path: &Path,
+    compression_level: i32,
+    snapshot_interval: Option<Duration>,
+    capture_signals: bool,
+) -> Result<()> {
+    let file = File::create(path).wrap_err_with(|| format!("creating {}", path.display()))?;
+    let file = BufWriter::new(file);
+    // the header remembers when the session started, records are relative
+    // to that
+    let anchor = rx.anchor();
+    let w = match compression_level {
+        0 => TraceWriter::new(file, &anchor)?,
+        level => TraceWriter::compressed(file, level, &anchor)?,
+    };
+    let mut recorder = Recorder {
+        w,
+        count: 0,
+        snapshot_interval,
+        folded: HashMap::new(),
+        last_snapshot: Duration::ZERO,
+        capture_signals,
+        capturing: capture::capturing(),
+    };
+
+    while let Ok(ev) = rx.recv() {
+        recorder.write(ev.at.since(anchor.monotonic), &ev.event)?;
+
+        // write out whatever else is pending, then flush, so that a recording
+        // that gets interrupted is only missing the last few events (or the
+        // last chunk, if it's compressed)
+        while let Ok(ev) = rx.try_recv() {
+            recorder.write(ev.at.since(anchor.monotonic), &ev.event)?;
+        }
+        recorder.w.flush()?;
+    }
+    recorder.w.finish()?;
+
+    info!("recorded {} events to {}", recorder.count, path.display());
+    Ok(())
+}
+
+struct Recorder {
+    w: TraceWriter<BufWriter<File>>,
+    count: usize,
+    snapshot_interval: Option<Duration>,
+
+    /// What every tracee looks like so far, if we make snapshots or might
+    /// have to catch up after not capturing for a while
+    folded: HashMap<TraceeId, replay::Folded>,
+    last_snapshot: Duration,
+
+    capture_signals: bool,
+
+    /// Whether we were capturing as of the last event
+    capturing: bool,
+}
+
+impl Recorder {
+    fn write(&mut self, at: Duration, event: &MeviEvent) -> Result<()> {
+        if self.capture_signals {
+            self.check_capture(at)?;
+        }
+        let skip = match event {
+            MeviEvent::TraceeEvent(_, payload) => !self.capturing && capture::is_activity(payload),
+            _ => false,
+        };
+        if !skip {
+            self.w.write(at, event)?;
+            self.count += 1;
+        }
+
+        if self.snapshot_interval.is_none() && !self.capture_signals {
+            return Ok(());
+        }
+        replay::fold(&mut self.folded, event);
+        let Some(interval) = self.snapshot_interval else {
+            return Ok(());
+        };
+        // seeking to a snapshot shouldn't show more than playing up to it
+        if self.capturing && at.saturating_sub(self.last_snapshot) >= interval {
+            let snapshots = self
+                .folded
+                .iter()
+                .map(|(tid, tracee)| tracee.snapshot(*tid))
+                .collect();
+            self.w.write(at, &MeviEvent::Snapshot(snapshots))?;
+            self.last_snapshot = at;
+        }
+        Ok(())
+    }
+
+    /// Notices when we start or stop capturing. When we start again, where
+    /// every page stands now goes in first, so the trace adds up.
+    fn check_capture(&mut self, at: Duration) -> Result<()> {
+        let capturing = capture::capturing();
+        if capturing == self.capturing {
+            return Ok(());
+        }
+        self.capturing = capturing;
+        if !capturing {
+            info!("stopped capturing, only keeping track of mappings");
+            return Ok(());
+        }
+
+        info!("capturing again");
+        for (tid, tracee) in &self.folded {
+            for payload in tracee.mem_states() {
+                self.w.write(at, &MeviEvent::TraceeEvent(*tid, payload))?;
+                self.count += 1;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! `mevi serve` and `mevi run --server`: a server that outlives whatever it
+//! shows, and tracers that register their sessions with it over a Unix
+//! socket, so one frontend can watch every experiment on a machine.
+
+use std::{collections::HashSet, path::Path};
+
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
+};
+use mevi_common::{protocol, MeviEvent, TimedEvent, TraceeId, TraceePayload};
+use mevi_tracer::channel::{EventRx, EventTx};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{info, warn};
+
+use crate::session;
+
+/// How many events a tracer sends the server in one frame, at most
+const BATCH_LEN: usize = 1024;
+
+/// Accepts tracers on a Unix socket at `path`, and feeds whatever they send
+/// to `tx`, for as long as we're up
+pub(crate) fn listen(path: &Path, tx: EventTx) -> Result<()> {
+    // a mevi that didn't get to clean up leaves its socket behind
+    _ = std::fs::remove_file(path);
+    let listener =
+        UnixListener::bind(path).wrap_err_with(|| format!("listening on {}", path.display()))?;
+    info!("accepting sessions on {}", path.display());
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("accepting tracer: {e}");
+                    continue;
+                }
+            };
+            let who = match stream.peer_cred().ok().and_then(|cred| cred.pid()) {
+                Some(pid) => format!("tracer {pid}"),
+                None => "tracer".to_owned(),
+            };
+            let stream = match stream.into_std() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("{who}: {e}");
+                    continue;
+                }
+            };
+
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                // sending blocks when the relay is behind, so each session
+                // gets a runtime of its own rather than stalling the server's
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                let mut session = Session {
+                    tx,
+                    live: Default::default(),
+                };
+                let res = rt.block_on(async {
+                    let io = UnixStream::from_std(stream)?;
+                    session.run(io).await
+                });
+                match res {
+                    Ok(()) => info!("{who} is done"),
+                    Err(e) => warn!("{who}: {e:?}"),
+                }
+                session.hang_up();
+            });
+        }
+    });
+    Ok(())
+}
+
+/// A tracer that registered with us
+struct Session {
+    tx: EventTx,
+
+    /// Tracees it told us about, and hasn't seen exit yet
+    live: HashSet<TraceeId>,
+}
+
+impl Session {
+    async fn run(&mut self, mut io: UnixStream) -> Result<()> {
+        // we speak first, like for viewers. Tracers downgrade what they send
+        // to whatever we both know, so there's nothing else to keep track of.
+        let ours = protocol::Hello::ours();
+        session::write_frame(&mut io, &protocol::encode_hello(&ours)).await?;
+        let theirs = match session::read_frame(&mut io).await? {
+            Some(frame) => match protocol::decode(&frame)? {
+                protocol::Frame::Hello(hello) => hello,
+                _ => bail!("sent frames before saying hello"),
+            },
+            None => bail!("left before saying hello"),
+        };
+        ours.negotiate(&theirs)?;
+        info!("registered a new session");
+
+        while let Some(frame) = session::read_frame(&mut io).await? {
+            match protocol::decode(&frame)? {
+                protocol::Frame::Events(evs) => {
+                    for ev in evs {
+                        self.forward(ev)?;
+                    }
+                }
+                protocol::Frame::Heartbeat(_) => {}
+                _ => bail!("sent something other than events"),
+            }
+        }
+        Ok(())
+    }
+
+    fn forward(&mut self, ev: TimedEvent) -> Result<()> {
+        match &ev.event {
+            MeviEvent::TraceeEvent(tid, TraceePayload::Exit) => {
+                self.live.remove(tid);
+            }
+            MeviEvent::TraceeEvent(tid, _) => {
+                self.live.insert(*tid);
+            }
+            MeviEvent::SessionInfo(info) => info!("the session is {info}"),
+            // those are the relay's to make
+            MeviEvent::Snapshot(_) | MeviEvent::SessionEnd(_) => return Ok(()),
+        }
+        // same machine, same monotonic clock
+        self.tx
+            .send_at(ev.at, ev.event)
+            .map_err(|_| eyre!("the relay is gone"))
+    }
+
+    /// Whatever the tracer didn't see exit, we won't hear about again
+    fn hang_up(self) {
+        for tid in self.live {
+            _ = self
+                .tx
+                .send(MeviEvent::TraceeEvent(tid, TraceePayload::Exit));
+        }
+    }
+}
+
+/// Sends the server listening at `path` whatever the tracer sends `rx`,
+/// until the tracer's done
+pub(crate) fn register(rx: EventRx, path: &Path) -> Result<()> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let mut io = rt
+        .block_on(UnixStream::connect(path))
+        .wrap_err_with(|| format!("connecting to mevi serve at {}", path.display()))?;
+
+    let features = rt.block_on(async {
+        // the server speaks first
+        let theirs = match session::read_frame(&mut io).await? {
+            Some(frame) => match protocol::decode(&frame)? {
+                protocol::Frame::Hello(hello) => hello,
+                _ => bail!("the server didn't say hello first"),
+            },
+            None => bail!("the server hung up before saying hello"),
+        };
+        let ours = protocol::Hello::ours();
+        session::write_frame(&mut io, &protocol::encode_hello(&ours)).await?;
+        Ok(ours.negotiate(&theirs)?)
+    })?;
+    info!("registered with the server at {}", path.display());
+
+    // once every sender is gone, the tracer's done
+    while let Ok(first) = rx.recv() {
+        let batch = std::iter::once(first)
+            .chain(std::iter::from_fn(|| rx.try_recv().ok()))
+            .take(BATCH_LEN)
+            .filter_map(|TimedEvent { at, seq, event }| {
+                let event = protocol::downgrade(event, features)?;
+                Some(TimedEvent { at, seq, event })
+            })
+            .collect::<Vec<_>>();
+        let frame = protocol::encode_events(&batch)?;
+        rt.block_on(session::write_frame(&mut io, &frame))
+            .wrap_err("the server is gone")?;
+    }
+    Ok(())
+}
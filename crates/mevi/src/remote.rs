@@ -0,0 +1,392 @@
+//! Tracing on one machine and looking at it from another: a mevi started with
+//! `--listen-tcp` streams events over TCP (TLS if it has a certificate), and
+//! `mevi connect` relays them to a local frontend as if it was tracing
+//! itself.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
+};
+use mevi_common::{
+    protocol, ClockAnchor, MemState, MeviEvent, TimedEvent, Timestamp, TraceeId, TraceePayload,
+};
+use mevi_tracer::channel::EventTx;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    rustls::{self, ServerName},
+    TlsAcceptor, TlsConnector,
+};
+use tracing::{info, warn};
+
+use crate::{
+    fanout::Clients,
+    session::{self, Conn, Encoding, Io},
+    Tracees,
+};
+
+/// How long we wait before reconnecting, at first
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+
+/// How long we wait before reconnecting, once the remote has been gone for
+/// a while
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// TLS settings for HTTPS and `--listen-tcp`, from a PEM certificate chain and
+/// key
+pub(crate) fn server_tls(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let certs = read_pem(cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(rustls::Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        bail!("no certificate in {}", cert.display());
+    }
+    let key = read_pem(key)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(rustls::PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| eyre!("no private key in {}", key.display()))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// TLS settings for `mevi connect`, trusting only the CA in `ca`
+pub(crate) fn client_tls(ca: &Path) -> Result<TlsConnector> {
+    let mut roots = rustls::RootCertStore::empty();
+    for item in read_pem(ca)? {
+        if let rustls_pemfile::Item::X509Certificate(der) = item {
+            roots.add(&rustls::Certificate(der))?;
+        }
+    }
+    if roots.is_empty() {
+        bail!("no certificate in {}", ca.display());
+    }
+
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<rustls_pemfile::Item>> {
+    let file = File::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .wrap_err_with(|| format!("reading {}", path.display()))
+}
+
+/// Streams events to every viewer that connects to `listener`, the same way
+/// websocket clients get them
+pub(crate) async fn serve(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    token: Option<Arc<str>>,
+    clients: Clients,
+    tracees: Tracees,
+    anchor: ClockAnchor,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("accepting viewer: {e}");
+                continue;
+            }
+        };
+        // events are batched already, don't make them wait any longer
+        _ = stream.set_nodelay(true);
+
+        let tls = tls.clone();
+        let token = token.clone();
+        let clients = clients.clone();
+        let tracees = tracees.clone();
+        tokio::spawn(async move {
+            let io: Box<dyn Io> = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => Box::new(stream),
+                    Err(e) => {
+                        warn!("TLS handshake with {peer} failed: {e}");
+                        return;
+                    }
+                },
+                None => Box::new(stream),
+            };
+
+            info!("viewer connected from {peer}");
+            session::serve(
+                Conn::Stream(io),
+                Encoding::Postcard,
+                token,
+                clients,
+                tracees,
+                anchor,
+            )
+            .await;
+            info!("viewer {peer} left");
+        });
+    }
+}
+
+/// Connects to a mevi that was started with `--listen-tcp`, and feeds whatever
+/// it streams to `tx`. When the connection drops, we keep trying to get it
+/// back, and pick up where we left off, or from a snapshot if the remote
+/// can't do that.
+pub(crate) fn spawn_viewer(
+    addr: String,
+    tls: Option<TlsConnector>,
+    token: Option<String>,
+    tx: EventTx,
+    anchor: ClockAnchor,
+) {
+    std::thread::spawn(move || {
+        // sending blocks when the relay is behind, so this gets a runtime of
+        // its own rather than stalling the server's
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let mut viewer = Viewer {
+                tx,
+                anchor,
+                live: Default::default(),
+                seen: Default::default(),
+                connected: false,
+            };
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                match viewer.session(&addr, tls.as_ref(), token.as_deref()).await {
+                    Ok(()) => warn!("{addr} hung up"),
+                    Err(e) => warn!("{addr}: {e:?}"),
+                }
+                if std::mem::take(&mut viewer.connected) {
+                    backoff = MIN_BACKOFF;
+                }
+                info!("reconnecting to {addr} in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        });
+    });
+}
+
+struct Viewer {
+    tx: EventTx,
+
+    /// Our own clock, remote timestamps are translated to it
+    anchor: ClockAnchor,
+
+    /// Tracees we told the relay about, and haven't seen exit yet
+    live: HashSet<TraceeId>,
+
+    /// The next sequence number we expect from the remote, for each of its
+    /// tracees, so we can ask for just what we missed when reconnecting
+    seen: HashMap<TraceeId, u64>,
+
+    /// Whether the last session got as far as the handshake
+    connected: bool,
+}
+
+impl Viewer {
+    async fn session(
+        &mut self,
+        addr: &str,
+        tls: Option<&TlsConnector>,
+        token: Option<&str>,
+    ) -> Result<()> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .wrap_err_with(|| format!("connecting to {addr}"))?;
+        _ = stream.set_nodelay(true);
+        let mut io: Box<dyn Io> = match tls {
+            Some(tls) => {
+                let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let name = ServerName::try_from(host)
+                    .map_err(|_| eyre!("{host:?} can't be checked against a certificate"))?;
+                Box::new(tls.connect(name, stream).await?)
+            }
+            None => Box::new(stream),
+        };
+
+        // the server speaks first
+        let theirs = match session::read_frame(&mut io).await? {
+            Some(frame) => match protocol::decode(&frame)? {
+                protocol::Frame::Hello(hello) => hello,
+                _ => bail!("{addr} didn't say hello first"),
+            },
+            None => bail!("{addr} hung up before saying hello"),
+        };
+        // overlay deltas only make sense to whoever got the samples before
+        // them: we want whole samples, our own clients get deltas of those
+        let ours = protocol::Hello {
+            features: protocol::Hello::ours()
+                .features
+                .difference(protocol::Features::OVERLAY_DELTAS),
+            ..protocol::Hello::ours()
+        };
+        session::write_frame(&mut io, &protocol::encode_hello(&ours)).await?;
+        ours.negotiate(&theirs)?;
+        if let Some(token) = token {
+            session::write_frame(&mut io, &protocol::encode_token(token)?).await?;
+        }
+        let resume = self
+            .seen
+            .iter()
+            .map(|(&tid, &seq)| (tid, seq))
+            .collect::<Vec<_>>();
+        session::write_frame(&mut io, &protocol::encode_resume(&resume)?).await?;
+        info!("connected to {addr}");
+        self.connected = true;
+
+        let mut remote_anchor = None;
+        loop {
+            // the remote sends heartbeats when it has nothing else to say,
+            // so a long silence means the connection is dead
+            let frame = tokio::time::timeout(
+                protocol::HEARTBEAT_INTERVAL * 3,
+                session::read_frame(&mut io),
+            )
+            .await
+            .map_err(|_| eyre!("{addr} went quiet"))??;
+            let Some(frame) = frame else {
+                return Ok(());
+            };
+            match protocol::decode(&frame)? {
+                protocol::Frame::Hello(_) => bail!("{addr} said hello twice"),
+                protocol::Frame::Resume(_) => bail!("{addr} asked us to resume"),
+                protocol::Frame::Token(_) => bail!("{addr} sent us a token"),
+                protocol::Frame::Clock(anchor) => remote_anchor = Some(anchor),
+                protocol::Frame::Heartbeat(_) => {}
+                protocol::Frame::Events(evs) => {
+                    for ev in evs {
+                        self.forward(ev, remote_anchor.as_ref())?;
+                    }
+                }
+            }
+        }
+    }
+
+    fn forward(&mut self, ev: TimedEvent, remote_anchor: Option<&ClockAnchor>) -> Result<()> {
+        // the remote's monotonic clock means nothing here, but wall clocks
+        // are hopefully close enough
+        let at = match remote_anchor {
+            Some(remote) => self.anchor.to_timestamp(remote.to_unix_nanos(ev.at)),
+            None => Timestamp::now(),
+        };
+
+        let snapshots = match ev.event {
+            MeviEvent::TraceeEvent(tid, payload) => {
+                match payload {
+                    TraceePayload::Exit => self.seen.remove(&tid),
+                    _ => self.seen.insert(tid, ev.seq + 1),
+                };
+                return self.send(at, tid, payload);
+            }
+            MeviEvent::Snapshot(snapshots) => snapshots,
+            MeviEvent::SessionEnd(_) => {
+                // our tracees stay where they are, the remote's relay keeps
+                // serving until it's told to stop
+                info!("the remote session is over");
+                return Ok(());
+            }
+            ev @ MeviEvent::SessionInfo(_) => {
+                return self
+                    .tx
+                    .send_at(at, ev)
+                    .map_err(|_| eyre!("the relay is gone"));
+            }
+        };
+        self.seen = snapshots.iter().map(|s| (s.tid, s.seq)).collect();
+
+        // like seeking through a replay: forget everything we knew, and
+        // describe what the remote knows with regular events
+        for tid in std::mem::take(&mut self.live) {
+            self.send(at, tid, TraceePayload::Exit)?;
+        }
+        for snapshot in snapshots {
+            let tid = snapshot.tid;
+            self.send(
+                at,
+                tid,
+                TraceePayload::CmdLineChange {
+                    cmdline: snapshot.cmdline,
+                },
+            )?;
+            if let Some(info) = snapshot.info {
+                self.send(at, tid, TraceePayload::ProcessInfo(info))?;
+            }
+            if let Some(parent) = snapshot.parent {
+                self.send(
+                    at,
+                    tid,
+                    TraceePayload::ParentChange {
+                        parent: Some(parent),
+                    },
+                )?;
+            }
+            for (thread, name) in snapshot.threads {
+                self.send(at, tid, TraceePayload::ThreadName { thread, name })?;
+            }
+            for (range, kind) in snapshot.kinds.iter() {
+                self.send(
+                    at,
+                    tid,
+                    TraceePayload::Map {
+                        range: range.clone(),
+                        state: MemState::Untracked,
+                        kind: *kind,
+                        file: snapshot.files.get(&range.start).cloned(),
+                        perms: snapshot
+                            .perms
+                            .get(&range.start)
+                            .copied()
+                            .unwrap_or_default(),
+                        raw: None,
+                    },
+                )?;
+            }
+            for (range, state) in snapshot.map.iter() {
+                self.send(
+                    at,
+                    tid,
+                    TraceePayload::MemStateChange {
+                        range: range.clone(),
+                        state: *state,
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, at: Timestamp, tid: TraceeId, payload: TraceePayload) -> Result<()> {
+        match payload {
+            TraceePayload::Exit => self.live.remove(&tid),
+            _ => self.live.insert(tid),
+        };
+        self.tx
+            .send_at(at, MeviEvent::TraceeEvent(tid, payload))
+            .map_err(|_| eyre!("the relay is gone"))
+    }
+}
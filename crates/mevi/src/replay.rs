@@ -0,0 +1,354 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use mevi_common::{
+    trace::{self, ReplayStatus, TraceReader, TraceRecord},
+    MappingFiles, MappingKinds, MappingPermsMap, MemMap, MemState, MeviEvent, ProcessInfo,
+    TraceeId, TraceePayload, TraceeSnapshot,
+};
+use mevi_tracer::channel::EventTx;
+use tracing::{info, warn};
+
+/// How often we update the status while waiting for the next event
+const STATUS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) enum Control {
+    Play,
+    Pause,
+    Speed(f64),
+    Seek(Duration),
+}
+
+/// Lets the server steer a replay, and see where it's at
+#[derive(Clone)]
+pub(crate) struct Handle {
+    ctl: mpsc::Sender<Control>,
+    status: Arc<Mutex<ReplayStatus>>,
+}
+
+impl Handle {
+    pub(crate) fn send(&self, ctl: Control) {
+        // if the player is gone, there's nothing to steer anymore
+        _ = self.ctl.send(ctl);
+    }
+
+    pub(crate) fn status(&self) -> ReplayStatus {
+        *self.status.lock().unwrap()
+    }
+}
+
+/// Reads a whole trace, and starts feeding its events to `tx` as if they were
+/// happening now, `speed` times faster.
+pub(crate) fn spawn(path: &Path, tx: EventTx, speed: f64) -> Result<Handle> {
+    let file = File::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+    let mut records = vec![];
+    for record in TraceReader::new(BufReader::new(file))? {
+        match record {
+            Ok(record) => records.push(record),
+            // that's what a recording that got interrupted looks like
+            Err(trace::Error::Truncated) => {
+                warn!("{} ends with a truncated record", path.display());
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    info!("replaying {} events from {}", records.len(), path.display());
+
+    let duration = records.last().map(|r| r.at).unwrap_or_default();
+    let status = Arc::new(Mutex::new(ReplayStatus {
+        position_ms: 0,
+        duration_ms: duration.as_millis() as u64,
+        paused: false,
+        speed,
+    }));
+    let (ctl_tx, ctl_rx) = mpsc::channel();
+
+    let mut player = Player {
+        records,
+        tx,
+        status: status.clone(),
+        next: 0,
+        anchor: (Instant::now(), Duration::ZERO),
+        paused: false,
+        speed,
+        live: Default::default(),
+    };
+    std::thread::spawn(move || {
+        // this only fails if the relay is gone, and everyone watching with it
+        _ = player.run(ctl_rx);
+    });
+
+    Ok(Handle {
+        ctl: ctl_tx,
+        status,
+    })
+}
+
+struct Player {
+    records: Vec<TraceRecord>,
+    tx: EventTx,
+    status: Arc<Mutex<ReplayStatus>>,
+
+    /// Index of the next record to send
+    next: usize,
+
+    /// A point in wall-clock time, and where in the trace we were then
+    anchor: (Instant, Duration),
+
+    paused: bool,
+    speed: f64,
+
+    /// Tracees the frontend currently knows about, so we can make it forget
+    /// them when seeking
+    live: HashSet<TraceeId>,
+}
+
+impl Player {
+    fn run(&mut self, ctl_rx: mpsc::Receiver<Control>) -> Result<(), mpsc::SendError<MeviEvent>> {
+        loop {
+            self.update_status();
+
+            let timeout = match self.records.get(self.next) {
+                Some(record) if !self.paused => record
+                    .at
+                    .saturating_sub(self.position())
+                    .div_f64(self.speed)
+                    .min(STATUS_INTERVAL),
+                // paused, or at the end of the trace
+                _ => STATUS_INTERVAL,
+            };
+
+            match ctl_rx.recv_timeout(timeout) {
+                Ok(ctl) => self.control(ctl)?,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if self.paused {
+                        continue;
+                    }
+                    let position = self.position();
+                    while let Some(record) = self.records.get(self.next) {
+                        if record.at > position {
+                            break;
+                        }
+                        let ev = record.event.clone();
+                        self.next += 1;
+                        // those are only there for seeking, the deltas around
+                        // them already say it all
+                        if matches!(ev, MeviEvent::Snapshot(_)) {
+                            continue;
+                        }
+                        self.send(ev)?;
+                    }
+                }
+                // the server went away
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    /// Where in the trace we are right now
+    fn position(&self) -> Duration {
+        let duration = self.records.last().map(|r| r.at).unwrap_or_default();
+        if self.paused {
+            return self.anchor.1;
+        }
+        let (wall, at) = self.anchor;
+        (at + wall.elapsed().mul_f64(self.speed)).min(duration)
+    }
+
+    fn reanchor(&mut self, at: Duration) {
+        self.anchor = (Instant::now(), at);
+    }
+
+    fn control(&mut self, ctl: Control) -> Result<(), mpsc::SendError<MeviEvent>> {
+        let position = self.position();
+        match ctl {
+            Control::Play => self.paused = false,
+            Control::Pause => self.paused = true,
+            Control::Speed(speed) => self.speed = speed,
+            Control::Seek(at) => {
+                self.seek(at)?;
+                return Ok(());
+            }
+        }
+        self.reanchor(position);
+        Ok(())
+    }
+
+    fn seek(&mut self, at: Duration) -> Result<(), mpsc::SendError<MeviEvent>> {
+        let at = at.min(self.records.last().map(|r| r.at).unwrap_or_default());
+        for tid in std::mem::take(&mut self.live) {
+            self.send(MeviEvent::TraceeEvent(tid, TraceePayload::Exit))?;
+        }
+
+        // rather than sending everything that happened until then, work out
+        // what things looked like and only send that, starting from the last
+        // snapshot if the trace has any
+        self.next = self.records.partition_point(|r| r.at <= at);
+        let start = self.records[..self.next]
+            .iter()
+            .rposition(|r| matches!(r.event, MeviEvent::Snapshot(_)))
+            .unwrap_or(0);
+        let mut folded = HashMap::new();
+        for record in &self.records[start..self.next] {
+            fold(&mut folded, &record.event);
+        }
+
+        for (tid, tracee) in folded {
+            for payload in tracee.into_payloads() {
+                self.send(MeviEvent::TraceeEvent(tid, payload))?;
+            }
+        }
+
+        self.reanchor(at);
+        Ok(())
+    }
+
+    fn send(&mut self, ev: MeviEvent) -> Result<(), mpsc::SendError<MeviEvent>> {
+        if let MeviEvent::TraceeEvent(tid, payload) = &ev {
+            match payload {
+                TraceePayload::Exit => self.live.remove(tid),
+                _ => self.live.insert(*tid),
+            };
+        }
+        self.tx.send(ev)
+    }
+
+    fn update_status(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.position_ms = self.position().as_millis() as u64;
+        status.paused = self.paused;
+        status.speed = self.speed;
+    }
+}
+
+/// What a tracee looks like after a run of events: what seeking sends, and
+/// what `--snapshot-interval` records
+#[derive(Default)]
+pub(crate) struct Folded {
+    cmdline: Vec<String>,
+    map: MemMap,
+    kinds: MappingKinds,
+    files: MappingFiles,
+    perms: MappingPermsMap,
+    info: Option<ProcessInfo>,
+    parent: Option<TraceeId>,
+    threads: HashMap<TraceeId, String>,
+}
+
+/// Applies `ev` to what we know of every tracee. A snapshot replaces all
+/// of it, and tracees that exit are forgotten.
+pub(crate) fn fold(folded: &mut HashMap<TraceeId, Folded>, ev: &MeviEvent) {
+    match ev {
+        MeviEvent::TraceeEvent(tid, TraceePayload::Exit) => {
+            folded.remove(tid);
+        }
+        MeviEvent::TraceeEvent(tid, payload) => folded.entry(*tid).or_default().apply(payload),
+        MeviEvent::Snapshot(snapshots) => {
+            *folded = snapshots
+                .iter()
+                .map(|snapshot| (snapshot.tid, Folded::from_snapshot(snapshot)))
+                .collect();
+        }
+        MeviEvent::SessionEnd(_) | MeviEvent::SessionInfo(_) => {}
+    }
+}
+
+impl Folded {
+    fn apply(&mut self, payload: &TraceePayload) {
+        payload.apply_to_memmap(&mut self.map);
+        payload.apply_to_kinds(&mut self.kinds);
+        payload.apply_to_files(&mut self.files);
+        payload.apply_to_perms(&mut self.perms);
+        match payload {
+            TraceePayload::CmdLineChange { cmdline } => self.cmdline = cmdline.clone(),
+            TraceePayload::ProcessInfo(info) => self.info = Some(info.clone()),
+            TraceePayload::ParentChange { parent } => self.parent = *parent,
+            TraceePayload::ThreadName { thread, name } => {
+                self.threads.insert(*thread, name.clone());
+            }
+            _ => {}
+        }
+    }
+
+    fn from_snapshot(snapshot: &TraceeSnapshot) -> Self {
+        Self {
+            cmdline: snapshot.cmdline.clone(),
+            map: snapshot.map.clone(),
+            kinds: snapshot.kinds.clone(),
+            files: snapshot.files.clone(),
+            perms: snapshot.perms.clone(),
+            info: snapshot.info.clone(),
+            parent: snapshot.parent,
+            threads: snapshot.threads.iter().cloned().collect(),
+        }
+    }
+
+    /// Traces don't number events, so `seq` is always zero
+    pub(crate) fn snapshot(&self, tid: TraceeId) -> TraceeSnapshot {
+        TraceeSnapshot {
+            tid,
+            cmdline: self.cmdline.clone(),
+            map: self.map.clone(),
+            kinds: self.kinds.clone(),
+            files: self.files.clone(),
+            perms: self.perms.clone(),
+            seq: 0,
+            resident_pages: None,
+            info: self.info.clone(),
+            parent: self.parent,
+            threads: self
+                .threads
+                .iter()
+                .map(|(thread, name)| (*thread, name.clone()))
+                .collect(),
+        }
+    }
+
+    /// The events that get a tracee from nothing to here
+    fn into_payloads(self) -> Vec<TraceePayload> {
+        let mut payloads = vec![TraceePayload::CmdLineChange {
+            cmdline: self.cmdline,
+        }];
+        if let Some(info) = self.info {
+            payloads.push(TraceePayload::ProcessInfo(info));
+        }
+        if let Some(parent) = self.parent {
+            payloads.push(TraceePayload::ParentChange {
+                parent: Some(parent),
+            });
+        }
+        for (thread, name) in self.threads {
+            payloads.push(TraceePayload::ThreadName { thread, name });
+        }
+        for (range, kind) in self.kinds.iter() {
+            payloads.push(TraceePayload::Map {
+                range: range.clone(),
+                state: MemState::Untracked,
+                kind: *kind,
+                file: self.files.get(&range.start).cloned(),
+                perms: self.perms.get(&range.start).copied().unwrap_or_default(),
+                raw: None,
+            });
+        }
+        payloads.extend(self.mem_states());
+        payloads
+    }
+
+    /// Where every page stands, as events
+    pub(crate) fn mem_states(&self) -> impl Iterator<Item = TraceePayload> + '_ {
+        self.map
+            .iter()
+            .map(|(range, state)| TraceePayload::MemStateChange {
+                range: range.clone(),
+                state: *state,
+            })
+    }
+}
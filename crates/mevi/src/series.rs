@@ -0,0 +1,89 @@
+//! Resident bytes over time, for `/api/tracees/:tid/series`: every
+//! `--series-interval`, what each tracee has resident goes into a ring, so
+//! drawing a chart doesn't take replaying every event.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mevi_common::TraceeId;
+use serde::Serialize;
+
+use crate::{export, Tracees};
+
+/// How many samples each tracee keeps, oldest dropped first: an hour's
+/// worth at the default interval
+const SERIES_LEN: usize = 3600;
+
+#[derive(Clone)]
+pub(crate) struct Series {
+    pub(crate) interval: Duration,
+    points: Arc<Mutex<HashMap<TraceeId, VecDeque<Point>>>>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Point {
+    /// Milliseconds since the Unix epoch
+    at_ms: u64,
+    mapped: u64,
+    resident: u64,
+
+    /// Resident bytes in each category of mapping, cf.
+    /// [mevi_common::MappingKind::category]
+    by_category: BTreeMap<&'static str, u64>,
+}
+
+impl Series {
+    /// Samples `tracees` every `interval`, for as long as mevi runs
+    pub(crate) fn spawn(interval: Duration, tracees: Tracees) -> Self {
+        let series = Self {
+            interval,
+            points: Default::default(),
+        };
+        tokio::spawn(series.clone().run(tracees));
+        series
+    }
+
+    /// What we have for `tid`, oldest first, if it's still around
+    pub(crate) fn get(&self, tid: TraceeId) -> Option<Vec<Point>> {
+        let points = self.points.lock().unwrap();
+        points
+            .get(&tid)
+            .map(|points| points.iter().cloned().collect())
+    }
+
+    async fn run(self, tracees: Tracees) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            let at_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+
+            let tracees = tracees.lock().unwrap();
+            let mut points = self.points.lock().unwrap();
+            // once a tracee exits, the API forgets about it
+            points.retain(|tid, _| tracees.contains_key(tid));
+            for tracee in tracees.values() {
+                let (mapped, resident) = tracee.totals();
+                let by_category = export::by_category(&tracee.map, &tracee.kinds)
+                    .into_iter()
+                    .map(|(category, (_, resident))| (category, resident))
+                    .collect();
+                let points = points.entry(tracee.tid).or_default();
+                if points.len() == SERIES_LEN {
+                    points.pop_front();
+                }
+                points.push_back(Point {
+                    at_ms,
+                    mapped,
+                    resident,
+                    by_category,
+                });
+            }
+        }
+    }
+}
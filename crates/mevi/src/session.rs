@@ -0,0 +1,291 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use axum::extract::ws::{Message, WebSocket};
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use mevi_common::{protocol, ClockAnchor, TimedEvent, Timestamp, TraceeId};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::UnixListener,
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+use crate::{auth, fanout::Clients, Tracees};
+
+lazy_static::lazy_static! {
+    static ref MEVI_INTERVAL: Duration = Duration::from_millis(
+        std::env::var("MEVI_INTERVAL").unwrap_or_else(|_| "32".to_string()).parse().unwrap()
+    );
+}
+
+/// Anything a stream of frames can go over
+pub(crate) trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// Unlike websockets, byte streams don't keep frames apart for us, so each
+/// one is prefixed with its length, as a little-endian u32.
+pub(crate) async fn write_frame(io: &mut (impl AsyncWrite + Unpin), frame: &[u8]) -> Result<()> {
+    io.write_all(&(frame.len() as u32).to_le_bytes()).await?;
+    io.write_all(frame).await?;
+    io.flush().await?;
+    Ok(())
+}
+
+/// The next frame, or `None` if the peer hung up in between two frames
+pub(crate) async fn read_frame(io: &mut (impl AsyncRead + Unpin)) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match io.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > protocol::MAX_FRAME_LEN {
+        bail!("peer sent a {len}-byte frame, that's too big");
+    }
+    let mut frame = vec![0u8; len as usize];
+    io.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+/// How the server encodes frames once the handshake is done. The handshake
+/// itself is always binary, so clients can tell whether they're compatible
+/// before anything else.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Encoding {
+    /// What [protocol::decode] reads
+    #[default]
+    Postcard,
+
+    /// One JSON object per frame, in text websocket messages, for poking at
+    /// the stream by hand. Much slower to make and to read.
+    Json,
+
+    /// The same objects as JSON, field names and all, but in MessagePack,
+    /// in binary websocket messages: for clients that can't read postcard
+    /// but shouldn't pay for JSON either.
+    Msgpack,
+}
+
+/// A frame the server sends after the handshake
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outgoing<'a> {
+    Clock(&'a ClockAnchor),
+    Events(&'a [TimedEvent]),
+    Heartbeat(Timestamp),
+}
+
+impl Outgoing<'_> {
+    fn encode(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            Outgoing::Clock(anchor) => protocol::encode_clock(anchor)?,
+            Outgoing::Events(evs) => protocol::encode_events(evs)?,
+            Outgoing::Heartbeat(at) => protocol::encode_heartbeat(*at)?,
+        })
+    }
+}
+
+/// A client, over whichever transport it came in on
+pub(crate) enum Conn {
+    Ws(WebSocket),
+    Stream(Box<dyn Io>),
+}
+
+impl Conn {
+    async fn send(&mut self, frame: Vec<u8>) -> Result<()> {
+        match self {
+            Conn::Ws(ws) => ws.send(Message::Binary(frame)).await?,
+            Conn::Stream(io) => write_frame(io, &frame).await?,
+        }
+        Ok(())
+    }
+
+    async fn send_as(&mut self, encoding: Encoding, frame: Outgoing<'_>) -> Result<()> {
+        match (encoding, self) {
+            (Encoding::Postcard, conn) => conn.send(frame.encode()?).await?,
+            (Encoding::Json, Conn::Ws(ws)) => {
+                ws.send(Message::Text(serde_json::to_string(&frame)?))
+                    .await?
+            }
+            (Encoding::Json, Conn::Stream(io)) => {
+                write_frame(io, &serde_json::to_vec(&frame)?).await?
+            }
+            (Encoding::Msgpack, conn) => conn.send(rmp_serde::to_vec_named(&frame)?).await?,
+        }
+        Ok(())
+    }
+
+    /// The next frame, or `None` once the client is gone
+    async fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Conn::Ws(ws) => loop {
+                match ws.recv().await {
+                    Some(Ok(Message::Binary(data))) => return Ok(Some(data)),
+                    Some(Ok(Message::Close(_))) | None => return Ok(None),
+                    // pings and such
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                }
+            },
+            Conn::Stream(io) => read_frame(io).await,
+        }
+    }
+}
+
+/// Tells the client what we speak, and waits to hear what it speaks back,
+/// and where it wants to resume from. Websockets have been through the HTTP
+/// token check already, byte streams have to show `token` here, if there is
+/// one.
+async fn handshake(
+    conn: &mut Conn,
+    token: Option<&str>,
+) -> Result<(protocol::Features, Vec<(TraceeId, u64)>)> {
+    let ours = protocol::Hello::ours();
+    conn.send(protocol::encode_hello(&ours)).await?;
+
+    let features = match conn.recv().await? {
+        Some(data) => match protocol::decode(&data)? {
+            protocol::Frame::Hello(theirs) => ours.negotiate(&theirs)?,
+            _ => bail!("client sent frames before saying hello"),
+        },
+        None => bail!("client left before saying hello"),
+    };
+    let mut shown = None;
+    loop {
+        let Some(data) = conn.recv().await? else {
+            bail!("client left right after saying hello");
+        };
+        match protocol::decode(&data)? {
+            protocol::Frame::Token(theirs) if shown.is_none() => shown = Some(theirs),
+            protocol::Frame::Resume(from) => {
+                let shown = shown.as_deref().unwrap_or_default();
+                if token.is_some_and(|token| !auth::same(shown.as_bytes(), token.as_bytes())) {
+                    bail!("client didn't show the token");
+                }
+                return Ok((features, from));
+            }
+            _ => bail!("client didn't say where to resume from"),
+        }
+    }
+}
+
+/// Lets local clients get events without going through HTTP: same frames as
+/// over TCP, on a Unix socket at `path`
+pub(crate) fn listen_unix(
+    path: &Path,
+    token: Option<Arc<str>>,
+    clients: Clients,
+    tracees: Tracees,
+    anchor: ClockAnchor,
+) -> Result<()> {
+    // a mevi that didn't get to clean up leaves its socket behind
+    _ = std::fs::remove_file(path);
+    let listener =
+        UnixListener::bind(path).wrap_err_with(|| format!("listening on {}", path.display()))?;
+    info!("streaming events on {}", path.display());
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("accepting local client: {e}");
+                    continue;
+                }
+            };
+            let conn = Conn::Stream(Box::new(stream));
+            let (token, clients, tracees) = (token.clone(), clients.clone(), tracees.clone());
+            tokio::spawn(serve(
+                conn,
+                Encoding::Postcard,
+                token,
+                clients,
+                tracees,
+                anchor,
+            ));
+        }
+    });
+    Ok(())
+}
+
+/// Streams events to a client until it goes away. Clients that don't show
+/// `token` (if there is one) don't get any.
+pub(crate) async fn serve(
+    mut conn: Conn,
+    encoding: Encoding,
+    token: Option<Arc<str>>,
+    clients: Clients,
+    tracees: Tracees,
+    anchor: ClockAnchor,
+) {
+    if let Err(e) = stream(
+        &mut conn,
+        encoding,
+        token.as_deref(),
+        clients,
+        tracees,
+        anchor,
+    )
+    .await
+    {
+        warn!("dropping client: {e}");
+    }
+}
+
+async fn stream(
+    conn: &mut Conn,
+    encoding: Encoding,
+    token: Option<&str>,
+    clients: Clients,
+    tracees: Tracees,
+    anchor: ClockAnchor,
+) -> Result<()> {
+    let (features, resume) = handshake(conn, token).await?;
+    debug!("client negotiated features {:#x}", features.bits());
+    let mut events = crate::subscribe(&clients, &tracees, &resume);
+
+    // so the client can put event timestamps on a wall clock
+    conn.send_as(encoding, Outgoing::Clock(&anchor)).await?;
+
+    let interval = *MEVI_INTERVAL;
+    let mut next_flush = Instant::now() + interval;
+    let mut queue = vec![];
+    let mut deltas = protocol::OverlayDeltas::default();
+    let mut last_sent = Instant::now();
+
+    loop {
+        match tokio::time::timeout_at(next_flush, events.recv()).await {
+            // the relay is gone, so are we
+            Ok(None) => return Ok(()),
+            Ok(Some(ev)) => {
+                let TimedEvent { at, seq, event } = ev;
+                let Some(mut event) = protocol::downgrade(event, features) else {
+                    continue;
+                };
+                if features.contains(protocol::Features::OVERLAY_DELTAS) {
+                    event = deltas.encode(event);
+                }
+                queue.push(TimedEvent { at, seq, event });
+            }
+            Err(_elapsed) => {
+                if !queue.is_empty() {
+                    conn.send_as(encoding, Outgoing::Events(&queue[..])).await?;
+                    queue.clear();
+                    last_sent = Instant::now();
+                } else if last_sent.elapsed() >= protocol::HEARTBEAT_INTERVAL {
+                    conn.send_as(encoding, Outgoing::Heartbeat(Timestamp::now()))
+                        .await?;
+                    last_sent = Instant::now();
+                }
+                next_flush += interval;
+            }
+        };
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap, ops::Range, path::Path, sync::mpsc, thread::JoinHandle, time::Duration,
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use mevi_common::{ClockAnchor, Timestamp, TraceeId, TraceePayload};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use tracing::{info, warn};
+
+use crate::cli::Sink;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS processes (
+    tid INTEGER NOT NULL,
+    cmdline TEXT,
+    started_at_ms INTEGER NOT NULL,
+    exited_at_ms INTEGER
+);
+CREATE TABLE IF NOT EXISTS mappings (
+    tid INTEGER NOT NULL,
+    start_addr INTEGER NOT NULL,
+    end_addr INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    mapped_at_ms INTEGER NOT NULL,
+    unmapped_at_ms INTEGER
+);
+CREATE TABLE IF NOT EXISTS events (
+    at_ms INTEGER NOT NULL,
+    tid INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    start_addr INTEGER,
+    end_addr INTEGER,
+    state TEXT,
+    payload TEXT
+);
+CREATE TABLE IF NOT EXISTS samples (
+    at_ms INTEGER NOT NULL,
+    tid INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS mappings_by_tid ON mappings (tid, start_addr);
+CREATE INDEX IF NOT EXISTS events_by_tid ON events (tid, at_ms);
+CREATE INDEX IF NOT EXISTS samples_by_tid ON samples (tid, at_ms);
+";
+
+/// Lets the relay hand events to a sink, which writes them out on its own
+/// thread
+pub(crate) struct Handle {
+    tx: mpsc::Sender<(Duration, TraceeId, TraceePayload)>,
+    anchor: ClockAnchor,
+    thread: JoinHandle<()>,
+}
+
+impl Handle {
+    pub(crate) fn send(&self, at: Timestamp, tid: TraceeId, payload: &TraceePayload) {
+        let at = at.since(self.anchor.monotonic);
+        // if the sink is gone, it already said why
+        _ = self.tx.send((at, tid, payload.clone()));
+    }
+
+    /// Waits for the sink to write out everything it was sent
+    pub(crate) fn finish(self) {
+        drop(self.tx);
+        _ = self.thread.join();
+    }
+}
+
+pub(crate) fn spawn(sink: &Sink, anchor: ClockAnchor) -> Result<Handle> {
+    let (tx, rx) = mpsc::channel();
+    let thread = match sink {
+        Sink::Sqlite(path) => {
+            let mut db = Sqlite::open(path)?;
+            let path = path.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = db.run(rx) {
+                    warn!("sqlite sink {} stopped: {e:?}", path.display());
+                }
+            })
+        }
+    };
+    Ok(Handle { tx, anchor, thread })
+}
+
+/// Normalizes events into tables, so questions like "which mappings were
+/// around at t=42s" are a query away.
+///
+/// Timestamps are milliseconds since mevi started, by the time events were
+/// captured rather than written. Like with exports, a
+/// mapping is an address range: if part of it gets unmapped, the whole row
+/// ends.
+struct Sqlite {
+    conn: Connection,
+
+    /// The `processes` row for every tracee we've seen and that's still
+    /// around
+    processes: HashMap<TraceeId, i64>,
+}
+
+impl Sqlite {
+    fn open(path: &Path) -> Result<Self> {
+        let conn =
+            Connection::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        info!("writing events to {}", path.display());
+        Ok(Self {
+            conn,
+            processes: Default::default(),
+        })
+    }
+
+    fn run(&mut self, rx: mpsc::Receiver<(Duration, TraceeId, TraceePayload)>) -> Result<()> {
+        // one transaction per batch, committing row by row is way too slow
+        // for a page fault storm
+        while let Ok(first) = rx.recv() {
+            let tx = self.conn.transaction()?;
+            for (at, tid, payload) in std::iter::once(first).chain(rx.try_iter()) {
+                write(
+                    &tx,
+                    &mut self.processes,
+                    at.as_millis() as i64,
+                    tid,
+                    &payload,
+                )?;
+            }
+            tx.commit()?;
+        }
+        Ok(())
+    }
+}
+
+fn write(
+    tx: &Transaction,
+    processes: &mut HashMap<TraceeId, i64>,
+    at: i64,
+    tid: TraceeId,
+    payload: &TraceePayload,
+) -> Result<()> {
+    let pid = tid.0 as i64;
+    if !processes.contains_key(&tid) {
+        tx.prepare_cached("INSERT INTO processes (tid, started_at_ms) VALUES (?1, ?2)")?
+            .execute(params![pid, at])?;
+        processes.insert(tid, tx.last_insert_rowid());
+    }
+    let process = processes[&tid];
+
+    if let TraceePayload::Sample(sample) = payload {
+        let (name, value) = variant(sample)?;
+        tx.prepare_cached("INSERT INTO samples (at_ms, tid, name, value) VALUES (?1, ?2, ?3, ?4)")?
+            .execute(params![at, pid, name, value])?;
+        return Ok(());
+    }
+
+    let (name, value) = variant(payload)?;
+    let (range, state) = match payload {
+        TraceePayload::Map { range, state, .. }
+        | TraceePayload::MemStateChange { range, state } => (Some(range), Some(*state)),
+        TraceePayload::PageFault { range, .. } | TraceePayload::Unmap { range } => {
+            (Some(range), None)
+        }
+        TraceePayload::Remap { new_range, .. } => (Some(new_range), None),
+        _ => (None, None),
+    };
+    tx.prepare_cached(
+        "INSERT INTO events (at_ms, tid, kind, start_addr, end_addr, state, payload)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?
+    .execute(params![
+        at,
+        pid,
+        name,
+        range.map(|r| r.start as i64),
+        range.map(|r| r.end as i64),
+        state.map(|s| format!("{s:?}")),
+        value,
+    ])?;
+
+    match payload {
+        TraceePayload::Map { range, kind, .. } => {
+            tx.prepare_cached(
+                "INSERT INTO mappings (tid, start_addr, end_addr, kind, mapped_at_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                pid,
+                range.start as i64,
+                range.end as i64,
+                format!("{kind:?}"),
+                at
+            ])?;
+        }
+        TraceePayload::Unmap { range } => unmap(tx, at, pid, range)?,
+        TraceePayload::Remap {
+            old_range,
+            new_range,
+        } => {
+            let kind: Option<String> = tx
+                .prepare_cached(
+                    "SELECT kind FROM mappings
+                     WHERE tid = ?1 AND start_addr = ?2 AND unmapped_at_ms IS NULL",
+                )?
+                .query_row(params![pid, old_range.start as i64], |row| row.get(0))
+                .optional()?;
+            unmap(tx, at, pid, old_range)?;
+            if let Some(kind) = kind {
+                tx.prepare_cached(
+                    "INSERT INTO mappings (tid, start_addr, end_addr, kind, mapped_at_ms)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?
+                .execute(params![
+                    pid,
+                    new_range.start as i64,
+                    new_range.end as i64,
+                    kind,
+                    at
+                ])?;
+            }
+        }
+        TraceePayload::Exec { .. } => unmap(tx, at, pid, &(0..u64::MAX))?,
+        TraceePayload::CmdLineChange { cmdline } => {
+            tx.prepare_cached("UPDATE processes SET cmdline = ?1 WHERE rowid = ?2")?
+                .execute(params![cmdline.join(" "), process])?;
+        }
+        TraceePayload::Exit => {
+            unmap(tx, at, pid, &(0..u64::MAX))?;
+            tx.prepare_cached("UPDATE processes SET exited_at_ms = ?1 WHERE rowid = ?2")?
+                .execute(params![at, process])?;
+            processes.remove(&tid);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Ends every current mapping that overlaps `range`
+fn unmap(tx: &Transaction, at: i64, pid: i64, range: &Range<u64>) -> Result<()> {
+    tx.prepare_cached(
+        "UPDATE mappings SET unmapped_at_ms = ?1
+         WHERE tid = ?2 AND unmapped_at_ms IS NULL AND start_addr < ?3 AND end_addr > ?4",
+    )?
+    .execute(params![
+        at,
+        pid,
+        range.end.min(i64::MAX as u64) as i64,
+        range.start as i64
+    ])?;
+    Ok(())
+}
+
+/// Splits an externally-tagged enum into its variant name and its fields as
+/// JSON (null for unit variants)
+fn variant(v: &impl serde::Serialize) -> Result<(String, String)> {
+    Ok(match serde_json::to_value(v)? {
+        serde_json::Value::Object(map) if map.len() == 1 => {
+            let (name, value) = map.into_iter().next().unwrap();
+            (name, value.to_string())
+        }
+        serde_json::Value::String(name) => (name, "null".to_owned()),
+        other => ("unknown".to_owned(), other.to_string()),
+    })
+}
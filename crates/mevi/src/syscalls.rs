@@ -0,0 +1,121 @@
+//! `--watch-syscalls`: syscalls that get reported as they happen, on top of
+//! the ones we always decode to keep track of memory.
+
+/// Syscalls we know by name. Anything else can be watched by number.
+const NAMES: &[(&str, i64)] = &[
+    // memory
+    ("mmap", libc::SYS_mmap),
+    ("munmap", libc::SYS_munmap),
+    ("mremap", libc::SYS_mremap),
+    ("mprotect", libc::SYS_mprotect),
+    ("pkey_mprotect", libc::SYS_pkey_mprotect),
+    ("madvise", libc::SYS_madvise),
+    ("process_madvise", libc::SYS_process_madvise),
+    ("brk", libc::SYS_brk),
+    ("mbind", libc::SYS_mbind),
+    ("set_mempolicy", libc::SYS_set_mempolicy),
+    ("get_mempolicy", libc::SYS_get_mempolicy),
+    ("migrate_pages", libc::SYS_migrate_pages),
+    ("move_pages", libc::SYS_move_pages),
+    ("mlock", libc::SYS_mlock),
+    ("mlock2", libc::SYS_mlock2),
+    ("munlock", libc::SYS_munlock),
+    ("mlockall", libc::SYS_mlockall),
+    ("munlockall", libc::SYS_munlockall),
+    ("msync", libc::SYS_msync),
+    ("mincore", libc::SYS_mincore),
+    ("remap_file_pages", libc::SYS_remap_file_pages),
+    ("memfd_create", libc::SYS_memfd_create),
+    ("membarrier", libc::SYS_membarrier),
+    ("userfaultfd", libc::SYS_userfaultfd),
+    ("shmget", libc::SYS_shmget),
+    ("shmat", libc::SYS_shmat),
+    ("shmdt", libc::SYS_shmdt),
+    ("shmctl", libc::SYS_shmctl),
+    // files
+    ("open", libc::SYS_open),
+    ("openat", libc::SYS_openat),
+    ("close", libc::SYS_close),
+    ("read", libc::SYS_read),
+    ("write", libc::SYS_write),
+    ("pread64", libc::SYS_pread64),
+    ("pwrite64", libc::SYS_pwrite64),
+    ("readv", libc::SYS_readv),
+    ("writev", libc::SYS_writev),
+    ("lseek", libc::SYS_lseek),
+    ("fstat", libc::SYS_fstat),
+    ("ftruncate", libc::SYS_ftruncate),
+    ("fallocate", libc::SYS_fallocate),
+    ("fsync", libc::SYS_fsync),
+    ("fdatasync", libc::SYS_fdatasync),
+    ("sendfile", libc::SYS_sendfile),
+    ("splice", libc::SYS_splice),
+    ("ioctl", libc::SYS_ioctl),
+    ("fcntl", libc::SYS_fcntl),
+    ("dup", libc::SYS_dup),
+    ("dup2", libc::SYS_dup2),
+    ("dup3", libc::SYS_dup3),
+    ("pipe", libc::SYS_pipe),
+    ("pipe2", libc::SYS_pipe2),
+    ("unlink", libc::SYS_unlink),
+    ("unlinkat", libc::SYS_unlinkat),
+    // processes and threads
+    ("clone", libc::SYS_clone),
+    ("clone3", libc::SYS_clone3),
+    ("fork", libc::SYS_fork),
+    ("vfork", libc::SYS_vfork),
+    ("execve", libc::SYS_execve),
+    ("execveat", libc::SYS_execveat),
+    ("exit", libc::SYS_exit),
+    ("exit_group", libc::SYS_exit_group),
+    ("wait4", libc::SYS_wait4),
+    ("kill", libc::SYS_kill),
+    ("tgkill", libc::SYS_tgkill),
+    ("prctl", libc::SYS_prctl),
+    ("arch_prctl", libc::SYS_arch_prctl),
+    ("set_tid_address", libc::SYS_set_tid_address),
+    ("rseq", libc::SYS_rseq),
+    ("futex", libc::SYS_futex),
+    ("sched_yield", libc::SYS_sched_yield),
+    ("nanosleep", libc::SYS_nanosleep),
+    ("clock_nanosleep", libc::SYS_clock_nanosleep),
+    ("getrlimit", libc::SYS_getrlimit),
+    ("setrlimit", libc::SYS_setrlimit),
+    ("prlimit64", libc::SYS_prlimit64),
+    // network
+    ("socket", libc::SYS_socket),
+    ("connect", libc::SYS_connect),
+    ("accept", libc::SYS_accept),
+    ("accept4", libc::SYS_accept4),
+    ("bind", libc::SYS_bind),
+    ("listen", libc::SYS_listen),
+    ("sendto", libc::SYS_sendto),
+    ("recvfrom", libc::SYS_recvfrom),
+    ("sendmsg", libc::SYS_sendmsg),
+    ("recvmsg", libc::SYS_recvmsg),
+    // waiting on things
+    ("poll", libc::SYS_poll),
+    ("ppoll", libc::SYS_ppoll),
+    ("select", libc::SYS_select),
+    ("epoll_create1", libc::SYS_epoll_create1),
+    ("epoll_ctl", libc::SYS_epoll_ctl),
+    ("epoll_wait", libc::SYS_epoll_wait),
+    ("epoll_pwait", libc::SYS_epoll_pwait),
+    ("eventfd2", libc::SYS_eventfd2),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("io_uring_enter", libc::SYS_io_uring_enter),
+];
+
+/// A syscall name from [NAMES], or a number (for which we make up a name)
+pub(crate) fn parse(s: &str) -> Option<(i64, String)> {
+    if let Some(&(name, nr)) = NAMES.iter().find(|(name, _)| *name == s) {
+        return Some((nr, name.to_owned()));
+    }
+    let nr = s.parse().ok()?;
+    let name = NAMES
+        .iter()
+        .find(|(_, known)| *known == nr)
+        .map(|(name, _)| (*name).to_owned())
+        .unwrap_or_else(|| format!("syscall_{nr}"));
+    Some((nr, name))
+}
@@ -0,0 +1,381 @@
+//! `mevi tui`: roughly what the frontend shows, in a terminal, for machines
+//! where opening a browser isn't an option
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::Stdout,
+    sync::mpsc::TryRecvError,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use humansize::{make_format, BINARY};
+use mevi_common::{
+    resident_in, MappingKinds, MemMap, MeviEvent, Timestamp, TraceeId, TraceePayload,
+};
+use mevi_tracer::channel::EventRx;
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Row, Sparkline, Table},
+    Terminal,
+};
+
+use crate::hot::Hot;
+
+/// How often we redraw, and check for keys
+const TICK: Duration = Duration::from_millis(100);
+
+/// The fault rate is counted over buckets that long...
+const RATE_BUCKET: Duration = Duration::from_secs(1);
+
+/// ...and we keep that many of them
+const RATE_HISTORY: usize = 300;
+
+/// How many events we take in between two redraws, at most, so a busy
+/// tracee doesn't make us unresponsive
+const MAX_EVENTS_PER_TICK: usize = 100_000;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Range, kind, size and resident bytes
+const MAPPING_WIDTHS: [Constraint; 4] = [
+    Constraint::Length(30),
+    Constraint::Length(14),
+    Constraint::Length(12),
+    Constraint::Length(12),
+];
+
+#[derive(Default)]
+struct Tracee {
+    cmdline: Vec<String>,
+    map: MemMap,
+    kinds: MappingKinds,
+
+    /// Pages faulted in per [RATE_BUCKET], oldest first, the last one being
+    /// the current bucket
+    fault_rate: VecDeque<u64>,
+
+    /// Where it's been faulting lately
+    hot: Hot,
+
+    exited: bool,
+}
+
+#[derive(Default)]
+struct App {
+    tracees: BTreeMap<TraceeId, Tracee>,
+
+    /// Which tracee we're looking at
+    selected: usize,
+
+    /// Whether the tracer is done, and nothing more is coming
+    done: bool,
+
+    /// Whether to show the hottest regions instead of the mappings
+    show_hot: bool,
+}
+
+impl App {
+    fn apply(&mut self, tid: TraceeId, at: Timestamp, payload: TraceePayload) {
+        let tracee = self.tracees.entry(tid).or_insert_with(|| Tracee {
+            fault_rate: [0].into(),
+            ..Default::default()
+        });
+        payload.apply_to_memmap(&mut tracee.map);
+        payload.apply_to_kinds(&mut tracee.kinds);
+        match payload {
+            TraceePayload::CmdLineChange { cmdline } => tracee.cmdline = cmdline,
+            TraceePayload::PageFault { range, .. } => {
+                *tracee.fault_rate.back_mut().unwrap() += (range.end - range.start) / PAGE_SIZE;
+                tracee.hot.fault(at, &range);
+            }
+            TraceePayload::Exec { .. } => tracee.hot.clear(),
+            TraceePayload::Exit => tracee.exited = true,
+            _ => {}
+        }
+    }
+
+    /// Starts a new fault rate bucket for every tracee that's still around
+    fn next_bucket(&mut self) {
+        for tracee in self.tracees.values_mut().filter(|tracee| !tracee.exited) {
+            if tracee.fault_rate.len() == RATE_HISTORY {
+                tracee.fault_rate.pop_front();
+            }
+            tracee.fault_rate.push_back(0);
+        }
+    }
+
+    /// Forgets faults that are too old to count as hot
+    fn expire(&mut self, now: Timestamp) {
+        for tracee in self.tracees.values_mut() {
+            tracee.hot.expire(now);
+        }
+    }
+
+    fn selected(&self) -> Option<(&TraceeId, &Tracee)> {
+        self.tracees.iter().nth(self.selected)
+    }
+}
+
+/// Puts the terminal back the way we found it, however we leave
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        _ = terminal::disable_raw_mode();
+        _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+    }
+}
+
+/// Shows events from `rx` until the user quits
+pub(crate) fn run(rx: EventRx) -> Result<()> {
+    terminal::enable_raw_mode()?;
+    let _guard = Guard;
+    execute!(std::io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+
+    let mut app = App::default();
+    let mut bucket_start = Instant::now();
+    loop {
+        for _ in 0..MAX_EVENTS_PER_TICK {
+            match rx.try_recv() {
+                Ok(ev) => {
+                    if let MeviEvent::TraceeEvent(tid, payload) = ev.event {
+                        app.apply(tid, ev.at, payload);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    app.done = true;
+                    break;
+                }
+            }
+        }
+        if bucket_start.elapsed() >= RATE_BUCKET {
+            bucket_start += RATE_BUCKET;
+            app.next_bucket();
+        }
+        app.expire(Timestamp::now());
+
+        draw(&mut terminal, &app)?;
+
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let count = app.tracees.len().max(1);
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => {
+                        app.selected = (app.selected + 1) % count
+                    }
+                    KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+                        app.selected = (app.selected + count - 1) % count
+                    }
+                    KeyCode::Char('f') => app.show_hot = !app.show_hot,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn draw(terminal: &mut Terminal<CrosstermBackend<Stdout>>, app: &App) -> Result<()> {
+    terminal.draw(|f| {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1),
+                Constraint::Percentage(40),
+                Constraint::Min(5),
+                Constraint::Length(5),
+            ])
+            .split(f.size());
+
+        f.render_widget(header(app), rows[0]);
+        let Some((_, tracee)) = app.selected() else {
+            let msg = if app.done {
+                "the tracer is done, press q to quit"
+            } else {
+                "waiting for events..."
+            };
+            f.render_widget(Paragraph::new(msg), rows[1]);
+            return;
+        };
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("address space (mapped ranges, end to end)");
+        let inner = block.inner(rows[1]);
+        f.render_widget(heatmap(tracee, inner).block(block), rows[1]);
+        if app.show_hot {
+            f.render_widget(hot(tracee), rows[2]);
+        } else {
+            f.render_widget(mappings(tracee), rows[2]);
+        }
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("pages faulted in, per second");
+        // the newest buckets, as many as fit
+        let width = block.inner(rows[3]).width as usize;
+        let rate: Vec<u64> = tracee.fault_rate.iter().copied().collect();
+        let rate = &rate[rate.len().saturating_sub(width)..];
+        f.render_widget(
+            Sparkline::default()
+                .block(block)
+                .data(rate)
+                .style(Style::default().fg(Color::Yellow)),
+            rows[3],
+        );
+    })?;
+    Ok(())
+}
+
+/// One tab per tracee, and totals for the one we're looking at
+fn header(app: &App) -> Paragraph<'static> {
+    let formatter = make_format(BINARY);
+    let mut spans = vec![];
+    for (i, (tid, tracee)) in app.tracees.iter().enumerate() {
+        let mut style = Style::default();
+        if i == app.selected {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        if tracee.exited {
+            style = style.fg(Color::DarkGray);
+        }
+        spans.push(Span::styled(format!(" {} ", tid.0), style));
+    }
+    if let Some((_, tracee)) = app.selected() {
+        let (mapped, resident) =
+            tracee
+                .map
+                .iter()
+                .fold((0, 0), |(mapped, resident), (range, state)| {
+                    let size = range.end - range.start;
+                    let resident = resident + if state.is_resident() { size } else { 0 };
+                    (mapped + size, resident)
+                });
+        spans.push(Span::raw(format!(
+            " {} resident of {} mapped  {}",
+            formatter(resident),
+            formatter(mapped),
+            tracee.cmdline.join(" "),
+        )));
+    }
+    Paragraph::new(Line::from(spans))
+}
+
+/// Every mapped range, laid end to end and cut into one cell per character,
+/// each shaded by how much of it is resident
+fn heatmap(tracee: &Tracee, area: Rect) -> Paragraph<'static> {
+    let cells = area.width as u64 * area.height as u64;
+    let mapped: u64 = tracee
+        .map
+        .iter()
+        .map(|(range, _)| range.end - range.start)
+        .sum();
+    if cells == 0 || mapped == 0 {
+        return Paragraph::new("");
+    }
+    let per_cell = (mapped + cells - 1) / cells;
+
+    let mut resident = vec![0u64; cells as usize];
+    let mut pos = 0;
+    for (range, state) in tracee.map.iter() {
+        let mut left = range.end - range.start;
+        while left > 0 {
+            let cell = pos / per_cell;
+            let take = left.min((cell + 1) * per_cell - pos);
+            if state.is_resident() {
+                resident[cell as usize] += take;
+            }
+            pos += take;
+            left -= take;
+        }
+    }
+    let used = ((mapped + per_cell - 1) / per_cell) as usize;
+
+    let lines = resident[..used]
+        .chunks(area.width as usize)
+        .map(|row| {
+            let text: String = row
+                .iter()
+                .map(|&bytes| match bytes * 4 / per_cell {
+                    _ if bytes == 0 => '·',
+                    0 => '░',
+                    1 => '▒',
+                    2 => '▓',
+                    _ => '█',
+                })
+                .collect();
+            Line::styled(text, Style::default().fg(Color::Green))
+        })
+        .collect::<Vec<_>>();
+    Paragraph::new(lines)
+}
+
+/// Mappings, biggest resident set first
+fn mappings(tracee: &Tracee) -> Table<'static> {
+    let formatter = make_format(BINARY);
+    let mut rows = tracee
+        .kinds
+        .iter()
+        .map(|(range, kind)| (range.clone(), *kind, resident_in(&tracee.map, range)))
+        .collect::<Vec<_>>();
+    rows.sort_by_key(|(_, _, resident)| std::cmp::Reverse(*resident));
+
+    let rows = rows.into_iter().map(|(range, kind, resident)| {
+        Row::new(vec![
+            format!("{:#x}-{:#x}", range.start, range.end),
+            format!("{kind:?}"),
+            formatter(range.end - range.start).to_string(),
+            formatter(resident).to_string(),
+        ])
+    });
+    Table::new(rows)
+        .header(
+            Row::new(vec!["range", "kind", "size", "resident"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL).title("mappings"))
+        .widths(&MAPPING_WIDTHS)
+}
+
+/// The regions that took the most faults lately, hottest first
+fn hot(tracee: &Tracee) -> Table<'static> {
+    let formatter = make_format(BINARY);
+    let rows = tracee.hot.top(100).into_iter().map(|(range, bytes)| {
+        let kind = tracee
+            .kinds
+            .overlapping(&range)
+            .next()
+            .map(|(_, kind)| format!("{kind:?}"))
+            .unwrap_or_default();
+        Row::new(vec![
+            format!("{:#x}-{:#x}", range.start, range.end),
+            kind,
+            formatter(bytes).to_string(),
+            format!("{}/s", formatter(bytes / crate::hot::WINDOW.as_secs())),
+        ])
+    });
+    Table::new(rows)
+        .header(
+            Row::new(vec!["region", "kind", "faulted", "rate"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL).title(format!(
+            "hottest regions (last {}s)",
+            crate::hot::WINDOW.as_secs()
+        )))
+        .widths(&MAPPING_WIDTHS)
+}